@@ -0,0 +1,146 @@
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use yasp::basic::{Grammar, Parser, Text};
+
+const ARITHMETIC: &str = r#"
+    expression = term , (("+" | "-") , term)*;
+    term = factor , (("*" | "/") , factor)*;
+    factor = ("(" , expression , ")") | number;
+    number = digit+;
+    digit = "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9";
+"#;
+
+const REGEX_HEAVY: &str = r#"
+    document = line*;
+    line = (assignment | comment) , newline;
+    assignment = name , ws , "=" , ws , value;
+    value = #'[0-9]+([.][0-9]+)?' | #'"[^"]*"' | name;
+    comment = #'#[^\n]*';
+    name = #'[a-zA-Z_][a-zA-Z0-9_]*';
+    ws = #'[ \t]*';
+    newline = #'\n';
+"#;
+
+const TERMINAL_HEAVY: &str = r#"
+    data = record+;
+    record = "<" , ("0" | "1" | "2" | "3")+ , ">" , [","];
+"#;
+
+/// A grammar together with the start rule, built once and reused across iterations
+struct Harness {
+    grammar: Grammar<Text>,
+    start: &'static str,
+}
+
+impl Harness {
+    fn new(ebnf: &str, start: &'static str) -> Self {
+        let grammar = Grammar::load_ebnf(ebnf).expect("invalid benchmark grammar");
+        Self { grammar, start }
+    }
+
+    fn bench(&self, c: &mut Criterion, group: &str, inputs: &[(String, String)]) {
+        self.bench_parser(c, group, &self.grammar.parser(), inputs);
+    }
+
+    fn bench_parser(&self, c: &mut Criterion, group: &str, parser: &Parser<'_, Text>, inputs: &[(String, String)]) {
+        let mut group = c.benchmark_group(group);
+        for (label, input) in inputs {
+            let (token, diagnostics) = parser
+                .parse_non_term(self.start, input)
+                .unwrap()
+                .expect("benchmark input failed to parse");
+            assert!(diagnostics.is_empty(), "{label}: unexpected diagnostics {diagnostics:?}");
+            assert_eq!(token.span.end, input.len(), "{label}: input not fully consumed");
+
+            group.throughput(Throughput::Bytes(input.len() as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(label), input, |b, input| {
+                b.iter(|| {
+                    parser
+                        .parse_non_term(self.start, input)
+                        .unwrap()
+                        .expect("benchmark input failed to parse")
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+/// `1+2*3-4/5+...` with `terms` terms
+fn flat_expression(terms: usize) -> String {
+    let ops = ['+', '*', '-', '/'];
+    let mut s = String::new();
+    for i in 0..terms {
+        if i > 0 {
+            s.push(ops[i % ops.len()]);
+        }
+        s.push_str(&(i * 7919 % 1000).to_string());
+    }
+    s
+}
+
+/// `((((1+2)*3)+4)...)` nested `depth` times
+fn nested_expression(depth: usize) -> String {
+    let mut s = "1".to_string();
+    for i in 0..depth {
+        s = format!("({s}+{i})");
+    }
+    s
+}
+
+fn config_file(lines: usize) -> String {
+    let mut s = String::new();
+    for i in 0..lines {
+        match i % 3 {
+            0 => s.push_str(&format!("value_{i} = {i}.5\n")),
+            1 => s.push_str(&format!("name_{i} = \"text {i}\"\n")),
+            _ => s.push_str(&format!("# comment number {i}\n")),
+        }
+    }
+    s
+}
+
+/// `<0123...>,<...>` with `records` records of 16 digits
+fn records(records: usize) -> String {
+    (0..records)
+        .map(|i| format!("<{}>", (0..16).map(|j| char::from(b'0' + ((i + j) % 4) as u8)).collect::<String>()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn arithmetic(c: &mut Criterion) {
+    let harness = Harness::new(ARITHMETIC, "expression");
+
+    let flat = [10, 100, 1000]
+        .map(|n| (format!("{n} terms"), flat_expression(n)));
+    harness.bench(c, "arithmetic/flat", &flat);
+
+    let nested = [5, 20, 80]
+        .map(|n| (format!("depth {n}"), nested_expression(n)));
+    harness.bench(c, "arithmetic/nested", &nested);
+
+    let pruned = harness.grammar.parser().prune_alt_branches();
+    harness.bench_parser(c, "arithmetic/flat pruned", &pruned, &flat);
+    harness.bench_parser(c, "arithmetic/nested pruned", &pruned, &nested);
+
+    let bounded = harness.grammar.parser().cache_capacity(256);
+    harness.bench_parser(c, "arithmetic/flat bounded cache", &bounded, &flat);
+}
+
+fn regex_heavy(c: &mut Criterion) {
+    let harness = Harness::new(REGEX_HEAVY, "document");
+
+    let inputs = [10, 100, 500]
+        .map(|n| (format!("{n} lines"), config_file(n)));
+    harness.bench(c, "regex/config", &inputs);
+}
+
+fn terminal_heavy(c: &mut Criterion) {
+    let harness = Harness::new(TERMINAL_HEAVY, "data");
+
+    let inputs = [10, 100, 500]
+        .map(|n| (format!("{n} records"), records(n)));
+    harness.bench(c, "terminals/records", &inputs);
+}
+
+criterion_group!(benches, arithmetic, regex_heavy, terminal_heavy);
+criterion_main!(benches);
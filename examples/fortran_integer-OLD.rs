@@ -1,12 +1,6 @@
 use std::{ops::Range, path::PathBuf};
 
-use yasp::basic::{Grammar, Text, Token};
-
-
-trait FromToken: Sized {
-    const GRAM: &str;
-    fn from_token(src: &str, tok: &Token) -> anyhow::Result<Self>;
-}
+use yasp::basic::{FromToken, Grammar, Text, Token};
 
 #[derive(Debug, Clone)]
 pub struct SignedIntLiteralConstant {
@@ -63,13 +57,7 @@ fn parse<G: FromToken>(src: &str) -> anyhow::Result<G> {
     std::fs::write(here.join("fortran_integer.ebnf"), ebnf)
         .map_err(|e| anyhow::anyhow!("Failed to write EBNF to file: {}", e))?;
 
-    let (tok, diagnostics) = grammar.parse_non_term(G::GRAM, src)?
-        .ok_or(anyhow::anyhow!("Failed to parse"))?;
-    if !diagnostics.is_empty() {
-        return Err(anyhow::anyhow!("Diagnostics: {:?}", diagnostics));
-    }
-    let result = G::from_token(src, &tok)?;
-    Ok(result)
+    grammar.parse_into(src)
 }
 
 fn main() {
@@ -1,20 +1,21 @@
 use std::path::PathBuf;
 
-use yasp::basic::{Grammar, Text};
+use yasp::{basic::{EbnfPrinter, Grammar, RuleOrder, Text}, parsers::naive::Diagnostic};
 
 fn main() {
     // load grammar from YAML file
-    let grammar: Grammar<Text> = serde_yaml::from_str(include_str!("fortran_integer.yaml"))
-        .expect("Failed to deserialize grammar from YAML");
+    let grammar: Grammar<Text> = Grammar::deserialize_validated(
+        serde_yaml::Deserializer::from_str(include_str!("fortran_integer.yaml")),
+    ).expect("Failed to load grammar from YAML");
 
-    // bonus: generate EBNF
+    // bonus: generate EBNF, each rule after the first one using it
     std::fs::write(
         PathBuf::from(env!("CARGO_MANIFEST_DIR"))
             .join("examples")
             .join("fortran_integer.ebnf"),
         format!(
             "(* Generated from fortran_integer.yaml *)\n\n{}",
-            grammar.to_ebnf(true),
+            EbnfPrinter::new().rule_order(RuleOrder::Dependency).print(&grammar),
         ),
     ).expect("Failed to write EBNF to file");
 
@@ -22,28 +23,27 @@ fn main() {
     let src = "1234567890_some_kind";
 
     // parse the input string using the grammar
-    let (tok, diagnostics) = grammar.parse_non_term("signed-int-literal-constant", src)
-        .expect("error while parsing")
-        .expect("Failed to parse");
+    let result = grammar.parse("signed-int-literal-constant", src)
+        .expect("error while parsing");
 
-    if !diagnostics.is_empty() {
+    if !result.is_complete(src.len()) {
         eprintln!("Diagnostics:");
-        for diag in diagnostics {
+        for diag in Diagnostic::dedup(result.diagnostics.clone()) {
             eprintln!("  {}", diag.message());
         }
-        panic!("Diagnostics is not empty");
     }
 
+    let tok = result.unwrap_complete(src.len());
+    let tok = tok.with_source(src);
+
     if let Some(digits) = tok.iter_grams("digit-string").next() {
-        let digits = &src[digits.span.clone()];
-        println!("Digits: {}", digits);
+        println!("Digits: {}", digits.text().expect("Span out of the source"));
     } else {
         panic!("No digits found");
     }
 
     if let Some(kind_param) = tok.iter_grams("kind-param").next() {
-        let kind_param = &src[kind_param.span.clone()];
-        println!("Kind param: {}", kind_param);
+        println!("Kind param: {}", kind_param.text().expect("Span out of the source"));
     } else {
         eprintln!("No kind param found");
     }
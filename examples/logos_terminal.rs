@@ -0,0 +1,47 @@
+//! Reusing a `logos` lexer for the terminals of a grammar
+//!
+//! Run with `cargo run --example logos_terminal --features logos`.
+
+use logos::Logos;
+use yasp::basic::{Grammar, LogosTerminal, Node};
+
+#[derive(Logos, Debug, Clone, PartialEq)]
+#[logos(skip r"[ \t\n]+")]
+enum Tok {
+    #[regex("[0-9]+(\\.[0-9]+)?", |lex| lex.slice().parse().ok())]
+    Number(f64),
+    #[token("+")]
+    Plus,
+    #[token("*")]
+    Times,
+    #[token("(")]
+    Open,
+    #[token(")")]
+    Close,
+}
+
+fn main() {
+    // the structure of the grammar, with the terminals of the lexer
+    let tok = |kind, name| Node::Terminal(LogosTerminal::new(kind, name));
+    let rule = |name: &str| Node::NonTerm(name.to_string());
+    let mut grammar = Grammar::new();
+    grammar.add_element("sum", Node::seq([rule("product"), Node::rep(Node::seq([rule("plus"), rule("product")]), 0..)])).unwrap();
+    grammar.add_element("product", Node::seq([rule("atom"), Node::rep(Node::seq([rule("times"), rule("atom")]), 0..)])).unwrap();
+    grammar.add_element("atom", Node::alt([rule("number"), Node::seq([rule("open"), rule("sum"), rule("close")])])).unwrap();
+    grammar.add_element("number", tok(Tok::Number(0.0), "number")).unwrap();
+    grammar.add_element("plus", tok(Tok::Plus, "plus")).unwrap();
+    grammar.add_element("times", tok(Tok::Times, "times")).unwrap();
+    grammar.add_element("open", tok(Tok::Open, "open")).unwrap();
+    grammar.add_element("close", tok(Tok::Close, "close")).unwrap();
+    grammar.start = Some("sum".to_string());
+    println!("{grammar}");
+
+    let src = "1.5 * (2 + 3)\n + 4";
+    let result = grammar.parse("sum", src).expect("error while parsing");
+    let tok = result.unwrap_complete(src.len());
+
+    // the lexer skips whitespace before a token, which is part of its span
+    for number in tok.iter_grams("number") {
+        println!("Number: {:?}", src[number.span.clone()].trim());
+    }
+}
@@ -1,20 +1,67 @@
-use std::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
 
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 
-use crate::parsers::naive;
+use crate::{parsers::naive, prelude::*};
 
-use super::{Node, State, TerminalNode, Text, Token};
+use super::{FromToken, Node, ParseResult, Parser, RegexDelimiter, Resumable, State, TerminalNode, Text, Token};
+
+mod ambiguity;
+mod completions;
+#[cfg(feature = "std")]
+mod dialect;
+mod diff;
+mod equivalence;
+mod first;
+#[cfg(feature = "std")]
+mod generate;
+mod import;
+#[cfg(feature = "std")]
+mod json;
+mod lint;
+mod nullable;
+mod order;
+mod railroad;
+#[cfg(feature = "std")]
+mod reduce;
+#[cfg(feature = "std")]
+mod terminals;
+mod tokenize;
+mod validation;
+
+#[cfg(feature = "std")]
+pub use dialect::*;
+pub use diff::*;
+pub use first::*;
+pub use lint::*;
+pub use order::*;
+#[cfg(feature = "std")]
+pub use reduce::*;
+pub use validation::*;
 
 
 /// A grammar
+///
+/// # Binary format
+///
+/// The bincode encoding of grammars, nodes and tokens follows the order of the
+/// fields and variants, so it changes whenever they do and is not considered
+/// stable across versions of this crate. It is meant for caches that can be
+/// rebuilt, the serde formats are the ones to persist grammars with.
 #[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
 #[derive(Serialize, Deserialize)]
 #[derive(Encode, Decode)]
 pub struct Grammar<T: Clone> {
     pub start: Option<String>,
     pub rules: BTreeMap<String, Node<T>>,
+    /// Rules whose matches have no token of their own, see [`Grammar::add_fragment`]
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub fragments: BTreeSet<String>,
+    /// Rules other than [`Grammar::start`] to parse from, see [`Grammar::entry_points`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entry_points: Vec<String>,
 }
 
 impl<T: TerminalNode> Grammar<T> {
@@ -22,33 +69,122 @@ impl<T: TerminalNode> Grammar<T> {
         Self {
             start: None,
             rules: Default::default(),
+            fragments: Default::default(),
+            entry_points: vec![],
         }
     }
 
     pub fn add_element(&mut self, name: impl Into<String>, element: impl Into<Node<T>>) -> anyhow::Result<()> {
-        let name = name.into();
-        let element = element.into();
-        let prev = self.rules.remove(&name);
-        if let Some(prev) = prev {
-            if prev != element {
+        self.add_element_with(name.into(), element.into(), Node::eq)
+    }
+
+    /// Same as [`Grammar::add_element`], but a rule that only differs by its
+    /// tags and meta is not a conflict, see [`Node::structurally_eq`]
+    ///
+    /// The existing rule is kept with its annotations.
+    pub fn add_element_structural(&mut self, name: impl Into<String>, element: impl Into<Node<T>>) -> anyhow::Result<()> {
+        self.add_element_with(name.into(), element.into(), Node::structurally_eq)
+    }
+
+    fn add_element_with(&mut self, name: String, element: Node<T>, eq: fn(&Node<T>, &Node<T>) -> bool) -> anyhow::Result<()> {
+        if let Some(prev) = self.rules.get(&name) {
+            if !eq(prev, &element) {
                 return Err(anyhow::anyhow!(
                     "Element {name} already exists and is different: {prev:#?} != {element:#?}"
                 ));
             } else {
                 log::warn!("Element {name} already exists, and is the same");
+                return Ok(());
             }
         }
-        self.rules.insert(name.clone(), element);
+        self.rules.insert(name, element);
         Ok(())
     }
 
+    /// Define the rule `name`, replacing its previous body if any
+    ///
+    /// Unlike [`Grammar::add_element`], redefining a rule is not an error,
+    /// which is useful to customize an existing grammar. References to the
+    /// rule resolve to the new body. Returns the previous body.
+    pub fn set_element(&mut self, name: impl Into<String>, element: impl Into<Node<T>>) -> Option<Node<T>> {
+        self.rules.insert(name.into(), element.into())
+    }
+
+    /// Same as [`Grammar::add_element`], for a fragment rule
+    ///
+    /// The match of a fragment is spliced into the token of the node that
+    /// references it, instead of being wrapped in a token with the name of the
+    /// rule as [`gram`](Token::gram). This keeps helper rules out of the parse
+    /// tree. A fragment used as the start of a parse has the same result as
+    /// any other rule, which is the one of its body.
+    ///
+    /// EBNF does not record which rules are fragments.
+    pub fn add_fragment(&mut self, name: impl Into<String>, element: impl Into<Node<T>>) -> anyhow::Result<()> {
+        let name = name.into();
+        self.add_element(name.clone(), element)?;
+        self.fragments.insert(name);
+        Ok(())
+    }
+
+    /// Whether matches of the rule `name` have no token of their own, see [`Grammar::add_fragment`]
+    pub fn is_fragment(&self, name: &str) -> bool {
+        self.fragments.contains(name)
+    }
+
     pub fn merge(mut self, other: Self) -> anyhow::Result<Self> {
         for (name, element) in other.rules.into_iter() {
             self.add_element(name, element)?;
         }
+        self.fragments.extend(other.fragments);
+        self.entry_points.extend(other.entry_points);
         Ok(self)
     }
 
+    /// Same as [`Grammar::merge`], using [`Grammar::add_element_structural`] for the rules
+    pub fn merge_structural(mut self, other: Self) -> anyhow::Result<Self> {
+        for (name, element) in other.rules.into_iter() {
+            self.add_element_structural(name, element)?;
+        }
+        self.fragments.extend(other.fragments);
+        self.entry_points.extend(other.entry_points);
+        Ok(self)
+    }
+
+    /// The grammar with the annotations of the rules removed, see [`Node::strip_annotations`]
+    ///
+    /// It parses the same inputs into the same trees, without tags nor meta.
+    pub fn strip_annotations(&self) -> Grammar<T> {
+        let mut grammar = self.clone();
+        for node in grammar.rules.values_mut() {
+            node.strip_annotations();
+        }
+        grammar
+    }
+
+    /// Factor the leading terminals shared by alternatives out of the rules, see [`Node::left_factor`]
+    ///
+    /// Returns the number of factored groups of alternatives.
+    pub fn left_factor(&mut self) -> usize {
+        self.rules.values_mut().map(Node::left_factor).sum()
+    }
+
+    /// The total [complexity](Node::complexity) of the rules
+    pub fn complexity(&self) -> usize {
+        self.rules.values().map(Node::complexity).sum()
+    }
+
+    /// Fail if the [complexity](Grammar::complexity) is more than `limit`
+    ///
+    /// Useful to refuse grammars from untrusted sources before parsing with
+    /// them.
+    pub fn reject_if_exceeds(&self, limit: usize) -> anyhow::Result<()> {
+        let complexity = self.complexity();
+        if complexity > limit {
+            anyhow::bail!("Grammar complexity {complexity} exceeds the limit of {limit}");
+        }
+        Ok(())
+    }
+
     pub fn has(&self, name: &str) -> bool {
         self.rules.contains_key(name)
     }
@@ -75,38 +211,225 @@ impl<T: TerminalNode> Grammar<T> {
         for (_, element) in self.rules.iter_mut() {
             element.rename_reference(&name, &new_name);
         }
+        if self.fragments.remove(&name) {
+            self.fragments.insert(new_name.clone());
+        }
+        for entry in &mut self.entry_points {
+            if *entry == name {
+                *entry = new_name.clone();
+            }
+        }
+        if self.start.as_ref() == Some(&name) {
+            self.start = Some(new_name);
+        }
 
         Ok(self)
     }
 
+    /// Set [`Grammar::start`] to `name`, failing if there is no such rule
+    ///
+    /// Assign the field to set a rule that is not defined yet.
+    pub fn with_start(mut self, name: impl Into<String>) -> anyhow::Result<Self> {
+        let name = name.into();
+        if !self.rules.contains_key(&name) {
+            anyhow::bail!("Start rule {name:?} is not defined");
+        }
+        self.start = Some(name);
+        Ok(self)
+    }
+
+    /// Replace every terminal equal to `from` by `to`, in all the rules
+    ///
+    /// Returns the number of terminals replaced.
+    pub fn replace_terminal(&mut self, from: &T, to: T) -> usize
+    where
+        T: PartialEq,
+    {
+        let mut count = 0;
+        for node in self.rules.values_mut() {
+            node.walk_mut(&mut |n| {
+                if let Node::Terminal(t) = n && t == from {
+                    *t = to.clone();
+                    count += 1;
+                }
+            });
+        }
+        count
+    }
+
+    /// Convert every terminal of every rule with `f`, see [`Node::map_terminals`]
+    pub fn map_terminals<U: Clone>(self, mut f: impl FnMut(T) -> U) -> Grammar<U> {
+        Grammar {
+            start: self.start,
+            rules: self.rules.into_iter().map(|(name, node)| (name, node.map_terminals(&mut f))).collect(),
+            fragments: self.fragments,
+            entry_points: self.entry_points,
+        }
+    }
+
+    /// The name of the start rule, if set
+    pub fn start_name(&self) -> Option<&str> {
+        self.start.as_deref()
+    }
+
+    /// The start rule, `None` if `start` is not set or names an undefined rule
+    pub fn start_rule(&self) -> Option<&Node<T>> {
+        self.rules.get(self.start_name()?)
+    }
+
+    /// Same as [`Grammar::start_rule`], but fails explaining what is missing
+    pub fn require_start(&self) -> anyhow::Result<(&str, &Node<T>)> {
+        let name = self.start_name().ok_or_else(|| anyhow::anyhow!("Grammar has no start rule"))?;
+        let node = self.rules.get(name).ok_or_else(|| {
+            anyhow::anyhow!("Start rule {name:?} is not defined")
+        })?;
+        Ok((name, node))
+    }
+
+    /// The rules a parse can start from, [`Grammar::start`] first followed by [`Grammar::entry_points`]
+    ///
+    /// Duplicates are listed once. EBNF only records the start.
+    pub fn entry_points(&self) -> Vec<&str> {
+        let mut entry_points: Vec<&str> = vec![];
+        for name in self.start.iter().chain(&self.entry_points) {
+            if !entry_points.contains(&name.as_str()) {
+                entry_points.push(name);
+            }
+        }
+        entry_points
+    }
+
+    /// Parse `source` from each of the [`Grammar::entry_points`] and keep the best result
+    ///
+    /// The selection is the one of a choice between the entry points: the
+    /// longest match wins, a match without diagnostics being preferred over
+    /// one of the same length with diagnostics, and the earliest entry point
+    /// wins ties. A parse of the whole source without diagnostics is thus
+    /// always selected if there is one, but the result can also leave input
+    /// behind, as [`Grammar::parse_non_term`] does. Returns the name of the
+    /// selected entry point with its result, `None` if none matches.
+    pub fn parse_any(
+        &self,
+        source: &T::Src,
+    ) -> anyhow::Result<Option<(&str, Token, Vec<naive::Diagnostic>)>> {
+        let entry_points = self.entry_points();
+        if entry_points.is_empty() {
+            anyhow::bail!("Grammar has no entry points");
+        }
+        let mut best: Option<(&str, Token, Vec<naive::Diagnostic>)> = None;
+        for name in entry_points {
+            let Some((token, diagnostics)) = self.parse_non_term(name, source)? else {
+                continue;
+            };
+            let key = |token: &Token, diagnostics: &[naive::Diagnostic]| (token.span.end, diagnostics.is_empty());
+            if best.as_ref().is_none_or(|(_, t, d)| key(&token, &diagnostics) > key(t, d)) {
+                best = Some((name, token, diagnostics));
+            }
+        }
+        Ok(best)
+    }
+
+    /// Map each rule to the set of non-terminals it references directly
+    pub fn rule_dependencies(&self) -> BTreeMap<String, BTreeSet<String>> {
+        self.rules
+            .iter()
+            .map(|(name, node)| {
+                let mut deps = BTreeSet::new();
+                node.walk(&mut |n| {
+                    if let Node::NonTerm(dep) = n {
+                        deps.insert(dep.clone());
+                    }
+                });
+                (name.clone(), deps)
+            })
+            .collect()
+    }
+
+    /// Every distinct terminal of the grammar
+    ///
+    /// Terminals are listed in order of first appearance, visiting the rules
+    /// by name and each rule depth-first.
+    pub fn terminals(&self) -> Vec<&T> {
+        let mut terminals: Vec<&T> = vec![];
+        for node in self.rules.values() {
+            node.walk(&mut |n| {
+                if let Node::Terminal(t) = n
+                    && !terminals.contains(&t)
+                {
+                    terminals.push(t);
+                }
+            });
+        }
+        terminals
+    }
+
+    /// The rules in EBNF, one per line, `aligned` pads the names to the same width
+    ///
+    /// If `start` is set, the rules are preceded by a `(* start: name *)` comment,
+    /// which [`Grammar::load_ebnf`] reads back. See [`EbnfPrinter`] to break
+    /// long rules on several lines.
+    ///
+    /// [`EbnfPrinter`]: crate::basic::EbnfPrinter
     pub fn to_ebnf(&self, aligned: bool) -> String {
+        self.to_ebnf_terminals(aligned, &T::to_ebnf)
+    }
+
+    /// Same as [`Grammar::to_ebnf`], preceded by a `(* start: name *)` comment if `start` is set
+    #[deprecated(note = "`to_ebnf` writes the start comment too, so that `load_ebnf` reads the start back")]
+    pub fn ebnf_with_start_comment(&self, aligned: bool) -> String {
+        self.to_ebnf(aligned)
+    }
+
+    /// Same as [`Grammar::to_ebnf`], writing the terminals with `terminal`
+    fn to_ebnf_terminals(&self, aligned: bool, terminal: &dyn Fn(&T) -> String) -> String {
         let mut ebnf = String::new();
+        if let Some(start) = &self.start {
+            ebnf.push_str(&format!("(* start: {start} *)\n"));
+        }
         if !aligned {
             for (name, element) in &self.rules {
-                ebnf.push_str(&format!("{} = {};\n", name, element.to_ebnf()));
+                ebnf.push_str(&format!("{} = {};\n", name, element.to_ebnf_with(terminal)));
             }
         } else {
             let len = |s: &String| s.chars().count();
             let max_len = self.rules.keys().map(len).max().unwrap_or(0);
             for (name, element) in &self.rules {
                 let padding = " ".repeat(max_len - len(name));
-                ebnf.push_str(&format!("{}{} = {};\n", name, padding, element.to_ebnf()));
+                ebnf.push_str(&format!("{}{} = {};\n", name, padding, element.to_ebnf_with(terminal)));
             }
         }
         ebnf
     }
 
+    /// A [`Parser`] for this grammar, to customize parsing options
+    pub fn parser(&self) -> Parser<'_, T> {
+        Parser::new(self)
+    }
+
     pub fn parse_non_term(
         &self,
         non_term: &str,
         source: &T::Src,
     ) -> anyhow::Result<Option<(Token, Vec<naive::Diagnostic>)>> {
-        self.parse_node(
-            self.rules.get(non_term).ok_or_else(|| {
-                anyhow::anyhow!("No rule for start node {non_term:?}")
-            })?,
-            source,
-        )
+        self.parser().parse_non_term(non_term, source)
+    }
+
+    /// Parse `non_term` from the start of `source`, see [`ParseResult`]
+    pub fn parse(&self, non_term: &str, source: &T::Src) -> anyhow::Result<ParseResult> {
+        self.parser().parse(non_term, source)
+    }
+
+    /// Parse `non_term` starting at byte offset `pos` of `source`
+    ///
+    /// Spans in the result are relative to `source`, not to `&source[pos..]`.
+    /// It is an error if `pos` is not a boundary of `source`, see [`Parser::parse_non_term_at`].
+    pub fn parse_non_term_at(
+        &self,
+        non_term: &str,
+        source: &T::Src,
+        pos: usize,
+    ) -> anyhow::Result<Option<(Token, Vec<naive::Diagnostic>)>> {
+        self.parser().parse_non_term_at(non_term, source, pos)
     }
 
     pub fn parse_node(
@@ -114,79 +437,733 @@ impl<T: TerminalNode> Grammar<T> {
         node: &Node<T>,
         source: &T::Src,
     ) -> anyhow::Result<Option<(Token, Vec<naive::Diagnostic>)>> {
-        naive::parse_recursive(
+        self.parse_node_at(node, source, 0)
+    }
+
+    pub fn parse_node_at(
+        &self,
+        node: &Node<T>,
+        source: &T::Src,
+        pos: usize,
+    ) -> anyhow::Result<Option<(Token, Vec<naive::Diagnostic>)>> {
+        super::parser::check_start::<T>(source, pos)?;
+        naive::parse_recursive_at(
             source,
             node,
+            pos,
             State::new(self),
         )
     }
 }
 
+impl<T: TerminalNode<Src = str>> Grammar<T> {
+    /// Parse `src` from the rule [`V::GRAM`](FromToken::GRAM) and build a `V` from it
+    ///
+    /// It is an error if the rule does not match or if there are diagnostics.
+    pub fn parse_into<V: FromToken>(&self, src: &str) -> anyhow::Result<V> {
+        let (tok, diagnostics) = self.parse_non_term(V::GRAM, src)?
+            .ok_or_else(|| anyhow::anyhow!("Failed to parse {:?}", V::GRAM))?;
+        if !diagnostics.is_empty() {
+            let messages = diagnostics.iter().map(|d| d.message()).collect::<Vec<_>>();
+            anyhow::bail!("Failed to parse {:?}: {}", V::GRAM, messages.join(", "));
+        }
+        V::from_token(src, &tok)
+    }
+
+    /// Parse `non_term` from `source`, asking for more input if it ends mid-parse
+    ///
+    /// See [`Parser::parse_resumable`].
+    pub fn parse_resumable(&self, non_term: &str, source: &str) -> anyhow::Result<Resumable> {
+        self.parser().parse_resumable(non_term, source)
+    }
+}
+
+/// Aligned EBNF, see [`Grammar::to_ebnf`]
+impl<T: TerminalNode> fmt::Display for Grammar<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_ebnf(true).fmt(f)
+    }
+}
+
 impl Grammar<Text> {
+    /// Same as [`Grammar::to_ebnf`], writing the regexes with `delimiter`, see [`Text::to_ebnf_with`]
+    pub fn to_ebnf_with(&self, aligned: bool, delimiter: RegexDelimiter) -> String {
+        self.to_ebnf_terminals(aligned, &|text| text.to_ebnf_with(delimiter))
+    }
+
+    /// The literal strings among [`Grammar::terminals`], such as keywords and punctuation
+    pub fn string_literals(&self) -> Vec<&str> {
+        self.terminals()
+            .into_iter()
+            .filter_map(|t| match t {
+                Text::String(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The patterns of the regexes among [`Grammar::terminals`]
+    pub fn regexes(&self) -> Vec<&str> {
+        self.terminals()
+            .into_iter()
+            .filter_map(|t| match t {
+                Text::Regex(pattern) | Text::RegexFlags { pattern, .. } => Some(pattern.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Replace the regexes without metacharacters by the literal they match
+    ///
+    /// Literals are faster to match and their FIRST sets are exact, see
+    /// [`Text::literal`] for the regexes that are converted. Matching is
+    /// unchanged, only the terminals expected by diagnostics are written as
+    /// literals. Returns the number of terminals replaced.
+    pub fn simplify_terminals(&mut self) -> usize {
+        let mut count = 0;
+        for node in self.rules.values_mut() {
+            node.walk_mut(&mut |n| {
+                if let Node::Terminal(t @ (Text::Regex(_) | Text::RegexFlags { .. })) = n
+                    && let Some(literal) = t.literal()
+                {
+                    *t = Text::String(literal);
+                    count += 1;
+                }
+            });
+        }
+        count
+    }
+
+    /// Load a grammar from EBNF, as written by [`Grammar::to_ebnf`]
+    ///
+    /// A leading `(* start: name *)` comment sets [`Grammar::start`].
+    /// Juxtaposed nodes, `a b`, are a sequence like `a , b`, as written by
+    /// [`Grammar::to_ebnf`]. Exceptions `a - b` bind tighter than sequences
+    /// and choices, their operands are names, terminals or bracketed groups,
+    /// see [`Node::Except`]. Repetitions are lazy with a `?` after their
+    /// operator, as `a*?`.
+    /// Names can be qualified, as `ns.name`, see [`Grammar::with_namespace`].
+    /// `@import` statements are an error, see [`Grammar::load_ebnf_with_imports`].
+    #[cfg(feature = "std")]
     pub fn load_ebnf(source: &str) -> anyhow::Result<Self> {
-        let result = ebnf::get_grammar(source)
+        Self::load_ebnf_with_imports(source, |uri| anyhow::bail!("Cannot resolve import {uri:?} without a resolver"))
+    }
+
+    /// Same as [`Grammar::load_ebnf`], `source` having no `@import` statements
+    #[cfg(feature = "std")]
+    fn load_ebnf_rules(source: &str) -> anyhow::Result<Self> {
+        let (start, rest) = split_start_comment(source);
+        let skipped = source.len() - rest.len();
+        let (source, terminals, offsets) = terminals::extract_terminals(rest)?;
+        let source = terminals::escape_qualified_names(&source)?;
+        let source = terminals::extract_exceptions(&source, |pos| {
+            skipped + offsets.original(terminals::unescaped_pos(&source, pos))
+        })?;
+        let source = terminals::extract_lazy_repetitions(&source);
+        let result = ebnf::get_grammar(&source)
             .map_err(|e| anyhow::anyhow!("Failed to parse EBNF: {e}"))?;
 
         use ebnf::{Node as EbnfNode, RegexExtKind, SymbolKind};
-        fn node_to_gram(node: EbnfNode) -> Node<Text> {
+        fn node_to_gram(node: EbnfNode, terminals: &[Text]) -> Node<Text> {
+            let convert = |node: Box<EbnfNode>| node_to_gram(*node, terminals);
             match node {
-                EbnfNode::String(s) => Node::Terminal(Text::String(s)),
+                // every terminal was replaced by its index
+                EbnfNode::String(i) => Node::Terminal(terminals[i.parse::<usize>().unwrap()].clone()),
                 EbnfNode::RegexString(re) => Node::Terminal(Text::Regex(re)),
-                EbnfNode::Terminal(s) => Node::NonTerm(s),
-                EbnfNode::Multiple(nodes) => {
-                    let mut flattened = Vec::new();
-                    for n in nodes {
-                        match node_to_gram(n) {
-                            Node::Alt(mut inner) => flattened.append(&mut inner),
-                            other => flattened.push(other),
-                        }
-                    }
-                    Node::Alt(flattened)
-                }
+                EbnfNode::Terminal(s) => Node::NonTerm(terminals::unescape_name(s)),
+                // juxtaposition, which is how `to_ebnf` writes sequences
+                EbnfNode::Multiple(nodes) => Node::seq(nodes.into_iter().map(|n| node_to_gram(n, terminals))),
                 EbnfNode::RegexExt(node, kind) => match kind {
-                    RegexExtKind::Repeat0 => Node::rep(node_to_gram(*node), 0..),
-                    RegexExtKind::Repeat1 => Node::rep(node_to_gram(*node), 1..),
-                    RegexExtKind::Optional => Node::rep(node_to_gram(*node), 0..=1),
+                    RegexExtKind::Repeat0 => Node::rep(convert(node), 0..),
+                    RegexExtKind::Repeat1 => Node::rep(convert(node), 1..),
+                    RegexExtKind::Optional => Node::rep(convert(node), 0..=1),
                 },
                 EbnfNode::Symbol(a, kind, b) => match kind {
-                    SymbolKind::Concatenation => {
-                        let mut elements = Vec::new();
-                        match node_to_gram(*a) {
-                            Node::Seq(mut inner) => elements.append(&mut inner),
-                            other => elements.push(other),
-                        }
-                        match node_to_gram(*b) {
-                            Node::Seq(mut inner) => elements.append(&mut inner),
-                            other => elements.push(other),
-                        }
-                        Node::Seq(elements)
-                    }
-                    SymbolKind::Alternation => {
-                        let mut branches = Vec::new();
-                        match node_to_gram(*a) {
-                            Node::Alt(mut inner) => branches.append(&mut inner),
-                            other => branches.push(other),
-                        }
-                        match node_to_gram(*b) {
-                            Node::Alt(mut inner) => branches.append(&mut inner),
-                            other => branches.push(other),
+                    SymbolKind::Concatenation => Node::seq([convert(a), convert(b)]),
+                    SymbolKind::Alternation => Node::alt([convert(a), convert(b)]),
+                },
+                // an exception or a lazy repetition, as rewritten by
+                // `extract_exceptions` and `extract_lazy_repetitions`
+                EbnfNode::Group(node) => match *node {
+                    EbnfNode::Symbol(a, SymbolKind::Concatenation, rest) => match *rest {
+                        EbnfNode::Symbol(marker, SymbolKind::Concatenation, b)
+                            if matches!(&*marker, EbnfNode::String(s) if s == "-") =>
+                        {
+                            Node::except(convert(a), convert(b))
                         }
-                        Node::Alt(branches)
-                    }
+                        EbnfNode::String(marker) if marker == "?" => match convert(a) {
+                            Node::Rep { node, range, .. } => Node::Rep { node, range, lazy: true },
+                            node => node,
+                        },
+                        rest => Node::seq([convert(a), node_to_gram(rest, terminals)]),
+                    },
+                    node => node_to_gram(node, terminals),
                 },
-                EbnfNode::Group(node) => node_to_gram(*node),
-                EbnfNode::Optional(node) => Node::rep(node_to_gram(*node), 0..=1),
-                EbnfNode::Repeat(node) => Node::rep(node_to_gram(*node), 0..),
+                EbnfNode::Optional(node) => Node::rep(convert(node), 0..=1),
+                EbnfNode::Repeat(node) => Node::rep(convert(node), 0..),
                 EbnfNode::Unknown => panic!("Unknown EBNF node encountered"),
             }
         }
 
         let mut grammar = Grammar::new();
+        grammar.start = start.map(str::to_string);
 
         for expr in result.expressions {
-            grammar.rules.insert(expr.lhs, node_to_gram(expr.rhs));
+            grammar.rules.insert(terminals::unescape_name(expr.lhs), node_to_gram(expr.rhs, &terminals));
         }
 
         Ok(grammar)
     }
-}
\ No newline at end of file
+}
+
+/// Split a leading `(* start: name *)` comment from `source`
+#[cfg(feature = "std")]
+fn split_start_comment(source: &str) -> (Option<&str>, &str) {
+    let start = source
+        .trim_start()
+        .strip_prefix("(*")
+        .and_then(|rest| rest.split_once("*)"))
+        .and_then(|(comment, rest)| Some((comment.trim().strip_prefix("start:")?.trim(), rest)));
+    match start {
+        Some((name, rest)) => (Some(name), rest),
+        None => (None, source),
+    }
+}
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_terminals() {
+        let mut grammar = crate::grammar! {
+            statement = ("if", condition, " then ", ("x" | "if"), ("keyword": "if"*));
+            condition = (" " | "if") %% ",";
+            /// A keyword
+            documented = "if";
+        };
+
+        let keyword = Text::regex_with_flags("if", "i").unwrap();
+        assert_eq!(grammar.replace_terminal(&Text::String("if".to_string()), keyword.clone()), 5);
+        assert_eq!(grammar.replace_terminal(&Text::String("if".to_string()), keyword.clone()), 0);
+        let mut terminals = vec![];
+        for node in grammar.rules.values() {
+            node.walk(&mut |n| if let Node::Terminal(t) = n {
+                terminals.push(t.clone());
+            });
+        }
+        assert_eq!(terminals.iter().filter(|t| **t == keyword).count(), 5);
+        assert_eq!(terminals.len(), 9);
+        let (_, diagnostics) = grammar.parse_non_term("statement", "IF ,if,If then iFiFIf").unwrap().unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(grammar.rules["documented"].description(), Some("A keyword"));
+
+        let lengths = grammar.clone().map_terminals(|t| t.to_ebnf().len());
+        assert_eq!(lengths.start, grammar.start);
+        assert_eq!(lengths.rules["documented"], Node::Terminal(keyword.to_ebnf().len()).with_description("A keyword"));
+    }
+
+    #[test]
+    fn simplify_terminals() {
+        let mut grammar = Grammar::load_ebnf(r#"
+            statement = #'if' , #' ' , condition , #'\.' , #'then' , #'[a-z]+';
+            condition = #'[0-9]+' | #'\(\)';
+        "#).unwrap();
+        let original = grammar.clone();
+
+        assert_eq!(grammar.simplify_terminals(), 5);
+        assert_eq!(grammar.regexes(), ["[0-9]+", "[a-z]+"]);
+        assert_eq!(grammar.string_literals(), ["()", "if", " ", ".", "then"]);
+        assert_eq!(grammar.simplify_terminals(), 0);
+        let parse = |grammar: &Grammar<Text>, src| grammar.parse_non_term("statement", src).unwrap().map(|(token, diagnostics)| {
+            (token, diagnostics.iter().map(|d| d.main_span()).collect::<Vec<_>>())
+        });
+        for src in ["if 1.thenx", "if ().thenabc", "if 1xthenx", "if 1.then", "if", ""] {
+            assert_eq!(parse(&grammar, src), parse(&original, src), "{src:?}");
+        }
+    }
+
+    #[test]
+    fn exceptions() {
+        let grammar = Grammar::load_ebnf(r#"
+            string = '"' , (any_char - '"')* , '"';
+            any_char = #'.';
+            name = ident - ("if" | "else");
+            ident = #'[a-z]+';
+            digits = ((#'[0-9]' - "0") - "1")+ , [";"];
+        "#).unwrap();
+        assert_eq!(grammar.rules["name"], crate::gram!(ident - ("if" | "else")));
+
+        let parses = |gram, src: &str| grammar.parse_non_term(gram, src).unwrap().is_some_and(|(token, diagnostics)| {
+            token.span == (0..src.len()) && diagnostics.is_empty()
+        });
+        assert!(parses("string", r#""abc""#));
+        assert!(parses("string", r#""""#));
+        assert!(!parses("string", r#""a"b""#));
+        assert!(parses("name", "iffy"));
+        assert!(!parses("name", "if"));
+        assert!(!parses("name", "else"));
+        assert!(parses("digits", "2345;"));
+        assert!(!parses("digits", "2315"));
+        assert!(!parses("digits", "0"));
+
+        // `-` binds tighter than sequences and choices, and reads back
+        let ebnf = grammar.to_ebnf(false);
+        assert!(ebnf.contains(r#"string = "\"" (any_char - "\"")* "\"";"#), "{ebnf}");
+        assert!(ebnf.contains(r#"digits = (/[0-9]/ - "0" - "1")+ [";"];"#), "{ebnf}");
+        assert_eq!(Grammar::load_ebnf(&ebnf).unwrap(), grammar);
+        let printed = crate::basic::EbnfPrinter::new().max_width(10).print(&grammar);
+        assert_eq!(Grammar::load_ebnf(&printed).unwrap(), grammar, "{printed}");
+
+        let error = |src| Grammar::load_ebnf(src).unwrap_err().to_string();
+        assert_eq!(error("a = - b;"), "Missing operand before `-` at 4");
+        assert_eq!(error("a = b , -;"), "Missing operand before `-` at 8");
+        assert_eq!(error(r#"a = "x" - ;"#), "Missing operand after `-` at 8");
+        assert_eq!(error("(* start: a *)\na = ns.b - c - ;"), "Missing operand after `-` at 28");
+        assert_eq!(error("a = 'long string' - b; (* - *) c = d - ;"), "Missing operand after `-` at 37");
+    }
+
+    #[test]
+    fn regex_delimiters() {
+        let mut grammar = Grammar::new();
+        grammar.add_element("date", Node::Terminal(Text::Regex(r"\d{4}/\d{2}".to_string()))).unwrap();
+        grammar.add_element("path", Node::Terminal(Text::Regex(r"[a-z]+(\/[a-z]+)*".to_string()))).unwrap();
+        grammar.add_element("quotes", Node::Terminal(Text::Regex(r#"['"]/"#.to_string()))).unwrap();
+
+        let slashes = grammar.to_ebnf(false);
+        assert!(slashes.contains(r"date = /\d{4}\/\d{2}/;"), "{slashes}");
+        let loaded = Grammar::load_ebnf(&slashes).unwrap();
+        assert_eq!(loaded.rules["date"], grammar.rules["date"]);
+        // the escaped slash of the pattern is read back unescaped
+        assert_eq!(loaded.rules["path"], Node::Terminal(Text::Regex("[a-z]+(/[a-z]+)*".to_string())));
+
+        let quoted = grammar.to_ebnf_with(false, RegexDelimiter::Quoted);
+        assert_eq!(quoted, [
+            r"date = #'\d{4}/\d{2}';",
+            r"path = #'[a-z]+(\/[a-z]+)*';",
+            r#"quotes = /['"]\//;"#,
+            "",
+        ].join("\n"));
+        assert_eq!(Grammar::load_ebnf(&quoted).unwrap(), grammar);
+        let src = "2024/05";
+        assert_eq!(grammar.parse("date", src).unwrap().unwrap_complete(src.len()).span, 0..7);
+    }
+
+    #[test]
+    fn left_factor() {
+        let mut grammar = Grammar::load_ebnf(r#"
+            statement = ("if" , " " , cond , " then") | ("if" , " " , cond) | ("while" , " " , cond) | ("while" , " do") | "if" | list;
+            list = ("a" , "b" , "c") | ("a" , "b" , "d") | ("a" , "e") | "a";
+            cond = "x" | "y";
+        "#).unwrap();
+        let original = grammar.clone();
+
+        assert_eq!(grammar.left_factor(), 4);
+        let factored = Grammar::load_ebnf(r#"
+            statement = ("if" , " " , ((cond , " then") | cond)) | ("while" , ((" " , cond) | " do")) | "if" | list;
+            list = "a" , (("b" , ("c" | "d")) | "e" | "");
+            cond = "x" | "y";
+        "#).unwrap();
+        assert_eq!(grammar.rules["statement"], factored.rules["statement"]);
+        assert_eq!(grammar.rules["list"].to_string(), factored.rules["list"].to_string());
+        assert_eq!(grammar.left_factor(), 0);
+
+        let parse = |grammar: &Grammar<Text>, src| grammar.parse_non_term("statement", src).unwrap().map(|(token, diagnostics)| {
+            (token.span, diagnostics.is_empty())
+        });
+        for src in ["if x then", "if y", "if x th", "if", "if ", "while x", "while do", "while ", "abc", "abd", "ab", "ae", "a", "af", "", "z"] {
+            assert_eq!(parse(&grammar, src), parse(&original, src), "{src:?}");
+        }
+    }
+
+    #[test]
+    fn set_element() {
+        let mut grammar = Grammar::load_ebnf(r#"
+            number = digit+;
+            digit = "0" | "1";
+        "#).unwrap();
+        assert!(grammar.add_element("digit", Node::Terminal(Text::Regex("[0-9]".to_string()))).is_err());
+
+        let previous = grammar.set_element("digit", Node::Terminal(Text::Regex("[0-9]".to_string())));
+        assert_eq!(previous, Some(Node::alt([Node::Terminal(Text::String("0".to_string())), Node::Terminal(Text::String("1".to_string()))])));
+        let (token, _) = grammar.parse_non_term("number", "1729").unwrap().unwrap();
+        assert_eq!(token.span, 0..4);
+
+        assert_eq!(grammar.set_element("sign", Node::Terminal(Text::String("-".to_string()))), None);
+        assert!(grammar.has("sign"));
+    }
+
+    #[test]
+    fn parse_into() {
+        #[derive(Debug, PartialEq)]
+        struct Assignment {
+            name: String,
+            value: i64,
+        }
+
+        impl FromToken for Assignment {
+            const GRAM: &str = "assignment";
+            fn from_token(src: &str, tok: &Token) -> anyhow::Result<Self> {
+                let name = tok.iter_grams("name").next().ok_or_else(|| anyhow::anyhow!("No name"))?;
+                let value = tok.iter_grams("value").next().ok_or_else(|| anyhow::anyhow!("No value"))?;
+                Ok(Assignment {
+                    name: src[name.span.clone()].to_string(),
+                    value: src[value.span.clone()].parse()?,
+                })
+            }
+        }
+
+        let grammar = Grammar::load_ebnf(r#"
+            assignment = name , "=" , value;
+            name = #'[a-z]+';
+            value = #'-?[0-9]+';
+        "#).unwrap();
+        assert_eq!(grammar.parse_into::<Assignment>("x=-12").unwrap(), Assignment { name: "x".to_string(), value: -12 });
+        assert_eq!(
+            grammar.parse_into::<Assignment>("x=").unwrap_err().to_string(),
+            "Failed to parse \"assignment\": Incomplete parse at 2: expected value",
+        );
+        assert!(grammar.parse_into::<Assignment>("=1").is_err());
+        assert!(grammar.parse_into::<Assignment>("x=99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn fragments() {
+        let mut grammar = Grammar::load_ebnf(r#"
+            number = digits , ["." , digits];
+        "#).unwrap();
+        grammar.add_fragment("digits", Node::rep(Node::Terminal(Text::Regex("[0-9]".to_string())), 1..)).unwrap();
+        assert!(grammar.is_fragment("digits"));
+        assert!(grammar.add_fragment("digits", Node::Terminal(Text::String("0".to_string()))).is_err());
+
+        let (token, diagnostics) = grammar.parse_non_term("number", "12.5").unwrap().unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(token.iter_grams("digits").count(), 0);
+        assert_eq!(token.children[0].span, 0..2);
+        assert_eq!(token.children[0].children.len(), 2);
+        assert_eq!(token.validate_spans(), Ok(()));
+        let (all, _) = grammar.parse_all("number", "12.5", 2).unwrap().pop().unwrap();
+        assert_eq!(all, token);
+
+        // as the start, a fragment is like any other rule
+        let (token, _) = grammar.parse_non_term("digits", "12").unwrap().unwrap();
+        assert_eq!(token.span, 0..2);
+
+        let renamed = grammar.clone().with_renamed_element("digits", "int").unwrap();
+        assert!(renamed.is_fragment("int") && !renamed.is_fragment("digits"));
+        let yaml = serde_yaml::to_string(&grammar).unwrap();
+        assert_eq!(serde_yaml::from_str::<Grammar<Text>>(&yaml).unwrap(), grammar);
+        let merged = Grammar::new().merge(grammar).unwrap();
+        assert!(merged.is_fragment("digits"));
+    }
+
+    #[test]
+    fn entry_points() {
+        let mut grammar = Grammar::load_ebnf(r#"
+            (* start: statement *)
+            statement = name , "=" , expression , ";";
+            expression = term , ("+" , term)*;
+            term = name | #'[0-9]+';
+            name = #'[a-z]+';
+        "#).unwrap();
+        grammar.entry_points = vec!["expression".to_string(), "statement".to_string(), "term".to_string()];
+        assert_eq!(grammar.entry_points(), ["statement", "expression", "term"]);
+
+        let parse = |src| grammar.parse_any(src).unwrap().map(|(name, token, diagnostics)| {
+            (name, token.span, diagnostics.len())
+        });
+        assert_eq!(parse("x=1+y;"), Some(("statement", 0..6, 0)));
+        assert_eq!(parse("1+y"), Some(("expression", 0..3, 0)));
+        // `expression` and `term` match the same, the earliest wins
+        assert_eq!(parse("x"), Some(("expression", 0..1, 0)));
+        // a complete match wins over an incomplete one of the same length
+        assert_eq!(parse("x="), Some(("statement", 0..2, 1)));
+        assert_eq!(parse("1+"), Some(("expression", 0..2, 1)));
+        assert_eq!(parse("1;"), Some(("expression", 0..1, 0)));
+        assert_eq!(parse(";"), None);
+
+        assert!(grammar.check().is_empty());
+        grammar.entry_points.push("undefined".to_string());
+        assert_eq!(grammar.check(), [ValidationError::UndefinedStart("undefined".to_string())]);
+        assert!(grammar.parse_any("x").is_err());
+        assert!(Grammar::<Text>::new().parse_any("x").is_err());
+        let renamed = grammar.with_renamed_element("term", "atom").unwrap();
+        assert_eq!(renamed.entry_points(), ["statement", "expression", "atom", "undefined"]);
+    }
+
+    #[test]
+    fn structural_merge() {
+        let a = Node::Terminal(Text::String("a".to_string()));
+        assert!(Node::tagged(a.clone(), "x").structurally_eq(&a));
+        assert!(a.structurally_eq(&Node::tagged(a.clone().with_description("d"), "x")));
+        assert!(Node::seq([Node::tagged(a.clone(), "x")]).structurally_eq(&Node::seq([a.clone()])));
+        assert!(Node::rep(Node::tagged(a.clone(), "x"), 1..).structurally_eq(&Node::rep(a.clone(), 1..)));
+        assert!(!Node::rep(a.clone(), 1..).structurally_eq(&Node::rep(a.clone(), 0..)));
+        assert!(!Node::rep(a.clone(), 1..).structurally_eq(&Node::rep_lazy(a.clone(), 1..)));
+        assert!(!Node::seq([a.clone()]).structurally_eq(&Node::alt([a.clone()])));
+        assert!(!Node::seq([a.clone()]).structurally_eq(&Node::seq([a.clone(), a.clone()])));
+        assert!(!Node::tagged(a.clone(), "x").structurally_eq(&Node::Terminal(Text::String("b".to_string()))));
+
+        // structurally equal nodes parse the same, a greedy repetition gives
+        // back its matches and a choice is revisited whatever their wrappers
+        fn spans(token: &Token) -> Vec<core::ops::Range<usize>> {
+            core::iter::once(token.span.clone()).chain(token.children.iter().flat_map(spans)).collect()
+        }
+        let pairs = [
+            (Node::seq([Node::tagged(Node::rep(a.clone(), 0..), "d"), a.clone()]), "aa"),
+            (Node::seq([Node::rep(a.clone(), 0..).with_description("d"), a.clone()]), "aa"),
+            (Node::seq([Node::tagged(crate::gram!(("a" | "ab")), "t"), crate::gram!("bc")]), "abc"),
+        ];
+        let grammar = Grammar::new();
+        for (node, src) in pairs {
+            let mut plain = node.clone();
+            plain.strip_annotations();
+            assert!(node.structurally_eq(&plain));
+            for parser in [grammar.parser(), grammar.parser().revisit_choices()] {
+                let annotated = parser.parse_node(&node, src).unwrap().map(|(t, _)| spans(&t));
+                let stripped = parser.parse_node(&plain, src).unwrap().map(|(t, _)| spans(&t));
+                assert_eq!(annotated, stripped, "{node:?} on {src:?}");
+            }
+        }
+
+        let mut annotated = Grammar::new();
+        annotated.add_element("a", Node::tagged(a.clone(), "letter").with_description("An a")).unwrap();
+        let mut plain = Grammar::new();
+        plain.add_element("a", a.clone()).unwrap();
+        plain.add_element("b", Node::Terminal(Text::String("b".to_string()))).unwrap();
+
+        assert!(annotated.clone().merge(plain.clone()).is_err());
+        let merged = annotated.clone().merge_structural(plain.clone()).unwrap();
+        assert_eq!(merged.rules["a"], annotated.rules["a"]);
+        assert!(merged.has("b"));
+
+        let mut conflicting = Grammar::new();
+        conflicting.add_element("a", Node::tagged(Node::Terminal(Text::String("A".to_string())), "letter")).unwrap();
+        assert!(annotated.merge_structural(conflicting).is_err());
+    }
+
+    #[test]
+    fn complexity() {
+        let grammar = Grammar::load_ebnf(r#"
+            number = digit+;
+            digit = "0" | "1";
+        "#).unwrap();
+        assert_eq!(grammar.rules["number"].complexity(), 2);
+        assert_eq!(grammar.rules["digit"].complexity(), 3);
+        assert_eq!(grammar.complexity(), 5);
+        assert_eq!(Node::<Text>::Empty.complexity(), 1);
+        assert_eq!(Grammar::<Text>::new().complexity(), 0);
+
+        assert!(grammar.reject_if_exceeds(5).is_ok());
+        let error = grammar.reject_if_exceeds(4).unwrap_err();
+        assert_eq!(error.to_string(), "Grammar complexity 5 exceeds the limit of 4");
+    }
+
+    #[test]
+    fn rule_dependencies() {
+        let grammar = Grammar::load_ebnf(r#"
+            expression = term , (("+" | "-") , term)*;
+            term = factor , (("*" | "/") , factor)*;
+            factor = ("(" , expression , ")") | number;
+            number = digit+;
+            digit = "0" | "1";
+        "#).unwrap();
+
+        let deps = grammar.rule_dependencies();
+        let set = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<BTreeSet<_>>();
+        assert_eq!(deps["expression"], set(&["term"]));
+        assert_eq!(deps["term"], set(&["factor"]));
+        assert_eq!(deps["factor"], set(&["expression", "number"]));
+        assert_eq!(deps["number"], set(&["digit"]));
+        assert_eq!(deps["digit"], set(&[]));
+    }
+
+    #[test]
+    fn ebnf_terminals_round_trip() {
+        let mut grammar = Grammar::new();
+        grammar.add_element("slash", Node::Terminal(Text::String("/".to_string()))).unwrap();
+        grammar.add_element("slashes", Node::Terminal(Text::String("/a/".to_string()))).unwrap();
+        grammar.add_element("escapes", Node::Terminal(Text::String("\"'\\\n".to_string()))).unwrap();
+        grammar.add_element("path", Node::Terminal(Text::Regex("[a-z]+(/[a-z]+)*".to_string()))).unwrap();
+        grammar.add_element("digits", Node::Terminal(Text::Regex("\\d+".to_string()))).unwrap();
+        grammar.add_element("word", Node::Terminal(Text::regex_with_flags("[a-z]+", "i").unwrap())).unwrap();
+        grammar.add_element("end", Node::Terminal(Text::keyword_with("end", "a-z-").unwrap())).unwrap();
+        assert_eq!(Grammar::load_ebnf(&grammar.to_ebnf(false)).unwrap(), grammar);
+    }
+
+    #[test]
+    fn terminals() {
+        let grammar = Grammar::load_ebnf(r#"
+            assignment = name , "=" , value , [";"];
+            name = #'[a-z]+';
+            value = number | name | "true" | "false";
+            number = #'[0-9]+' , ["." , #'[0-9]+'];
+        "#).unwrap();
+        assert_eq!(grammar.terminals(), [
+            &Text::String("=".to_string()),
+            &Text::String(";".to_string()),
+            &Text::Regex("[a-z]+".to_string()),
+            &Text::Regex("[0-9]+".to_string()),
+            &Text::String(".".to_string()),
+            &Text::String("true".to_string()),
+            &Text::String("false".to_string()),
+        ]);
+        assert_eq!(grammar.string_literals(), ["=", ";", ".", "true", "false"]);
+        assert_eq!(grammar.regexes(), ["[a-z]+", "[0-9]+"]);
+    }
+
+    #[test]
+    fn start_rule() {
+        let mut grammar = Grammar::load_ebnf(r#"
+            number = digit+;
+            digit = "0" | "1";
+        "#).unwrap();
+        assert_eq!(grammar.start_name(), None);
+        assert_eq!(grammar.start_rule(), None);
+        assert!(grammar.require_start().is_err());
+
+        grammar.start = Some("integer".to_string());
+        assert_eq!(grammar.start_name(), Some("integer"));
+        assert_eq!(grammar.start_rule(), None);
+        let err = grammar.require_start().unwrap_err();
+        assert!(err.to_string().contains("\"integer\""));
+
+        grammar.start = Some("number".to_string());
+        assert_eq!(grammar.start_rule(), Some(&grammar.rules["number"]));
+        let (name, node) = grammar.require_start().unwrap();
+        assert_eq!(name, "number");
+        assert_eq!(node, &grammar.rules["number"]);
+    }
+
+    #[test]
+    fn with_start() {
+        let grammar = Grammar::load_ebnf(r#"
+            number = digit+;
+            digit = "0" | "1";
+        "#).unwrap();
+        let err = grammar.clone().with_start("integer").unwrap_err();
+        assert!(err.to_string().contains("\"integer\""));
+
+        let grammar = grammar.with_start("number").unwrap();
+        assert_eq!(grammar.start_name(), Some("number"));
+        let grammar = grammar.with_renamed_element("number", "integer").unwrap();
+        assert_eq!(grammar.require_start().unwrap().0, "integer");
+    }
+
+    #[test]
+    fn display() {
+        let mut grammar = Grammar::load_ebnf(r#"
+            expression = term , ("+" , term)*;
+            term = "1" | "2";
+        "#).unwrap();
+        assert_eq!(grammar.to_string(), [
+            "expression = term (\"+\" term)*;",
+            "term       = \"1\" | \"2\";",
+            "",
+        ].join("\n"));
+
+        grammar.start = Some("expression".to_string());
+        assert_eq!(grammar.to_string(), [
+            "(* start: expression *)",
+            "expression = term (\"+\" term)*;",
+            "term       = \"1\" | \"2\";",
+            "",
+        ].join("\n"));
+        #[allow(deprecated)]
+        let ebnf = grammar.ebnf_with_start_comment(false);
+        assert_eq!(ebnf, [
+            "(* start: expression *)",
+            "expression = term (\"+\" term)*;",
+            "term = \"1\" | \"2\";",
+            "",
+        ].join("\n"));
+        assert_eq!(grammar.to_ebnf(false), ebnf);
+    }
+
+    #[test]
+    fn ebnf_round_trip() {
+        let mut grammar = Grammar::load_ebnf(r#"
+            expression = term , (("+" | "-") , term)*;
+            term = factor , ("*" , factor)*;
+            factor = ("(" , expression , ")") | number;
+            number = digit+;
+            digit = "0" | "1";
+        "#).unwrap();
+        assert_eq!(grammar.start, None);
+        assert_eq!(Grammar::load_ebnf(&grammar.to_ebnf(true)).unwrap(), grammar);
+
+        grammar.start = Some("expression".to_string());
+        for aligned in [false, true] {
+            assert_eq!(Grammar::load_ebnf(&grammar.to_ebnf(aligned)).unwrap(), grammar);
+        }
+    }
+
+    #[test]
+    fn lazy_round_trip() {
+        let t = |s: &str| Node::Terminal(Text::String(s.to_string()));
+        let mut grammar = Grammar::new();
+        grammar.add_element("ones", Node::seq([Node::rep_lazy(t("1"), 0..), t("1")])).unwrap();
+        grammar.add_element("pairs", Node::rep_lazy(Node::seq([t("x"), t("y")]), 1..)).unwrap();
+        grammar.add_element("maybe", Node::seq([Node::rep_lazy(Node::NonTerm("ones".to_string()), 0..=1), t("?")])).unwrap();
+        let ebnf = grammar.to_ebnf(false);
+        assert!(ebnf.contains(r#"ones = "1"*? "1";"#), "{ebnf}");
+        assert!(ebnf.contains(r#"maybe = ones?? "?";"#), "{ebnf}");
+        assert_eq!(Grammar::load_ebnf(&ebnf).unwrap(), grammar);
+        assert_eq!(Grammar::load_ebnf("a = b ? ;\nb = (\"x\" , c)+? ;").unwrap().rules["b"].to_ebnf(), "(\"x\" c)+?");
+
+        // the written out counts are lazy too
+        let mut grammar = Grammar::new();
+        grammar.add_element("s", Node::seq([t("x"), Node::rep_lazy(t("a"), 2..=3), t("b")])).unwrap();
+        assert_eq!(grammar.rules["s"].to_ebnf(), r#""x" "a" "a" "a"?? "b""#);
+        let reloaded = Grammar::load_ebnf(&grammar.to_ebnf(false)).unwrap();
+        for src in ["x", "xab", "xaab", "xaaab", "xaaaab"] {
+            let result = grammar.parse("s", src).unwrap();
+            assert_eq!(reloaded.parse("s", src).unwrap().is_complete(src.len()), result.is_complete(src.len()), "{src:?}");
+        }
+        assert!(grammar.parse("s", "x").unwrap().token.is_some());
+    }
+
+    #[test]
+    fn juxtaposition() {
+        let grammar = Grammar::load_ebnf(r#"
+            spaced = a b "c";
+            commas = a , b , "c";
+            group = (a b) | c;
+        "#).unwrap();
+        assert_eq!(grammar.rules["spaced"], crate::gram!((a, b, "c")));
+        assert_eq!(grammar.rules["spaced"], grammar.rules["commas"]);
+        assert_eq!(grammar.rules["group"], crate::gram!(((a, b) | c)));
+    }
+
+    #[test]
+    fn parse_non_term_at() {
+        let grammar = Grammar::load_ebnf(r#"
+            pair = number , "," , number;
+            number = digit+;
+            digit = "0" | "1" | "2" | "3";
+        "#).unwrap();
+
+        let src = "key: 12,3";
+        let (token, diagnostics) = grammar.parse_non_term_at("pair", src, 5).unwrap().unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(token.span, 5..9);
+        let numbers = token.iter_grams("number").map(|t| &src[t.span.clone()]).collect::<Vec<_>>();
+        assert_eq!(numbers, vec!["12", "3"]);
+
+        let src = "key: 12,";
+        let (token, diagnostics) = grammar.parse_non_term_at("pair", src, 5).unwrap().unwrap();
+        assert_eq!(token.span, 5..8);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].main_span(), 8..8);
+
+        // past the end or inside a char
+        let src = "kéy: 12,3";
+        assert!(grammar.parse_non_term_at("pair", src, 6).unwrap().is_some());
+        assert!(grammar.parse_non_term_at("pair", src, 10).unwrap().is_none());
+        let err = grammar.parse_non_term_at("pair", src, 11).unwrap_err();
+        assert_eq!(err.to_string(), "Cannot start parsing at 11, which is not a boundary of the source");
+        assert!(grammar.parse_non_term_at("pair", src, 2).is_err());
+        assert!(grammar.parse_node_at(&grammar.rules["pair"], src, 2).is_err());
+        assert!(grammar.parser().prune_alt_branches().parse_non_term_at("pair", src, 11).is_err());
+        assert!(grammar.parser().prune_alt_branches().parse_node_at(&grammar.rules["number"], src, 2).is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use alloc::rc::Rc;
+use core::ops::RangeInclusive;
+
+use crate::{basic::{Node, TerminalNode, Token}, collections::{Map, Set}, parsers::naive, prelude::*};
+
+use super::Grammar;
+
+impl<T: TerminalNode<Src = str>> Grammar<T> {
+    /// Every parse of the whole `src` from the `start` rule, at most `max_results`
+    ///
+    /// Unlike [`Grammar::parse_non_term`], which keeps the longest branch of
+    /// each choice, this enumerates all the ways the grammar derives `src`, so
+    /// more than one result means the grammar is ambiguous on this input. The
+    /// derivations follow the grammar rather than the longest-match strategy,
+    /// so they can include parses that [`Grammar::parse_non_term`] never
+    /// produces. Only complete parses are returned, their diagnostics are
+    /// empty.
+    ///
+    /// # Cost
+    ///
+    /// This is meant for grammar debugging, not for parsing: the number of
+    /// parses can grow exponentially with the input. Partial results are
+    /// memoized and at most `max_results` of them are kept for each node,
+    /// position and end, which bounds the work without hiding ambiguity.
+    ///
+    /// It is an error if a rule derives itself without consuming input, as the
+    /// parses would be infinite.
+    pub fn parse_all(
+        &self,
+        start: &str,
+        src: &str,
+        max_results: usize,
+    ) -> anyhow::Result<Vec<(Token, Vec<naive::Diagnostic>)>> {
+        let node = self.rules.get(start).ok_or_else(|| {
+            anyhow::anyhow!("No rule for start node {start:?}")
+        })?;
+        let mut enumerator = Enumerator {
+            grammar: self,
+            src,
+            max_results,
+            memo: Map::new(),
+            active: Set::new(),
+        };
+        Ok(enumerator
+            .matches(node, 0)?
+            .iter()
+            .filter(|token| token.span.end == src.len())
+            .take(max_results)
+            .map(|token| (token.clone(), vec![]))
+            .collect())
+    }
+}
+
+/// Memoized enumeration of all the matches of the nodes of a grammar
+struct Enumerator<'a, T: TerminalNode<Src = str>> {
+    grammar: &'a Grammar<T>,
+    src: &'a str,
+    max_results: usize,
+    /// Matches by address of the node and position
+    memo: Map<(*const Node<T>, usize), Rc<Vec<Token>>>,
+    /// Rules being enumerated, to detect derivations that do not consume input
+    active: Set<(&'a str, usize)>,
+}
+
+impl<'a, T: TerminalNode<Src = str>> Enumerator<'a, T> {
+    /// All the matches of `node` at `pos`, in derivation order
+    fn matches(&mut self, node: &'a Node<T>, pos: usize) -> anyhow::Result<Rc<Vec<Token>>> {
+        let key = (node as *const _, pos);
+        if let Some(matches) = self.memo.get(&key) {
+            return Ok(matches.clone());
+        }
+        let matches = match node {
+            Node::Seq(elements) => {
+                let mut partials = vec![(vec![], pos)];
+                for element in elements {
+                    let mut next = vec![];
+                    for (parsed, end) in partials {
+                        for token in self.matches(element, end)?.iter() {
+                            let mut parsed: Vec<Token> = parsed.clone();
+                            let end = token.span.end;
+                            parsed.push(token.clone());
+                            next.push((parsed, end));
+                        }
+                    }
+                    partials = self.cap(next, |(_, end)| *end);
+                }
+                partials.into_iter().map(|(parsed, end)| group(parsed, pos, end)).collect()
+            }
+            Node::Alt(branches) => {
+                let mut matches = vec![];
+                for branch in branches {
+                    matches.extend(self.matches(branch, pos)?.iter().cloned());
+                }
+                matches
+            }
+            Node::Rep { node, range, .. } => self.repetitions(node, None, range.clone(), pos)?,
+            Node::RepSep { node, sep, range } => self.repetitions(node, Some(sep), range.clone(), pos)?,
+            Node::Terminal(t) => match t.parses(self.src, pos)? {
+                Some(end) => vec![group(vec![], pos, end)],
+                None => vec![],
+            },
+            Node::NonTerm(name) => {
+                let rule = self.grammar.rules.get(name).ok_or_else(|| {
+                    anyhow::anyhow!("No rule for non-terminal {name:?}")
+                })?;
+                if !self.active.insert((name, pos)) {
+                    anyhow::bail!("Rule {name:?} derives itself at {pos} without consuming input");
+                }
+                let matches = self.matches(rule, pos);
+                self.active.remove(&(name.as_str(), pos));
+                let matches = matches?;
+                if self.grammar.is_fragment(name) {
+                    matches.as_ref().clone()
+                } else {
+                    matches
+                        .iter()
+                        .map(|token| Token {
+                            span: token.span.clone(),
+                            gram: Some(name.clone()),
+                            tags: vec![],
+                            meta: Default::default(),
+                            children: vec![token.clone()],
+                        })
+                        .collect()
+                }
+            }
+            Node::Tagged { node, tags } => {
+                let mut matches = self.matches(node, pos)?.as_ref().clone();
+                for token in &mut matches {
+                    token.tags.extend(tags.iter().cloned());
+                }
+                matches
+            }
+            Node::Meta { node, meta } => {
+                let mut matches = self.matches(node, pos)?.as_ref().clone();
+                for token in &mut matches {
+                    token.meta.extend(meta.clone());
+                }
+                matches
+            }
+            Node::Empty => vec![group(vec![], pos, pos)],
+            Node::Except { node, minus } => {
+                let excluded = self.matches(minus, pos)?.iter().map(|t| t.span.end).collect::<Set<_>>();
+                let matches = self.matches(node, pos)?;
+                matches.iter().filter(|t| !excluded.contains(&t.span.end)).cloned().collect()
+            }
+        };
+        let matches = Rc::new(self.cap(matches, |token| token.span.end));
+        self.memo.insert(key, matches.clone());
+        Ok(matches)
+    }
+
+    /// The matches of `node` repeated `range` times, separated by `sep`
+    ///
+    /// As in the parser, every element (with the separator before it) must
+    /// consume input, unless it is needed to reach the minimum. Separators
+    /// are not part of the match.
+    fn repetitions(
+        &mut self,
+        node: &'a Node<T>,
+        sep: Option<&'a Node<T>>,
+        range: RangeInclusive<usize>,
+        pos: usize,
+    ) -> anyhow::Result<Vec<Token>> {
+        let mut matches = vec![];
+        let mut frontier = vec![(vec![], pos)];
+        while !frontier.is_empty() {
+            let mut next = vec![];
+            for (parsed, end) in frontier {
+                let count = parsed.len();
+                if count >= *range.start() {
+                    matches.push(group(parsed.clone(), pos, end));
+                }
+                if count >= *range.end() {
+                    continue;
+                }
+                let starts = match sep {
+                    Some(sep) if count > 0 => self.matches(sep, end)?.iter().map(|t| t.span.end).collect(),
+                    _ => vec![end],
+                };
+                for start in starts {
+                    for token in self.matches(node, start)?.iter() {
+                        if token.span.end > end || count < *range.start() {
+                            let mut parsed: Vec<Token> = parsed.clone();
+                            let end = token.span.end;
+                            parsed.push(token.clone());
+                            next.push((parsed, end));
+                        }
+                    }
+                }
+            }
+            frontier = self.cap(next, |(_, end)| *end);
+        }
+        Ok(matches)
+    }
+
+    /// Keep at most `max_results` of the items ending at each position
+    ///
+    /// What follows only depends on the end, so any other derivation of the
+    /// same span could replace a dropped one.
+    fn cap<I>(&self, items: Vec<I>, end: impl Fn(&I) -> usize) -> Vec<I> {
+        let mut counts = Map::<usize, usize>::new();
+        items
+            .into_iter()
+            .filter(|item| {
+                let count = counts.entry(end(item)).or_default();
+                *count += 1;
+                *count <= self.max_results
+            })
+            .collect()
+    }
+}
+
+/// An anonymous token grouping `children`, as the parser builds them
+fn group(children: Vec<Token>, pos: usize, end: usize) -> Token {
+    let start = children.first().map_or(pos, |c| c.span.start);
+    let end = children.last().map_or(end, |c| c.span.end);
+    Token {
+        span: start..end,
+        gram: None,
+        tags: vec![],
+        meta: Default::default(),
+        children,
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::basic::Text;
+
+    use super::*;
+
+    #[test]
+    fn parse_all() {
+        let grammar = Grammar::<Text>::load_ebnf(r#"
+            list = num , ("," , num)*;
+            num = "1" | "2" | "3";
+            ambiguous = "a"* , "a"*;
+            loop = loop , "x";
+        "#).unwrap();
+
+        let shapes = |start: &str, src: &str, max: usize| -> Vec<String> {
+            grammar.parse_all(start, src, max).unwrap()
+                .into_iter()
+                .map(|(token, diagnostics)| {
+                    assert!(diagnostics.is_empty());
+                    token.to_sexpr()
+                })
+                .collect()
+        };
+
+        // a single parse, the same as the parser's
+        let (token, _) = grammar.parse_non_term("list", "1,2,3").unwrap().unwrap();
+        assert_eq!(shapes("list", "1,2,3", 10), [token.to_sexpr()]);
+        assert_eq!(shapes("list", "1,2,", 10), Vec::<String>::new());
+
+        // `a` split in all three ways
+        assert_eq!(shapes("ambiguous", "aa", 10).len(), 3);
+        assert_eq!(shapes("ambiguous", "aa", 2).len(), 2);
+        assert_eq!(shapes("ambiguous", "", 10).len(), 1);
+
+        assert!(grammar.parse_all("loop", "xx", 10).is_err());
+        assert!(grammar.parse_all("nope", "", 10).is_err());
+    }
+}
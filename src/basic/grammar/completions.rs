@@ -0,0 +1,110 @@
+use alloc::collections::BTreeSet;
+use core::cell::RefCell;
+
+use crate::{basic::{Node, Text}, collections::Set, parsers::naive::TraceEvent, prelude::*};
+
+use super::Grammar;
+
+impl Grammar<Text> {
+    /// The literals that can come next at byte offset `pos` of `src`, for autocompletion
+    ///
+    /// `src` is parsed from the `start` rule up to `pos`, the text after it is
+    /// ignored. The nodes the parser tries at `pos`, such as the `expected`
+    /// node of a [`naive::Diagnostic::Incomplete`] there, are the ones the
+    /// prefix can continue with, except those a sequence or repetition tries
+    /// after an incomplete element. The result is the union of the literals of
+    /// their [FIRST sets](Grammar::first_sets), sorted. Regexes match too many
+    /// strings to be listed and are left out.
+    ///
+    /// [`naive::Diagnostic::Incomplete`]: crate::parsers::naive::Diagnostic::Incomplete
+    ///
+    /// The result is empty if the prefix cannot be parsed up to `pos`.
+    pub fn completions(&self, start: &str, src: &str, pos: usize) -> anyhow::Result<Vec<String>> {
+        let Some(prefix) = src.get(..pos) else {
+            anyhow::bail!("Position {pos} is not a character boundary of the source");
+        };
+
+        let sets = self.first_sets();
+        let tried = RefCell::new(Tried::default());
+        let parser = self.parser().with_tracer(|event: TraceEvent<'_, &Node<Text>>| {
+            let tried = &mut *tried.borrow_mut();
+            match event {
+                TraceEvent::Enter { node, pos: at, depth } => {
+                    tried.path.truncate(depth);
+                    let continues_incomplete = tried.path.iter().any(|e| e.after_incomplete);
+                    tried.path.push(Entered { choice: matches!(node, Node::Alt(_)), after_incomplete: false });
+                    if at == pos && !continues_incomplete && tried.seen.insert(*node as *const _) {
+                        tried.literals.extend(node.first_set(&sets).literals);
+                    }
+                }
+                TraceEvent::Exit { parsed, depth } => {
+                    // a repetition that went on can complete after an incomplete child
+                    let went_on = tried.path.get(depth).is_some_and(|e| e.after_incomplete);
+                    tried.path.truncate(depth);
+                    let incomplete = parsed.is_some_and(|p| p.incomplete.is_some() || went_on);
+                    if let Some(parent) = tried.path.last_mut() {
+                        parent.after_incomplete |= incomplete && !parent.choice;
+                    }
+                }
+            }
+        });
+        parser.parse_non_term(start, prefix)?;
+
+        let literals = tried.into_inner().literals;
+        Ok(literals.into_iter().collect())
+    }
+}
+
+/// The nodes entered at the cursor, see [`Grammar::completions`]
+#[derive(Default)]
+struct Tried {
+    /// The nodes being parsed
+    path: Vec<Entered>,
+    seen: Set<*const Node<Text>>,
+    literals: BTreeSet<String>,
+}
+
+struct Entered {
+    choice: bool,
+    /// Whether an incomplete child was parsed
+    ///
+    /// What a sequence or repetition parses after an incomplete child does
+    /// not follow the prefix, unlike the next branch of a choice.
+    after_incomplete: bool,
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completions() {
+        let grammar = Grammar::load_ebnf(r#"
+            expression = term , (("+" | "-") , term)*;
+            term = factor , (("*" | "/") , factor)*;
+            factor = ("(" , expression , ")") | number | name;
+            number = digit+;
+            digit = "0" | "1" | "2";
+            name = #'[a-z]+';
+        "#).unwrap();
+        let completions = |src: &str| grammar.completions("expression", src, src.len()).unwrap();
+
+        let operand = ["(", "0", "1", "2"];
+        assert_eq!(completions(""), operand);
+        assert_eq!(completions("1+"), operand);
+        assert_eq!(completions("(1*("), operand);
+        assert_eq!(completions("1"), ["*", "+", "-", "/", "0", "1", "2"]);
+        assert_eq!(completions("(x"), [")", "*", "+", "-", "/"]);
+        assert_eq!(completions(")"), Vec::<String>::new());
+
+        // every branch that reaches the cursor counts
+        let branches = Grammar::load_ebnf(r#"s = ("a" , "b" , "c") | ("a" , "b" , "d") | "x";"#).unwrap();
+        assert_eq!(branches.completions("s", "ab", 2).unwrap(), ["c", "d"]);
+        assert_eq!(branches.completions("s", "ab", 0).unwrap(), ["a", "x"]);
+
+        // the text after the cursor does not matter
+        assert_eq!(grammar.completions("expression", "1+2", 2).unwrap(), operand);
+        assert!(grammar.completions("nope", "", 0).is_err());
+        assert!(grammar.completions("expression", "é", 1).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use std::{iter::Peekable, str::Chars};
+
+use crate::basic::{Node, Text};
+
+use super::Grammar;
+
+/// EBNF flavours understood by [`Grammar::load_ebnf_dialect`]
+///
+/// | feature         | `Iso`                      | `W3c`                     |
+/// |-----------------|----------------------------|---------------------------|
+/// | rule definition | `a = ...;` or `a ::= ...;` | `a ::= ...`, no `;`       |
+/// | concatenation   | `,` or space               | space                     |
+/// | optional        | `[...]` or `?`             | `?`                       |
+/// | repetition      | `{...}`, `*` or `+`        | `*` or `+`                |
+/// | strings         | `"..."`, `'...'`           | `"..."`, `'...'`, `#xN`   |
+/// | regexes         | `#'...'`                   | `[...]` character classes |
+/// | exception       | `a - b`                    | not supported             |
+/// | comments        | leading `(* start: a *)`   | `/* ... */`               |
+/// | start rule      | the `start` comment        | the first rule            |
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum EbnfDialect {
+    /// The dialect of the `ebnf` crate, also written by [`Grammar::to_ebnf`]
+    #[default]
+    Iso,
+    /// The notation of the W3C specifications, such as XML
+    W3c,
+}
+
+impl Grammar<Text> {
+    /// Same as [`Grammar::load_ebnf`], for any of the supported [`EbnfDialect`]s
+    pub fn load_ebnf_dialect(source: &str, dialect: EbnfDialect) -> anyhow::Result<Self> {
+        match dialect {
+            EbnfDialect::Iso => Self::load_ebnf(source),
+            EbnfDialect::W3c => load_w3c(source),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum W3cToken {
+    Name(String),
+    Define,
+    Pipe,
+    Open,
+    Close,
+    Postfix(char),
+    String(String),
+    Class(String),
+}
+
+fn load_w3c(source: &str) -> anyhow::Result<Grammar<Text>> {
+    let mut tokens = tokenize_w3c(source)?.into_iter().peekable();
+    let mut grammar = Grammar::new();
+    while let Some(token) = tokens.next() {
+        let W3cToken::Name(name) = token else {
+            anyhow::bail!("Expected a rule name, found {token:?}");
+        };
+        if tokens.next() != Some(W3cToken::Define) {
+            anyhow::bail!("Expected `::=` after {name:?}");
+        }
+        let node = parse_w3c_alt(&mut tokens)?;
+        if grammar.start.is_none() {
+            grammar.start = Some(name.clone());
+        }
+        grammar.add_element(name, node)?;
+    }
+    Ok(grammar)
+}
+
+type W3cTokens = Peekable<std::vec::IntoIter<W3cToken>>;
+
+fn parse_w3c_alt(tokens: &mut W3cTokens) -> anyhow::Result<Node<Text>> {
+    let mut branches = vec![parse_w3c_seq(tokens)?];
+    while tokens.next_if_eq(&W3cToken::Pipe).is_some() {
+        branches.push(parse_w3c_seq(tokens)?);
+    }
+    Ok(match branches.len() {
+        1 => branches.pop().unwrap(),
+        _ => Node::alt(branches),
+    })
+}
+
+fn parse_w3c_seq(tokens: &mut W3cTokens) -> anyhow::Result<Node<Text>> {
+    let mut elements = vec![];
+    loop {
+        let mut node = match tokens.peek() {
+            Some(W3cToken::Name(_)) => {
+                // a name followed by `::=` starts the next rule
+                let mut ahead = tokens.clone();
+                ahead.next();
+                if ahead.peek() == Some(&W3cToken::Define) {
+                    break;
+                }
+                let Some(W3cToken::Name(name)) = tokens.next() else { unreachable!() };
+                Node::NonTerm(name)
+            }
+            Some(W3cToken::String(_) | W3cToken::Class(_)) => match tokens.next() {
+                Some(W3cToken::String(s)) => Node::Terminal(Text::String(s)),
+                Some(W3cToken::Class(c)) => Node::Terminal(Text::Regex(c)),
+                _ => unreachable!(),
+            },
+            Some(W3cToken::Open) => {
+                tokens.next();
+                let node = parse_w3c_alt(tokens)?;
+                if tokens.next() != Some(W3cToken::Close) {
+                    anyhow::bail!("Unbalanced parentheses");
+                }
+                node
+            }
+            Some(W3cToken::Postfix(op)) => anyhow::bail!("Unexpected `{op}`"),
+            Some(W3cToken::Define) => anyhow::bail!("Unexpected `::=`"),
+            Some(W3cToken::Pipe | W3cToken::Close) | None => break,
+        };
+        while let Some(W3cToken::Postfix(op)) = tokens.peek() {
+            node = match op {
+                '?' => Node::rep(node, 0..=1),
+                '*' => Node::rep(node, 0..),
+                _ => Node::rep(node, 1..),
+            };
+            tokens.next();
+        }
+        elements.push(node);
+    }
+    Ok(match elements.len() {
+        1 => elements.pop().unwrap(),
+        _ => Node::seq(elements),
+    })
+}
+
+fn tokenize_w3c(source: &str) -> anyhow::Result<Vec<W3cToken>> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '/' if chars.next_if_eq(&'*').is_some() => {
+                let mut prev = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if prev == '*' => break,
+                        Some(c) => prev = c,
+                        None => anyhow::bail!("Unterminated comment"),
+                    }
+                }
+                continue;
+            }
+            ':' => {
+                if chars.next() != Some(':') || chars.next() != Some('=') {
+                    anyhow::bail!("Expected `::=`");
+                }
+                W3cToken::Define
+            }
+            '|' => W3cToken::Pipe,
+            '(' => W3cToken::Open,
+            ')' => W3cToken::Close,
+            '?' | '*' | '+' => W3cToken::Postfix(c),
+            '"' | '\'' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some(c) => s.push(c),
+                        None => anyhow::bail!("Unterminated string"),
+                    }
+                }
+                W3cToken::String(s)
+            }
+            '#' => W3cToken::String(code_point(&mut chars)?.to_string()),
+            '[' => {
+                let mut class = String::from("[");
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some('#') => class.push_str(&regex::escape(&code_point(&mut chars)?.to_string())),
+                        Some(c @ ('\\' | '[' | '&' | '~')) => {
+                            class.push('\\');
+                            class.push(c);
+                        }
+                        Some(c) => class.push(c),
+                        None => anyhow::bail!("Unterminated character class"),
+                    }
+                }
+                class.push(']');
+                W3cToken::Class(class)
+            }
+            '-' => anyhow::bail!("Exclusion (`-`) is not supported"),
+            c if c.is_alphabetic() || c == '_' => {
+                let mut name = String::from(c);
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+                    name.push(c);
+                }
+                W3cToken::Name(name)
+            }
+            c => anyhow::bail!("Unexpected character {c:?}"),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// The character of a `#xN` code point, after the `#`
+fn code_point(chars: &mut Peekable<Chars>) -> anyhow::Result<char> {
+    if chars.next() != Some('x') {
+        anyhow::bail!("Expected `#x` code point");
+    }
+    let mut hex = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_hexdigit) {
+        hex.push(c);
+    }
+    u32::from_str_radix(&hex, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| anyhow::anyhow!("Invalid code point #x{hex}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gram;
+
+    use super::*;
+
+    #[test]
+    fn w3c() {
+        let grammar = Grammar::load_ebnf_dialect(r#"
+            /* a JSON-like number */
+            number ::= '-'? int frac?
+            int    ::= "0" | [1-9] [0-9]*
+            frac   ::= '.' digits
+            digits ::= ([#x30-#x39])+
+        "#, EbnfDialect::W3c).unwrap();
+
+        assert_eq!(grammar.start_name(), Some("number"));
+        assert_eq!(grammar.rules["number"], gram!((("-"?), int, (frac?))));
+        assert_eq!(grammar.rules["int"], gram!(("0" | ((#r"[1-9]"), ((#r"[0-9]")*)))));
+        assert_eq!(grammar.rules["digits"], gram!((#r"[0-9]")+));
+
+        let src = "-120.5";
+        let (token, diagnostics) = grammar.parse_non_term("number", src).unwrap().unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(token.span, 0..6);
+        assert_eq!(token.iter_grams("frac").map(|t| &src[t.span.clone()]).collect::<Vec<_>>(), [".5"]);
+
+        assert!(Grammar::load_ebnf_dialect("a ::= b - c", EbnfDialect::W3c).is_err());
+        assert!(Grammar::load_ebnf_dialect("a ::= (b", EbnfDialect::W3c).is_err());
+    }
+
+    #[test]
+    fn iso() {
+        let source = "(* start: a *)\na = \"x\" , b;\nb = \"y\";\n";
+        let grammar = Grammar::load_ebnf_dialect(source, EbnfDialect::Iso).unwrap();
+        assert_eq!(grammar, Grammar::load_ebnf(source).unwrap());
+        assert_eq!(grammar.start_name(), Some("a"));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{basic::{Node, TerminalNode}, prelude::*};
+
+use super::Grammar;
+
+/// Differences between two grammars, see [`Grammar::diff`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrammarDiff<'a, T> {
+    /// The start rule changed from the first to the second
+    pub start: Option<(Option<&'a str>, Option<&'a str>)>,
+    /// Rules only present in the new grammar
+    pub added: Vec<(&'a str, &'a Node<T>)>,
+    /// Rules only present in the old grammar
+    pub removed: Vec<(&'a str, &'a Node<T>)>,
+    /// Rules present in both grammars with different bodies
+    pub changed: Vec<RuleChange<'a, T>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleChange<'a, T> {
+    pub name: &'a str,
+    pub old: &'a Node<T>,
+    pub new: &'a Node<T>,
+}
+
+impl<T: TerminalNode> Grammar<T> {
+    /// Compare `self` (the old grammar) with `other` (the new one)
+    ///
+    /// Rule bodies are compared structurally with `PartialEq`, rules are
+    /// reported in alphabetical order.
+    pub fn diff<'a>(&'a self, other: &'a Self) -> GrammarDiff<'a, T> {
+        let start = (self.start != other.start)
+            .then_some((self.start.as_deref(), other.start.as_deref()));
+
+        let added = other.rules
+            .iter()
+            .filter(|(name, _)| !self.rules.contains_key(*name))
+            .map(|(name, node)| (name.as_str(), node))
+            .collect();
+
+        let mut removed = vec![];
+        let mut changed = vec![];
+        for (name, old) in &self.rules {
+            match other.rules.get(name) {
+                None => removed.push((name.as_str(), old)),
+                Some(new) if new != old => changed.push(RuleChange { name, old, new }),
+                Some(_) => {}
+            }
+        }
+
+        GrammarDiff { start, added, removed, changed }
+    }
+}
+
+impl<T> GrammarDiff<'_, T> {
+    pub fn is_empty(&self) -> bool {
+        self.start.is_none() && self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl<T: TerminalNode> GrammarDiff<'_, T> {
+    /// A line-based diff of the EBNF of the differing rules
+    ///
+    /// Removed lines are prefixed with `-` and added lines with `+`, a changed
+    /// rule produces both.
+    pub fn to_ebnf_diff(&self) -> String {
+        let mut out = String::new();
+        if let Some((old, new)) = &self.start {
+            if let Some(old) = old {
+                out.push_str(&format!("- (* start: {old} *)\n"));
+            }
+            if let Some(new) = new {
+                out.push_str(&format!("+ (* start: {new} *)\n"));
+            }
+        }
+        for (name, node) in &self.removed {
+            out.push_str(&format!("- {name} = {};\n", node.to_ebnf()));
+        }
+        for RuleChange { name, old, new } in &self.changed {
+            out.push_str(&format!("- {name} = {};\n", old.to_ebnf()));
+            out.push_str(&format!("+ {name} = {};\n", new.to_ebnf()));
+        }
+        for (name, node) in &self.added {
+            out.push_str(&format!("+ {name} = {};\n", node.to_ebnf()));
+        }
+        out
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{basic::Text, gram};
+
+    use super::*;
+
+    #[test]
+    fn diff() {
+        let old = Grammar::<Text>::load_ebnf(r#"
+            number = digit+;
+            digit = "0" | "1";
+            sign = "+" | "-";
+        "#).unwrap();
+        let mut new = Grammar::<Text>::load_ebnf(r#"
+            number = [sign] , digit+;
+            digit = "0" | "1";
+            letter = "a" | "b";
+        "#).unwrap();
+        new.start = Some("number".to_string());
+
+        assert!(old.diff(&old).is_empty());
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.start, Some((None, Some("number"))));
+        assert_eq!(diff.added, vec![("letter", &gram!(("a" | "b")))]);
+        assert_eq!(diff.removed, vec![("sign", &gram!(("+" | "-")))]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].name, "number");
+
+        assert_eq!(diff.to_ebnf_diff(), [
+            "+ (* start: number *)",
+            "- sign = \"+\" | \"-\";",
+            "- number = digit+;",
+            "+ number = [sign] digit+;",
+            "+ letter = \"a\" | \"b\";",
+            "",
+        ].join("\n"));
+    }
+}
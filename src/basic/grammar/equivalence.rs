@@ -0,0 +1,192 @@
+use alloc::collections::BTreeMap;
+use core::fmt::Write;
+
+use crate::{basic::{Node, TerminalNode}, prelude::*};
+
+use super::Grammar;
+
+/// The class of each rule of a grammar, see [`Grammar::equivalent_up_to_renaming`]
+type Classes<'a> = BTreeMap<&'a str, usize>;
+
+impl<T: TerminalNode> Grammar<T> {
+    /// Whether the grammars are the same up to the names of their rules and the order of choices
+    ///
+    /// This is meant for testing grammar transforms that rename rules, such
+    /// as prefixing them. The rules are split into classes, refined until
+    /// stable, of rules whose bodies are equal once the branches of their
+    /// choices are sorted and each reference is replaced by the class of the
+    /// rule it names. The grammars are equivalent if they have as many rules
+    /// of each class, the same fragments, and start rules and entry points of
+    /// the same classes. References to undefined rules must have the same
+    /// names, and tags and meta must be equal.
+    ///
+    /// It is a heuristic, not language equivalence: grammars matching the same
+    /// inputs with differently shaped rules, such as after
+    /// [`Grammar::left_factor`], or with equivalent terminals, such as a
+    /// regex and the literal it matches, are not equivalent. Rules with
+    /// different names also produce tokens with different grams.
+    pub fn equivalent_up_to_renaming(&self, other: &Self) -> bool {
+        if self.rules.len() != other.rules.len() {
+            return false;
+        }
+        let grammars = [self, other];
+
+        // fragments and rules start in different classes
+        let mut classes: [Classes; 2] = grammars.map(|g| {
+            g.rules.keys().map(|name| (name.as_str(), usize::from(g.is_fragment(name)))).collect()
+        });
+        let mut count = 0;
+        loop {
+            let signatures: [BTreeMap<&str, String>; 2] = [0, 1].map(|i| {
+                grammars[i].rules.iter().map(|(name, node)| {
+                    let mut signature = format!("{}:", classes[i][name.as_str()]);
+                    write_canonical(node, &classes[i], &mut signature);
+                    (name.as_str(), signature)
+                }).collect()
+            });
+            let mut distinct = signatures.iter().flat_map(BTreeMap::values).collect::<Vec<_>>();
+            distinct.sort_unstable();
+            distinct.dedup();
+            classes = [0, 1].map(|i| {
+                signatures[i].iter().map(|(name, signature)| (*name, distinct.binary_search(&signature).unwrap())).collect()
+            });
+            // the signatures include the previous classes, which are only ever split
+            if distinct.len() == count {
+                break;
+            }
+            count = distinct.len();
+        }
+
+        let sorted = |classes: &Classes| {
+            let mut sorted = classes.values().copied().collect::<Vec<_>>();
+            sorted.sort_unstable();
+            sorted
+        };
+        let class_of = |i: usize, name: &String| classes[i].get(name.as_str()).copied().ok_or_else(|| name.clone());
+        sorted(&classes[0]) == sorted(&classes[1])
+            && self.start.as_ref().map(|name| class_of(0, name)) == other.start.as_ref().map(|name| class_of(1, name))
+            && self.entry_points.iter().map(|name| class_of(0, name)).eq(other.entry_points.iter().map(|name| class_of(1, name)))
+    }
+}
+
+/// Write `node` with the branches of its choices sorted and its references replaced by their `classes`
+fn write_canonical<T: TerminalNode>(node: &Node<T>, classes: &Classes, out: &mut String) {
+    let write_all = |name: &str, nodes: &[&Node<T>], out: &mut String| {
+        out.push_str(name);
+        out.push('(');
+        for node in nodes {
+            write_canonical(node, classes, out);
+            out.push(',');
+        }
+        out.push(')');
+    };
+    match node {
+        Node::Seq(nodes) => write_all("seq", &nodes.iter().collect::<Vec<_>>(), out),
+        Node::Alt(branches) => {
+            let mut branches = branches.iter().map(|branch| {
+                let mut out = String::new();
+                write_canonical(branch, classes, &mut out);
+                out
+            }).collect::<Vec<_>>();
+            branches.sort_unstable();
+            let _ = write!(out, "alt({})", branches.join(","));
+        }
+        Node::Rep { node, range, lazy } => write_all(&format!("rep{range:?}{lazy}"), &[node], out),
+        Node::RepSep { node, sep, range } => write_all(&format!("rep_sep{range:?}"), &[node, sep], out),
+        Node::Terminal(terminal) => {
+            let _ = write!(out, "{terminal:?}");
+        }
+        Node::NonTerm(name) => {
+            let _ = match classes.get(name.as_str()) {
+                Some(class) => write!(out, "#{class}"),
+                None => write!(out, "?{name:?}"),
+            };
+        }
+        Node::Tagged { node, tags } => write_all(&format!("tagged{tags:?}"), &[node], out),
+        Node::Meta { node, meta } => write_all(&format!("meta{meta:?}"), &[node], out),
+        Node::Empty => out.push_str("empty"),
+        Node::Except { node, minus } => write_all("except", &[node, minus], out),
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{basic::Text, gram};
+
+    use super::*;
+
+    fn sample() -> Grammar<Text> {
+        Grammar::load_ebnf(r#"
+            (* start: list *)
+            list = item , ("," , item)*;
+            item = number | name | "(" , list , ")";
+            number = #'[0-9]+';
+            name = #'[a-z]+';
+        "#).unwrap()
+    }
+
+    #[test]
+    fn equivalent_up_to_renaming() {
+        let grammar = sample();
+        assert!(grammar.equivalent_up_to_renaming(&grammar));
+
+        // renamed and namespaced
+        let renamed = grammar.clone().with_renamed_element("item", "element").unwrap();
+        assert_ne!(renamed, grammar);
+        assert!(renamed.equivalent_up_to_renaming(&grammar));
+        let mut namespaced = grammar.clone();
+        for name in grammar.rules.keys() {
+            namespaced = namespaced.with_renamed_element(name, format!("ns.{name}")).unwrap();
+        }
+        assert_eq!(namespaced.start.as_deref(), Some("ns.list"));
+        assert!(namespaced.equivalent_up_to_renaming(&grammar));
+        assert!(grammar.equivalent_up_to_renaming(&namespaced));
+
+        // choices in another order
+        let mut reordered = namespaced.clone();
+        let Node::Alt(branches) = reordered.rules.get_mut("ns.item").unwrap() else { panic!() };
+        branches.reverse();
+        assert!(reordered.equivalent_up_to_renaming(&grammar));
+
+        // swapping the bodies of two rules is a renaming, changing one is not
+        let mut swapped = grammar.clone();
+        swapped.set_element("item", gram!((name | number | ("(", list, ")"))));
+        assert!(swapped.equivalent_up_to_renaming(&grammar));
+        swapped.set_element("name", gram!(#r"[0-9]+"));
+        assert!(!swapped.equivalent_up_to_renaming(&grammar));
+        swapped.set_element("number", gram!(#r"[a-z]+"));
+        assert!(swapped.equivalent_up_to_renaming(&grammar));
+    }
+
+    #[test]
+    fn not_equivalent() {
+        let grammar = sample();
+        let with = |f: fn(&mut Grammar<Text>)| {
+            let mut other = grammar.clone();
+            f(&mut other);
+            other
+        };
+        for other in [
+            with(|g| { g.set_element("name", gram!(#r"[a-z_]+")); }),
+            with(|g| { g.set_element("list", gram!(item %% ",")); }),
+            with(|g| { g.set_element("unused", gram!(#r"[0-9]+")); }),
+            with(|g| g.start = Some("item".to_string())),
+            with(|g| g.start = None),
+            with(|g| g.entry_points = vec!["item".to_string()]),
+            with(|g| { g.fragments.insert("name".to_string()); }),
+            with(|g| { g.set_element("item", Node::tagged(gram!((number | name | ("(", list, ")"))), "t")); }),
+            with(|g| { g.set_element("item", gram!((number | other | ("(", list, ")")))); }),
+        ] {
+            assert!(!other.equivalent_up_to_renaming(&grammar), "{other}");
+            assert!(!grammar.equivalent_up_to_renaming(&other), "{other}");
+        }
+
+        // the same language with differently shaped rules
+        let mut factored = Grammar::load_ebnf(r#"
+            keyword = ("in" , "t") | ("in" , "to");
+        "#).unwrap();
+        let original = factored.clone();
+        assert_eq!(factored.left_factor(), 1);
+        assert!(!factored.equivalent_up_to_renaming(&original));
+    }
+}
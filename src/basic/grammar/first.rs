@@ -0,0 +1,204 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use crate::{basic::{Node, TerminalNode, Text}, prelude::*};
+
+use super::Grammar;
+
+/// The terminals that can begin a match, see [`Grammar::first_sets`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct FirstSet {
+    /// Non-empty literals a match can begin with
+    pub literals: BTreeSet<String>,
+    /// Whether a regex can begin a match, and with it any text
+    pub any_regex: bool,
+    /// Whether the match can be empty, so that what follows can begin it too
+    pub nullable: bool,
+}
+
+impl FirstSet {
+    /// Whether a match can begin at the start of `rest`
+    ///
+    /// This is exact for literals and conservative otherwise: a `false` means
+    /// the node definitely cannot match there.
+    pub fn can_start(&self, rest: &str) -> bool {
+        self.nullable || self.any_regex || self.literals.iter().any(|l| rest.starts_with(l.as_str()))
+    }
+
+    fn extend(&mut self, other: FirstSet) {
+        self.literals.extend(other.literals);
+        self.any_regex |= other.any_regex;
+    }
+}
+
+impl Node<Text> {
+    /// The [`FirstSet`] of the node, given the ones of the rules it references
+    ///
+    /// Undefined rules are assumed to match nothing.
+    pub fn first_set(&self, rules: &BTreeMap<String, FirstSet>) -> FirstSet {
+        match self {
+            Node::Seq(elements) => {
+                let mut first = FirstSet { nullable: true, ..Default::default() };
+                for element in elements {
+                    let element = element.first_set(rules);
+                    let nullable = element.nullable;
+                    first.extend(element);
+                    if !nullable {
+                        first.nullable = false;
+                        break;
+                    }
+                }
+                first
+            }
+            Node::Alt(branches) => {
+                let mut first = FirstSet::default();
+                for branch in branches {
+                    let branch = branch.first_set(rules);
+                    first.nullable |= branch.nullable;
+                    first.extend(branch);
+                }
+                first
+            }
+            Node::Rep { node, range, .. } => {
+                let mut first = node.first_set(rules);
+                first.nullable |= *range.start() == 0;
+                first
+            }
+            Node::RepSep { node, sep, range } => {
+                let mut first = node.first_set(rules);
+                if first.nullable {
+                    first.extend(sep.first_set(rules));
+                }
+                first.nullable |= *range.start() == 0;
+                first
+            }
+            Node::Terminal(Text::String(s)) if s.is_empty() => FirstSet { nullable: true, ..Default::default() },
+            Node::Terminal(Text::String(s)) => FirstSet { literals: [s.clone()].into(), ..Default::default() },
+            Node::Terminal(Text::Keyword { word, .. }) if !word.is_empty() => {
+                FirstSet { literals: [word.clone()].into(), ..Default::default() }
+            }
+            Node::Terminal(t) => FirstSet { any_regex: true, nullable: t.is_nullable(), ..Default::default() },
+            Node::NonTerm(name) => rules.get(name).cloned().unwrap_or_default(),
+            Node::Tagged { node, .. } => node.first_set(rules),
+            Node::Meta { node, .. } => node.first_set(rules),
+            Node::Empty => FirstSet { nullable: true, ..Default::default() },
+            // `minus` only rules out some matches of the node
+            Node::Except { node, .. } => node.first_set(rules),
+        }
+    }
+
+    /// The [`FirstSet`] of the results of the node, complete or not, given the ones of the rules
+    ///
+    /// A sequence has a result as soon as its first element has one, so
+    /// `(["a"] , "b")` has a zero-width incomplete one anywhere, see
+    /// [`Grammar::result_sets`].
+    pub(crate) fn result_set(&self, rules: &BTreeMap<String, FirstSet>) -> FirstSet {
+        match self {
+            Node::Seq(elements) => match elements.first() {
+                Some(first) => first.result_set(rules),
+                None => FirstSet { nullable: true, ..Default::default() },
+            },
+            Node::Alt(branches) => {
+                let mut first = FirstSet::default();
+                for branch in branches {
+                    let branch = branch.result_set(rules);
+                    first.nullable |= branch.nullable;
+                    first.extend(branch);
+                }
+                first
+            }
+            Node::Rep { node, range, .. } | Node::RepSep { node, range, .. } => {
+                let mut first = node.result_set(rules);
+                first.nullable |= *range.start() == 0;
+                first
+            }
+            Node::NonTerm(name) => rules.get(name).cloned().unwrap_or_default(),
+            Node::Tagged { node, .. } | Node::Meta { node, .. } | Node::Except { node, .. } => node.result_set(rules),
+            Node::Terminal(_) | Node::Empty => self.first_set(rules),
+        }
+    }
+}
+
+impl Grammar<Text> {
+    /// The [`FirstSet`] of every rule, computed as a fixpoint over the references
+    pub fn first_sets(&self) -> BTreeMap<String, FirstSet> {
+        let mut sets = BTreeMap::new();
+        loop {
+            let next = self.rules
+                .iter()
+                .map(|(name, node)| (name.clone(), node.first_set(&sets)))
+                .collect::<BTreeMap<_, _>>();
+            if next == sets {
+                return sets;
+            }
+            sets = next;
+        }
+    }
+
+    /// The [`FirstSet`] of the results of every rule, complete or not, see [`Node::result_set`]
+    pub(crate) fn result_sets(&self) -> BTreeMap<String, FirstSet> {
+        let mut sets = BTreeMap::new();
+        loop {
+            let next = self.rules
+                .iter()
+                .map(|(name, node)| (name.clone(), node.result_set(&sets)))
+                .collect::<BTreeMap<_, _>>();
+            if next == sets {
+                return sets;
+            }
+            sets = next;
+        }
+    }
+
+    /// The [`FirstSet`] of `node`, with the rules of this grammar
+    pub fn first_set(&self, node: &Node<Text>) -> FirstSet {
+        node.first_set(&self.first_sets())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::gram;
+
+    use super::*;
+
+    fn first(literals: &[&str], any_regex: bool, nullable: bool) -> FirstSet {
+        FirstSet {
+            literals: literals.iter().map(|l| l.to_string()).collect(),
+            any_regex,
+            nullable,
+        }
+    }
+
+    #[test]
+    fn first_sets() {
+        let grammar = Grammar::load_ebnf(r#"
+            expression = term , (("+" | "-") , term)*;
+            term = factor , (("*" | "/") , factor)*;
+            factor = ("(" , expression , ")") | number | name;
+            number = sign , digit+;
+            sign = ["-"];
+            digit = "0" | "1";
+            name = #'[a-z]+';
+            keyword = `end` | `if`[a-z-];
+            list = ws , ("," , ws)*;
+            ws = " "*;
+        "#).unwrap();
+
+        let sets = grammar.first_sets();
+        assert_eq!(sets["sign"], first(&["-"], false, true));
+        assert_eq!(sets["number"], first(&["-", "0", "1"], false, false));
+        assert_eq!(sets["factor"], first(&["(", "-", "0", "1"], true, false));
+        assert_eq!(sets["expression"], sets["factor"]);
+        assert_eq!(sets["list"], first(&[" ", ","], false, true));
+        assert_eq!(sets["keyword"], first(&["end", "if"], false, false));
+
+        assert_eq!(grammar.first_set(&gram!((sign, "+"))), first(&["-", "+"], false, false));
+        assert_eq!(grammar.first_set(&gram!(sign %% "x")), first(&["-", "x"], false, true));
+        assert_eq!(grammar.first_set(&gram!(undefined)), first(&[], false, false));
+
+        assert!(sets["number"].can_start("-1"));
+        assert!(!sets["number"].can_start("+1"));
+        assert!(sets["factor"].can_start("x"));
+        assert!(sets["sign"].can_start(""));
+    }
+}
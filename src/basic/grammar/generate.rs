@@ -0,0 +1,281 @@
+//! Random inputs produced by a grammar
+
+use regex_syntax::hir::{Class, Hir, HirKind};
+
+use crate::{basic::{Node, Text}, collections::Map};
+
+use super::Grammar;
+
+/// Extra matches of a repetition beyond its minimum, at most
+const MAX_EXTRA_REPETITIONS: usize = 3;
+
+/// Inputs of the node of an exception drawn until one is not excluded, at most
+const MAX_EXCEPT_ATTEMPTS: usize = 10;
+
+impl Grammar<Text> {
+    /// A random input of the rule `start`, with at most `max_depth` nested non-terminals
+    ///
+    /// Choices and repetition counts are drawn with `rng`, which returns an
+    /// index below its argument: `|n| rng.random_range(0..n)` with the `rand`
+    /// crate. Repetitions match at most a few times more than their minimum.
+    /// When the depth runs out, only the branches and counts that can still
+    /// complete are drawn; `None` if there are none, or if `start` is not
+    /// defined.
+    ///
+    /// Literals are written as is, regexes produce a random match of a subset
+    /// of their syntax: assertions such as `^` or `\b` are ignored. The node
+    /// of an exception is drawn again, a few times, while its `minus` matches. The input
+    /// is in the language of the grammar read as a context-free grammar, so
+    /// it can fail to parse back where the parser differs, such as a greedy
+    /// regex consuming the text of the next terminal.
+    pub fn generate(&self, start: &str, rng: &mut impl FnMut(usize) -> usize, max_depth: usize) -> Option<String> {
+        let mut generator = Generator { grammar: self, heights: self.heights(), rng };
+        let mut out = String::new();
+        generator.node(&Node::NonTerm(start.to_string()), max_depth, &mut out)?;
+        Some(out)
+    }
+
+    /// The minimum depth of nested non-terminals each rule can be completed with
+    ///
+    /// Rules that can never complete, such as `a = "x" , a`, are missing.
+    fn heights(&self) -> Map<&str, usize> {
+        let mut heights = Map::new();
+        loop {
+            let mut changed = false;
+            for (name, node) in &self.rules {
+                let height = height(node, &heights);
+                if height < heights.get(name.as_str()).copied().unwrap_or(usize::MAX) {
+                    heights.insert(name.as_str(), height);
+                    changed = true;
+                }
+            }
+            if !changed {
+                return heights;
+            }
+        }
+    }
+}
+
+/// The minimum depth of nested non-terminals `node` can be completed with, `usize::MAX` if it cannot
+fn height(node: &Node<Text>, heights: &Map<&str, usize>) -> usize {
+    match node {
+        Node::Seq(elements) => elements.iter().map(|n| height(n, heights)).max().unwrap_or(0),
+        Node::Alt(branches) => branches.iter().map(|n| height(n, heights)).min().unwrap_or(usize::MAX),
+        Node::Rep { range, .. } | Node::RepSep { range, .. } if *range.start() == 0 => 0,
+        Node::Rep { node, .. } => height(node, heights),
+        Node::RepSep { node, range, .. } if *range.start() == 1 => height(node, heights),
+        Node::RepSep { node, sep, .. } => height(node, heights).max(height(sep, heights)),
+        Node::NonTerm(name) => heights.get(name.as_str()).map_or(usize::MAX, |h| h.saturating_add(1)),
+        Node::Tagged { node, .. } | Node::Meta { node, .. } | Node::Except { node, .. } => height(node, heights),
+        Node::Terminal(_) | Node::Empty => 0,
+    }
+}
+
+struct Generator<'g, 'r, F> {
+    grammar: &'g Grammar<Text>,
+    heights: Map<&'g str, usize>,
+    rng: &'r mut F,
+}
+
+impl<F: FnMut(usize) -> usize> Generator<'_, '_, F> {
+    /// Write a random input of `node` to `out`, with at most `depth` nested non-terminals
+    fn node(&mut self, node: &Node<Text>, depth: usize, out: &mut String) -> Option<()> {
+        match node {
+            Node::Seq(elements) => {
+                for element in elements {
+                    self.node(element, depth, out)?;
+                }
+            }
+            Node::Alt(branches) => {
+                let viable = branches.iter().filter(|b| self.height(b) <= depth).collect::<Vec<_>>();
+                let branch = viable.get(self.choose(viable.len())?)?;
+                self.node(branch, depth, out)?;
+            }
+            Node::Rep { node, range, .. } => {
+                for _ in 0..self.count(*range.start(), *range.end(), node, depth) {
+                    self.node(node, depth, out)?;
+                }
+            }
+            Node::RepSep { node, sep, range } => {
+                let mut count = self.count(*range.start(), *range.end(), node, depth);
+                if self.height(sep) > depth {
+                    count = count.min(1);
+                }
+                for i in 0..count {
+                    if i > 0 {
+                        self.node(sep, depth, out)?;
+                    }
+                    self.node(node, depth, out)?;
+                }
+            }
+            Node::Terminal(text) => self.terminal(text, out)?,
+            Node::NonTerm(name) => {
+                let rule = self.grammar.rules.get(name)?;
+                if self.height(node) > depth {
+                    return None;
+                }
+                self.node(rule, depth - 1, out)?;
+            }
+            Node::Tagged { node, .. } | Node::Meta { node, .. } => self.node(node, depth, out)?,
+            Node::Empty => {}
+            Node::Except { node, minus } => {
+                for _ in 0..MAX_EXCEPT_ATTEMPTS {
+                    let mut text = String::new();
+                    self.node(node, depth, &mut text)?;
+                    if !self.matches(minus, &text) {
+                        out.push_str(&text);
+                        return Some(());
+                    }
+                }
+                return None;
+            }
+        }
+        Some(())
+    }
+
+    fn terminal(&mut self, text: &Text, out: &mut String) -> Option<()> {
+        let (pattern, case_insensitive, dot_all, multiline) = match text {
+            Text::String(word) | Text::Keyword { word, .. } => {
+                out.push_str(word);
+                return Some(());
+            }
+            Text::Regex(pattern) => (pattern, false, false, false),
+            Text::RegexFlags { pattern, case_insensitive, dot_all, multiline } => {
+                (pattern, *case_insensitive, *dot_all, *multiline)
+            }
+        };
+        let hir = regex_syntax::ParserBuilder::new()
+            .case_insensitive(case_insensitive)
+            .dot_matches_new_line(dot_all)
+            .multi_line(multiline)
+            .build()
+            .parse(pattern)
+            .ok()?;
+        self.hir(&hir, out)
+    }
+
+    /// Write a random match of `hir` to `out`
+    fn hir(&mut self, hir: &Hir, out: &mut String) -> Option<()> {
+        match hir.kind() {
+            HirKind::Empty | HirKind::Look(_) => {}
+            HirKind::Literal(literal) => out.push_str(core::str::from_utf8(&literal.0).ok()?),
+            HirKind::Class(Class::Unicode(class)) => {
+                let range = class.ranges().get(self.choose(class.ranges().len())?)?;
+                let (start, end) = (u32::from(range.start()), u32::from(range.end()));
+                let offset = self.choose((end - start + 1) as usize)? as u32;
+                // the middle of a range can be a surrogate, which is not a char
+                out.push(char::from_u32(start + offset).unwrap_or(range.start()));
+            }
+            HirKind::Class(Class::Bytes(class)) => {
+                let ascii = class.ranges().iter().filter(|r| r.start().is_ascii()).collect::<Vec<_>>();
+                let range = ascii.get(self.choose(ascii.len())?)?;
+                let end = range.end().min(0x7f);
+                let offset = self.choose(usize::from(end - range.start()) + 1)? as u8;
+                out.push(char::from(range.start() + offset));
+            }
+            HirKind::Repetition(repetition) => {
+                let min = repetition.min as usize;
+                let max = repetition.max.map_or(usize::MAX, |max| max as usize);
+                let count = min + self.choose(max.min(min + MAX_EXTRA_REPETITIONS) - min + 1)?;
+                for _ in 0..count {
+                    self.hir(&repetition.sub, out)?;
+                }
+            }
+            HirKind::Capture(capture) => self.hir(&capture.sub, out)?,
+            HirKind::Concat(hirs) => {
+                for hir in hirs {
+                    self.hir(hir, out)?;
+                }
+            }
+            HirKind::Alternation(hirs) => {
+                let hir = hirs.get(self.choose(hirs.len())?)?;
+                self.hir(hir, out)?;
+            }
+        }
+        Some(())
+    }
+
+    /// Whether `node` completely matches the whole `text`, as the parser checks exceptions
+    fn matches(&self, node: &Node<Text>, text: &str) -> bool {
+        let parsed = self.grammar.parser().parse_node(node, text).ok().flatten();
+        parsed.is_some_and(|(token, diagnostics)| token.span.end == text.len() && diagnostics.is_empty())
+    }
+
+    /// A random count of a repetition of `node`, the minimum if `depth` does not allow more
+    fn count(&mut self, min: usize, max: usize, node: &Node<Text>, depth: usize) -> usize {
+        if self.height(node) > depth {
+            return min;
+        }
+        let extra = (max - min).min(MAX_EXTRA_REPETITIONS);
+        min + self.choose(extra + 1).unwrap_or(0)
+    }
+
+    /// A random index below `n`, `None` if `n` is zero
+    fn choose(&mut self, n: usize) -> Option<usize> {
+        (n > 0).then(|| (self.rng)(n).min(n - 1))
+    }
+
+    fn height(&self, node: &Node<Text>) -> usize {
+        height(node, &self.heights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{basic::TerminalNode, testing::xorshift};
+
+    use super::*;
+
+    #[test]
+    fn generate() {
+        let grammar = Grammar::load_ebnf(r#"
+            expression = term , (("+" | "-") , term)*;
+            term = factor , (("*" | "/") , factor)*;
+            factor = ("(" , expression , ")") | number | call;
+            call = name , "(" , [expression , ("," , expression)*] , ")";
+            number = #'[0-9]+(\.[0-9]{1,3})?';
+            name = #'(?i)[a-z_][a-z0-9_]*';
+        "#).unwrap();
+        let mut rng = xorshift(0x2545_f491_4f6c_dd1d);
+
+        let mut longest = 0;
+        for _ in 0..50 {
+            let src = grammar.generate("expression", &mut rng, 5).unwrap();
+            let (token, diagnostics) = grammar.parse_non_term("expression", &src).unwrap().unwrap();
+            assert_eq!((token.span, diagnostics), (0..src.len(), vec![]), "{src:?}");
+            longest = longest.max(src.len());
+        }
+        assert!(longest > 20);
+
+        // the shortest inputs nest `expression`, `term`, `factor` and `number`
+        assert_eq!(grammar.generate("expression", &mut rng, 3), None);
+        assert!(grammar.generate("expression", &mut rng, 4).is_some());
+        assert_eq!(grammar.generate("undefined", &mut rng, 10), None);
+
+        let mut grammar = grammar;
+        grammar.add_element("endless", Node::Seq(vec![
+            Node::Terminal(Text::String("x".to_string())),
+            Node::NonTerm("endless".to_string()),
+        ])).unwrap();
+        assert_eq!(grammar.generate("endless", &mut rng, 100), None);
+    }
+
+    #[test]
+    fn regex_terminals() {
+        let mut rng = xorshift(42);
+        let terminals = [
+            Text::Regex(r"[a-z]{2,4}-\d+".to_string()),
+            Text::Regex(r"^(foo|bar)?baz\b".to_string()),
+            Text::Regex(r"[α-ω]+|\p{Greek}".to_string()),
+            Text::Regex(r"(?-u:\w)x".to_string()),
+            Text::RegexFlags { pattern: "a.c".to_string(), case_insensitive: true, dot_all: true, multiline: false },
+        ];
+        for text in terminals {
+            for _ in 0..50 {
+                let mut out = String::new();
+                Generator { grammar: &Grammar::new(), heights: Map::new(), rng: &mut rng }.terminal(&text, &mut out).unwrap();
+                assert_eq!(text.parses(&out, 0).unwrap(), Some(out.len()), "{text:?} {out:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{basic::TerminalNode, prelude::*};
+#[cfg(feature = "std")]
+use crate::basic::Text;
+
+use super::Grammar;
+
+impl<T: TerminalNode> Grammar<T> {
+    /// The grammar with its rules renamed `ns.name`
+    ///
+    /// References to the rules of the grammar, its fragments, entry points
+    /// and start are renamed with them. References to rules the grammar does
+    /// not define are left as they are, to be defined by the grammar it is
+    /// merged into.
+    pub fn with_namespace(mut self, ns: &str) -> Self {
+        // the longest names first: `name` is renamed after a rule named
+        // `ns.name`, if any, and only its references are renamed
+        let mut names = self.rules.keys().cloned().collect::<Vec<_>>();
+        names.sort_by_key(|name| core::cmp::Reverse(name.len()));
+        for name in names {
+            let qualified = format!("{ns}.{name}");
+            self = self.with_renamed_element(name, qualified).expect("the longer names are already renamed");
+        }
+        self
+    }
+}
+
+#[cfg(feature = "std")]
+impl Grammar<Text> {
+    /// Same as [`Grammar::load_ebnf`], resolving the `@import "uri" as ns;` statements of `source`
+    ///
+    /// An import, on a line of its own, merges the rules of the grammar
+    /// `resolve(uri)` returns under the namespace `ns`, see
+    /// [`Grammar::with_namespace`]: its rule `name` is referenced as
+    /// `ns.name`. Its start and entry points are dropped. Imports are
+    /// resolved recursively, with the same `resolve`, and nested namespaces
+    /// add up: the rule `name` of a grammar imported as `inner` by a grammar
+    /// imported as `outer` is `outer.inner.name`.
+    ///
+    /// A grammar importing one of the grammars it is imported by, directly
+    /// or not, is an error naming the cycle. Grammars are identified by
+    /// their uri, as written. For two grammars to reference each other, one
+    /// of them can leave the rules of the other undefined instead, their
+    /// references being kept by the import.
+    pub fn load_ebnf_with_imports(
+        source: &str,
+        mut resolve: impl FnMut(&str) -> anyhow::Result<String>,
+    ) -> anyhow::Result<Self> {
+        load_with_imports(source, &mut resolve, &mut vec![])
+    }
+}
+
+/// Load `source`, `importing` being the uris of the grammars importing it
+#[cfg(feature = "std")]
+fn load_with_imports(
+    source: &str,
+    resolve: &mut dyn FnMut(&str) -> anyhow::Result<String>,
+    importing: &mut Vec<String>,
+) -> anyhow::Result<Grammar<Text>> {
+    let (imports, source) = split_imports(source)?;
+    let mut grammar = Grammar::load_ebnf_rules(&source)?;
+    for (uri, ns) in imports {
+        if importing.contains(&uri) {
+            let cycle = importing.iter().skip_while(|u| **u != uri).chain([&uri]);
+            anyhow::bail!("Import cycle: {}", cycle.map(|u| format!("{u:?}")).collect::<Vec<_>>().join(" -> "));
+        }
+        let imported = resolve(&uri).map_err(|e| anyhow::anyhow!("Failed to resolve import {uri:?}: {e}"))?;
+        importing.push(uri);
+        let imported = load_with_imports(&imported, resolve, importing);
+        let uri = importing.pop().unwrap();
+        let mut imported = imported.map_err(|e| anyhow::anyhow!("In import {uri:?}: {e}"))?.with_namespace(&ns);
+        imported.start = None;
+        imported.entry_points.clear();
+        grammar = grammar.merge(imported)?;
+    }
+    Ok(grammar)
+}
+
+/// Split the `@import "uri" as ns;` lines from `source`, as `(uri, ns)` pairs
+///
+/// The lines are blanked, so that the rest of `source` keeps its lines.
+#[cfg(feature = "std")]
+fn split_imports(source: &str) -> anyhow::Result<(Vec<(String, String)>, String)> {
+    let mut imports = vec![];
+    let mut rest = String::with_capacity(source.len());
+    for line in source.split_inclusive('\n') {
+        let Some(statement) = line.trim_start().strip_prefix("@import") else {
+            rest.push_str(line);
+            continue;
+        };
+        let invalid = || anyhow::anyhow!("Invalid import {:?}, expected `@import \"uri\" as ns;`", line.trim());
+        let (uri, statement) = statement
+            .trim_start()
+            .strip_prefix('"')
+            .and_then(|s| s.split_once('"'))
+            .ok_or_else(invalid)?;
+        let ns = statement
+            .trim_start()
+            .strip_prefix("as")
+            .filter(|s| s.starts_with(char::is_whitespace))
+            .and_then(|s| s.trim().strip_suffix(';'))
+            .map(str::trim)
+            .ok_or_else(invalid)?;
+        let is_name = ns.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && ns.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !is_name {
+            return Err(invalid());
+        }
+        imports.push((uri.to_string(), ns.to_string()));
+        rest.push_str(if line.ends_with('\n') { "\n" } else { "" });
+    }
+    Ok((imports, rest))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{basic::Node, collections::Map};
+
+    use super::*;
+
+    #[test]
+    fn with_namespace() {
+        let mut grammar = Grammar::load_ebnf(r#"
+            (* start: list *)
+            list = item , ("," , item)*;
+            item = number | external;
+            number = #'[0-9]+';
+        "#).unwrap();
+        grammar.add_fragment("digits", Node::Terminal(Text::Regex("[0-9]".to_string()))).unwrap();
+        grammar.entry_points.push("item".to_string());
+        let grammar = grammar.with_namespace("ns");
+        assert_eq!(grammar.start.as_deref(), Some("ns.list"));
+        assert_eq!(grammar.entry_points, ["ns.item"]);
+        assert!(grammar.fragments.contains("ns.digits"));
+        assert_eq!(grammar.rules.keys().collect::<Vec<_>>(), ["ns.digits", "ns.item", "ns.list", "ns.number"]);
+        assert_eq!(grammar.rules["ns.item"], Node::alt([
+            Node::NonTerm("ns.number".to_string()),
+            Node::NonTerm("external".to_string()),
+        ]));
+
+        // qualified names are written as they are, and read back
+        let ebnf = grammar.to_ebnf(false);
+        assert!(ebnf.contains("ns.item = ns.number | external;"), "{ebnf}");
+        assert_eq!(Grammar::load_ebnf(&ebnf).unwrap().rules, grammar.rules);
+
+        // a rule already named like a qualified one is qualified too
+        let grammar = Grammar::load_ebnf("a = ns.a , \"x\";\nns.a = a | \"y\";").unwrap().with_namespace("ns");
+        assert_eq!(grammar.rules.keys().collect::<Vec<_>>(), ["ns.a", "ns.ns.a"]);
+        assert_eq!(grammar.rules["ns.a"], Node::seq([
+            Node::NonTerm("ns.ns.a".to_string()),
+            Node::Terminal(Text::String("x".to_string())),
+        ]));
+        assert_eq!(grammar.rules["ns.ns.a"], Node::alt([
+            Node::NonTerm("ns.a".to_string()),
+            Node::Terminal(Text::String("y".to_string())),
+        ]));
+    }
+
+    fn files(files: &[(&str, &str)]) -> impl FnMut(&str) -> anyhow::Result<String> {
+        let files = files.iter().map(|(uri, source)| (uri.to_string(), source.to_string())).collect::<Map<_, _>>();
+        move |uri| files.get(uri).cloned().ok_or_else(|| anyhow::anyhow!("No file {uri}"))
+    }
+
+    #[test]
+    fn two_files() {
+        let resolve = files(&[("numbers.ebnf", r#"
+            (* start: number *)
+            number = digits , ["." , digits];
+            digits = #'[0-9]+';
+        "#)]);
+        let grammar = Grammar::load_ebnf_with_imports(r#"
+            (* start: list *)
+            @import "numbers.ebnf" as num;
+            list = num.number , ("," , num.number)*;
+        "#, resolve).unwrap();
+        assert_eq!(grammar.start.as_deref(), Some("list"));
+        assert_eq!(grammar.rules.keys().collect::<Vec<_>>(), ["list", "num.digits", "num.number"]);
+
+        let src = "1.5,2";
+        let token = grammar.parse("list", src).unwrap().unwrap_complete(src.len());
+        let numbers = token.iter_grams("num.number").map(|t| &src[t.span.clone()]).collect::<Vec<_>>();
+        assert_eq!(numbers, ["1.5", "2"]);
+
+        // without a resolver, imports are an error
+        let err = Grammar::load_ebnf("@import \"numbers.ebnf\" as num;\nlist = num.number;").unwrap_err();
+        assert!(err.to_string().contains("numbers.ebnf"), "{err}");
+    }
+
+    #[test]
+    fn nested_and_cycles() {
+        let grammar = Grammar::load_ebnf_with_imports(
+            "@import \"b\" as b;\na = b.b;",
+            files(&[("b", "@import \"c\" as c;\nb = c.c , a;"), ("c", "c = \"c\";")]),
+        ).unwrap();
+        assert_eq!(grammar.rules.keys().collect::<Vec<_>>(), ["a", "b.b", "b.c.c"]);
+        // `a` is left for the importing grammar to define
+        assert_eq!(grammar.rules["b.b"], Node::seq([
+            Node::NonTerm("b.c.c".to_string()),
+            Node::NonTerm("a".to_string()),
+        ]));
+
+        let err = Grammar::load_ebnf_with_imports(
+            "@import \"b\" as b;\na = b.b;",
+            files(&[("b", "@import \"c\" as c;\nb = \"b\";"), ("c", "@import \"b\" as b;\nc = \"c\";")]),
+        ).unwrap_err();
+        assert!(err.to_string().contains(r#"Import cycle: "b" -> "c" -> "b""#), "{err}");
+
+        let err = Grammar::load_ebnf_with_imports("@import \"b\" as b;", files(&[])).unwrap_err();
+        assert!(err.to_string().contains("No file b"), "{err}");
+        assert!(Grammar::load_ebnf_with_imports("@import b as b;", files(&[])).is_err());
+        assert!(Grammar::load_ebnf_with_imports("@import \"b\" as b.c;", files(&[])).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+use crate::basic::TerminalNode;
+
+use super::Grammar;
+
+impl<T: TerminalNode + Serialize> Grammar<T> {
+    /// The grammar as compact JSON, in the same encoding as the YAML files
+    ///
+    /// Each node is a map with a single key naming its variant, such as
+    /// `{"rep": {"node": ..., "range": {"min": 1}}}`.
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Same as [`Grammar::to_json`], indented
+    pub fn to_json_pretty(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+impl<T: TerminalNode + for<'de> Deserialize<'de>> Grammar<T> {
+    /// The inverse of [`Grammar::to_json`]
+    ///
+    /// Only the encoding is checked, see [`Grammar::deserialize_validated`]
+    /// to validate the grammar too.
+    pub fn from_json(src: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(src)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{basic::{Node, Text}, gram};
+
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let mut grammar = Grammar::<Text>::new();
+        grammar.start = Some("list".to_string());
+        grammar.add_element("list", gram!(("[", (item %% ","), "]")).with_description("A list")).unwrap();
+        grammar.add_element("item", Node::alt([Node::tagged(gram!(#r"[a-z]+"), "x"), gram!(digit*), gram!(digit?)])).unwrap();
+        grammar.add_element("digit", Node::rep(gram!(#r"[0-9]"), 2..=4)).unwrap();
+
+        let json = grammar.to_json().unwrap();
+        assert_eq!(Grammar::<Text>::from_json(&json).unwrap(), grammar);
+        assert_eq!(Grammar::<Text>::from_json(&grammar.to_json_pretty().unwrap()).unwrap(), grammar);
+        assert!(json.contains(r#"{"rep":{"node":{"term":"/[0-9]/"},"range":{"min":2,"max":4}}}"#), "{json}");
+        assert!(json.contains(r#"{"meta":{"node":"#), "{json}");
+
+        let json = r#"{"rules": {"a": {"rep": {"node": {"non_term": "b"}, "range": {}}}}}"#;
+        let grammar = Grammar::<Text>::from_json(json).unwrap();
+        assert_eq!(grammar.rules["a"], gram!(b*));
+
+        for json in [
+            r#"{"rules": {"a": {"non_term": "b", "term": "c"}}}"#,
+            r#"{"rules": {"a": {"nope": "b"}}}"#,
+            r#"{"rules": {"a": {}}}"#,
+            r#"{"rules": {"a": {"rep": {"node": {"term": "b"}, "range": {"min": "1"}}}}}"#,
+        ] {
+            assert!(Grammar::<Text>::from_json(json).is_err(), "{json}");
+        }
+    }
+}
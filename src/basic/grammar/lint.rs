@@ -0,0 +1,168 @@
+use alloc::collections::BTreeMap;
+use core::fmt;
+
+use crate::{basic::{Node, Text}, prelude::*};
+
+use super::Grammar;
+
+/// A performance smell found by [`Grammar::lint`]
+///
+/// Unlike a [`ValidationError`](super::ValidationError), the grammar works
+/// as written, but is probably slower or larger than it needs to be.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GrammarLint {
+    /// The rule the lint is about
+    pub rule: String,
+    pub kind: LintKind,
+    /// What the grammar author could do about it
+    pub suggestion: String,
+}
+
+/// The kinds of [`GrammarLint`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LintKind {
+    /// An `Alt` with at least [`GrammarLint::WIDE_ALT`] branches, all tried in turn
+    WideAlt { branches: usize },
+    /// A regex without metacharacters, see [`Text::literal`]
+    LiteralRegex { pattern: String },
+    /// A rule referenced only once, by another rule, and not an entry point
+    SingleUse { referenced_by: String },
+    /// A repetition of a node that can match the empty string
+    NullableRepetition,
+}
+
+impl GrammarLint {
+    /// The number of branches from which an `Alt` is [`LintKind::WideAlt`]
+    pub const WIDE_ALT: usize = 32;
+}
+
+impl fmt::Display for LintKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintKind::WideAlt { branches } => write!(f, "a choice with {branches} branches"),
+            LintKind::LiteralRegex { pattern } => write!(f, "the regex /{pattern}/ only matches a literal"),
+            LintKind::SingleUse { referenced_by } => write!(f, "only referenced by {referenced_by:?}"),
+            LintKind::NullableRepetition => write!(f, "a repetition of a node that can match the empty string"),
+        }
+    }
+}
+
+impl fmt::Display for GrammarLint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rule {:?}: {}, {}", self.rule, self.kind, self.suggestion)
+    }
+}
+
+impl Grammar<Text> {
+    /// Report the performance smells of the grammar, rule by rule
+    ///
+    /// These are hints for grammar authors rather than errors: see
+    /// [`Grammar::check`] for the problems that make a grammar invalid. The
+    /// lints of each rule are reported in the order of [`LintKind`].
+    pub fn lint(&self) -> Vec<GrammarLint> {
+        let nullable = self.nullable_rules();
+        let entry_points = self.entry_points();
+
+        // the rules referencing each rule, once per reference
+        let mut referenced_by = BTreeMap::<&str, Vec<&str>>::new();
+        for (name, node) in &self.rules {
+            node.walk(&mut |n| {
+                if let Node::NonTerm(reference) = n {
+                    referenced_by.entry(reference.as_str()).or_default().push(name);
+                }
+            });
+        }
+
+        let mut lints = vec![];
+        for (name, node) in &self.rules {
+            let mut lint = |kind, suggestion: &str| lints.push(GrammarLint {
+                rule: name.clone(),
+                kind,
+                suggestion: suggestion.to_string(),
+            });
+
+            let mut wide_alts = vec![];
+            let mut literal_regexes = vec![];
+            let mut nullable_rep = false;
+            node.walk(&mut |n| match n {
+                Node::Alt(branches) if branches.len() >= GrammarLint::WIDE_ALT => wide_alts.push(branches),
+                Node::Terminal(text @ (Text::Regex(pattern) | Text::RegexFlags { pattern, .. }))
+                    if text.literal().is_some() => literal_regexes.push(pattern),
+                Node::Rep { node, .. } if node.is_nullable(&nullable) => nullable_rep = true,
+                Node::RepSep { node, sep, .. }
+                    if node.is_nullable(&nullable) && sep.is_nullable(&nullable) => nullable_rep = true,
+                _ => {}
+            });
+
+            for branches in wide_alts {
+                let literals = branches.iter().all(|b| matches!(b, Node::Terminal(Text::String(_))));
+                let suggestion = if literals {
+                    "match the literals with a single regex such as /(a|b)/, or parse with `Parser::prune_alt_branches`"
+                } else {
+                    "group the branches by their first terminal, or parse with `Parser::prune_alt_branches`"
+                };
+                lint(LintKind::WideAlt { branches: branches.len() }, suggestion);
+            }
+            for pattern in literal_regexes {
+                lint(LintKind::LiteralRegex { pattern: pattern.clone() }, "write it as a string literal");
+            }
+            if nullable_rep {
+                lint(LintKind::NullableRepetition, "make the repeated node non-empty, for example `(a?)*` as `a*`");
+            }
+            if let Some(&[referenced_by]) = referenced_by.get(name.as_str()).map(Vec::as_slice)
+                && referenced_by != name
+                && !entry_points.contains(&name.as_str())
+            {
+                let kind = LintKind::SingleUse { referenced_by: referenced_by.to_string() };
+                lint(kind, "inline it there, unless its token is needed");
+            }
+        }
+        lints
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lint() {
+        let keywords = (0..GrammarLint::WIDE_ALT).map(|i| format!("\"k{i}\"")).collect::<Vec<_>>().join(" | ");
+        let grammar = Grammar::load_ebnf(&format!(r#"
+            (* start: program *)
+            program = statement , (";" , statement)*;
+            statement = keyword , args;
+            keyword = {keywords};
+            args = (#'[a-z]*')* , #'\.';
+            list = item , ["," , list];
+            item = #'[0-9]+';
+        "#)).unwrap();
+
+        let lints = grammar.lint();
+        let lint = |rule: &str, kind, suggestion: &str| GrammarLint {
+            rule: rule.to_string(),
+            kind,
+            suggestion: suggestion.to_string(),
+        };
+        assert_eq!(lints, vec![
+            lint("args", LintKind::LiteralRegex { pattern: "\\.".to_string() }, "write it as a string literal"),
+            lint("args", LintKind::NullableRepetition, "make the repeated node non-empty, for example `(a?)*` as `a*`"),
+            lint("args", LintKind::SingleUse { referenced_by: "statement".to_string() }, "inline it there, unless its token is needed"),
+            lint("item", LintKind::SingleUse { referenced_by: "list".to_string() }, "inline it there, unless its token is needed"),
+            lint(
+                "keyword",
+                LintKind::WideAlt { branches: GrammarLint::WIDE_ALT },
+                "match the literals with a single regex such as /(a|b)/, or parse with `Parser::prune_alt_branches`",
+            ),
+            lint("keyword", LintKind::SingleUse { referenced_by: "statement".to_string() }, "inline it there, unless its token is needed"),
+        ]);
+        assert_eq!(lints[0].to_string(), "rule \"args\": the regex /\\./ only matches a literal, write it as a string literal");
+
+        // inlining a rule referenced twice would duplicate it
+        let grammar = Grammar::load_ebnf(r#"
+            pair = item , "=" , item;
+            item = #'[0-9]+';
+        "#).unwrap();
+        assert_eq!(grammar.lint(), vec![]);
+    }
+}
@@ -0,0 +1,88 @@
+use alloc::collections::BTreeSet;
+
+use crate::{basic::{Node, TerminalNode}, prelude::*};
+
+use super::Grammar;
+
+impl<T: TerminalNode> Node<T> {
+    /// Whether the node can match the empty string
+    ///
+    /// `nullable_rules` are the non-terminals known to match the empty string,
+    /// see [`Grammar::nullable_rules`].
+    pub fn is_nullable(&self, nullable_rules: &BTreeSet<String>) -> bool {
+        match self {
+            Node::Seq(elements) => elements.iter().all(|e| e.is_nullable(nullable_rules)),
+            Node::Alt(branches) => branches.iter().any(|b| b.is_nullable(nullable_rules)),
+            Node::Rep { node, range, .. } | Node::RepSep { node, range, .. } => {
+                *range.start() == 0 || node.is_nullable(nullable_rules)
+            }
+            Node::Terminal(t) => t.is_nullable(),
+            Node::NonTerm(name) => nullable_rules.contains(name),
+            Node::Tagged { node, .. } => node.is_nullable(nullable_rules),
+            Node::Meta { node, .. } => node.is_nullable(nullable_rules),
+            Node::Empty => true,
+            // whether `minus` excludes the empty match depends on the input
+            Node::Except { node, .. } => node.is_nullable(nullable_rules),
+        }
+    }
+}
+
+impl<T: TerminalNode> Grammar<T> {
+    /// The rules that can match the empty string
+    ///
+    /// Computed as a fixpoint over the rule references, undefined rules are
+    /// not nullable.
+    pub fn nullable_rules(&self) -> BTreeSet<String> {
+        let mut nullable = BTreeSet::new();
+        loop {
+            let before = nullable.len();
+            for (name, node) in &self.rules {
+                if !nullable.contains(name) && node.is_nullable(&nullable) {
+                    nullable.insert(name.clone());
+                }
+            }
+            if nullable.len() == before {
+                return nullable;
+            }
+        }
+    }
+
+    /// Whether `node` can match the empty string, with the rules of this grammar
+    pub fn nullable(&self, node: &Node<T>) -> bool {
+        node.is_nullable(&self.nullable_rules())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{basic::Text, gram};
+
+    use super::*;
+
+    #[test]
+    fn nullable() {
+        let grammar = Grammar::load_ebnf(r#"
+            list = "[" , items , "]";
+            items = [item , ("," , item)*];
+            item = word | number;
+            word = #'[a-z]*';
+            number = #'[0-9]+';
+            a = b , "x";
+            b = c*;
+            c = "c" | empty;
+            empty = "";
+            d = d , "d";
+        "#).unwrap();
+
+        let set = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<BTreeSet<_>>();
+        assert_eq!(grammar.nullable_rules(), set(&["b", "c", "empty", "item", "items", "word"]));
+
+        assert!(grammar.nullable(&gram!((items, (b %% ",")))));
+        assert!(grammar.nullable(&gram!(number?)));
+        assert!(!grammar.nullable(&gram!((items, number))));
+        assert!(!grammar.nullable(&gram!(undefined)));
+        assert!(grammar.nullable(&Node::Seq(vec![])));
+        assert!(!grammar.nullable(&Node::Alt(vec![])));
+        assert!(!grammar.nullable(&Node::Terminal(Text::Regex("[".to_string()))));
+    }
+}
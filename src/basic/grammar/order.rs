@@ -0,0 +1,104 @@
+use alloc::collections::{BTreeSet, VecDeque};
+
+use crate::{basic::{Node, TerminalNode}, prelude::*};
+
+use super::Grammar;
+
+/// The order in which [`EbnfPrinter`] writes the rules, see [`Grammar::sort_rules`]
+///
+/// [`EbnfPrinter`]: crate::basic::EbnfPrinter
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum RuleOrder {
+    /// By name, the order of [`Grammar::rules`]
+    #[default]
+    Alphabetical,
+    /// The entry points first, then each rule after the first one referencing it
+    ///
+    /// The rules are visited breadth first from the [`Grammar::entry_points`],
+    /// then from the rules no other rule references, then from the remaining
+    /// ones, by name. The references of a rule are visited in the order they
+    /// appear in its body, so helpers come after the rules that use them.
+    Dependency,
+    /// The listed rules first, in this order, then the others by name
+    ///
+    /// Names that are not rules of the grammar are ignored.
+    Explicit(Vec<String>),
+}
+
+impl<T: TerminalNode> Grammar<T> {
+    /// The names of the rules in the given `order`
+    ///
+    /// Every rule is listed once.
+    pub fn sort_rules(&self, order: &RuleOrder) -> Vec<&str> {
+        let roots: Vec<&str> = match order {
+            RuleOrder::Alphabetical => return self.rules.keys().map(String::as_str).collect(),
+            RuleOrder::Explicit(names) => names.iter().map(String::as_str).collect(),
+            RuleOrder::Dependency => {
+                let mut referenced = BTreeSet::new();
+                for node in self.rules.values() {
+                    node.walk(&mut |n| {
+                        if let Node::NonTerm(name) = n {
+                            referenced.insert(name.as_str());
+                        }
+                    });
+                }
+                let unreferenced = self.rules.keys().map(String::as_str).filter(|name| !referenced.contains(name));
+                self.entry_points().into_iter().chain(unreferenced).collect()
+            }
+        };
+        let follow = *order == RuleOrder::Dependency;
+
+        let mut sorted = vec![];
+        let mut seen = BTreeSet::new();
+        let mut queue = VecDeque::new();
+        let all = self.rules.keys().map(String::as_str);
+        for root in roots.into_iter().chain(all) {
+            queue.push_back(root);
+            while let Some(name) = queue.pop_front() {
+                let Some((name, node)) = self.rules.get_key_value(name) else {
+                    continue;
+                };
+                if !seen.insert(name.as_str()) {
+                    continue;
+                }
+                sorted.push(name.as_str());
+                if follow {
+                    node.walk(&mut |n| {
+                        if let Node::NonTerm(dep) = n {
+                            queue.push_back(dep.as_str());
+                        }
+                    });
+                }
+            }
+        }
+        sorted
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::basic::{EbnfPrinter, Text};
+
+    use super::*;
+
+    #[test]
+    fn sort_rules() {
+        let grammar = Grammar::<Text>::load_ebnf(r#"
+            (* start: program *)
+            atom = number | "(" , expr , ")";
+            expr = term , ("+" , term)*;
+            number = #'[0-9]+';
+            program = expr , ";";
+            term = atom , ("*" , atom)*;
+            unused = "x";
+        "#).unwrap();
+        assert_eq!(grammar.sort_rules(&RuleOrder::Alphabetical), ["atom", "expr", "number", "program", "term", "unused"]);
+        assert_eq!(grammar.sort_rules(&RuleOrder::Dependency), ["program", "expr", "term", "atom", "number", "unused"]);
+        let explicit = RuleOrder::Explicit(vec!["term".to_string(), "missing".to_string()]);
+        assert_eq!(grammar.sort_rules(&explicit), ["term", "atom", "expr", "number", "program", "unused"]);
+
+        let ebnf = EbnfPrinter::new().rule_order(RuleOrder::Dependency).print(&grammar);
+        assert_eq!(ebnf.lines().nth(1), Some("program = expr \";\";"));
+        assert_eq!(Grammar::load_ebnf(&ebnf).unwrap(), grammar);
+    }
+}
@@ -0,0 +1,288 @@
+//! Railroad (syntax) diagrams rendered as SVG
+
+use core::fmt::Write;
+
+use crate::{basic::{Node, TerminalNode}, prelude::*};
+
+use super::Grammar;
+
+const CHAR_WIDTH: f64 = 8.0;
+const BOX_HEIGHT: f64 = 24.0;
+const BOX_PADDING: f64 = 10.0;
+const H_GAP: f64 = 16.0;
+const V_GAP: f64 = 12.0;
+const ARC: f64 = 16.0;
+const MARGIN: f64 = 20.0;
+
+const STYLE: &str = "\
+path { stroke: #333; stroke-width: 2; fill: none; }
+rect { stroke: #333; stroke-width: 2; fill: #f5f5dc; }
+rect.terminal { fill: #dfefff; }
+text { font-family: monospace; font-size: 14px; text-anchor: middle; dominant-baseline: central; }
+text.label { font-size: 11px; }";
+
+impl<T: TerminalNode> Grammar<T> {
+    /// Render `rule` as an SVG railroad diagram
+    ///
+    /// Sequences are drawn as a line, choices as parallel tracks, repetitions
+    /// as loops, terminals as rounded boxes and non-terminals as square boxes.
+    /// `Tagged` and `Meta` wrappers are transparent, the [description] of the
+    /// rule becomes the title of the diagram.
+    ///
+    /// [description]: Node::description
+    pub fn to_railroad_svg(&self, rule: &str) -> anyhow::Result<String> {
+        let node = self.rules.get(rule).ok_or_else(|| {
+            anyhow::anyhow!("No rule named {rule:?}")
+        })?;
+        Ok(Rail::from_node(node).to_svg(node.description()))
+    }
+}
+
+/// Layout tree of a diagram
+#[derive(Debug, Clone)]
+enum Rail {
+    Skip,
+    Terminal(String),
+    NonTerm(String),
+    Seq(Vec<Rail>),
+    Choice(Vec<Rail>),
+    Loop { body: Box<Rail>, label: Option<String> },
+}
+
+impl Rail {
+    fn from_node<T: TerminalNode>(node: &Node<T>) -> Self {
+        match node {
+            Node::Seq(elements) if elements.is_empty() => Rail::Skip,
+            Node::Seq(elements) => Rail::Seq(elements.iter().map(Self::from_node).collect()),
+            Node::Alt(branches) => Rail::Choice(branches.iter().map(Self::from_node).collect()),
+            Node::Rep { node, range, .. } => {
+                let body = Self::from_node(node);
+                match (*range.start(), *range.end()) {
+                    (0, 1) => Rail::Choice(vec![Rail::Skip, body]),
+                    (0, usize::MAX) => Rail::Choice(vec![
+                        Rail::Skip,
+                        Rail::Loop { body: Box::new(body), label: None },
+                    ]),
+                    (1, usize::MAX) => Rail::Loop { body: Box::new(body), label: None },
+                    (min, usize::MAX) => Rail::Loop { body: Box::new(body), label: Some(format!("{min}..")) },
+                    (min, max) => Rail::Loop { body: Box::new(body), label: Some(format!("{min}..={max}")) },
+                }
+            }
+            Node::RepSep { node, sep, range } => {
+                let body = Self::from_node(node);
+                let more = Rail::Loop {
+                    body: Box::new(Rail::Seq(vec![Self::from_node(sep), body.clone()])),
+                    label: None,
+                };
+                let list = Rail::Seq(vec![body, Rail::Choice(vec![Rail::Skip, more])]);
+                match *range.start() {
+                    0 => Rail::Choice(vec![Rail::Skip, list]),
+                    _ => list,
+                }
+            }
+            Node::Terminal(t) => Rail::Terminal(t.to_ebnf()),
+            Node::NonTerm(name) => Rail::NonTerm(name.clone()),
+            Node::Tagged { node, .. } => Self::from_node(node),
+            Node::Meta { node, .. } => Self::from_node(node),
+            Node::Empty => Rail::Skip,
+            // a single box, written `node - minus`
+            Node::Except { .. } => Rail::NonTerm(node.to_ebnf()),
+        }
+    }
+
+    /// `(width, height, baseline)` where `baseline` is the height of the track from the top
+    fn size(&self) -> (f64, f64, f64) {
+        match self {
+            Rail::Skip => (0.0, 0.0, 0.0),
+            Rail::Terminal(text) | Rail::NonTerm(text) => {
+                (box_width(text), BOX_HEIGHT, BOX_HEIGHT / 2.0)
+            }
+            Rail::Seq(elements) => {
+                let sizes = elements.iter().map(Rail::size).collect::<Vec<_>>();
+                let width = sizes.iter().map(|s| s.0).sum::<f64>()
+                    + H_GAP * (sizes.len().saturating_sub(1)) as f64;
+                let above = sizes.iter().map(|s| s.2).fold(0.0, f64::max);
+                let below = sizes.iter().map(|s| s.1 - s.2).fold(0.0, f64::max);
+                (width, above + below, above)
+            }
+            Rail::Choice(branches) => {
+                let sizes = branches.iter().map(Rail::size).collect::<Vec<_>>();
+                let width = sizes.iter().map(|s| s.0).fold(0.0, f64::max) + 2.0 * ARC * 2.0;
+                let height = sizes.iter().map(|s| s.1.max(V_GAP)).sum::<f64>()
+                    + V_GAP * (sizes.len().saturating_sub(1)) as f64;
+                let baseline = sizes.first().map_or(0.0, |s| s.2);
+                (width, height, baseline)
+            }
+            Rail::Loop { body, .. } => {
+                let (w, h, b) = body.size();
+                (w + 2.0 * ARC, h + V_GAP + ARC / 2.0, b)
+            }
+        }
+    }
+
+    /// Render with the top-left corner at `(x, y)`
+    fn render(&self, x: f64, y: f64, out: &mut String) {
+        let (width, _, baseline) = self.size();
+        let line = y + baseline;
+        match self {
+            Rail::Skip => {}
+            Rail::Terminal(text) | Rail::NonTerm(text) => {
+                let (class, radius) = match self {
+                    Rail::Terminal(_) => ("terminal", BOX_HEIGHT / 2.0),
+                    _ => ("non-terminal", 0.0),
+                };
+                let _ = writeln!(
+                    out,
+                    r#"<rect class="{class}" x="{x}" y="{y}" width="{width}" height="{BOX_HEIGHT}" rx="{radius}"/>"#,
+                );
+                let _ = writeln!(
+                    out,
+                    r#"<text x="{}" y="{line}">{}</text>"#,
+                    x + width / 2.0,
+                    escape(text),
+                );
+            }
+            Rail::Seq(elements) => {
+                let mut cx = x;
+                for (i, element) in elements.iter().enumerate() {
+                    let (w, _, b) = element.size();
+                    if i > 0 {
+                        hline(out, cx, line, H_GAP);
+                        cx += H_GAP;
+                    }
+                    element.render(cx, line - b, out);
+                    cx += w;
+                }
+            }
+            Rail::Choice(branches) => {
+                let inner = width - 4.0 * ARC;
+                let mut cy = y;
+                for branch in branches {
+                    let (w, h, b) = branch.size();
+                    let track = cy + b;
+                    let left = x + 2.0 * ARC;
+                    if track == line {
+                        hline(out, x, line, 2.0 * ARC);
+                        hline(out, left + inner, line, 2.0 * ARC);
+                    } else {
+                        curve(out, x, line, left, track);
+                        curve(out, x + width, line, left + inner, track);
+                    }
+                    branch.render(left + (inner - w) / 2.0, cy, out);
+                    hline(out, left, track, (inner - w) / 2.0);
+                    hline(out, left + (inner + w) / 2.0, track, (inner - w) / 2.0);
+                    cy += h.max(V_GAP) + V_GAP;
+                }
+            }
+            Rail::Loop { body, label } => {
+                let (w, h, _) = body.size();
+                hline(out, x, line, ARC);
+                hline(out, x + ARC + w, line, ARC);
+                body.render(x + ARC, y, out);
+                let bottom = y + h + V_GAP;
+                let _ = writeln!(
+                    out,
+                    r#"<path d="M{} {line} Q{} {line} {} {} L{} {} Q{} {bottom} {} {bottom} L{} {bottom} Q{} {bottom} {} {} L{} {} Q{} {line} {} {line}"/>"#,
+                    x + ARC + w, x + 2.0 * ARC + w, x + 2.0 * ARC + w, line + ARC / 2.0,
+                    x + 2.0 * ARC + w, bottom - ARC / 2.0, x + 2.0 * ARC + w, x + ARC + w,
+                    x + ARC, x, x, bottom - ARC / 2.0,
+                    x, line + ARC / 2.0, x, x + ARC,
+                );
+                if let Some(label) = label {
+                    let _ = writeln!(
+                        out,
+                        r#"<text class="label" x="{}" y="{}">{}</text>"#,
+                        x + ARC + w / 2.0,
+                        bottom + ARC / 2.0,
+                        escape(label),
+                    );
+                }
+            }
+        }
+    }
+
+    fn to_svg(&self, title: Option<&str>) -> String {
+        let (w, h, b) = self.size();
+        let width = w + 2.0 * MARGIN + 2.0 * H_GAP;
+        let height = h + 2.0 * MARGIN + V_GAP;
+        let line = MARGIN + b;
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#,
+        );
+        if let Some(title) = title {
+            let _ = writeln!(out, "<title>{}</title>", escape(title));
+        }
+        let _ = writeln!(out, "<style>\n{STYLE}\n</style>");
+        // start and end markers
+        let _ = writeln!(out, r#"<path d="M{} {} v{}"/>"#, MARGIN, line - 8.0, 16.0);
+        let _ = writeln!(out, r#"<path d="M{} {} v{}"/>"#, width - MARGIN, line - 8.0, 16.0);
+        hline(&mut out, MARGIN, line, H_GAP);
+        self.render(MARGIN + H_GAP, MARGIN, &mut out);
+        hline(&mut out, MARGIN + H_GAP + w, line, H_GAP);
+        out.push_str("</svg>\n");
+        out
+    }
+}
+
+fn box_width(text: &str) -> f64 {
+    text.chars().count() as f64 * CHAR_WIDTH + 2.0 * BOX_PADDING
+}
+
+fn hline(out: &mut String, x: f64, y: f64, length: f64) {
+    if length > 0.0 {
+        let _ = writeln!(out, r#"<path d="M{x} {y} h{length}"/>"#);
+    }
+}
+
+/// S-shaped connection between two tracks
+fn curve(out: &mut String, x1: f64, y1: f64, x2: f64, y2: f64) {
+    let mx = (x1 + x2) / 2.0;
+    let _ = writeln!(out, r#"<path d="M{x1} {y1} C{mx} {y1} {mx} {y2} {x2} {y2}"/>"#);
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::basic::Text;
+
+    use super::*;
+
+    #[test]
+    fn railroad_svg() {
+        let grammar = Grammar::<Text>::load_ebnf(r#"
+            expression = term , (("+" | "-") , term)*;
+            term = "<" , name , ">";
+        "#).unwrap();
+
+        let svg = grammar.to_railroad_svg("expression").unwrap();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.ends_with("</svg>\n"));
+        // two non-terminal boxes and two terminal boxes
+        assert_eq!(svg.matches(r#"class="non-terminal""#).count(), 2);
+        assert_eq!(svg.matches(r#"class="terminal""#).count(), 2);
+        assert!(svg.contains(">term</text>"));
+        assert!(svg.contains(">&quot;+&quot;</text>"));
+
+        let svg = grammar.to_railroad_svg("term").unwrap();
+        assert!(svg.contains(">&quot;&lt;&quot;</text>"));
+
+        assert!(!svg.contains("<title>"));
+        assert!(grammar.to_railroad_svg("missing").is_err());
+
+        let grammar = crate::grammar! {
+            /// Angle <brackets>
+            term = ("<", name, ">");
+        };
+        let svg = grammar.to_railroad_svg("term").unwrap();
+        assert!(svg.contains("<title>Angle &lt;brackets&gt;</title>"));
+    }
+}
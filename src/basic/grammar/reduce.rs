@@ -0,0 +1,147 @@
+//! Reducing token trees to values, rule by rule
+
+use alloc::collections::BTreeMap;
+use core::fmt;
+
+use serde_json::Value;
+
+use crate::basic::{TerminalNode, Token};
+
+use super::Grammar;
+
+type Reduction<'a> = dyn Fn(&Token, &str, Vec<Value>) -> Value + 'a;
+
+/// Semantic actions turning the tokens of some rules into values
+///
+/// Each reduction receives the token of its rule, the text of the token and
+/// the values of its nearest reduced descendants, in source order. Tokens of
+/// other rules are transparent: they pass the values of their descendants to
+/// their ancestors. Reductions run bottom-up on the final tree, so the tokens
+/// of discarded alternatives are never reduced.
+#[derive(Default)]
+pub struct Reducer<'a> {
+    reductions: BTreeMap<String, Box<Reduction<'a>>>,
+}
+
+impl fmt::Debug for Reducer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reducer").field("rules", &self.reductions.keys()).finish()
+    }
+}
+
+impl<'a> Reducer<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reduce the tokens of the rule `gram` with `f`, replacing any previous reduction
+    pub fn rule(
+        mut self,
+        gram: impl Into<String>,
+        f: impl Fn(&Token, &str, Vec<Value>) -> Value + 'a,
+    ) -> Self {
+        self.reductions.insert(gram.into(), Box::new(f));
+        self
+    }
+
+    /// The values of `token` parsed from `src`
+    ///
+    /// A single value if the rule of `token` has a reduction, otherwise the
+    /// values of its nearest reduced descendants.
+    pub fn reduce(&self, token: &Token, src: &str) -> anyhow::Result<Vec<Value>> {
+        let mut values = vec![];
+        for child in &token.children {
+            values.extend(self.reduce(child, src)?);
+        }
+        match token.gram.as_deref().and_then(|gram| self.reductions.get(gram)) {
+            Some(f) => Ok(vec![f(token, token.span_str(src)?, values)]),
+            None => Ok(values),
+        }
+    }
+}
+
+impl<T: TerminalNode<Src = str>> Grammar<T> {
+    /// Parse `src` from the rule `gram` and reduce it with `reducer`
+    ///
+    /// The value of the rule if it has a reduction, otherwise the array of
+    /// the values of its nearest reduced descendants, see [`Reducer::reduce`].
+    /// As for [`Grammar::parse_into`], it is an error if the rule does not
+    /// match or if there are diagnostics.
+    pub fn parse_reduced(&self, gram: &str, src: &str, reducer: &Reducer) -> anyhow::Result<Value> {
+        let (mut tok, diagnostics) = self.parse_non_term(gram, src)?
+            .ok_or_else(|| anyhow::anyhow!("Failed to parse {gram:?}"))?;
+        if !diagnostics.is_empty() {
+            let messages = diagnostics.iter().map(|d| d.message()).collect::<Vec<_>>();
+            anyhow::bail!("Failed to parse {gram:?}: {}", messages.join(", "));
+        }
+        // the root is the token of the rule, unnamed like any body
+        tok.gram = Some(gram.to_string());
+        let mut values = reducer.reduce(&tok, src)?;
+        if reducer.reductions.contains_key(gram) {
+            Ok(values.remove(0))
+        } else {
+            Ok(Value::Array(values))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::basic::Text;
+
+    use super::*;
+
+    fn grammar() -> Grammar<Text> {
+        Grammar::load_ebnf(r#"
+            sum = term , (op , term)*;
+            term = number | ("(" , sum , ")");
+            op = "+" | "-";
+            number = #'[0-9]+';
+        "#).unwrap()
+    }
+
+    fn reducer() -> Reducer<'static> {
+        Reducer::new()
+            .rule("number", |_, text, _| json!(text.parse::<i64>().unwrap()))
+            .rule("op", |_, text, _| json!(text))
+            .rule("sum", |_, _, values| {
+                let mut values = values.into_iter();
+                let mut lhs = values.next().unwrap();
+                while let (Some(op), Some(rhs)) = (values.next(), values.next()) {
+                    lhs = json!({ "op": op, "lhs": lhs, "rhs": rhs });
+                }
+                lhs
+            })
+    }
+
+    #[test]
+    fn reduce() {
+        let grammar = grammar();
+        let reducer = reducer();
+
+        assert_eq!(grammar.parse_reduced("sum", "42", &reducer).unwrap(), json!(42));
+        assert_eq!(
+            grammar.parse_reduced("sum", "1+(2-3)-4", &reducer).unwrap(),
+            json!({
+                "op": "-",
+                "lhs": { "op": "+", "lhs": 1, "rhs": { "op": "-", "lhs": 2, "rhs": 3 } },
+                "rhs": 4,
+            }),
+        );
+
+        // `term` has no reduction, its value is the one of the nested sum
+        assert_eq!(
+            grammar.parse_reduced("term", "(1+2)", &reducer).unwrap(),
+            json!([{ "op": "+", "lhs": 1, "rhs": 2 }]),
+        );
+        let (token, _) = grammar.parse_non_term("sum", "(1+2)").unwrap().unwrap();
+        assert_eq!(reducer.reduce(&token, "(1+2)").unwrap(), [json!({ "op": "+", "lhs": 1, "rhs": 2 })]);
+        assert_eq!(Reducer::new().reduce(&token, "(1+2)").unwrap(), Vec::<Value>::new());
+        assert!(reducer.reduce(&token, "1").is_err());
+
+        assert!(grammar.parse_reduced("sum", "1+", &reducer).is_err());
+        assert!(grammar.parse_reduced("sum", "x", &reducer).is_err());
+    }
+}
@@ -0,0 +1,327 @@
+use std::{iter::Peekable, str::CharIndices};
+
+use crate::basic::{text, Text};
+
+/// Lex the terminals of an EBNF `source`, as written by [`Text::to_ebnf`]
+///
+/// The `ebnf` crate keeps string escapes verbatim and does not know the `/re/`
+/// form, so terminals are lexed here: each one is replaced by a `"N"`
+/// placeholder, `N` being its index in the returned terminals. `(* *)`
+/// comments are dropped.
+///
+/// - `"..."` and `'...'` are literals, with Rust escapes
+/// - `/.../` is a regex, `\/` escaping a `/`, followed by its `ims` flags
+/// - `#"..."` and `#'...'` are regexes, taken verbatim
+/// - `` `...` `` is a keyword, followed by its identifier chars in `[...]` if not the default
+pub(super) fn extract_terminals(source: &str) -> anyhow::Result<(String, Vec<Text>, Offsets)> {
+    let mut out = String::with_capacity(source.len());
+    let mut terminals = vec![];
+    let mut offsets = Offsets::default();
+    let mut chars = source.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let terminal = match c {
+            '"' | '\'' => Text::String(literal(&mut chars, c)?),
+            '/' => {
+                let pattern = slash_regex(&mut chars)?;
+                let mut flags = String::new();
+                while let Some((_, c)) = chars.next_if(|(_, c)| matches!(c, 'i' | 'm' | 's')) {
+                    flags.push(c);
+                }
+                Text::regex_with_flags(pattern, &flags)?
+            }
+            '`' => {
+                let Some((keyword, len)) = text::split_keyword(&source[i..]) else {
+                    anyhow::bail!("Invalid keyword at {i}");
+                };
+                while chars.next_if(|(j, _)| *j < i + len).is_some() {}
+                keyword
+            }
+            '#' if matches!(chars.peek(), Some((_, '"' | '\''))) => {
+                let (_, quote) = chars.next().unwrap();
+                Text::Regex(verbatim(&mut chars, quote)?)
+            }
+            '(' if chars.next_if(|(_, c)| *c == '*').is_some() => {
+                let Some(end) = source[i + 2..].find("*)") else {
+                    anyhow::bail!("Unterminated comment at {i}");
+                };
+                while chars.next_if(|(j, _)| *j < i + 2 + end + 2).is_some() {}
+                out.push(' ');
+                offsets.anchor(out.len(), i + 2 + end + 2);
+                continue;
+            }
+            c => {
+                out.push(c);
+                continue;
+            }
+        };
+        out.push_str(&format!("\"{}\"", terminals.len()));
+        terminals.push(terminal);
+        offsets.anchor(out.len(), chars.peek().map_or(source.len(), |(j, _)| *j));
+    }
+    Ok((out, terminals, offsets))
+}
+
+/// The positions in a source of the positions in its rewrite by [`extract_terminals`]
+#[derive(Debug, Default)]
+pub(super) struct Offsets {
+    /// The positions after each placeholder or comment, in the rewrite and in the source
+    anchors: Vec<(usize, usize)>,
+}
+
+impl Offsets {
+    fn anchor(&mut self, pos: usize, original: usize) {
+        self.anchors.push((pos, original));
+    }
+
+    /// The position in the source of `pos` in the rewrite, which is not inside a placeholder
+    pub(super) fn original(&self, pos: usize) -> usize {
+        match self.anchors.partition_point(|&(anchor, _)| anchor <= pos) {
+            0 => pos,
+            i => {
+                let (anchor, original) = self.anchors[i - 1];
+                original + pos - anchor
+            }
+        }
+    }
+}
+
+/// Rewrite the exceptions `a - b` of a `source` with extracted terminals as `(a , "-" , b)`
+///
+/// The `ebnf` crate has no `-` operator, so `load_ebnf` turns the `"-"`
+/// marker, which cannot be a placeholder, back into an exception. As in ISO
+/// EBNF, `-` binds tighter than concatenation and alternation: its operands
+/// are a name, a terminal or a bracketed group, with their postfix operators,
+/// and `a - b - c` is `(a - b) - c`.
+///
+/// Errors give the position of the `-` in the original source, as mapped
+/// back by `original` from its position in `source`.
+pub(super) fn extract_exceptions(source: &str, original: impl Fn(usize) -> usize) -> anyhow::Result<String> {
+    // the rewrites only add markers, so the `-` operators are the ones of `source`
+    let mut operators = source.match_indices('-').map(|(i, _)| original(i));
+    let mut source = source.to_string();
+    // past the last marker
+    let mut from = 0;
+    while let Some(minus) = source[from..].find('-').map(|i| from + i) {
+        let at = operators.next().unwrap_or_default();
+        let (before, after) = (&source[..minus], &source[minus + 1..]);
+        let Some(start) = operand_start(before) else {
+            anyhow::bail!("Missing operand before `-` at {at}");
+        };
+        let Some(len) = operand_len(after) else {
+            anyhow::bail!("Missing operand after `-` at {at}");
+        };
+        let (left, right) = (before[start..].trim(), after[..len].trim());
+        let marked = format!("{}({left} , \"-\"", &before[..start]);
+        from = marked.len();
+        source = format!("{marked} , {right}){}", &after[len..]);
+    }
+    Ok(source)
+}
+
+/// Rewrite the lazy repetitions `a*?`, `a+?` and `a??` of a `source` with extracted terminals as `(a* , "?")`
+///
+/// The `ebnf` crate has no lazy operators, so `load_ebnf` turns the `"?"`
+/// marker, which cannot be a placeholder, back into a lazy repetition. The
+/// operand is found as for [`extract_exceptions`].
+pub(super) fn extract_lazy_repetitions(source: &str) -> String {
+    let mut source = source.to_string();
+    // past the last marker
+    let mut from = 0;
+    while let Some(lazy) = source[from..].find('?').map(|i| from + i) {
+        let before = &source[..lazy];
+        let Some(start) = operand_start(before).filter(|_| before.ends_with(['*', '+', '?'])) else {
+            from = lazy + 1;
+            continue;
+        };
+        let marked = format!("{}({} , \"?\")", &before[..start], &before[start..]);
+        from = marked.len();
+        source = format!("{marked}{}", &source[lazy + 1..]);
+    }
+    source
+}
+
+/// The marker of the `.` of qualified names, see [`escape_qualified_names`]
+const DOT: &str = "__dot__";
+
+/// The position before [`escape_qualified_names`] of `pos` in its rewrite `escaped`
+pub(super) fn unescaped_pos(escaped: &str, pos: usize) -> usize {
+    pos - escaped[..pos].matches(DOT).count() * (DOT.len() - 1)
+}
+
+/// Rewrite the qualified names `ns.name` of a `source` with extracted terminals
+///
+/// The `ebnf` crate has no `.` in names, so each `.` between name chars is
+/// replaced by a marker that [`unescape_name`] turns back into a `.`. Names
+/// cannot contain the marker themselves.
+pub(super) fn escape_qualified_names(source: &str) -> anyhow::Result<String> {
+    if source.contains(DOT) {
+        anyhow::bail!("Names cannot contain {DOT:?}");
+    }
+    let mut out = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut prev = None;
+    while let Some(c) = chars.next() {
+        if c == '.' && prev.is_some_and(is_name_char) && chars.peek().is_some_and(|&c| is_name_char(c)) {
+            out.push_str(DOT);
+        } else {
+            out.push(c);
+        }
+        prev = Some(c);
+    }
+    Ok(out)
+}
+
+/// A name of a source rewritten by [`escape_qualified_names`], as it was written
+pub(super) fn unescape_name(name: String) -> String {
+    if name.contains(DOT) { name.replace(DOT, ".") } else { name }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The start of the operand at the end of `before`, if any
+fn operand_start(before: &str) -> Option<usize> {
+    let before = before.trim_end().trim_end_matches(['*', '+', '?']);
+    match before.chars().next_back()? {
+        // a `"N"` placeholder
+        '"' => before[..before.len() - 1].rfind('"'),
+        ')' | ']' | '}' => {
+            let mut depth = 0;
+            for (i, c) in before.char_indices().rev() {
+                match c {
+                    ')' | ']' | '}' => depth += 1,
+                    '(' | '[' | '{' => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            None
+        }
+        c if is_name_char(c) => Some(before.rfind(|c| !is_name_char(c)).map_or(0, |i| i + 1)),
+        _ => None,
+    }
+}
+
+/// The length of the operand at the start of `after`, leading spaces included, if any
+fn operand_len(after: &str) -> Option<usize> {
+    let operand = after.trim_start();
+    let mut len = match operand.chars().next()? {
+        '"' => operand[1..].find('"')? + 2,
+        '(' | '[' | '{' => {
+            let mut depth = 0;
+            let close = operand.char_indices().find(|&(_, c)| {
+                match c {
+                    '(' | '[' | '{' => depth += 1,
+                    ')' | ']' | '}' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })?;
+            close.0 + 1
+        }
+        c if is_name_char(c) => operand.find(|c| !is_name_char(c)).unwrap_or(operand.len()),
+        _ => return None,
+    };
+    len += operand[len..].len() - operand[len..].trim_start_matches(['*', '+', '?']).len();
+    Some(after.len() - operand.len() + len)
+}
+
+fn literal(chars: &mut Peekable<CharIndices>, quote: char) -> anyhow::Result<String> {
+    let mut s = String::new();
+    loop {
+        let c = match chars.next() {
+            Some((_, c)) if c == quote => return Ok(s),
+            Some((_, '\\')) => match chars.next() {
+                Some((_, 'n')) => '\n',
+                Some((_, 'r')) => '\r',
+                Some((_, 't')) => '\t',
+                Some((_, '0')) => '\0',
+                Some((_, c @ ('\\' | '"' | '\''))) => c,
+                Some((_, 'u')) if chars.next_if(|(_, c)| *c == '{').is_some() => {
+                    let mut hex = String::new();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| *c != '}') {
+                        hex.push(c);
+                    }
+                    chars.next();
+                    u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| anyhow::anyhow!("Invalid unicode escape \\u{{{hex}}}"))?
+                }
+                Some((i, c)) => anyhow::bail!("Invalid escape \\{c} at {i}"),
+                None => anyhow::bail!("Unterminated string"),
+            },
+            Some((_, c)) => c,
+            None => anyhow::bail!("Unterminated string"),
+        };
+        s.push(c);
+    }
+}
+
+fn slash_regex(chars: &mut Peekable<CharIndices>) -> anyhow::Result<String> {
+    let mut re = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '/')) => return Ok(re),
+            Some((_, '\\')) if chars.next_if(|(_, c)| *c == '/').is_some() => re.push('/'),
+            Some((_, '\\')) => {
+                re.push('\\');
+                if let Some((_, c)) = chars.next() {
+                    re.push(c);
+                }
+            }
+            Some((_, c)) => re.push(c),
+            None => anyhow::bail!("Unterminated regex"),
+        }
+    }
+}
+
+fn verbatim(chars: &mut Peekable<CharIndices>, quote: char) -> anyhow::Result<String> {
+    let mut re = String::new();
+    loop {
+        match chars.next() {
+            Some((_, c)) if c == quote => return Ok(re),
+            Some((_, '\\')) => {
+                re.push('\\');
+                if let Some((_, c)) = chars.next() {
+                    re.push(c);
+                }
+            }
+            Some((_, c)) => re.push(c),
+            None => anyhow::bail!("Unterminated regex"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_terminals() {
+        let src = r#"a = "x\"\n" , /[a\/b]+/ (* comment *) | #'\d' , '/' | /a/i | `end` | `end`[a-z-];"#;
+        let (out, terminals, offsets) = super::extract_terminals(src).unwrap();
+        assert_eq!(out, r#"a = "0" , "1"   | "2" , "3" | "4" | "5" | "6";"#);
+        assert_eq!(terminals, vec![
+            Text::String("x\"\n".to_string()),
+            Text::Regex("[a/b]+".to_string()),
+            Text::Regex("\\d".to_string()),
+            Text::String("/".to_string()),
+            Text::regex_with_flags("a", "i").unwrap(),
+            Text::keyword("end"),
+            Text::keyword_with("end", "a-z-").unwrap(),
+        ]);
+        assert_eq!(offsets.original(out.find('|').unwrap()), src.find('|').unwrap());
+        assert_eq!(offsets.original(out.len() - 1), src.len() - 1);
+        assert_eq!(offsets.original(1), 1);
+
+        assert!(super::extract_terminals(r#"a = "x"#).is_err());
+        assert!(super::extract_terminals(r#"a = "\q";"#).is_err());
+        assert!(super::extract_terminals("a = (* x").is_err());
+        assert!(super::extract_terminals("a = `end").is_err());
+        assert!(super::extract_terminals("a = `end`[z-a];").is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use core::ops::Range;
+
+use crate::{basic::TerminalNode, prelude::*};
+
+use super::Grammar;
+
+impl<T: TerminalNode<Src = str>> Grammar<T> {
+    /// Split `src` into a flat stream of `(rule, span)` tokens, like a classic lexer
+    ///
+    /// At each position the rule with the longest clean match wins, ties going
+    /// to the first rule by name. See [`Grammar::tokenize_with`] to pick the
+    /// rules and their priority.
+    pub fn tokenize<'g>(&'g self, src: &str) -> anyhow::Result<Vec<(&'g str, Range<usize>)>> {
+        let rules = self.rules.keys().map(String::as_str).collect::<Vec<_>>();
+        self.tokenize_with(&rules, src)
+    }
+
+    /// Same as [`Grammar::tokenize`], only matching `rules`, ties going to the first one
+    ///
+    /// Matches that are empty, incomplete or report diagnostics are ignored, it
+    /// is an error if no rule matches at some position.
+    pub fn tokenize_with<'g>(
+        &'g self,
+        rules: &[&str],
+        src: &str,
+    ) -> anyhow::Result<Vec<(&'g str, Range<usize>)>> {
+        let rules = rules
+            .iter()
+            .map(|&name| {
+                self.rules
+                    .get_key_value(name)
+                    .map(|(name, _)| name.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("No rule for non-terminal {name:?}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut tokens = vec![];
+        let mut pos = 0;
+        while pos < src.len() {
+            let mut best: Option<(&str, usize)> = None;
+            for &rule in &rules {
+                if let Some((token, diagnostics)) = self.parse_non_term_at(rule, src, pos)?
+                    && diagnostics.is_empty()
+                    && token.span.end > best.map_or(pos, |(_, end)| end)
+                {
+                    best = Some((rule, token.span.end));
+                }
+            }
+            let Some((rule, end)) = best else {
+                anyhow::bail!("No token matches at {pos}");
+            };
+            tokens.push((rule, pos..end));
+            pos = end;
+        }
+        Ok(tokens)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::basic::Text;
+
+    use super::*;
+
+    #[test]
+    fn tokenize() {
+        let grammar: Grammar<Text> = Grammar::load_ebnf(r#"
+            keyword = "let" | "in";
+            ident = #'[a-z]+';
+            number = #'[0-9]+';
+            op = "=" | "+" | "==";
+            ws = #'[ ]+';
+        "#).unwrap();
+
+        let src = "let x == 1+ in";
+        fn kinds<'a>(src: &'a str, tokens: Vec<(&'a str, Range<usize>)>) -> Vec<(&'a str, &'a str)> {
+            tokens.into_iter().map(|(rule, span)| (rule, &src[span])).collect()
+        }
+
+        // rules by name: `ident` wins the ties with `keyword`
+        assert_eq!(kinds(src, grammar.tokenize(src).unwrap()), [
+            ("ident", "let"), ("ws", " "), ("ident", "x"), ("ws", " "), ("op", "=="),
+            ("ws", " "), ("number", "1"), ("op", "+"), ("ws", " "), ("ident", "in"),
+        ]);
+
+        let rules = ["keyword", "ident", "number", "op", "ws"];
+        assert_eq!(kinds(src, grammar.tokenize_with(&rules, src).unwrap()), [
+            ("keyword", "let"), ("ws", " "), ("ident", "x"), ("ws", " "), ("op", "=="),
+            ("ws", " "), ("number", "1"), ("op", "+"), ("ws", " "), ("keyword", "in"),
+        ]);
+        // the longest match wins over priority
+        assert_eq!(kinds("inx", grammar.tokenize_with(&rules, "inx").unwrap()), [("ident", "inx")]);
+
+        let err = grammar.tokenize("let ?").unwrap_err();
+        assert_eq!(err.to_string(), "No token matches at 4");
+        assert!(grammar.tokenize_with(&["nope"], src).is_err());
+        assert_eq!(grammar.tokenize("").unwrap(), []);
+    }
+}
@@ -0,0 +1,373 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+
+#[cfg(feature = "std")]
+use serde::{Deserialize, Deserializer};
+
+#[cfg(feature = "std")]
+use crate::basic::Text;
+use crate::{basic::{Node, TerminalNode}, prelude::*};
+
+use super::Grammar;
+
+/// A problem found by [`Grammar::check`] or [`Grammar::check_left_recursion`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ValidationError {
+    /// `start` or one of the [entry points](Grammar::entry_points) names a rule that does not exist
+    UndefinedStart(String),
+    /// `rule` references a non-terminal that does not exist
+    UndefinedReference { rule: String, reference: String },
+    /// `rule` contains an `Alt` without branches, which can never match
+    EmptyAlt { rule: String },
+    /// The rules in `cycle` can reach themselves without consuming input
+    ///
+    /// Only reported by [`Grammar::check_left_recursion`]: the parser grows
+    /// left recursive rules from a seed, but [`Grammar::parse_all`] rejects
+    /// them.
+    LeftRecursion { cycle: Vec<String> },
+    /// `rule` repeats a node that can match the empty string
+    ///
+    /// The parser stops such a repetition at the first empty match, repeated
+    /// up to the minimum number of matches, whatever it is, but this
+    /// usually hints at a mistake in the grammar, such as `(a?)*`.
+    NullableRepetition { rule: String },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::UndefinedStart(name) => write!(f, "start rule {name:?} is not defined"),
+            ValidationError::UndefinedReference { rule, reference } => {
+                write!(f, "rule {rule:?} references undefined rule {reference:?}")
+            }
+            ValidationError::EmptyAlt { rule } => write!(f, "rule {rule:?} contains an empty choice"),
+            ValidationError::LeftRecursion { cycle } => write!(f, "left recursion: {}", cycle.join(" -> ")),
+            ValidationError::NullableRepetition { rule } => {
+                write!(f, "rule {rule:?} repeats a node that can match the empty string")
+            }
+        }
+    }
+}
+
+/// An invalid pattern found by [`Grammar::check_regex_terminals`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RegexError {
+    /// The rule containing the terminal
+    pub rule: String,
+    pub pattern: String,
+    /// The error of the regex compiler
+    pub message: String,
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rule {:?}: {}", self.rule, self.message)
+    }
+}
+
+impl<T: TerminalNode> Grammar<T> {
+    /// Run the static checks on the grammar, returning all the problems found
+    ///
+    /// Note that an empty `Seq` is valid and matches the empty string.
+    pub fn check(&self) -> Vec<ValidationError> {
+        let mut errors = vec![];
+
+        for start in self.entry_points() {
+            if !self.rules.contains_key(start) {
+                errors.push(ValidationError::UndefinedStart(start.to_string()));
+            }
+        }
+
+        let nullable = self.nullable_rules();
+        for (name, node) in &self.rules {
+            let mut reported = BTreeSet::new();
+            let mut empty_alt = false;
+            let mut nullable_rep = false;
+            node.walk(&mut |n| match n {
+                Node::NonTerm(reference)
+                    if !self.rules.contains_key(reference) && reported.insert(reference.clone()) =>
+                {
+                    errors.push(ValidationError::UndefinedReference {
+                        rule: name.clone(),
+                        reference: reference.clone(),
+                    });
+                }
+                Node::Alt(branches) if branches.is_empty() => empty_alt = true,
+                Node::Rep { node, .. } if node.is_nullable(&nullable) => nullable_rep = true,
+                Node::RepSep { node, sep, .. }
+                    if node.is_nullable(&nullable) && sep.is_nullable(&nullable) => nullable_rep = true,
+                _ => {}
+            });
+            if empty_alt {
+                errors.push(ValidationError::EmptyAlt { rule: name.clone() });
+            }
+            if nullable_rep {
+                errors.push(ValidationError::NullableRepetition { rule: name.clone() });
+            }
+        }
+
+        errors
+    }
+
+    /// The cycles of rules that can reach themselves without consuming input
+    ///
+    /// Not part of [`Grammar::check`], since the parser handles them, but
+    /// worth running on grammars meant for [`Grammar::parse_all`].
+    pub fn check_left_recursion(&self) -> Vec<ValidationError> {
+        self.left_recursion_cycles()
+            .into_iter()
+            .map(|cycle| ValidationError::LeftRecursion { cycle })
+            .collect()
+    }
+
+    /// Same as [`Grammar::check`], but combines all the problems into a single error
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let errors = self.check();
+        if errors.is_empty() {
+            return Ok(());
+        }
+        let list = errors.iter().map(|e| format!("  - {e}")).collect::<Vec<_>>().join("\n");
+        Err(anyhow::anyhow!("Invalid grammar:\n{list}"))
+    }
+
+    /// Deserialize a grammar and [`validate`](Grammar::validate) it
+    ///
+    /// Works with any serde format, for example
+    /// `Grammar::deserialize_validated(serde_yaml::Deserializer::from_str(src))`.
+    #[cfg(feature = "std")]
+    pub fn deserialize_validated<'de, D>(deserializer: D) -> anyhow::Result<Self>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+        D::Error: Send + Sync + 'static,
+    {
+        let grammar = Self::deserialize(deserializer)?;
+        grammar.validate()?;
+        Ok(grammar)
+    }
+
+    /// Cycles in the graph of references that can be reached without consuming input
+    fn left_recursion_cycles(&self) -> Vec<Vec<String>> {
+        let nullable = self.nullable_rules();
+        let graph = self.rules
+            .iter()
+            .map(|(name, node)| {
+                let mut refs = BTreeSet::new();
+                left_references(node, &nullable, &mut refs);
+                (name.as_str(), refs)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let mut cycles = vec![];
+        let mut done = BTreeSet::new();
+        for &name in graph.keys() {
+            let mut path = vec![];
+            find_cycles(name, &graph, &mut path, &mut done, &mut cycles);
+        }
+        cycles
+    }
+}
+
+#[cfg(feature = "std")]
+impl Grammar<Text> {
+    /// Compile every regex terminal once, returning all the invalid patterns
+    ///
+    /// Regexes are otherwise compiled when the parser reaches them, so a bad
+    /// pattern in a grammar loaded from an untrusted source only fails the
+    /// inputs that get there. Each pattern is reported once per rule, in the
+    /// order of the rules. Complements [`Grammar::check`], which does not
+    /// know about the terminals.
+    pub fn check_regex_terminals(&self) -> Result<(), Vec<RegexError>> {
+        let mut errors = vec![];
+        for (name, node) in &self.rules {
+            let mut reported = BTreeSet::new();
+            node.walk(&mut |n| {
+                if let Node::Terminal(t @ (Text::Regex(pattern) | Text::RegexFlags { pattern, .. })) = n
+                    && let Err(e) = t.regex()
+                    && reported.insert(pattern.clone())
+                {
+                    errors.push(RegexError {
+                        rule: name.clone(),
+                        pattern: pattern.clone(),
+                        message: e.to_string(),
+                    });
+                }
+            });
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+/// Collect the non-terminals that can appear in leftmost position
+///
+/// Returns whether the node can match without consuming input, according to
+/// the `nullable` rules.
+fn left_references<'a, T: TerminalNode>(
+    node: &'a Node<T>,
+    nullable: &BTreeSet<String>,
+    refs: &mut BTreeSet<&'a str>,
+) -> bool {
+    match node {
+        Node::Seq(elements) => {
+            for element in elements {
+                if !left_references(element, nullable, refs) {
+                    return false;
+                }
+            }
+            true
+        }
+        Node::Alt(branches) => {
+            let mut any_nullable = false;
+            for branch in branches {
+                any_nullable |= left_references(branch, nullable, refs);
+            }
+            any_nullable
+        }
+        Node::Rep { node, range, .. } => left_references(node, nullable, refs) || *range.start() == 0,
+        Node::RepSep { node, range, .. } => left_references(node, nullable, refs) || *range.start() == 0,
+        Node::Terminal(t) => t.is_nullable(),
+        Node::NonTerm(name) => {
+            refs.insert(name);
+            nullable.contains(name)
+        }
+        Node::Tagged { node, .. } => left_references(node, nullable, refs),
+        Node::Meta { node, .. } => left_references(node, nullable, refs),
+        Node::Empty => true,
+        // `minus` is parsed at the same position as the node
+        Node::Except { node, minus } => {
+            left_references(minus, nullable, refs);
+            left_references(node, nullable, refs)
+        }
+    }
+}
+
+fn find_cycles<'a>(
+    name: &'a str,
+    graph: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+    path: &mut Vec<&'a str>,
+    done: &mut BTreeSet<&'a str>,
+    cycles: &mut Vec<Vec<String>>,
+) {
+    if let Some(i) = path.iter().position(|n| *n == name) {
+        let mut cycle = path[i..].iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        cycle.push(name.to_string());
+        cycles.push(cycle);
+        return;
+    }
+    if done.contains(name) {
+        return;
+    }
+    path.push(name);
+    for next in graph.get(name).into_iter().flatten() {
+        find_cycles(next, graph, path, done, cycles);
+    }
+    path.pop();
+    done.insert(name);
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{basic::Text, gram};
+
+    use super::*;
+
+    #[test]
+    fn check() {
+        let mut grammar = Grammar::<Text>::load_ebnf(r#"
+            expression = expression , "+" , term | term;
+            term = [sign] , factor;
+            factor = number | "(" , expression , ")";
+        "#).unwrap();
+        grammar.start = Some("main".to_string());
+        grammar.rules.insert("sign".to_string(), Node::Alt(vec![]));
+
+        let errors = grammar.check();
+        assert_eq!(errors, vec![
+            ValidationError::UndefinedStart("main".to_string()),
+            ValidationError::UndefinedReference {
+                rule: "factor".to_string(),
+                reference: "number".to_string(),
+            },
+            ValidationError::EmptyAlt { rule: "sign".to_string() },
+        ]);
+        assert!(grammar.validate().is_err());
+        assert_eq!(grammar.check_left_recursion(), vec![ValidationError::LeftRecursion {
+            cycle: vec!["expression".to_string(), "expression".to_string()],
+        }]);
+
+        let grammar = Grammar::<Text>::load_ebnf(r#"
+            e = (e , "+" , t) | t;
+            t = "x";
+        "#).unwrap();
+        grammar.validate().unwrap();
+        assert!(grammar.parse_non_term("e", "x+x").unwrap().unwrap().1.is_empty());
+    }
+
+    #[test]
+    fn indirect_left_recursion() {
+        let mut grammar = Grammar::<Text>::new();
+        grammar.add_element("a", gram!((("x"?), b))).unwrap();
+        grammar.add_element("b", gram!((a | "y"))).unwrap();
+        assert_eq!(grammar.check_left_recursion(), vec![ValidationError::LeftRecursion {
+            cycle: vec!["a".to_string(), "b".to_string(), "a".to_string()],
+        }]);
+    }
+
+    #[test]
+    fn nullable_repetition() {
+        let mut grammar = Grammar::<Text>::new();
+        grammar.add_element("a", gram!(("x"?)*)).unwrap();
+        grammar.add_element("b", gram!(((ws %% ","), ("y" %% ws)))).unwrap();
+        grammar.add_element("c", gram!(ws %% (","?))).unwrap();
+        grammar.add_element("d", Node::rep(gram!(ws), 2..=3)).unwrap();
+        grammar.add_element("ws", gram!(" "?)).unwrap();
+        assert_eq!(grammar.check(), vec![
+            ValidationError::NullableRepetition { rule: "a".to_string() },
+            ValidationError::NullableRepetition { rule: "c".to_string() },
+            ValidationError::NullableRepetition { rule: "d".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn left_recursion_through_nullable_rule() {
+        let mut grammar = Grammar::<Text>::new();
+        grammar.add_element("a", gram!((ws, a, "x"))).unwrap();
+        grammar.add_element("ws", gram!(" "*)).unwrap();
+        assert_eq!(grammar.check_left_recursion(), vec![ValidationError::LeftRecursion {
+            cycle: vec!["a".to_string(), "a".to_string()],
+        }]);
+    }
+
+    #[test]
+    fn check_regex_terminals() {
+        let yaml = include_str!("../../../examples/fortran_integer.yaml");
+        let grammar = serde_yaml::from_str::<Grammar<Text>>(yaml).unwrap();
+        assert_eq!(grammar.check_regex_terminals(), Ok(()));
+
+        let yaml = r#"
+            rules:
+              a: {seq: [{term: "/[0-9/"}, {non_term: b}, {term: "/[0-9/"}]}
+              b: {alt: [{term: "/x/"}, {term: {regex: "(y", flags: i}}, {term: "/(/"}]}
+        "#;
+        let grammar = serde_yaml::from_str::<Grammar<Text>>(yaml).unwrap();
+        let errors = grammar.check_regex_terminals().unwrap_err();
+        let found = errors.iter().map(|e| (e.rule.as_str(), e.pattern.as_str())).collect::<Vec<_>>();
+        assert_eq!(found, [("a", "[0-9"), ("b", "(y"), ("b", "(")]);
+        assert!(errors[0].to_string().starts_with("rule \"a\": Invalid regex:"), "{}", errors[0]);
+        assert!(grammar.validate().is_ok());
+    }
+
+    #[test]
+    fn deserialize_validated() {
+        let yaml = include_str!("../../../examples/fortran_integer.yaml");
+        let grammar = Grammar::<Text>::deserialize_validated(serde_yaml::Deserializer::from_str(yaml));
+        assert!(grammar.is_ok());
+
+        let yaml = "rules:\n  a:\n    non_term: b\n";
+        let err = Grammar::<Text>::deserialize_validated(serde_yaml::Deserializer::from_str(yaml))
+            .unwrap_err();
+        assert!(err.to_string().contains("undefined rule \"b\""), "{err}");
+    }
+}
@@ -0,0 +1,169 @@
+use core::{fmt, mem};
+
+use logos::Logos;
+
+use crate::prelude::*;
+
+use super::{text, TerminalNode, Text};
+
+/// A terminal matching a token of a [`logos`] lexer
+///
+/// The lexer runs on the source from the position of the terminal, which
+/// matches if its first token is of the same variant as `kind`, whatever
+/// the data of the variant. The terminal consumes what the lexer consumed,
+/// including the input the lexer skipped before the token, such as the
+/// whitespace of `#[logos(skip ...)]`. A lexing error is not a match.
+///
+/// As [`TerminalNode::parses`] only reports where a match ends, the span
+/// of a token starts at the skipped input, not at the lexeme: `x` in
+/// `let x` spans ` x`. Skip the input in the grammar instead, with a
+/// [`Text`] terminal before the [`LogosTerminal`]s, for spans of the lexemes.
+///
+/// With a [limit](crate::basic::Parser::max_terminal_match), the lexer
+/// only sees the input within the limit, so a token cut by the limit is
+/// lexed as its prefix, as are the regexes of [`Text`].
+///
+/// The lexer cannot borrow from the source, and its extras must have a
+/// default. It is run again at each position it is tried at, so grammars
+/// with many terminals are faster when the lexer has few tokens.
+#[derive(Clone)]
+pub struct LogosTerminal<T> {
+    kind: T,
+    name: String,
+}
+
+impl<T> LogosTerminal<T> {
+    /// Match the tokens of the variant of `kind`, written `? name ?` in EBNF
+    pub fn new(kind: T, name: impl Into<String>) -> Self {
+        Self { kind, name: name.into() }
+    }
+
+    pub fn kind(&self) -> &T {
+        &self.kind
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for LogosTerminal<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogosTerminal").field("kind", &self.kind).field("name", &self.name).finish()
+    }
+}
+
+/// Terminals of the same variant are equal, whatever its data
+impl<T> PartialEq for LogosTerminal<T> {
+    fn eq(&self, other: &Self) -> bool {
+        mem::discriminant(&self.kind) == mem::discriminant(&other.kind) && self.name == other.name
+    }
+}
+
+impl<T> Eq for LogosTerminal<T> {}
+
+impl<T> TerminalNode for LogosTerminal<T>
+where
+    T: for<'s> Logos<'s, Source = str, Extras: Default> + fmt::Debug + Clone + 'static,
+{
+    type Src = str;
+    fn parses(&self, src: &str, pos: usize) -> anyhow::Result<Option<usize>> {
+        self.parses_within(src, pos, usize::MAX)
+    }
+    /// Lexes only the `max_len` bytes after `pos`, less if they end inside a char
+    fn parses_within(&self, src: &str, pos: usize, max_len: usize) -> anyhow::Result<Option<usize>> {
+        if !src.is_char_boundary(pos) {
+            anyhow::bail!("Position {pos} is not on a char boundary of a source of {} bytes", src.len());
+        }
+        let mut lexer = T::lexer(&src[pos..text::window_end(src, pos, max_len)]);
+        Ok(match lexer.next() {
+            Some(Ok(token)) if mem::discriminant(&token) == mem::discriminant(&self.kind) => {
+                Some(pos + lexer.span().end)
+            }
+            _ => None,
+        })
+    }
+    fn line_len(src: &str, pos: usize) -> Option<usize> {
+        Text::line_len(src, pos)
+    }
+    fn is_boundary(src: &str, pos: usize) -> bool {
+        Text::is_boundary(src, pos)
+    }
+    /// An ISO EBNF special sequence, `? name ?`
+    fn to_ebnf(&self) -> String {
+        format!("? {} ?", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::basic::{Grammar, Node};
+
+    use super::*;
+
+    #[derive(Logos, Debug, Clone, PartialEq)]
+    #[logos(skip r"[ \t]+")]
+    enum Tok {
+        #[token("let")]
+        Let,
+        #[token("=")]
+        Eq,
+        #[regex("[0-9]+", |lex| lex.slice().parse().ok())]
+        Number(u64),
+        #[regex("[a-z]+")]
+        Ident,
+        #[regex("#[^;]*")]
+        Comment,
+    }
+
+    #[test]
+    fn logos_terminal() {
+        let terminal = |kind, name| Node::Terminal(LogosTerminal::new(kind, name));
+        let mut grammar = Grammar::new();
+        grammar.add_element("binding", Node::seq([
+            terminal(Tok::Let, "let"),
+            terminal(Tok::Ident, "ident"),
+            terminal(Tok::Eq, "eq"),
+            terminal(Tok::Number(0), "number"),
+        ])).unwrap();
+        assert_eq!(grammar.to_ebnf(false), "binding = ? let ? ? ident ? ? eq ? ? number ?;\n");
+        assert_eq!(LogosTerminal::new(Tok::Number(1), "n"), LogosTerminal::new(Tok::Number(2), "n"));
+
+        let src = "let x = 42";
+        let (token, diagnostics) = grammar.parse_non_term("binding", src).unwrap().unwrap();
+        assert!(diagnostics.is_empty());
+        let spans = token.children.iter().map(|t| t.span.clone()).collect::<Vec<_>>();
+        // the skipped whitespace is part of the next token
+        assert_eq!(spans, [0..3, 3..5, 5..7, 7..10]);
+
+        // `lets` is an identifier for the lexer, not `let`
+        assert!(grammar.parse_non_term("binding", "lets x = 1").unwrap().is_none());
+        let (_, diagnostics) = grammar.parse_non_term("binding", "let x 1").unwrap().unwrap();
+        assert_eq!(diagnostics[0].message(), "Incomplete parse at 5: expected ? eq ?");
+        assert!(LogosTerminal::new(Tok::Eq, "eq").parses("=", 1).unwrap().is_none());
+        assert!(LogosTerminal::new(Tok::Eq, "eq").parses("é", 1).is_err());
+    }
+
+    #[test]
+    fn parses_within() {
+        let number = LogosTerminal::new(Tok::Number(0), "number");
+        assert_eq!(number.parses_within("x 123", 1, usize::MAX).unwrap(), Some(5));
+        assert_eq!(number.parses_within("x 123", 1, 3).unwrap(), Some(4));
+        // only the skipped whitespace fits
+        assert_eq!(number.parses_within("x 123", 1, 1).unwrap(), None);
+        // the window ends on a char boundary
+        let ident = LogosTerminal::new(Tok::Ident, "ident");
+        assert_eq!(ident.parses_within("abé", 0, 3).unwrap(), Some(2));
+        assert!(number.parses_within("é1", 1, 1).is_err());
+
+        let mut grammar = Grammar::new();
+        grammar.add_element("comment", Node::Terminal(LogosTerminal::new(Tok::Comment, "comment"))).unwrap();
+        let src = "# a\nb;";
+        let (token, _) = grammar.parse_non_term("comment", src).unwrap().unwrap();
+        assert_eq!(token.span, 0..5);
+        let (token, _) = grammar.parser().line_bounded_terminals().parse_non_term("comment", src).unwrap().unwrap();
+        assert_eq!(token.span, 0..4);
+        let (token, _) = grammar.parser().max_terminal_match(2).parse_non_term("comment", src).unwrap().unwrap();
+        assert_eq!(token.span, 0..2);
+    }
+}
@@ -1,16 +1,45 @@
-use std::{collections::BTreeMap, fmt, ops::{RangeBounds, RangeInclusive}};
+use alloc::collections::BTreeMap;
+use core::{fmt, ops::{RangeBounds, RangeInclusive}};
 
 use bincode::{Decode, Encode};
 
-use crate::parsers::naive::{AbstractNode, Action, Parsed};
+use crate::{parsers::naive::{AbstractNode, Action, Parsed}, prelude::*};
 
-use super::{TerminalNode, Token};
+use super::{CAPTURE_PREFIX, TerminalNode, Token};
 
 mod serialization;
 
 mod parse_state;
 
+mod left_recursion;
+
+mod cache;
+
+#[cfg(feature = "parallel")]
+mod parallel;
+
+mod pruning;
+
+mod shared;
+
+mod streaming;
+
+mod incomplete;
+
+mod revisit;
+
+mod pretty;
+
+pub use incomplete::IncompleteSpans;
+pub(crate) use incomplete::extend_incomplete_spans;
+pub use pretty::EbnfPrinter;
 pub use parse_state::*;
+pub(crate) use pruning::AltPruning;
+pub(crate) use shared::SharedNodes;
+pub(crate) use streaming::OnComplete;
+
+/// Meta key of the human readable description of a node, see [`Node::with_description`]
+pub const DESCRIPTION: &str = "description";
 
 /// A grammar node
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -19,38 +48,129 @@ pub enum Node<T> {
     /// A sequence of nodes
     Seq(Vec<Node<T>>),
     /// A choice between nodes
+    ///
+    /// Every branch is tried and the longest match is kept, a complete match
+    /// being preferred over an incomplete one of the same length. On ties,
+    /// the earliest branch wins.
     Alt(Vec<Node<T>>),
     /// A repetition of nodes
-    Rep { node: Box<Node<T>>, range: RangeInclusive<usize> },
+    ///
+    /// A greedy repetition matches as many nodes as possible. As a direct
+    /// element of a sequence, it gives back its last node each time the
+    /// elements that follow it fail to match, so `(#'[a-z]'*, "b")` matches
+    /// `"ab"`. A lazy repetition matches as few nodes as possible, see
+    /// [`Node::rep_lazy`].
+    Rep { node: Box<Node<T>>, range: RangeInclusive<usize>, lazy: bool },
+    /// A repetition of nodes separated by `sep`, `range` bounds the number of `node`s
+    RepSep { node: Box<Node<T>>, sep: Box<Node<T>>, range: RangeInclusive<usize> },
     /// A terminal node
     Terminal(T),
     /// A non-terminal node
     NonTerm(String),
-    /// A string tag attached to a node
-    Tagged { node: Box<Node<T>>, tag: String },
+    /// String tags attached to a node, pushed in order to the tags of its token
+    Tagged { node: Box<Node<T>>, tags: Vec<String> },
     /// Meta information
     Meta { node: Box<Node<T>>, meta: BTreeMap<String, String> },
+    /// Matches the empty string, always succeeding with a zero-width token
+    Empty,
+    /// `node` unless `minus` matches the same text, written `node - minus`
+    ///
+    /// Once `node` matches, `minus` is parsed at the same position: if it
+    /// completely matches up to the same end, the exception fails. So
+    /// `#'.' - "\""` matches any char but a quote.
+    Except { node: Box<Node<T>>, minus: Box<Node<T>> },
 }
 
 impl<T> Node<T> {
     pub fn rep(node: impl Into<Node<T>>, range: impl RangeBounds<usize>) -> Self {
-        let start = match range.start_bound() {
-            std::ops::Bound::Included(&start) => start,
-            std::ops::Bound::Excluded(&start) => start + 1, // TODO check for overflow
-            std::ops::Bound::Unbounded => 0,
+        Self::Rep { node: Box::new(node.into()), range: inclusive_range(range), lazy: false }
+    }
+
+    /// A repetition matching as few nodes as possible, like `*?` in regexes
+    ///
+    /// The repetition stops at the minimum count. As a direct element of a
+    /// sequence, it takes one more node each time the elements that follow it
+    /// fail to match, so `("a"*?, "ab")` matches `"aab"`. Elsewhere, it always
+    /// matches the minimum.
+    pub fn rep_lazy(node: impl Into<Node<T>>, range: impl RangeBounds<usize>) -> Self {
+        Self::Rep { node: Box::new(node.into()), range: inclusive_range(range), lazy: true }
+    }
+
+    /// A sequence of `nodes`, nested sequences are flattened into it
+    pub fn seq(nodes: impl IntoIterator<Item = impl Into<Node<T>>>) -> Self {
+        let mut elements = vec![];
+        for node in nodes {
+            match node.into() {
+                Node::Seq(mut inner) => elements.append(&mut inner),
+                other => elements.push(other),
+            }
+        }
+        Self::Seq(elements)
+    }
+
+    /// A choice between `nodes`, nested choices are flattened into it
+    pub fn alt(nodes: impl IntoIterator<Item = impl Into<Node<T>>>) -> Self {
+        let mut branches = vec![];
+        for node in nodes {
+            match node.into() {
+                Node::Alt(mut inner) => branches.append(&mut inner),
+                other => branches.push(other),
+            }
+        }
+        Self::Alt(branches)
+    }
+
+    pub fn rep_sep(
+        node: impl Into<Node<T>>,
+        sep: impl Into<Node<T>>,
+        range: impl RangeBounds<usize>,
+    ) -> Self {
+        Self::RepSep {
+            node: Box::new(node.into()),
+            sep: Box::new(sep.into()),
+            range: inclusive_range(range),
+        }
+    }
+
+    /// `node` unless `minus` matches the same text, see [`Node::Except`]
+    pub fn except(node: impl Into<Node<T>>, minus: impl Into<Node<T>>) -> Self {
+        Self::Except {
+            node: Box::new(node.into()),
+            minus: Box::new(minus.into()),
+        }
+    }
+
+    /// `node` with one more tag, added to its `Tagged` if it has one
+    pub fn tagged(node: impl Into<Node<T>>, tag: impl Into<String>) -> Self {
+        Self::tagged_with(node, [tag])
+    }
+
+    /// `node` with the `tags` after its own, added to its `Tagged` if it has one
+    pub fn tagged_with<S: Into<String>>(node: impl Into<Node<T>>, tags: impl IntoIterator<Item = S>) -> Self {
+        let (node, mut all) = match node.into() {
+            Node::Tagged { node, tags } => (node, tags),
+            node => (Box::new(node), vec![]),
         };
-        let end = match range.end_bound() {
-            std::ops::Bound::Included(&end) => end,
-            std::ops::Bound::Excluded(&end) => end - 1, // TODO check for overflow
-            std::ops::Bound::Unbounded => usize::MAX,
+        all.extend(tags.into_iter().map(Into::into));
+        Self::Tagged { node, tags: all }
+    }
+
+    /// The node with a [`DESCRIPTION`] meta key, added to its `Meta` if it has one
+    pub fn with_description(self, description: impl Into<String>) -> Self {
+        let (node, mut meta) = match self {
+            Node::Meta { node, meta } => (node, meta),
+            node => (Box::new(node), BTreeMap::new()),
         };
-        Self::Rep { node: Box::new(node.into()), range: start..=end }
+        meta.insert(DESCRIPTION.to_string(), description.into());
+        Self::Meta { node, meta }
     }
 
-    pub fn tagged(node: impl Into<Node<T>>, tag: impl Into<String>) -> Self {
-        Self::Tagged {
-            node: Box::new(node.into()),
-            tag: tag.into(),
+    /// The [`DESCRIPTION`] meta key of the node, through its `Meta` and `Tagged` wrappers
+    pub fn description(&self) -> Option<&str> {
+        match self {
+            Node::Meta { node, meta } => meta.get(DESCRIPTION).map(String::as_str).or_else(|| node.description()),
+            Node::Tagged { node, .. } => node.description(),
+            _ => None,
         }
     }
 
@@ -69,6 +189,10 @@ impl<T> Node<T> {
             Node::Rep { node, .. } => {
                 node.rename_reference(old_name, new_name);
             }
+            Node::RepSep { node, sep, .. } => {
+                node.rename_reference(old_name, new_name);
+                sep.rename_reference(old_name, new_name);
+            }
             Node::Terminal(_) => {}
             Node::NonTerm(name) if name == old_name => {
                 *name = new_name.to_string();
@@ -80,6 +204,192 @@ impl<T> Node<T> {
             Node::Meta { node, .. } => {
                 node.rename_reference(old_name, new_name);
             }
+            Node::Empty => {}
+            Node::Except { node, minus } => {
+                node.rename_reference(old_name, new_name);
+                minus.rename_reference(old_name, new_name);
+            }
+        }
+    }
+
+    /// Convert every terminal with `f`, keeping the structure
+    pub fn map_terminals<U>(self, mut f: impl FnMut(T) -> U) -> Node<U> {
+        self.map_terminals_with(&mut f)
+    }
+
+    fn map_terminals_with<U>(self, f: &mut dyn FnMut(T) -> U) -> Node<U> {
+        match self {
+            Node::Seq(nodes) => Node::Seq(nodes.into_iter().map(|n| n.map_terminals_with(f)).collect()),
+            Node::Alt(nodes) => Node::Alt(nodes.into_iter().map(|n| n.map_terminals_with(f)).collect()),
+            Node::Rep { node, range, lazy } => Node::Rep { node: Box::new(node.map_terminals_with(f)), range, lazy },
+            Node::RepSep { node, sep, range } => Node::RepSep {
+                node: Box::new(node.map_terminals_with(f)),
+                sep: Box::new(sep.map_terminals_with(f)),
+                range,
+            },
+            Node::Terminal(t) => Node::Terminal(f(t)),
+            Node::NonTerm(name) => Node::NonTerm(name),
+            Node::Tagged { node, tags } => Node::Tagged { node: Box::new(node.map_terminals_with(f)), tags },
+            Node::Meta { node, meta } => Node::Meta { node: Box::new(node.map_terminals_with(f)), meta },
+            Node::Empty => Node::Empty,
+            Node::Except { node, minus } => Node::Except {
+                node: Box::new(node.map_terminals_with(f)),
+                minus: Box::new(minus.map_terminals_with(f)),
+            },
+        }
+    }
+
+    /// Visit this node and all its descendants, depth-first, parents first
+    pub fn walk<'a>(&'a self, f: &mut dyn FnMut(&'a Node<T>)) {
+        f(self);
+        match self {
+            Node::Seq(nodes) | Node::Alt(nodes) => {
+                for node in nodes {
+                    node.walk(f);
+                }
+            }
+            Node::Rep { node, .. } => node.walk(f),
+            Node::RepSep { node, sep, .. } | Node::Except { node, minus: sep } => {
+                node.walk(f);
+                sep.walk(f);
+            }
+            Node::Terminal(_) => {}
+            Node::NonTerm(_) => {}
+            Node::Tagged { node, .. } => node.walk(f),
+            Node::Meta { node, .. } => node.walk(f),
+            Node::Empty => {}
+        }
+    }
+
+    /// Whether the nodes parse the same way, ignoring their `Tagged` and `Meta` wrappers
+    pub fn structurally_eq(&self, other: &Node<T>) -> bool
+    where
+        T: PartialEq,
+    {
+        let all_eq = |a: &[Node<T>], b: &[Node<T>]| {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.structurally_eq(b))
+        };
+        match (self.unannotated(), other.unannotated()) {
+            (Node::Seq(a), Node::Seq(b)) => all_eq(a, b),
+            (Node::Alt(a), Node::Alt(b)) => all_eq(a, b),
+            (
+                Node::Rep { node: a, range: range_a, lazy: lazy_a },
+                Node::Rep { node: b, range: range_b, lazy: lazy_b },
+            ) => range_a == range_b && lazy_a == lazy_b && a.structurally_eq(b),
+            (
+                Node::RepSep { node: a, sep: sep_a, range: range_a },
+                Node::RepSep { node: b, sep: sep_b, range: range_b },
+            ) => range_a == range_b && a.structurally_eq(b) && sep_a.structurally_eq(sep_b),
+            (
+                Node::Except { node: a, minus: minus_a },
+                Node::Except { node: b, minus: minus_b },
+            ) => a.structurally_eq(b) && minus_a.structurally_eq(minus_b),
+            (Node::Terminal(a), Node::Terminal(b)) => a == b,
+            (Node::NonTerm(a), Node::NonTerm(b)) => a == b,
+            (Node::Empty, Node::Empty) => true,
+            _ => false,
+        }
+    }
+
+    /// The node inside the `Tagged` and `Meta` wrappers
+    fn unannotated(&self) -> &Node<T> {
+        match self {
+            Node::Tagged { node, .. } | Node::Meta { node, .. } => node.unannotated(),
+            node => node,
+        }
+    }
+
+    /// Remove the `Tagged` and `Meta` wrappers, at any depth
+    ///
+    /// The node matches the same as before, with fewer parsing steps, but its
+    /// tokens have no tags nor meta.
+    pub fn strip_annotations(&mut self) {
+        self.walk_mut(&mut |n| {
+            while let Node::Tagged { node, .. } | Node::Meta { node, .. } = n {
+                let inner = core::mem::replace(&mut **node, Node::Empty);
+                *n = inner;
+            }
+        });
+    }
+
+    /// Factor the leading terminals shared by adjacent `Alt` branches out of them, at any depth
+    ///
+    /// `("foo", "a") | ("foo", "b")` becomes `"foo", ("a" | "b")`, so that
+    /// `"foo"` is parsed once. Only adjacent branches are factored, keeping
+    /// the order that breaks ties: the node matches the same inputs with the
+    /// same spans, but its tokens are shaped differently and its diagnostics
+    /// can differ. Returns the number of factored groups of branches.
+    pub fn left_factor(&mut self) -> usize
+    where
+        T: PartialEq + Clone,
+    {
+        let mut count = 0;
+        self.walk_mut(&mut |n| {
+            let Node::Alt(branches) = n else {
+                return;
+            };
+            let mut factored = vec![];
+            let mut rest = core::mem::take(branches).into_iter().peekable();
+            while let Some(first) = rest.next() {
+                let mut group = vec![first];
+                while let Some(next) = rest.next_if(|b| shared_terminals(&group[0], b) > 0) {
+                    group.push(next);
+                }
+                if group.len() == 1 {
+                    factored.append(&mut group);
+                    continue;
+                }
+                let len = group.iter().map(|b| shared_terminals(&group[0], b)).min().unwrap_or(0);
+                let mut elements = leading_elements(&group[0])[..len].to_vec();
+                let suffixes = group.into_iter().map(|branch| {
+                    let mut suffix = match branch {
+                        Node::Seq(elements) => elements,
+                        other => vec![other],
+                    }.split_off(len);
+                    match suffix.len() {
+                        0 => Node::Empty,
+                        1 => suffix.remove(0),
+                        _ => Node::Seq(suffix),
+                    }
+                });
+                elements.push(Node::Alt(suffixes.collect()));
+                factored.push(Node::Seq(elements));
+                count += 1;
+            }
+            *n = Node::Alt(factored);
+        });
+        count
+    }
+
+    /// The number of nodes in the tree of this node, itself included
+    pub fn complexity(&self) -> usize {
+        let mut count = 0;
+        self.walk(&mut |_| count += 1);
+        count
+    }
+
+    /// Same as [`Node::walk`], but the nodes can be modified
+    ///
+    /// `f` is called on a node before its descendants, so these are the ones
+    /// `f` leaves in place.
+    pub fn walk_mut(&mut self, f: &mut dyn FnMut(&mut Node<T>)) {
+        f(self);
+        match self {
+            Node::Seq(nodes) | Node::Alt(nodes) => {
+                for node in nodes {
+                    node.walk_mut(f);
+                }
+            }
+            Node::Rep { node, .. } => node.walk_mut(f),
+            Node::RepSep { node, sep, .. } | Node::Except { node, minus: sep } => {
+                node.walk_mut(f);
+                sep.walk_mut(f);
+            }
+            Node::Terminal(_) => {}
+            Node::NonTerm(_) => {}
+            Node::Tagged { node, .. } => node.walk_mut(f),
+            Node::Meta { node, .. } => node.walk_mut(f),
+            Node::Empty => {}
         }
     }
 
@@ -87,25 +397,168 @@ impl<T> Node<T> {
     where
         T: TerminalNode,
     {
+        self.to_ebnf_with(&T::to_ebnf)
+    }
+
+    /// Same as [`Node::to_ebnf`], writing the terminals with `terminal`
+    pub(crate) fn to_ebnf_with(&self, terminal: &dyn Fn(&T) -> String) -> String
+    where
+        T: TerminalNode,
+    {
+        self.to_ebnf_prec(Prec::Alt, terminal)
+    }
+
+    /// EBNF of the node, parenthesized if it binds looser than `prec`
+    fn to_ebnf_prec(&self, prec: Prec, terminal: &dyn Fn(&T) -> String) -> String
+    where
+        T: TerminalNode,
+    {
+        if let Some(expanded) = self.ebnf_expansion() {
+            return expanded.to_ebnf_prec(prec, terminal);
+        }
+        let group = |s: String, own: Prec| if prec > own { format!("({s})") } else { s };
         match self {
-            Node::Seq(nodes) => nodes.iter().map(|n| n.to_ebnf()).collect::<Vec<_>>().join(" "),
-            Node::Alt(nodes) => nodes.iter().map(|n| n.to_ebnf()).collect::<Vec<_>>().join(" | "),
-            Node::Rep { node, range } => {
+            Node::Seq(nodes) if nodes.len() == 1 => nodes[0].to_ebnf_prec(prec, terminal),
+            Node::Seq(nodes) => group(
+                nodes.iter().map(|n| n.to_ebnf_prec(Prec::Seq, terminal)).collect::<Vec<_>>().join(" "),
+                Prec::Seq,
+            ),
+            Node::Alt(nodes) if nodes.len() == 1 => nodes[0].to_ebnf_prec(prec, terminal),
+            // sequences in a choice are grouped too: `ebnf` has no operator
+            // precedence and would read `a b | c` as `a (b | c)`
+            Node::Alt(nodes) => group(
+                nodes.iter().map(|n| n.to_ebnf_prec(Prec::Postfix, terminal)).collect::<Vec<_>>().join(" | "),
+                Prec::Alt,
+            ),
+            // lazy repetitions have no EBNF equivalent, they are written with
+            // the regex syntax
+            Node::Rep { node, range, lazy: true } => {
+                let node = node.to_ebnf_prec(Prec::Postfix, terminal);
                 match (*range.start(), *range.end()) {
-                    (0, 1) => format!("[{}]", node.to_ebnf()),
-                    (1, usize::MAX) => format!("{}+", node.to_ebnf()),
-                    (0, usize::MAX) => format!("{}*", node.to_ebnf()),
-                    _ => panic!("Unsupported repetition range in EBNF: {:?}", range),
+                    (0, 1) => format!("{node}??"),
+                    (1, usize::MAX) => format!("{node}+?"),
+                    (0, usize::MAX) => format!("{node}*?"),
+                    _ => unreachable!("written as its expansion"),
                 }
             }
-            Node::Terminal(value) => value.to_ebnf(),
+            Node::Rep { node, range, lazy: false } => {
+                match (*range.start(), *range.end()) {
+                    (0, 1) => format!("[{}]", node.to_ebnf_with(terminal)),
+                    (1, usize::MAX) => format!("{}+", node.to_ebnf_prec(Prec::Postfix, terminal)),
+                    (0, usize::MAX) => format!("{}*", node.to_ebnf_prec(Prec::Postfix, terminal)),
+                    _ => unreachable!("written as its expansion"),
+                }
+            }
+            Node::RepSep { .. } => unreachable!("written as its expansion"),
+            Node::Terminal(value) => terminal(value),
             Node::NonTerm(name) => name.clone(),
-            Node::Tagged { node, .. } => node.to_ebnf(),
-            Node::Meta { node, .. } => node.to_ebnf(),
+            Node::Tagged { node, .. } => node.to_ebnf_prec(prec, terminal),
+            Node::Meta { node, .. } => node.to_ebnf_prec(prec, terminal),
+            Node::Empty => "\"\"".to_string(),
+            Node::Except { node, minus } => group(
+                format!("{} - {}", node.to_ebnf_prec(Prec::Except, terminal), minus.to_ebnf_prec(Prec::Postfix, terminal)),
+                Prec::Except,
+            ),
+        }
+    }
+}
+
+impl<T: Clone> Node<T> {
+    /// The equivalent of a repetition that EBNF has no operator for, written instead of it
+    ///
+    /// EBNF only has `[a]`, `a*` and `a+`, and their lazy forms `a??`, `a*?`
+    /// and `a+?`: a separated repetition is written as `a (sep a)*`, optional
+    /// if it can be empty, and the other counts are written out, `2..=4` as
+    /// `a a [a [a]]` or lazily `a a (a a??)??`, and `3..` as `a a a+`.
+    pub(super) fn ebnf_expansion(&self) -> Option<Node<T>> {
+        match self {
+            Node::Rep { node, range, lazy } => {
+                let (min, max) = (*range.start(), *range.end());
+                if matches!((min, max), (0, 1) | (0, usize::MAX) | (1, usize::MAX)) {
+                    return None;
+                }
+                let mut elements = vec![(**node).clone(); min.min(max)];
+                if max == usize::MAX {
+                    elements.pop();
+                    elements.push(Node::Rep { node: node.clone(), range: 1..=usize::MAX, lazy: *lazy });
+                } else if max > min {
+                    // nested, so that each count matches one way
+                    let mut optional = Node::Rep { node: node.clone(), range: 0..=1, lazy: *lazy };
+                    for _ in min + 1..max {
+                        let inner = Node::Seq(vec![(**node).clone(), optional]);
+                        optional = Node::Rep { node: Box::new(inner), range: 0..=1, lazy: *lazy };
+                    }
+                    elements.push(optional);
+                }
+                Some(match elements.len() {
+                    0 => Node::Empty,
+                    1 => elements.pop().unwrap(),
+                    _ => Node::Seq(elements),
+                })
+            }
+            Node::RepSep { node, sep, range } => {
+                let (min, max) = (*range.start(), *range.end());
+                if max == 0 {
+                    return Some(Node::Empty);
+                }
+                let rest = Node::Rep {
+                    node: Box::new(Node::Seq(vec![(**sep).clone(), (**node).clone()])),
+                    range: min.saturating_sub(1)..=if max == usize::MAX { max } else { max - 1 },
+                    lazy: false,
+                };
+                let expanded = match rest.ebnf_expansion() {
+                    Some(Node::Empty) => (**node).clone(),
+                    _ => Node::Seq(vec![(**node).clone(), rest]),
+                };
+                Some(match min {
+                    0 => Node::Rep { node: Box::new(expanded), range: 0..=1, lazy: false },
+                    _ => expanded,
+                })
+            }
+            _ => None,
         }
     }
 }
 
+/// Binding strength of the EBNF operators, from the loosest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Prec {
+    Alt,
+    Seq,
+    Except,
+    Postfix,
+}
+
+/// The elements of a branch, a single one unless it is a sequence
+fn leading_elements<T>(branch: &Node<T>) -> &[Node<T>] {
+    match branch {
+        Node::Seq(elements) => elements,
+        other => core::slice::from_ref(other),
+    }
+}
+
+/// The number of equal terminals `a` and `b` begin with
+fn shared_terminals<T: PartialEq>(a: &Node<T>, b: &Node<T>) -> usize {
+    leading_elements(a)
+        .iter()
+        .zip(leading_elements(b))
+        .take_while(|(a, b)| matches!(a, Node::Terminal(_)) && a == b)
+        .count()
+}
+
+fn inclusive_range(range: impl RangeBounds<usize>) -> RangeInclusive<usize> {
+    let start = match range.start_bound() {
+        core::ops::Bound::Included(&start) => start,
+        core::ops::Bound::Excluded(&start) => start + 1, // TODO check for overflow
+        core::ops::Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        core::ops::Bound::Included(&end) => end,
+        core::ops::Bound::Excluded(&end) => end - 1, // TODO check for overflow
+        core::ops::Bound::Unbounded => usize::MAX,
+    };
+    start..=end
+}
 
 impl<'a, T: TerminalNode + 'static> AbstractNode for &'a Node<T> {
     type Src = T::Src;
@@ -118,34 +571,102 @@ impl<'a, T: TerminalNode + 'static> AbstractNode for &'a Node<T> {
         pos: usize,
         state: &mut Self::State,
     ) -> anyhow::Result<Action<Self>> {
+        if let Some(action) = state.enter_shared(self, pos) {
+            return Ok(action);
+        }
         let action = match self {
+            Node::Seq(seq) if seq.is_empty() => {
+                // an empty sequence matches the empty string
+                Action::Pop {
+                    parsed: Some(Parsed {
+                        token: Token {
+                            span: pos..pos,
+                            gram: None,
+                            tags: vec![],
+                            meta: Default::default(),
+                            children: vec![],
+                        },
+                        diagnostics: vec![],
+                        incomplete: None,
+                    }),
+                }
+            },
             Node::Seq(seq) => {
+                state.enter_sequence(pos);
                 Action::Push {
                     save_state: StackState::ParsingSequence {
                         elements: seq,
                         parsed: vec![],
                         diagnostics: vec![],
+                        marks: vec![],
+                        alternatives: vec![],
+                        extending: None,
+                        failure: None,
                     },
-                    next_node: &seq[0], // TODO: check for empty sequence
+                    next_node: &seq[0],
                     next_pos: pos,
                 }
             },
+            Node::Alt(seq) if seq.is_empty() => {
+                // an empty choice never matches
+                state.alternatives.clear();
+                Action::Pop {
+                    parsed: None,
+                }
+            },
             Node::Alt(seq) => {
+                state.alternatives.clear();
+                #[cfg(feature = "parallel")]
+                if let Some(parallel) = state.parallel_alt_for(seq) {
+                    let candidates = (parallel.parse)(state, seq, src, pos)?;
+                    let parsed = if state.revisit_choices {
+                        state.exit_choice(candidates)
+                    } else {
+                        parallel::select_choice(candidates)
+                    };
+                    if let Some(parsed) = &parsed {
+                        state.reach(parsed.token.span.end);
+                    }
+                    return Ok(Action::Pop { parsed });
+                }
+                let viable = state.viable_branches(seq, src, pos);
+                let Some(current) = pruning::next_viable(viable.as_deref(), 0, seq.len()) else {
+                    return Ok(Action::Pop { parsed: None });
+                };
                 Action::Push {
                     save_state: StackState::ParsingChoice {
                         start_pos: pos,
                         elements: seq,
-                        current: 0,
-                        parsed: vec![],
+                        current,
+                        best: None,
+                        viable,
+                        candidates: state.choice_candidates(),
                     },
-                    next_node: &seq[0], // TODO: check for empty choice
+                    next_node: &seq[current],
                     next_pos: pos,
                 }
             },
-            Node::Rep { node, range } => {
+            Node::Rep { range, lazy: true, .. } if *range.start() == 0 => {
+                // a lazy repetition is done as soon as the minimum is reached
+                Action::Pop {
+                    parsed: Some(Parsed {
+                        token: Token {
+                            span: pos..pos,
+                            gram: None,
+                            tags: vec![],
+                            meta: Default::default(),
+                            children: vec![],
+                        },
+                        diagnostics: vec![],
+                        incomplete: None,
+                    }),
+                }
+            },
+            Node::Rep { node, range, lazy } => {
                 let save_state = StackState::ParsingRepetition {
                     element: node,
                     range: range.clone(),
+                    lazy: *lazy,
                     parsed: vec![],
                     start_pos: pos,
                     diagnostics: vec![],
@@ -156,14 +677,44 @@ impl<'a, T: TerminalNode + 'static> AbstractNode for &'a Node<T> {
                     next_pos: pos,
                 }
             },
+            Node::RepSep { node, sep, range } => {
+                let save_state = StackState::ParsingSeparatedRepetition {
+                    element: node,
+                    sep,
+                    range: range.clone(),
+                    parsed: vec![],
+                    start_pos: pos,
+                    diagnostics: vec![],
+                    expecting_sep: false,
+                };
+                Action::Push {
+                    save_state,
+                    next_node: &**node,
+                    next_pos: pos,
+                }
+            },
             Node::Terminal(t) => {
-                let parsed = if let Some(end) = t.parses(src, pos)? {
+                let window = state.terminal_window(src, pos);
+                let end = match window {
+                    Some(max_len) => t.parses_within(src, pos, max_len)?,
+                    None => t.parses(src, pos)?,
+                };
+                state.reach(end.unwrap_or_else(|| t.reaches(src, pos)));
+                let parsed = if let Some(end) = end {
+                    let meta = if state.captures {
+                        t.captures(src, pos, window)?
+                            .into_iter()
+                            .map(|(name, span)| (format!("{CAPTURE_PREFIX}{name}"), format!("{}..{}", span.start, span.end)))
+                            .collect()
+                    } else {
+                        Default::default()
+                    };
                     Some(Parsed {
                         token: Token {
                             span: pos..end,
                             gram: None,
                             tags: vec![],
-                            meta: Default::default(),
+                            meta,
                             children: vec![],
                         },
                         diagnostics: vec![],
@@ -177,10 +728,12 @@ impl<'a, T: TerminalNode + 'static> AbstractNode for &'a Node<T> {
                 }
             },
             Node::NonTerm(name) => {
-                let cache_key = (name.clone(), pos);
-                if let Some(cached) = state.cache.get(&cache_key) {
+                if let Some(memoized) = state.enter_non_terminal(name, pos) {
+                    if let Some(parsed) = &memoized {
+                        state.reach(parsed.token.span.end);
+                    }
                     return Ok(Action::Pop {
-                        parsed: cached.clone(),
+                        parsed: memoized,
                     });
                 }
                 let node = state.grammar.rules.get(name).ok_or_else(|| {
@@ -195,10 +748,10 @@ impl<'a, T: TerminalNode + 'static> AbstractNode for &'a Node<T> {
                     next_pos: pos,
                 }
             },
-            Node::Tagged{ node, tag } => {
+            Node::Tagged{ node, tags } => {
                 Action::Push {
                     save_state: StackState::ParsingTagged {
-                        tag: tag.clone(),
+                        tags,
                     },
                     next_node: &**node,
                     next_pos: pos,
@@ -213,6 +766,32 @@ impl<'a, T: TerminalNode + 'static> AbstractNode for &'a Node<T> {
                     next_pos: pos,
                 }
             },
+            Node::Empty => {
+                Action::Pop {
+                    parsed: Some(Parsed {
+                        token: Token {
+                            span: pos..pos,
+                            gram: None,
+                            tags: vec![],
+                            meta: Default::default(),
+                            children: vec![],
+                        },
+                        diagnostics: vec![],
+                        incomplete: None,
+                    }),
+                }
+            },
+            Node::Except { node, minus } => {
+                Action::Push {
+                    save_state: StackState::ParsingExcept {
+                        start_pos: pos,
+                        minus,
+                        matched: None,
+                    },
+                    next_node: &**node,
+                    next_pos: pos,
+                }
+            },
         };
 
         Ok(action)
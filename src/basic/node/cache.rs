@@ -0,0 +1,72 @@
+//! Bounding the cache of non-terminal results
+
+use crate::{basic::TerminalNode, prelude::*};
+
+use super::{CacheEntry, State, left_recursion::Memo};
+
+impl<'a, T: TerminalNode> State<'a, T> {
+    /// Keep at most `capacity` results of non-terminals in the cache
+    ///
+    /// When it is full, the half of the results at the smallest positions,
+    /// the furthest behind the parse, are dropped. The non-terminals being
+    /// parsed are always kept, so that left recursion still works.
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Cache the final result of `name` at `pos`, evicting if the cache is full
+    pub(super) fn cache_result(&mut self, key: (&'a str, usize), parsed: CacheEntry<'a, T>) {
+        self.cache.insert(key, Memo::Done(parsed));
+        let Some(capacity) = self.cache_capacity else {
+            return;
+        };
+        // one entry is being parsed for each non-terminal in progress
+        let results = self.cache.len() - self.in_progress.len();
+        if results <= capacity {
+            return;
+        }
+        let mut keys = self.cache.iter()
+            .filter(|(_, memo)| matches!(memo, Memo::Done(_)))
+            .map(|(&key, _)| key)
+            .collect::<Vec<_>>();
+        keys.sort_unstable_by_key(|&(_, pos)| pos);
+        for key in &keys[..results - capacity / 2] {
+            self.cache.remove(key);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{basic::{Grammar, Text, Token}, parsers::naive::{Diagnostic, NoTracer}};
+
+    use super::*;
+
+    fn parse_list<'a>(mut state: State<'a, Text>, src: &str) -> ((Token, Vec<Diagnostic>), usize) {
+        let (name, node) = state.grammar.rules.get_key_value("list").unwrap();
+        let parsed = state.parse_rule_at(name, node, src, 0, &NoTracer).unwrap().unwrap();
+        (parsed.detach(), state.cache.len())
+    }
+
+    #[test]
+    fn cache_capacity() {
+        let grammar = Grammar::load_ebnf(r#"
+            list = item , ("," , item)*;
+            item = (pair | number) , [";"];
+            pair = number , ":" , number;
+            number = #'[0-9]+';
+        "#).unwrap();
+        let src = (0..100).map(|i| if i % 3 == 0 { format!("{i}:{i}") } else { i.to_string() }).collect::<Vec<_>>().join(",");
+
+        let (unbounded, entries) = parse_list(State::new(&grammar), &src);
+        assert_eq!(unbounded.0.span, 0..src.len());
+        assert!(unbounded.1.is_empty());
+        assert!(entries > 300, "{entries}");
+        for capacity in [0, 1, 10, 100] {
+            let (bounded, entries) = parse_list(State::new(&grammar).with_cache_capacity(capacity), &src);
+            assert_eq!(bounded, unbounded);
+            assert!(entries <= capacity, "{capacity}: {entries}");
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Extending the spans of incomplete sequences to the furthest position reached
+
+use crate::{basic::{TerminalNode, Token}, parsers::naive::Parsed, prelude::*};
+
+use super::{Node, State};
+
+/// The meta key of the furthest position reached by an incomplete sequence, until [`extend_incomplete_spans`]
+const REACHED: &str = "incomplete.reached";
+
+/// How far the token of an incomplete sequence extends, see [`Parser::incomplete_spans`]
+///
+/// [`Parser::incomplete_spans`]: crate::basic::Parser::incomplete_spans
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum IncompleteSpans {
+    /// Up to the end of the matched prefix, where the `Incomplete` diagnostic is
+    #[default]
+    Minimal,
+    /// Up to the furthest position reached while parsing the sequence,
+    /// including the partial matches of the terminals that failed, see
+    /// [`TerminalNode::reaches`]
+    Maximal,
+}
+
+impl<'a, T: TerminalNode> State<'a, T> {
+    /// Record how far the incomplete sequences reached, see [`IncompleteSpans`]
+    ///
+    /// The spans are unchanged while parsing, so that choices and the rest
+    /// of the parse see the matched prefix only: the tokens of incomplete
+    /// sequences record the furthest position in their meta, and
+    /// [`Parser::incomplete_spans`] extends them once the parse is done.
+    ///
+    /// [`Parser::incomplete_spans`]: crate::basic::Parser::incomplete_spans
+    pub fn with_incomplete_spans(mut self, mode: IncompleteSpans) -> Self {
+        self.reached = match mode {
+            IncompleteSpans::Minimal => None,
+            IncompleteSpans::Maximal => Some(vec![]),
+        };
+        self
+    }
+
+    /// The mode of the state, for the states of parallel branches
+    #[cfg(feature = "parallel")]
+    pub(super) fn incomplete_spans(&self) -> IncompleteSpans {
+        match self.reached {
+            Some(_) => IncompleteSpans::Maximal,
+            None => IncompleteSpans::Minimal,
+        }
+    }
+
+    /// Start tracking the positions reached by a sequence starting at `pos`
+    pub(super) fn enter_sequence(&mut self, pos: usize) {
+        if let Some(reached) = &mut self.reached {
+            reached.push(pos);
+        }
+    }
+
+    /// The furthest position the parse reached, as far as its terminals
+    /// could partially match, see [`TerminalNode::reaches`]
+    ///
+    /// Results reused from the cache count up to their end, but they
+    /// reached their own positions when they were parsed.
+    pub fn furthest(&self) -> usize {
+        self.furthest
+    }
+
+    /// Record that the innermost sequence reached `pos`
+    pub(super) fn reach(&mut self, pos: usize) {
+        self.furthest = self.furthest.max(pos);
+        if let Some(last) = self.reached.as_mut().and_then(|reached| reached.last_mut()) {
+            *last = (*last).max(pos);
+        }
+    }
+
+    /// The result of the innermost sequence, with the position it reached if it is incomplete
+    pub(super) fn exit_sequence(&mut self, parsed: Option<Parsed<&'a Node<T>>>) -> Option<Parsed<&'a Node<T>>> {
+        let Some(end) = self.reached.as_mut().and_then(Vec::pop) else {
+            return parsed;
+        };
+        self.reach(end);
+        parsed.map(|mut parsed| {
+            if parsed.incomplete.is_some() && end > parsed.token.span.end {
+                parsed.token.meta.insert(REACHED.to_string(), end.to_string());
+            }
+            parsed
+        })
+    }
+}
+
+/// Extend the incomplete sequences of a finished tree up to the positions they reached
+///
+/// A token extends up to the start of the token after it at most, so that
+/// siblings do not overlap, and its parents extend with it.
+pub(crate) fn extend_incomplete_spans(token: &mut Token) {
+    extend_up_to(token, usize::MAX);
+}
+
+fn extend_up_to(token: &mut Token, bound: usize) {
+    if let Some(reached) = token.meta.remove(REACHED) {
+        let reached = reached.parse::<usize>().expect("written by `exit_sequence`");
+        token.span.end = token.span.end.max(reached.min(bound));
+    }
+    let bounds = token.children.iter().skip(1).map(|child| child.span.start).chain([bound]).collect::<Vec<_>>();
+    for (child, bound) in token.children.iter_mut().zip(bounds) {
+        extend_up_to(child, bound);
+    }
+    if let Some(last) = token.children.last() {
+        token.span.end = token.span.end.max(last.span.end);
+    }
+}
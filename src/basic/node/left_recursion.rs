@@ -0,0 +1,260 @@
+//! Left recursion by growing a seed, as in Warth et al., "Packrat Parsers Can
+//! Support Left Recursion"
+//!
+//! A non-terminal reached again at the position it started at, without
+//! consuming input, does not recurse: it matches its current seed, which is
+//! initially a failure. Once the rule body is parsed, if the seed was used the
+//! result becomes the new seed and the body is parsed again, until the match
+//! stops growing. The last seed is the result, so `expr = (expr , "+" , term) | term`
+//! matches `1+2+3` as `((1+2)+3)`.
+//!
+//! The results that depend on a seed, such as the rules in between in
+//! indirect left recursion, are not cached until the seed is final.
+
+use crate::collections::map::Entry;
+
+use crate::parsers::naive::{self, Parsed, Tracer};
+
+use super::{CacheEntry, Node, State, TerminalNode, non_terminal_result};
+
+/// An entry of [`State::cache`]
+#[derive(Debug, Clone)]
+pub(super) enum Memo<'a, T: TerminalNode> {
+    /// The final result
+    Done(CacheEntry<'a, T>),
+    /// Being parsed, the index in [`State::in_progress`]
+    InProgress(usize),
+}
+
+/// A non-terminal being parsed
+#[derive(Debug, Clone)]
+pub(super) struct InProgress<'a, T: TerminalNode> {
+    name: &'a str,
+    pos: usize,
+    /// The result of the rule body so far, matched by recursive references
+    seed: CacheEntry<'a, T>,
+    /// Whether the seed was used, in which case the body is parsed again
+    recursive: bool,
+    /// [`State::seed_used`] when this one started
+    seed_used_before: Option<usize>,
+}
+
+/// What to do with the result of a rule body, see [`State::exit_non_terminal`]
+pub(super) enum Exit<'a, T: TerminalNode> {
+    /// The result is the new seed, the body must be parsed again
+    Grow,
+    /// The final result
+    Done(CacheEntry<'a, T>),
+}
+
+impl<'a, T: TerminalNode> State<'a, T> {
+    /// Parse the rule `name`, whose body is `node`, at `pos` as the root
+    ///
+    /// The root is a non-terminal like the others, so it grows if it is left
+    /// recursive, but its result is the one of the body.
+    pub(crate) fn parse_rule_at(
+        &mut self,
+        name: &'a str,
+        node: &'a Node<T>,
+        src: &T::Src,
+        pos: usize,
+        tracer: &impl Tracer<&'a Node<T>>,
+    ) -> anyhow::Result<Option<Parsed<&'a Node<T>>>>
+    where
+        T: 'static,
+    {
+        let parsed = match self.enter_non_terminal(name, pos) {
+            Some(memoized) => memoized,
+            None => loop {
+                let body = naive::parse_with_state_at(src, node, pos, self, tracer)?;
+                if let Exit::Done(parsed) = self.exit_non_terminal(non_terminal_result(name, body, false)) {
+                    // nothing reads the cached root
+                    self.cache.remove(&(name, pos));
+                    break parsed;
+                }
+            },
+        };
+        Ok(parsed.map(|mut parsed| {
+            parsed.token = parsed.token.children.pop().unwrap();
+            parsed
+        }))
+    }
+
+    /// The cached result of `name` at `pos`, or its seed if it is being
+    /// parsed there, which is then left recursive
+    ///
+    /// Otherwise, `None` and the body of `name` is recorded as being parsed at `pos`.
+    pub(super) fn enter_non_terminal(&mut self, name: &'a str, pos: usize) -> Option<CacheEntry<'a, T>> {
+        match self.cache.entry((name, pos)) {
+            Entry::Occupied(entry) => match *entry.get() {
+                Memo::Done(ref cached) => Some(cached.clone()),
+                Memo::InProgress(index) => {
+                    self.in_progress[index].recursive = true;
+                    self.seed_used = Some(self.seed_used.map_or(index, |i| i.min(index)));
+                    Some(self.in_progress[index].seed.clone())
+                }
+            },
+            Entry::Vacant(entry) => {
+                // the non-terminals being parsed are in the own cache, see `State::inherit_seeds`
+                #[cfg(feature = "parallel")]
+                if let Some(Memo::Done(cached)) = self.forked.as_ref().and_then(|f| f.get(&(name, pos))) {
+                    return Some(cached.clone());
+                }
+                entry.insert(Memo::InProgress(self.in_progress.len()));
+                self.in_progress.push(InProgress {
+                    name,
+                    pos,
+                    seed: None,
+                    recursive: false,
+                    seed_used_before: self.seed_used.take(),
+                });
+                None
+            }
+        }
+    }
+
+    /// Parse as if the non-terminals of `in_progress` were being parsed, for an `Alt` branch
+    ///
+    /// A branch reaching one of them again at the position it started at
+    /// matches its seed, see [`State::join_seeds`].
+    #[cfg(feature = "parallel")]
+    pub(super) fn inherit_seeds(&mut self, in_progress: &[InProgress<'a, T>]) {
+        for (index, entry) in in_progress.iter().enumerate() {
+            self.cache.insert((entry.name, entry.pos), Memo::InProgress(index));
+        }
+        self.in_progress = in_progress.to_vec();
+    }
+
+    /// Record the seeds used by a branch, whose state was created with [`State::inherit_seeds`]
+    ///
+    /// `in_progress` and `seed_used` are the ones of the state of the branch
+    /// once parsed, the non-terminals whose seed it used are grown.
+    #[cfg(feature = "parallel")]
+    pub(super) fn join_seeds(&mut self, in_progress: &[InProgress<'a, T>], seed_used: Option<usize>) {
+        for (entry, branch) in self.in_progress.iter_mut().zip(in_progress) {
+            entry.recursive |= branch.recursive;
+        }
+        self.seed_used = match (self.seed_used, seed_used) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    /// Handle the result of the body of the innermost non-terminal being parsed
+    ///
+    /// The final result is cached, unless it depends on the seed of another
+    /// non-terminal being parsed.
+    pub(super) fn exit_non_terminal(&mut self, parsed: Option<Parsed<&'a Node<T>>>) -> Exit<'a, T> {
+        let entry = self.in_progress.last_mut().expect("a non-terminal is being parsed");
+        let parsed = if entry.recursive {
+            let seed_end = entry.seed.as_ref().map(|s| s.token.span.end);
+            if let Some(parsed) = parsed.filter(|p| seed_end.is_none_or(|end| p.token.span.end > end)) {
+                entry.seed = Some(parsed);
+                return Exit::Grow;
+            }
+            entry.seed.take()
+        } else {
+            parsed
+        };
+
+        let entry = self.in_progress.pop().unwrap();
+        let key = (entry.name, entry.pos);
+        // the seeds of the non-terminals started after this one were only used while parsing it
+        let index = self.in_progress.len();
+        let outer = self.seed_used.filter(|&i| i < index);
+        self.seed_used = match (entry.seed_used_before, outer) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        if outer.is_none() {
+            self.cache_result(key, parsed.clone());
+        } else {
+            self.cache.remove(&key);
+        }
+        Exit::Done(parsed)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{basic::{Grammar, Text}, parsers::naive::Diagnostic};
+
+    fn arithmetic() -> Grammar<Text> {
+        Grammar::load_ebnf(r#"
+            expr = (expr , ("+" | "-") , term) | term;
+            term = (term , ("*" | "/") , factor) | factor;
+            factor = ("(" , expr , ")") | number;
+            number = #'[0-9]+';
+        "#).unwrap()
+    }
+
+    #[test]
+    fn direct_left_recursion() {
+        let grammar = arithmetic();
+        let sexpr = |src: &str| {
+            let (token, diagnostics) = grammar.parse_non_term("expr", src).unwrap().unwrap();
+            assert_eq!(token.validate_spans(), Ok(()));
+            assert!(diagnostics.is_empty(), "{src}: {diagnostics:?}");
+            assert_eq!(token.span, 0..src.len());
+            token.to_sexpr()
+        };
+
+        assert_eq!(sexpr("1"), "(term 0..1 (factor 0..1 (number 0..1)))");
+        // left associative
+        assert_eq!(
+            sexpr("1-2-3"),
+            "(_ 0..5 (expr 0..3 (expr 0..1 (term 0..1 (factor 0..1 (number 0..1)))) \
+            (term 2..3 (factor 2..3 (number 2..3)))) (term 4..5 (factor 4..5 (number 4..5))))",
+        );
+        assert_eq!(
+            sexpr("1+2*3"),
+            "(_ 0..5 (expr 0..1 (term 0..1 (factor 0..1 (number 0..1)))) \
+            (term 2..5 (term 2..3 (factor 2..3 (number 2..3))) (factor 4..5 (number 4..5))))",
+        );
+        sexpr("(1+2)*(3-4/5)-6");
+        // growing does not deepen the parser stack
+        let long = vec!["1"; 500].join("+");
+        sexpr(&long);
+
+        // what cannot be grown is reported as usual
+        let (token, diagnostics) = grammar.parse_non_term("expr", "1+2+").unwrap().unwrap();
+        assert_eq!(token.span, 0..4);
+        assert_eq!(diagnostics, [Diagnostic::Incomplete { span: 4..4, expected: "term".to_string() }]);
+        assert_eq!(grammar.parse_non_term("expr", "").unwrap(), None);
+        assert_eq!(grammar.parse_non_term("expr", "+").unwrap(), None);
+    }
+
+    #[test]
+    fn rules_using_a_seed_are_not_cached() {
+        // `sum` at 0 depends on the seed of `expr`, caching it would stop the growth
+        let grammar = Grammar::<Text>::load_ebnf(r#"
+            expr = sum | "1";
+            sum = expr , "+" , "1";
+        "#).unwrap();
+        let (token, diagnostics) = grammar.parse_non_term("expr", "1+1+1").unwrap().unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(token.to_sexpr(), "(sum 0..5 (expr 0..3 (sum 0..3 (expr 0..1))))");
+
+        // a nested left recursion grows on its own
+        let grammar = Grammar::<Text>::load_ebnf(r#"
+            list = (list , "," , item) | item;
+            item = "x" | ("[" , list , "]");
+        "#).unwrap();
+        let (token, diagnostics) = grammar.parse_non_term("list", "x,[x,x],x").unwrap().unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(token.span, 0..9);
+        // the root is not wrapped
+        assert_eq!(token.iter_grams("list").count(), 4);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel() {
+        let grammar = arithmetic();
+        for src in ["1+2*3", "(1+2)*(3-4/5)-6", "1+"] {
+            let sequential = grammar.parse_non_term("expr", src).unwrap();
+            let parallel = grammar.parser().parallel_alt(2).parse_non_term("expr", src).unwrap();
+            assert_eq!(sequential, parallel, "{src}");
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Concurrent parsing of `Alt` branches, enabled by the `parallel` feature
+
+use alloc::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::{basic::{Node, TerminalNode}, collections::Map, parsers::naive::{self, NoTracer, Parsed}, prelude::*};
+
+use super::{IncompleteSpans, State, choice_key, left_recursion::{InProgress, Memo}};
+
+type BranchParser<'a, T> = fn(
+    &mut State<'a, T>,
+    &'a [Node<T>],
+    &<T as TerminalNode>::Src,
+    usize,
+) -> anyhow::Result<Vec<Parsed<&'a Node<T>>>>;
+
+/// What the state of a branch hands back, see [`State::join_seeds`]
+type Branch<'a, T> = (
+    Option<Parsed<&'a Node<T>>>,
+    Map<(&'a str, usize), Memo<'a, T>>,
+    Vec<InProgress<'a, T>>,
+    Option<usize>,
+    usize,
+);
+
+/// The options of the enclosing [`State`] that the states of the branches are created with
+///
+/// The options held in `Rc`s are not among them, see [`State::with_parallel_alt`].
+#[derive(Debug, Clone, Copy)]
+pub(super) struct BranchOptions {
+    max_terminal_match: Option<usize>,
+    max_repetitions: Option<usize>,
+    line_bounded: bool,
+    captures: bool,
+    incomplete_spans: IncompleteSpans,
+    revisit_choices: bool,
+    cache_capacity: Option<usize>,
+}
+
+impl BranchOptions {
+    pub(super) fn of<T: TerminalNode>(state: &State<'_, T>) -> Self {
+        Self {
+            max_terminal_match: state.max_terminal_match,
+            max_repetitions: state.max_repetitions,
+            line_bounded: state.line_bounded,
+            captures: state.captures,
+            incomplete_spans: state.incomplete_spans(),
+            revisit_choices: state.revisit_choices,
+            cache_capacity: state.cache_capacity,
+        }
+    }
+
+    fn apply<'a, T: TerminalNode>(self, state: State<'a, T>) -> State<'a, T> {
+        let mut state = state.with_incomplete_spans(self.incomplete_spans);
+        state.max_terminal_match = self.max_terminal_match;
+        state.max_repetitions = self.max_repetitions;
+        state.line_bounded = self.line_bounded;
+        state.captures = self.captures;
+        state.revisit_choices = self.revisit_choices;
+        state.cache_capacity = self.cache_capacity;
+        state
+    }
+}
+
+/// The cache of a [`State`] while its `Alt` branches are parsed, read by their states
+#[derive(Debug)]
+pub(crate) struct Forked<'a, T: TerminalNode> {
+    cache: Map<(&'a str, usize), Memo<'a, T>>,
+    /// The cache of the state it forked from in turn, if any
+    parent: Option<Arc<Forked<'a, T>>>,
+}
+
+impl<'a, T: TerminalNode> Forked<'a, T> {
+    pub(super) fn get(&self, key: &(&'a str, usize)) -> Option<&Memo<'a, T>> {
+        self.cache.get(key).or_else(|| self.parent.as_ref()?.get(key))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ParallelAlt<'a, T: TerminalNode> {
+    pub(super) min_branches: usize,
+    /// Monomorphized with the `Send + Sync` bounds of [`State::with_parallel_alt`]
+    pub(super) parse: BranchParser<'a, T>,
+}
+
+impl<'a, T: TerminalNode + Send + Sync> State<'a, T>
+where
+    T::Src: Sync,
+{
+    /// Parse the branches of `Alt` nodes having at least `min_branches` branches concurrently
+    ///
+    /// Every branch is parsed with its own [`State`], which reads the cache
+    /// of the enclosing parse and the seeds of the left recursive
+    /// non-terminals being parsed. The results a branch caches are added to
+    /// the enclosing cache once all the branches are parsed, the branches
+    /// do not share them with each other, so non-terminals reachable from
+    /// several branches may be parsed more than once. The selected branch is
+    /// the same as in sequential parsing since the cache only memoizes
+    /// results and never changes them.
+    ///
+    /// The states of the branches cannot share the indexes and the callback
+    /// the enclosing state holds in `Rc`s, so a [`Parser`](crate::basic::Parser)
+    /// with this option fails to parse if it also has
+    /// [`prune_alt_branches`](crate::basic::Parser::prune_alt_branches),
+    /// [`memoize_shared_nodes`](crate::basic::Parser::memoize_shared_nodes) or
+    /// [`on_complete`](crate::basic::Parser::on_complete).
+    pub fn with_parallel_alt(mut self, min_branches: usize) -> Self {
+        self.parallel_alt = Some(ParallelAlt {
+            min_branches,
+            parse: parse_branches::<T>,
+        });
+        self
+    }
+}
+
+impl<'a, T: TerminalNode> State<'a, T> {
+    /// The parallel configuration, if `branches` should be parsed concurrently
+    pub(super) fn parallel_alt_for(&self, branches: &[Node<T>]) -> Option<ParallelAlt<'a, T>> {
+        self.parallel_alt.clone().filter(|p| branches.len() >= p.min_branches.max(2))
+    }
+}
+
+fn parse_branches<'a, T: TerminalNode + Send + Sync>(
+    state: &mut State<'a, T>,
+    branches: &'a [Node<T>],
+    src: &T::Src,
+    pos: usize,
+) -> anyhow::Result<Vec<Parsed<&'a Node<T>>>>
+where
+    T::Src: Sync,
+{
+    let grammar = state.grammar;
+    let min_branches = state.parallel_alt.as_ref().map_or(2, |p| p.min_branches);
+    let options = BranchOptions::of(state);
+    let forked = Arc::new(Forked {
+        cache: core::mem::take(&mut state.cache),
+        parent: state.forked.take(),
+    });
+    let in_progress = &state.in_progress;
+    let results = branches
+        .par_iter()
+        .map(|branch| -> anyhow::Result<Branch<'a, T>> {
+            let mut branch_state = options.apply(State::new(grammar).with_parallel_alt(min_branches));
+            branch_state.forked = Some(forked.clone());
+            branch_state.inherit_seeds(in_progress);
+            let parsed = naive::parse_with_state_at(src, branch, pos, &mut branch_state, &NoTracer)?;
+            Ok((parsed, branch_state.cache, branch_state.in_progress, branch_state.seed_used, branch_state.furthest))
+        })
+        .collect::<Vec<_>>();
+
+    let forked = Arc::into_inner(forked).expect("the states of the branches are dropped");
+    state.cache = forked.cache;
+    state.forked = forked.parent;
+    let mut candidates = vec![];
+    for result in results {
+        let (parsed, cache, in_progress, seed_used, furthest) = result?;
+        state.join_seeds(&in_progress, seed_used);
+        state.furthest = state.furthest.max(furthest);
+        for (key, memo) in cache {
+            if let Memo::Done(cached) = memo
+                && !state.cache.contains_key(&key)
+            {
+                state.cache_result(key, cached);
+            }
+        }
+        // the matches in branch order, selected as in the sequential `poll_choice`
+        candidates.extend(parsed);
+    }
+    Ok(candidates)
+}
+
+/// The candidate of a choice with the greatest [`choice_key`], the first one on ties
+pub(super) fn select_choice<T: TerminalNode>(candidates: Vec<Parsed<&Node<T>>>) -> Option<Parsed<&Node<T>>> {
+    // `max_by_key` would keep the last on ties
+    candidates.into_iter().reduce(|best, next| {
+        if choice_key(&next) > choice_key(&best) { next } else { best }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::basic::{Grammar, Text, Token};
+
+    use super::*;
+
+    #[test]
+    fn same_result_as_sequential() {
+        let grammar = Grammar::load_ebnf(r#"
+            expression = term , (("+" | "-") , term)*;
+            term = factor , (("*" | "/") , factor)*;
+            factor = ("(" , expression , ")") | number | name | call;
+            call = name , "(" , expression , ")";
+            number = digit+;
+            name = #'[a-z]+';
+            digit = "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9";
+        "#).unwrap();
+        let node = Node::<Text>::NonTerm("expression".to_string());
+
+        for src in ["1+2*3", "(1+sin(x))*33-y", "f(g(1)*2", "abc+"] {
+            let sequential = grammar.parse_node(&node, src).unwrap();
+            let parallel: Option<(Token, _)> = naive::parse_recursive(
+                src,
+                &node,
+                State::new(&grammar).with_parallel_alt(2),
+            ).unwrap();
+            assert_eq!(sequential, parallel, "{src}");
+
+            let sequential = grammar.parser().max_terminal_match(2).parse_node(&node, src).unwrap();
+            let parallel: Option<(Token, _)> = naive::parse_recursive(
+                src,
+                &node,
+                State::new(&grammar).with_parallel_alt(2).with_max_terminal_match(2),
+            ).unwrap();
+            assert_eq!(sequential, parallel, "{src}");
+        }
+    }
+
+    #[test]
+    fn left_recursion() {
+        // the choices of `expr` are forked where `expr` is being parsed
+        let grammar = Grammar::load_ebnf(r#"
+            expr = sum | call | name;
+            sum = expr , "+" , name;
+            call = expr , "(" , [expr] , ")";
+            name = #'[a-z]+';
+        "#).unwrap();
+        let (name, node) = grammar.rules.get_key_value("expr").unwrap();
+        for src in ["a+b(c+d)+e", "f()(g)", "a+", "+a", ""] {
+            let mut sequential = State::new(&grammar);
+            let mut parallel = State::new(&grammar).with_parallel_alt(2);
+            let expected = sequential.parse_rule_at(name, node, src, 0, &NoTracer).unwrap();
+            let parsed = parallel.parse_rule_at(name, node, src, 0, &NoTracer).unwrap();
+            assert_eq!(parsed.map(Parsed::detach), expected.map(Parsed::detach), "{src}");
+            // the results of the branches are cached by the enclosing parse
+            assert_eq!(parallel.cache.len(), sequential.cache.len(), "{src}");
+            assert!(parallel.in_progress.is_empty());
+        }
+    }
+}
@@ -1,20 +1,158 @@
-use std::{collections::{BTreeMap, HashMap}, ops::RangeInclusive};
+use alloc::{collections::BTreeMap, rc::Rc};
+use core::ops::RangeInclusive;
 
-use crate::{basic::{Grammar, Node, TerminalNode, Token}, parsers::naive::{AbstractStackState, Diagnostic, Parsed, StackPoll}};
+use crate::{basic::{Grammar, Node, TerminalNode, Token}, collections::Map, parsers::naive::{AbstractStackState, Diagnostic, Parsed, StackPoll}, prelude::*};
+
+use super::left_recursion::{Exit, InProgress, Memo};
+
+/// A cached result, owned since a [`Token`] owns its children
+pub(super) type CacheEntry<'a, T> = Option<Parsed<&'a Node<T>>>;
 
 #[derive(Debug, Clone)]
 pub struct State<'a, T: TerminalNode> {
     pub(super) grammar: &'a Grammar<T>,
-    pub(super) cache: HashMap<(String, usize), Option<Parsed<&'a Node<T>>>>
+    /// Results of non-terminals by `(name, position)`
+    ///
+    /// The keys borrow the names from the grammar, so a lookup does not
+    /// allocate. The results do not share their tokens: a result is cloned
+    /// when cached and on every hit, as the parent node takes its token.
+    pub(super) cache: Map<(&'a str, usize), Memo<'a, T>>,
+    /// See [`State::with_cache_capacity`]
+    pub(super) cache_capacity: Option<usize>,
+    #[cfg(feature = "parallel")]
+    pub(super) parallel_alt: Option<super::parallel::ParallelAlt<'a, T>>,
+    /// The caches of the states this one parses an `Alt` branch for, see [`State::with_parallel_alt`]
+    #[cfg(feature = "parallel")]
+    pub(super) forked: Option<alloc::sync::Arc<super::parallel::Forked<'a, T>>>,
+    pub(super) alt_pruning: Option<Rc<super::pruning::AltPruning<T>>>,
+    pub(super) shared: Option<Rc<super::shared::SharedNodes<T>>>,
+    /// Results of the repeated sub-expressions by `(id, position)`, see [`State::enter_shared`]
+    pub(super) shared_results: Map<(usize, usize), CacheEntry<'a, T>>,
+    /// The repeated sub-expression about to be parsed without its memoized result
+    pub(super) entering_shared: Option<&'a Node<T>>,
+    /// Non-terminals being parsed, innermost last, for left recursion
+    pub(super) in_progress: Vec<InProgress<'a, T>>,
+    /// Smallest index in `in_progress` whose seed was used, the results
+    /// depending on it are not cached
+    pub(super) seed_used: Option<usize>,
+    /// See [`State::with_max_terminal_match`]
+    pub(super) max_terminal_match: Option<usize>,
+    /// See [`State::with_max_repetitions`]
+    pub(super) max_repetitions: Option<usize>,
+    /// See [`State::with_line_bounded_terminals`]
+    pub(super) line_bounded: bool,
+    /// See [`State::with_captures`]
+    pub(super) captures: bool,
+    pub(super) on_complete: Option<super::streaming::OnComplete<'a>>,
+    /// Furthest position reached by each sequence being parsed, innermost
+    /// last, if tracked, see [`State::with_incomplete_spans`]
+    pub(super) reached: Option<Vec<usize>>,
+    /// Furthest position reached by the whole parse, see [`State::furthest`]
+    pub(super) furthest: usize,
+    /// See [`State::with_revisited_choices`]
+    pub(super) revisit_choices: bool,
+    /// Other matches of the choice that just finished, see [`State::exit_choice`]
+    pub(super) alternatives: Vec<Parsed<&'a Node<T>>>,
 }
 
 impl<'a, T: TerminalNode> State<'a, T> {
     pub fn new(grammar: &'a Grammar<T>) -> Self {
         Self {
             grammar,
-            cache: HashMap::new(),
+            cache: Map::new(),
+            cache_capacity: None,
+            #[cfg(feature = "parallel")]
+            parallel_alt: None,
+            #[cfg(feature = "parallel")]
+            forked: None,
+            alt_pruning: None,
+            shared: None,
+            shared_results: Map::new(),
+            entering_shared: None,
+            in_progress: vec![],
+            seed_used: None,
+            max_terminal_match: None,
+            max_repetitions: None,
+            line_bounded: false,
+            captures: false,
+            on_complete: None,
+            reached: None,
+            furthest: 0,
+            revisit_choices: false,
+            alternatives: vec![],
+        }
+    }
+
+    /// Match terminals with [`TerminalNode::parses_within`] and `max_len`
+    pub fn with_max_terminal_match(mut self, max_len: usize) -> Self {
+        self.max_terminal_match = Some(max_len);
+        self
+    }
+
+    /// Match terminals with [`TerminalNode::parses_within`] up to the end of their line
+    ///
+    /// The end of the line is given by [`TerminalNode::line_len`], with
+    /// [`State::with_max_terminal_match`] the shorter limit applies.
+    pub fn with_line_bounded_terminals(mut self) -> Self {
+        self.line_bounded = true;
+        self
+    }
+
+    /// The limit of the match of a terminal at `pos`, if any
+    pub(super) fn terminal_window(&self, src: &T::Src, pos: usize) -> Option<usize> {
+        let line_len = if self.line_bounded { T::line_len(src, pos) } else { None };
+        match (self.max_terminal_match, line_len) {
+            (Some(max_len), Some(line_len)) => Some(max_len.min(line_len)),
+            (max_len, line_len) => max_len.or(line_len),
         }
     }
+
+    /// Stop each repetition after `max` matches, with a [`Diagnostic::RepetitionLimit`]
+    ///
+    /// This applies to the matches of `Rep` and `RepSep` nodes whatever their
+    /// range, and the stopped repetition is incomplete.
+    pub fn with_max_repetitions(mut self, max: usize) -> Self {
+        self.max_repetitions = Some(max);
+        self
+    }
+
+    /// Record the [`TerminalNode::captures`] of terminals in the meta of their tokens, see [`Token::capture`]
+    pub fn with_captures(mut self) -> Self {
+        self.captures = true;
+        self
+    }
+}
+
+/// Ordering of the candidates of a choice, the greatest is selected
+pub(super) fn choice_key<T: TerminalNode>(parsed: &Parsed<&Node<T>>) -> (usize, bool) {
+    (parsed.token.span.end, parsed.incomplete.is_none())
+}
+
+/// The result of the non-terminal `name` whose rule body parsed as `body`
+///
+/// The body is wrapped in a token named after the rule, unless it is a `fragment`.
+pub(super) fn non_terminal_result<'a, T: TerminalNode>(
+    name: &str,
+    body: Option<Parsed<&'a Node<T>>>,
+    fragment: bool,
+) -> Option<Parsed<&'a Node<T>>> {
+    if fragment {
+        return body;
+    }
+    let Parsed { token, diagnostics, incomplete } = body?;
+    let start = token.span.start;
+    let end = token.span.end;
+    Some(Parsed {
+        token: Token {
+            span: start..end,
+            gram: Some(name.to_string()),
+            tags: vec![],
+            meta: Default::default(),
+            children: vec![token],
+        },
+        diagnostics,
+        incomplete,
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -23,89 +161,138 @@ pub enum StackState<'a, T: TerminalNode> {
         elements: &'a[Node<T>],
         parsed: Vec<Token>,
         diagnostics: Vec<Diagnostic>,
+        /// Length of `diagnostics` before each element of `parsed`
+        marks: Vec<usize>,
+        /// Other matches of each element of `parsed` that is a choice, see [`State::with_revisited_choices`]
+        alternatives: Vec<Vec<Parsed<&'a Node<T>>>>,
+        /// Index of the lazy repetition the node being parsed is one more match of
+        extending: Option<usize>,
+        /// Furthest partial match, the result if backtracking does not help
+        failure: Option<Box<Parsed<&'a Node<T>>>>,
     },
     ParsingChoice {
         start_pos: usize,
         elements: &'a[Node<T>],
         current: usize,
-        parsed: Vec<(Parsed<&'a Node<T>>, usize)>,
+        /// Longest match so far
+        best: Option<Parsed<&'a Node<T>>>,
+        /// Branches that can match, `None` if all are tried
+        viable: Option<Vec<bool>>,
+        /// Every match so far, in branch order, instead of `best` if choices are revisited
+        candidates: Option<Vec<Parsed<&'a Node<T>>>>,
     },
     ParsingRepetition {
         start_pos: usize,
         element: &'a Node<T>,
         range: RangeInclusive<usize>,
+        lazy: bool,
         parsed: Vec<Token>,
         diagnostics: Vec<Diagnostic>,
     },
+    ParsingSeparatedRepetition {
+        start_pos: usize,
+        element: &'a Node<T>,
+        sep: &'a Node<T>,
+        range: RangeInclusive<usize>,
+        parsed: Vec<Token>,
+        diagnostics: Vec<Diagnostic>,
+        /// Whether the node being parsed is `sep` rather than `element`
+        expecting_sep: bool,
+    },
     ParsingNonTerminal {
         start_pos: usize,
         name: &'a str,
     },
     ParsingTagged {
-        tag: String,
+        tags: &'a [String],
     },
     ParsingMeta {
         meta: BTreeMap<String, String>,
     },
+    ParsingExcept {
+        start_pos: usize,
+        minus: &'a Node<T>,
+        /// The match of the node, `None` while it is being parsed
+        matched: Option<Box<Parsed<&'a Node<T>>>>,
+    },
+    /// A repeated sub-expression whose result is memoized, see [`Parser::memoize_shared_nodes`]
+    ///
+    /// [`Parser::memoize_shared_nodes`]: crate::basic::Parser::memoize_shared_nodes
+    ParsingShared {
+        id: usize,
+        start_pos: usize,
+    },
 }
 
 impl<'a, T: TerminalNode + 'static> StackState<'a, T> {
+    #[allow(clippy::too_many_arguments)]
     fn poll_choice(
         next: Option<Parsed<&'a Node<T>>>,
         start_pos: usize,
         elements: &'a [Node<T>],
-        mut current: usize,
-        mut parsed: Vec<(Parsed<&'a Node<T>>, usize)>,
+        current: usize,
+        mut best: Option<Parsed<&'a Node<T>>>,
+        viable: Option<Vec<bool>>,
+        mut candidates: Option<Vec<Parsed<&'a Node<T>>>>,
+        state: &mut State<'a, T>,
     ) -> StackPoll<&'a Node<T>> {
-        assert_ne!(elements.len(), 0, "Empty choice");
-        parsed.extend(next.map(|p| (p, current)));
-        current += 1;
-        if current >= elements.len() {
-            if parsed.is_empty() {
-                StackPoll::Finished(None)
-            } else {
-                // pick the longest one
-                // TODO avoid sorting
-                parsed.sort_by_key(|(p, _)| (p.token.span.end, p.incomplete.is_none()));
-                StackPoll::Finished(parsed.pop().map(|p| p.0))
-            }
-        } else {
-            StackPoll::Feed(
+        // keep the longest one, preferring complete parses, the first wins on ties
+        if let Some(candidates) = &mut candidates {
+            candidates.extend(next);
+        } else if let Some(next) = next
+            && best.as_ref().is_none_or(|b| choice_key(&next) > choice_key(b))
+        {
+            best = Some(next);
+        }
+        match super::pruning::next_viable(viable.as_deref(), current + 1, elements.len()) {
+            Some(current) => StackPoll::Feed(
                 Self::ParsingChoice {
                     start_pos,
                     elements,
                     current,
-                    parsed,
+                    best,
+                    viable,
+                    candidates,
                 },
                 &elements[current],
                 start_pos,
-            )
+            ),
+            None => match candidates {
+                Some(candidates) => StackPoll::Finished(state.exit_choice(candidates)),
+                None => StackPoll::Finished(best),
+            },
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn poll_repetition(
         next: Option<Parsed<&'a Node<T>>>,
         element: &'a Node<T>,
         range: RangeInclusive<usize>,
+        lazy: bool,
         mut parsed: Vec<Token>,
         start_pos: usize,
         mut diagnostics: Vec<Diagnostic>,
+        max_repetitions: Option<usize>,
     ) -> StackPoll<&'a Node<T>> {
-        // FIXME this is not idiomatic
-        let next = if let Some(next) = next {
-            if next.token.span.start >= next.token.span.end {
-                None
-            } else {
-                Some(next)
+        // a body that matches without advancing would match forever, so the
+        // repetition stops there, using the empty match only if more are
+        // required, as many times as they are: it would match again in place
+        let pos = parsed.last().map(|f| f.span.end).unwrap_or(start_pos);
+        let (next, stuck) = match next {
+            Some(next) if next.token.span.end <= pos => {
+                (Some(next).filter(|_| parsed.len() < *range.start()), true)
             }
-        } else {
-            None
+            next => (next, false),
         };
 
         if let Some(Parsed { token, diagnostics: sub_diag, incomplete }) = next {
+            if stuck {
+                repeat_up_to(&mut parsed, &token, *range.start());
+            }
             parsed.push(token);
             diagnostics.extend(sub_diag);
-            if parsed.len() >= *range.end() {
+            if parsed.len() >= *range.end() || stuck || (lazy && parsed.len() >= *range.start()) {
                 let start = parsed.first().map(|f| f.span.start).unwrap_or(start_pos);
                 let end = parsed.last().map(|f| f.span.end).unwrap_or(start_pos);
                 StackPoll::Finished(Some(Parsed {
@@ -119,6 +306,21 @@ impl<'a, T: TerminalNode + 'static> StackState<'a, T> {
                     diagnostics,
                     incomplete,
                 }))
+            } else if let Some(limit) = max_repetitions.filter(|max| parsed.len() >= *max) {
+                let start = parsed.first().map(|f| f.span.start).unwrap_or(start_pos);
+                let end = parsed.last().map(|f| f.span.end).unwrap_or(start_pos);
+                diagnostics.push(Diagnostic::RepetitionLimit { span: start..end, limit });
+                StackPoll::Finished(Some(Parsed {
+                    token: Token {
+                        span: start..end,
+                        gram: None,
+                        tags: vec![],
+                        meta: Default::default(),
+                        children: parsed,
+                    },
+                    diagnostics,
+                    incomplete: Some(element),
+                }))
             } else {
                 let end = parsed.last().unwrap().span.end;
                 StackPoll::Feed(
@@ -126,6 +328,7 @@ impl<'a, T: TerminalNode + 'static> StackState<'a, T> {
                         start_pos,
                         element,
                         range,
+                        lazy,
                         parsed,
                         diagnostics,
                     },
@@ -133,7 +336,7 @@ impl<'a, T: TerminalNode + 'static> StackState<'a, T> {
                     end,
                 )
             }
-        } else if parsed.len() == 0 && *range.start() > 0 {
+        } else if parsed.is_empty() && *range.start() > 0 {
             StackPoll::Finished(None)
         } else if parsed.len() < *range.start() {
             let start = parsed.first().map(|f| f.span.start).unwrap_or(start_pos);
@@ -171,42 +374,120 @@ impl<'a, T: TerminalNode + 'static> StackState<'a, T> {
         }
     }
 
-    fn poll_non_terminal(
+    #[allow(clippy::too_many_arguments)]
+    fn poll_separated_repetition(
         next: Option<Parsed<&'a Node<T>>>,
-        name: &'a str,
+        element: &'a Node<T>,
+        sep: &'a Node<T>,
+        range: RangeInclusive<usize>,
+        mut parsed: Vec<Token>,
         start_pos: usize,
-        state: &mut State<'a, T>,
+        mut diagnostics: Vec<Diagnostic>,
+        expecting_sep: bool,
+        max_repetitions: Option<usize>,
     ) -> StackPoll<&'a Node<T>> {
-        let cache_key = (name.to_string(), start_pos);
-        let parsed = if let Some(Parsed { token, diagnostics, incomplete }) = next {
-            let start = token.span.start;
-            let end = token.span.end;
-            Some(Parsed {
-                token: Token {
-                    span: start..end,
-                    gram: Some(name.to_string()),
-                    tags: vec![],
-                    meta: Default::default(),
-                    children: vec![token],
-                },
+        let end = parsed.last().map(|f| f.span.end).unwrap_or(start_pos);
+        let mut limited = None;
+
+        let feed = |parsed, diagnostics, expecting_sep, node, pos| StackPoll::Feed(
+            Self::ParsingSeparatedRepetition {
+                start_pos,
+                element,
+                sep,
+                range: range.clone(),
+                parsed,
                 diagnostics,
-                incomplete,
-            })
+                expecting_sep,
+            },
+            node,
+            pos,
+        );
+
+        if expecting_sep {
+            // an incomplete separator is not consumed
+            if let Some(next) = next && next.incomplete.is_none() {
+                return feed(parsed, diagnostics, false, element, next.token.span.end);
+            }
+        } else if let Some(Parsed { token, diagnostics: sub_diag, .. }) = next {
+            // the element (and the separator before it) must make progress,
+            // an empty match is only used if more elements are required, as
+            // many times as they are
+            if token.span.end > end {
+                let next_end = token.span.end;
+                parsed.push(token);
+                diagnostics.extend(sub_diag);
+                if parsed.len() < *range.end() {
+                    match max_repetitions.filter(|max| parsed.len() >= *max) {
+                        Some(limit) => limited = Some(limit),
+                        None => return feed(parsed, diagnostics, true, sep, next_end),
+                    }
+                }
+            } else if parsed.len() < *range.start() {
+                repeat_up_to(&mut parsed, &token, *range.start());
+                parsed.push(token);
+                diagnostics.extend(sub_diag);
+            }
+        }
+
+        // the trailing separator, if any, is not part of the match
+        let start = parsed.first().map(|f| f.span.start).unwrap_or(start_pos);
+        let end = parsed.last().map(|f| f.span.end).unwrap_or(start_pos);
+        let incomplete = if parsed.is_empty() && *range.start() > 0 {
+            return StackPoll::Finished(None);
+        } else if let Some(limit) = limited {
+            diagnostics.push(Diagnostic::RepetitionLimit { span: start..end, limit });
+            Some(element)
+        } else if parsed.len() < *range.start() {
+            diagnostics.push(Diagnostic::Incomplete {
+                span: end..end,
+                expected: sep.to_ebnf(),
+            });
+            Some(sep)
         } else {
             None
         };
-        if !state.cache.contains_key(&cache_key) {
-            state.cache.insert(cache_key.clone(), parsed.clone()); // TODO avoid cloning id
+        StackPoll::Finished(Some(Parsed {
+            token: Token {
+                span: start..end,
+                gram: None,
+                tags: vec![],
+                meta: Default::default(),
+                children: parsed,
+            },
+            diagnostics,
+            incomplete,
+        }))
+    }
+
+    fn poll_non_terminal(
+        next: Option<Parsed<&'a Node<T>>>,
+        name: &'a str,
+        start_pos: usize,
+        state: &mut State<'a, T>,
+    ) -> StackPoll<&'a Node<T>> {
+        let parsed = non_terminal_result(name, next, state.grammar.is_fragment(name));
+        match state.exit_non_terminal(parsed) {
+            Exit::Grow => {
+                let node = &state.grammar.rules[name];
+                StackPoll::Feed(Self::ParsingNonTerminal { start_pos, name }, node, start_pos)
+            }
+            Exit::Done(parsed) => {
+                if let Some(parsed) = &parsed
+                    && !state.grammar.is_fragment(name)
+                {
+                    state.completed(name, &parsed.token);
+                }
+                StackPoll::Finished(parsed)
+            }
         }
-        StackPoll::Finished(parsed)
     }
 
     fn poll_tagged(
         next: Option<Parsed<&'a Node<T>>>,
-        tag: String,
+        tags: &'a [String],
     ) -> StackPoll<&'a Node<T>> {
         if let Some(mut next) = next {
-            next.token.tags.push(tag);
+            next.token.tags.extend(tags.iter().cloned());
             StackPoll::Finished(Some(next))
         } else {
             StackPoll::Finished(None)
@@ -225,21 +506,81 @@ impl<'a, T: TerminalNode + 'static> StackState<'a, T> {
         }
     }
 
+    fn poll_except(
+        next: Option<Parsed<&'a Node<T>>>,
+        start_pos: usize,
+        minus: &'a Node<T>,
+        matched: Option<Box<Parsed<&'a Node<T>>>>,
+    ) -> StackPoll<&'a Node<T>> {
+        let Some(matched) = matched else {
+            // the node matched, `minus` is parsed at the same position
+            return match next {
+                Some(next) => StackPoll::Feed(
+                    Self::ParsingExcept { start_pos, minus, matched: Some(Box::new(next)) },
+                    minus,
+                    start_pos,
+                ),
+                None => StackPoll::Finished(None),
+            };
+        };
+        let excluded = next.is_some_and(|n| n.incomplete.is_none() && n.token.span.end == matched.token.span.end);
+        StackPoll::Finished(if excluded { None } else { Some(*matched) })
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn poll_sequence(
         next: Option<Parsed<&'a Node<T>>>,
         elements: &'a[Node<T>],
         mut parsed: Vec<Token>,
         mut diagnostics: Vec<Diagnostic>,
+        mut marks: Vec<usize>,
+        mut alternatives: Vec<Vec<Parsed<&'a Node<T>>>>,
+        next_alternatives: Vec<Parsed<&'a Node<T>>>,
+        extending: Option<usize>,
+        failure: Option<Box<Parsed<&'a Node<T>>>>,
     ) -> StackPoll<&'a Node<T>>{
-        assert_ne!(elements.len(), 0, "Empty sequence");
+        if let Some(index) = extending {
+            // one more match of a lazy repetition, which must make progress
+            let rep = &mut parsed[index];
+            match next {
+                Some(Parsed { token, diagnostics: sub_diag, incomplete: None }) if token.span.end > rep.span.end => {
+                    rep.span.end = token.span.end;
+                    rep.children.push(token);
+                    diagnostics.extend(sub_diag);
+                    let end = rep.span.end;
+                    return StackPoll::Feed(
+                        Self::ParsingSequence {
+                            elements,
+                            parsed,
+                            diagnostics,
+                            marks,
+                            alternatives,
+                            extending: None,
+                            failure,
+                        },
+                        &elements[index + 1],
+                        end,
+                    );
+                }
+                _ => {
+                    let failure = failure.expect("backtracking follows a failure");
+                    return Self::backtrack(elements, parsed, diagnostics, marks, alternatives, index, failure);
+                }
+            }
+        }
+
         // TODO report incomplete sequence
         if let Some(Parsed { token, diagnostics: sub_diag, incomplete }) = next {
+            // only the choices that are elements of the sequence are revisited
+            let is_choice = matches!(elements[parsed.len()].unannotated(), Node::Alt(_));
+            alternatives.push(if is_choice { next_alternatives } else { vec![] });
+            marks.push(diagnostics.len());
             parsed.push(token);
             diagnostics.extend(sub_diag);
             if elements.len() == parsed.len() {
                 let start = parsed.first().unwrap().span.start;
                 let end = parsed.last().unwrap().span.end;
-                return StackPoll::Finished(Some(Parsed {
+                StackPoll::Finished(Some(Parsed {
                     token: Token {
                         span: start..end,
                         gram: None,
@@ -249,44 +590,138 @@ impl<'a, T: TerminalNode + 'static> StackState<'a, T> {
                     },
                     diagnostics,
                     incomplete,
-                }));
+                }))
             } else {
                 let n = parsed.len();
                 let end = parsed.last().unwrap().span.end;
-                return StackPoll::Feed(
+                StackPoll::Feed(
                     Self::ParsingSequence {
                         elements,
                         parsed,
                         diagnostics,
+                        marks,
+                        alternatives,
+                        extending: None,
+                        failure,
                     },
                     &elements[n],
                     end,
-                );
+                )
             }
+        } else if parsed.is_empty() {
+            StackPoll::Finished(None)
         } else {
-            if parsed.is_empty() {
-                return StackPoll::Finished(None);
-            } else {
-                let start = parsed.first().unwrap().span.start;
-                let end = parsed.last().unwrap().span.end;
-                let n = parsed.len();
-                let expected = &elements[n];
-                // TODO more specific error
-                diagnostics.push(Diagnostic::Incomplete {
-                    span: end..end,
-                    expected: expected.to_ebnf(),
-                });
-                return StackPoll::Finished(Some(Parsed {
-                    token: Token {
-                        span: start..end,
-                        gram: None,
-                        tags: vec![],
-                        meta: Default::default(),
-                        children: parsed,
+            let start = parsed.first().unwrap().span.start;
+            let end = parsed.last().unwrap().span.end;
+            let n = parsed.len();
+            let expected = &elements[n];
+            // TODO more specific error
+            let mut partial_diagnostics = diagnostics.clone();
+            partial_diagnostics.push(Diagnostic::Incomplete {
+                span: end..end,
+                expected: expected.to_ebnf(),
+            });
+            let partial = Parsed {
+                token: Token {
+                    span: start..end,
+                    gram: None,
+                    tags: vec![],
+                    meta: Default::default(),
+                    children: parsed.clone(),
+                },
+                diagnostics: partial_diagnostics,
+                incomplete: Some(expected),
+            };
+            // if backtracking does not help, the furthest attempt is reported
+            let failure = match failure {
+                Some(failure) if choice_key(&failure) >= choice_key(&partial) => failure,
+                _ => Box::new(partial),
+            };
+            Self::backtrack(elements, parsed, diagnostics, marks, alternatives, n, failure)
+        }
+    }
+
+    /// Retry the sequence from the last repetition or choice before `before`
+    /// that can match differently, dropping the elements after it
+    ///
+    /// A lazy repetition matches once more, a greedy one gives back its last
+    /// match, a choice takes its next alternative. Without any, the sequence
+    /// fails with `failure`.
+    fn backtrack(
+        elements: &'a[Node<T>],
+        mut parsed: Vec<Token>,
+        mut diagnostics: Vec<Diagnostic>,
+        mut marks: Vec<usize>,
+        mut alternatives: Vec<Vec<Parsed<&'a Node<T>>>>,
+        before: usize,
+        failure: Box<Parsed<&'a Node<T>>>,
+    ) -> StackPoll<&'a Node<T>> {
+        // the tags and meta of a repetition are on its own token
+        let index = (0..before).rev().find(|&i| match elements[i].unannotated() {
+            Node::Rep { range, lazy: true, .. } => parsed[i].children.len() < *range.end(),
+            Node::Rep { range, lazy: false, .. } => parsed[i].children.len() > *range.start(),
+            _ => !alternatives[i].is_empty(),
+        });
+        let Some(index) = index else {
+            return StackPoll::Finished(Some(*failure));
+        };
+        if !alternatives[index].is_empty() {
+            // the choice is parsed again as its next alternative
+            diagnostics.truncate(marks[index]);
+            parsed.truncate(index);
+            marks.truncate(index);
+            alternatives.truncate(index + 1);
+            let mut rest = alternatives.pop().unwrap();
+            let next = rest.remove(0);
+            return Self::poll_sequence(Some(next), elements, parsed, diagnostics, marks, alternatives, rest, None, Some(failure));
+        }
+        alternatives.truncate(index + 1);
+        if let Some(&mark) = marks.get(index + 1) {
+            diagnostics.truncate(mark);
+        }
+        parsed.truncate(index + 1);
+        marks.truncate(index + 1);
+        let rep = &mut parsed[index];
+        match elements[index].unannotated() {
+            Node::Rep { node, lazy: true, .. } => {
+                let end = rep.span.end;
+                StackPoll::Feed(
+                    Self::ParsingSequence {
+                        elements,
+                        parsed,
+                        diagnostics,
+                        marks,
+                        alternatives,
+                        extending: Some(index),
+                        failure: Some(failure),
                     },
-                    diagnostics,
-                    incomplete: Some(expected),
-                }));
+                    node,
+                    end,
+                )
+            }
+            _ => {
+                // its diagnostics are the last ones, and past its start
+                let given_back = rep.children.pop().unwrap();
+                while diagnostics.len() > marks[index]
+                    && diagnostics.last().is_some_and(|d| d.main_span().start > given_back.span.start)
+                {
+                    diagnostics.pop();
+                }
+                rep.span.end = rep.children.last().map_or(rep.span.start, |c| c.span.end);
+                let end = rep.span.end;
+                StackPoll::Feed(
+                    Self::ParsingSequence {
+                        elements,
+                        parsed,
+                        diagnostics,
+                        marks,
+                        alternatives,
+                        extending: None,
+                        failure: Some(failure),
+                    },
+                    &elements[index + 1],
+                    end,
+                )
             }
         }
     }
@@ -303,24 +738,52 @@ impl<'a, T: TerminalNode + 'static> AbstractStackState<&'a Node<T>> for StackSta
 
     fn poll(self, next: Option<Parsed<&'a Node<T>>>, state: &mut State<'a, T>) -> StackPoll<&'a Node<T>> {
         match self {
-            Self::ParsingSequence { elements, parsed, diagnostics } => {
-                Self::poll_sequence(next, elements, parsed, diagnostics)
+            Self::ParsingSequence { elements, parsed, diagnostics, marks, alternatives, extending, failure } => {
+                let next_alternatives = state.take_alternatives();
+                match Self::poll_sequence(next, elements, parsed, diagnostics, marks, alternatives, next_alternatives, extending, failure) {
+                    StackPoll::Finished(parsed) => StackPoll::Finished(state.exit_sequence(parsed)),
+                    poll => poll,
+                }
+            },
+            Self::ParsingChoice { start_pos, elements, current, best, viable, candidates } => {
+                Self::poll_choice(next, start_pos, elements, current, best, viable, candidates, state)
             },
-            Self::ParsingChoice { start_pos, elements, current, parsed } => {
-                Self::poll_choice(next, start_pos, elements, current, parsed)
+            Self::ParsingRepetition { element, range, lazy, parsed, start_pos, diagnostics } => {
+                Self::poll_repetition(next, element, range, lazy, parsed, start_pos, diagnostics, state.max_repetitions)
             },
-            Self::ParsingRepetition { element, range, parsed, start_pos, diagnostics } => {
-                Self::poll_repetition(next, element, range, parsed, start_pos, diagnostics)
+            Self::ParsingSeparatedRepetition { element, sep, range, parsed, start_pos, diagnostics, expecting_sep } => {
+                Self::poll_separated_repetition(next, element, sep, range, parsed, start_pos, diagnostics, expecting_sep, state.max_repetitions)
             },
             Self::ParsingNonTerminal { start_pos, name } => {
                 Self::poll_non_terminal(next, name, start_pos, state)
             },
-            Self::ParsingTagged { tag } => {
-                Self::poll_tagged(next, tag)
+            // the alternatives of an annotated choice are annotated as its match
+            Self::ParsingTagged { tags } => {
+                for alternative in &mut state.alternatives {
+                    alternative.token.tags.extend(tags.iter().cloned());
+                }
+                Self::poll_tagged(next, tags)
             },
             Self::ParsingMeta { meta } => {
+                for alternative in &mut state.alternatives {
+                    alternative.token.meta.extend(meta.clone());
+                }
                 Self::poll_meta(next, meta)
             },
+            Self::ParsingExcept { start_pos, minus, matched } => {
+                Self::poll_except(next, start_pos, minus, matched)
+            },
+            Self::ParsingShared { id, start_pos } => {
+                state.exit_shared(id, start_pos, &next);
+                StackPoll::Finished(next)
+            },
         }
     }
-}
\ No newline at end of file
+}
+
+/// Push copies of the empty match `token` until `parsed` has one less than `min` elements
+fn repeat_up_to(parsed: &mut Vec<Token>, token: &Token, min: usize) {
+    while parsed.len() + 1 < min {
+        parsed.push(token.clone());
+    }
+}
@@ -0,0 +1,313 @@
+//! EBNF with a configurable layout
+
+use crate::{basic::{Grammar, RuleOrder, TerminalNode}, prelude::*};
+
+use super::{Node, Prec};
+
+/// Writes nodes and grammars as EBNF, breaking what does not fit in a line
+///
+/// A node is written on one line, as by [`Node::to_ebnf`], if it fits in
+/// the remaining width. Otherwise its children are laid out on several
+/// lines: the elements of sequences fill lines up to the width, the
+/// branches of choices go one per line unless [`EbnfPrinter::wrap_alts`]
+/// is disabled, and parenthesized groups put their content on indented
+/// lines between the brackets. Terminals and references are never broken,
+/// so a line can only be longer than the width if one of them is.
+///
+/// The output reads back as the same grammar with [`Grammar::load_ebnf`],
+/// without the tags and meta, as the one of [`Grammar::to_ebnf`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EbnfPrinter {
+    max_width: usize,
+    indent: usize,
+    wrap_alts: bool,
+    rule_order: RuleOrder,
+}
+
+impl Default for EbnfPrinter {
+    fn default() -> Self {
+        Self {
+            max_width: 80,
+            indent: 4,
+            wrap_alts: true,
+            rule_order: RuleOrder::Alphabetical,
+        }
+    }
+}
+
+impl EbnfPrinter {
+    /// A printer for lines of 80 chars, indented by 4 spaces
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The width lines are kept within, in chars
+    pub fn max_width(mut self, max_width: usize) -> Self {
+        self.max_width = max_width;
+        self
+    }
+
+    /// The number of spaces of each level of indentation
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    /// Whether a choice that does not fit is written one branch per line,
+    /// the default, rather than filling lines like a sequence
+    pub fn wrap_alts(mut self, wrap_alts: bool) -> Self {
+        self.wrap_alts = wrap_alts;
+        self
+    }
+
+    /// The order of the rules written by [`EbnfPrinter::print`], by name by default
+    pub fn rule_order(mut self, rule_order: RuleOrder) -> Self {
+        self.rule_order = rule_order;
+        self
+    }
+
+    /// The EBNF of the rules of `grammar`, in the [order](EbnfPrinter::rule_order) of the printer
+    pub fn print<T: TerminalNode>(&self, grammar: &Grammar<T>) -> String {
+        let mut ebnf = String::new();
+        if let Some(start) = &grammar.start {
+            ebnf.push_str(&format!("(* start: {start} *)\n"));
+        }
+        for name in grammar.sort_rules(&self.rule_order) {
+            ebnf.push_str(&self.print_rule(name, &grammar.rules[name]));
+            ebnf.push('\n');
+        }
+        ebnf
+    }
+
+    /// The EBNF of the rule `name = node;`, without a trailing newline
+    ///
+    /// If it does not fit in a line, the body starts on the next one.
+    pub fn print_rule<T: TerminalNode>(&self, name: &str, node: &Node<T>) -> String {
+        let line = format!("{name} = {};", node.to_ebnf());
+        if width(&line) <= self.max_width {
+            return line;
+        }
+        let mut lines = vec![format!("{name} =")];
+        let max_width = self.inner_width(self.max_width);
+        let mut body = self.lines(node, Prec::Alt, max_width);
+        // make room for the `;` after the last line
+        if body.last().is_some_and(|line| width(line) + 1 > max_width) {
+            body = self.lines(node, Prec::Alt, max_width.saturating_sub(1));
+        }
+        lines.extend(self.indented(body));
+        lines.last_mut().unwrap().push(';');
+        lines.join("\n")
+    }
+
+    /// The EBNF of `node`, without a trailing newline
+    pub fn print_node<T: TerminalNode>(&self, node: &Node<T>) -> String {
+        self.lines(node, Prec::Alt, self.max_width).join("\n")
+    }
+
+    /// The lines of `node`, parenthesized if it binds looser than `prec`, within `max_width`
+    fn lines<T: TerminalNode>(&self, node: &Node<T>, prec: Prec, max_width: usize) -> Vec<String> {
+        let flat = node.to_ebnf_prec(prec, &T::to_ebnf);
+        if width(&flat) <= max_width {
+            return vec![flat];
+        }
+        if let Some(expanded) = node.ebnf_expansion() {
+            return self.lines(&expanded, prec, max_width);
+        }
+        match node {
+            Node::Seq(nodes) | Node::Alt(nodes) if nodes.len() == 1 => self.lines(&nodes[0], prec, max_width),
+            Node::Seq(nodes) if prec > Prec::Seq => {
+                self.group("(", self.fill(nodes, Prec::Seq, " ", self.inner_width(max_width)), ")")
+            }
+            Node::Seq(nodes) => self.fill(nodes, Prec::Seq, " ", max_width),
+            Node::Alt(nodes) if prec > Prec::Alt => self.group("(", self.branches(nodes, self.inner_width(max_width)), ")"),
+            Node::Alt(nodes) => self.branches(nodes, max_width),
+            Node::Rep { node, range, lazy } => {
+                let suffix = match (*range.start(), *range.end(), lazy) {
+                    (0, 1, false) => {
+                        return self.group("[", self.lines(node, Prec::Alt, self.inner_width(max_width)), "]");
+                    }
+                    (0, 1, true) => "??",
+                    (1, usize::MAX, false) => "+",
+                    (1, usize::MAX, true) => "+?",
+                    (0, usize::MAX, false) => "*",
+                    (0, usize::MAX, true) => "*?",
+                    _ => unreachable!("written as its expansion"),
+                };
+                let mut lines = self.lines(node, Prec::Postfix, max_width.saturating_sub(suffix.len()));
+                lines.last_mut().unwrap().push_str(suffix);
+                lines
+            }
+            Node::RepSep { .. } => unreachable!("written as its expansion"),
+            Node::Except { node, minus } => {
+                let operands = [(**node).clone(), (**minus).clone()];
+                if prec > Prec::Except {
+                    self.group("(", self.fill(&operands, Prec::Postfix, " - ", self.inner_width(max_width)), ")")
+                } else {
+                    self.fill(&operands, Prec::Postfix, " - ", max_width)
+                }
+            }
+            Node::Tagged { node, .. } | Node::Meta { node, .. } => self.lines(node, prec, max_width),
+            Node::Terminal(_) | Node::NonTerm(_) | Node::Empty => vec![flat],
+        }
+    }
+
+    /// The branches of a choice, one per line or filling lines
+    fn branches<T: TerminalNode>(&self, nodes: &[Node<T>], max_width: usize) -> Vec<String> {
+        if !self.wrap_alts {
+            return self.fill(nodes, Prec::Postfix, " | ", max_width);
+        }
+        let mut lines = vec![];
+        for (i, branch) in nodes.iter().enumerate() {
+            let prefix = if i == 0 { "  " } else { "| " };
+            for (j, line) in self.lines(branch, Prec::Postfix, max_width.saturating_sub(2)).into_iter().enumerate() {
+                lines.push(format!("{}{line}", if j == 0 { prefix } else { "  " }));
+            }
+        }
+        lines
+    }
+
+    /// `nodes` joined by `sep`, as many on each line as fit
+    fn fill<T: TerminalNode>(&self, nodes: &[Node<T>], prec: Prec, sep: &str, max_width: usize) -> Vec<String> {
+        let mut lines: Vec<String> = vec![];
+        let mut current: Option<String> = None;
+        for (i, node) in nodes.iter().enumerate() {
+            // room for the separator ending the line, if a node follows
+            let max_width = if i + 1 < nodes.len() {
+                max_width.saturating_sub(width(sep.trim_end()))
+            } else {
+                max_width
+            };
+            let mut node_lines = self.lines(node, prec, max_width);
+            match current.take() {
+                Some(line) if node_lines.len() == 1 && width(&line) + width(sep) + width(&node_lines[0]) <= max_width => {
+                    current = Some(line + sep + &node_lines[0]);
+                    continue;
+                }
+                // the separator ends the line, so that the next one starts with the node
+                Some(line) => lines.push(line + sep.trim_end()),
+                None => {}
+            }
+            current = node_lines.pop();
+            lines.extend(node_lines);
+        }
+        lines.extend(current);
+        lines
+    }
+
+    /// `lines` indented between the `open` and `close` brackets
+    fn group(&self, open: &str, lines: Vec<String>, close: &str) -> Vec<String> {
+        let mut group = vec![open.to_string()];
+        group.extend(self.indented(lines));
+        group.push(close.to_string());
+        group
+    }
+
+    fn indented(&self, lines: Vec<String>) -> impl Iterator<Item = String> {
+        let indent = " ".repeat(self.indent);
+        lines.into_iter().map(move |line| format!("{indent}{line}"))
+    }
+
+    fn inner_width(&self, max_width: usize) -> usize {
+        max_width.saturating_sub(self.indent)
+    }
+}
+
+fn width(line: &str) -> usize {
+    line.chars().count()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::basic::Text;
+
+    use super::*;
+
+    fn arithmetic() -> Grammar<Text> {
+        Grammar::load_ebnf(r#"
+            expression = term , (("+" | "-") , term)*;
+            term = factor , (("*" | "/") , factor)*;
+            factor = ("(" , expression , ")") | number | ("-" , factor) | (name , "(" , [expression , ("," , expression)*] , ")");
+            number = digit+ , ["." , digit+] , [("e" | "E") , ["+" | "-"] , digit+];
+            name = #'[a-z]+';
+            digit = "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9";
+        "#).unwrap()
+    }
+
+    #[test]
+    fn layout() {
+        let grammar = arithmetic();
+        assert_eq!(EbnfPrinter::new().max_width(usize::MAX).print(&grammar), grammar.to_ebnf(false));
+
+        let printer = EbnfPrinter::new().max_width(40);
+        assert_eq!(printer.print(&grammar), [
+            "digit =",
+            "      \"0\"",
+            "    | \"1\"",
+            "    | \"2\"",
+            "    | \"3\"",
+            "    | \"4\"",
+            "    | \"5\"",
+            "    | \"6\"",
+            "    | \"7\"",
+            "    | \"8\"",
+            "    | \"9\";",
+            "expression = term ((\"+\" | \"-\") term)*;",
+            "factor =",
+            "      (\"(\" expression \")\")",
+            "    | number",
+            "    | (\"-\" factor)",
+            "    | (",
+            "          name \"(\"",
+            "          [expression (\",\" expression)*]",
+            "          \")\"",
+            "      );",
+            "name = /[a-z]+/;",
+            "number =",
+            "    digit+ [\".\" digit+]",
+            "    [(\"e\" | \"E\") [\"+\" | \"-\"] digit+];",
+            "term = factor ((\"*\" | \"/\") factor)*;",
+            "",
+        ].join("\n"));
+
+        let printer = printer.indent(2).wrap_alts(false);
+        assert_eq!(printer.print_rule("digit", &grammar.rules["digit"]), [
+            "digit =",
+            "  \"0\" | \"1\" | \"2\" | \"3\" | \"4\" | \"5\" |",
+            "  \"6\" | \"7\" | \"8\" | \"9\";",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut grammar = arithmetic();
+        grammar.start = Some("expression".to_string());
+        // deeply nested choices of sequences
+        let mut nested = Node::NonTerm("digit".to_string());
+        for i in 0..20 {
+            let terminal = Node::Terminal(Text::String(format!("t{i}")));
+            nested = Node::Alt(vec![Node::Seq(vec![terminal.clone(), nested, terminal]), Node::NonTerm("name".to_string())]);
+        }
+        grammar.add_element("nested", nested).unwrap();
+        grammar.add_element("list", Node::rep_sep(Node::NonTerm("expression".to_string()), Node::Terminal(Text::String(", ".to_string())), ..)).unwrap();
+        // separated repetitions read back expanded
+        let expected = Grammar::load_ebnf(&grammar.to_ebnf(false)).unwrap();
+
+        for max_width in [0, 10, 30, 80, 1000] {
+            for indent in [0, 2, 4] {
+                for wrap_alts in [false, true] {
+                    let printer = EbnfPrinter::new().max_width(max_width).indent(indent).wrap_alts(wrap_alts);
+                    let ebnf = printer.print(&grammar);
+                    assert_eq!(Grammar::load_ebnf(&ebnf).unwrap(), expected, "{printer:?}\n{ebnf}");
+                    // terminals and references are wider than the narrowest
+                    // lines, and deep nesting indents past any width
+                    if max_width >= 30 {
+                        for (name, node) in grammar.rules.iter().filter(|(name, _)| *name != "nested") {
+                            let rule = printer.print_rule(name, node);
+                            assert!(rule.lines().all(|line| width(line) <= max_width), "{printer:?}\n{rule}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
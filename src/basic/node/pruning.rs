@@ -0,0 +1,121 @@
+//! Skipping `Alt` branches that cannot start at the current position
+
+use alloc::rc::Rc;
+use core::fmt;
+
+use crate::{basic::{FirstSet, Grammar, Node, TerminalNode, Text}, collections::Map, prelude::*};
+
+use super::State;
+
+/// The FIRST sets of the results of the `Alt` branches of a grammar, see [`Parser::prune_alt_branches`]
+///
+/// [`Parser::prune_alt_branches`]: crate::basic::Parser::prune_alt_branches
+pub(crate) struct AltPruning<T: TerminalNode> {
+    /// By address of the branch, branches outside the grammar are never skipped
+    branches: Map<*const Node<T>, FirstSet>,
+    can_start: fn(&FirstSet, &T::Src, usize) -> bool,
+}
+
+impl<T: TerminalNode> fmt::Debug for AltPruning<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AltPruning").field("branches", &self.branches.len()).finish_non_exhaustive()
+    }
+}
+
+impl AltPruning<Text> {
+    pub(crate) fn new(grammar: &Grammar<Text>) -> Self {
+        let sets = grammar.result_sets();
+        let mut branches = Map::new();
+        for node in grammar.rules.values() {
+            node.walk(&mut |n| {
+                if let Node::Alt(alt) = n {
+                    for branch in alt {
+                        branches.insert(branch as *const _, branch.result_set(&sets));
+                    }
+                }
+            });
+        }
+        Self {
+            branches,
+            can_start: |first, src, pos| src.get(pos..).is_some_and(|rest| first.can_start(rest)),
+        }
+    }
+}
+
+impl<'a, T: TerminalNode> State<'a, T> {
+    pub(crate) fn with_alt_pruning(mut self, pruning: Rc<AltPruning<T>>) -> Self {
+        self.alt_pruning = Some(pruning);
+        self
+    }
+
+    /// Which of `branches` can match at `pos`, `None` if pruning is disabled
+    pub(super) fn viable_branches(&self, branches: &[Node<T>], src: &T::Src, pos: usize) -> Option<Vec<bool>> {
+        let pruning = self.alt_pruning.as_ref()?;
+        Some(branches
+            .iter()
+            .map(|branch| {
+                pruning.branches
+                    .get(&(branch as *const _))
+                    .is_none_or(|first| (pruning.can_start)(first, src, pos))
+            })
+            .collect())
+    }
+}
+
+/// The first branch from `from` on which is not skipped
+pub(super) fn next_viable(viable: Option<&[bool]>, from: usize, len: usize) -> Option<usize> {
+    (from..len).find(|&i| viable.is_none_or(|v| v[i]))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::cell::RefCell;
+
+    use crate::parsers::naive::TraceEvent;
+
+    use super::*;
+
+    #[test]
+    fn same_result_and_fewer_steps() {
+        let grammar = Grammar::load_ebnf(r#"
+            expression = term , (("+" | "-") , term)*;
+            term = factor , (("*" | "/") , factor)*;
+            factor = ("(" , expression , ")") | number | name;
+            number = digit+;
+            digit = "0" | "1" | "2" | "3";
+            name = #'[a-z]+';
+        "#).unwrap();
+
+        let count = |parser: crate::basic::Parser<'_, Text>, src: &str| {
+            let steps = RefCell::new(0);
+            let parser = parser.with_tracer(|_: TraceEvent<'_, &Node<Text>>| *steps.borrow_mut() += 1);
+            let result = parser.parse_non_term("expression", src).unwrap();
+            (result, steps.into_inner())
+        };
+
+        for src in ["1+2*(3-x)", "(1", "1+", "", "+", "*", "1+*", "(1+)"] {
+            let (expected, full) = count(grammar.parser(), src);
+            let (result, pruned) = count(grammar.parser().prune_alt_branches(), src);
+            assert_eq!(result, expected, "{src:?}");
+            assert!(pruned <= full, "{src:?}");
+        }
+
+        let (_, full) = count(grammar.parser(), "1+2*(3-x)");
+        let (_, pruned) = count(grammar.parser().prune_alt_branches(), "1+2*(3-x)");
+        assert!(pruned < full);
+
+        // a branch starting with an optional element has an incomplete result anywhere
+        let grammar = Grammar::load_ebnf(r#"
+            s = (["a"] , "b") | "c";
+            t = (s , "d") | "e";
+        "#).unwrap();
+        for (rule, src) in [("s", "hello"), ("s", "c"), ("s", "ab"), ("t", "hello"), ("t", "e"), ("t", "bd")] {
+            let expected = grammar.parse_non_term(rule, src).unwrap();
+            let result = grammar.parser().prune_alt_branches().parse_non_term(rule, src).unwrap();
+            assert_eq!(result, expected, "{rule} {src:?}");
+        }
+        let (token, diagnostics) = grammar.parser().prune_alt_branches().parse_non_term("s", "hello").unwrap().unwrap();
+        assert_eq!(token.span, 0..0);
+        assert_eq!(diagnostics[0].message(), "Incomplete parse at 0: expected \"b\"");
+    }
+}
@@ -0,0 +1,50 @@
+//! Choices revisited when the rest of their sequence fails
+
+use crate::{basic::TerminalNode, parsers::naive::Parsed, prelude::*};
+
+use super::{Node, State, choice_key};
+
+impl<'a, T: TerminalNode> State<'a, T> {
+    /// Keep the shorter complete matches of choices, to retry the rest of their sequence with
+    ///
+    /// A choice that is an element of a sequence still selects the longest
+    /// match first. If the sequence then fails after it, the other lengths
+    /// the branches matched are tried in branch order before giving up, like
+    /// the matches a greedy repetition gives back.
+    pub fn with_revisited_choices(mut self) -> Self {
+        self.revisit_choices = true;
+        self
+    }
+
+    /// The candidates of a choice, collected until the last branch if choices are revisited
+    pub(super) fn choice_candidates(&self) -> Option<Vec<Parsed<&'a Node<T>>>> {
+        self.revisit_choices.then(Vec::new)
+    }
+
+    /// Select among the `candidates` of a choice, in branch order, the longest as usual
+    ///
+    /// The complete candidates ending elsewhere than the selected one are
+    /// left for the enclosing sequence, one per end, see [`State::take_alternatives`].
+    pub(super) fn exit_choice(&mut self, mut candidates: Vec<Parsed<&'a Node<T>>>) -> Option<Parsed<&'a Node<T>>> {
+        self.alternatives.clear();
+        let best = candidates.iter().enumerate()
+            .reduce(|best, next| if choice_key(next.1) > choice_key(best.1) { next } else { best })
+            .map(|(i, _)| i)?;
+        let best = candidates.remove(best);
+        let mut ends = vec![best.token.span.end];
+        self.alternatives = candidates.into_iter()
+            .filter(|c| c.incomplete.is_none())
+            .filter(|c| {
+                let new = !ends.contains(&c.token.span.end);
+                ends.push(c.token.span.end);
+                new
+            })
+            .collect();
+        Some(best)
+    }
+
+    /// The alternatives of the choice that just finished, see [`State::exit_choice`]
+    pub(super) fn take_alternatives(&mut self) -> Vec<Parsed<&'a Node<T>>> {
+        core::mem::take(&mut self.alternatives)
+    }
+}
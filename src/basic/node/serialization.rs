@@ -1,7 +1,10 @@
-use std::{collections::BTreeMap, fmt, ops::RangeInclusive};
+use alloc::collections::BTreeMap;
+use core::{fmt, ops::RangeInclusive};
 
 use serde::{de::{self, MapAccess, Visitor}, ser::SerializeMap, Deserialize, Serialize, Serializer};
 
+use crate::prelude::*;
+
 use super::Node;
 
 
@@ -11,12 +14,31 @@ struct Rep<T: Clone> {
     #[serde(serialize_with = "super::super::serde_span_serialization::serialize")]
     #[serde(deserialize_with = "super::super::serde_span_serialization::deserialize")]
     range: RangeInclusive<usize>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    lazy: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RepSep<T: Clone> {
+    node: Box<Node<T>>,
+    sep: Box<Node<T>>,
+    #[serde(serialize_with = "super::super::serde_span_serialization::serialize")]
+    #[serde(deserialize_with = "super::super::serde_span_serialization::deserialize")]
+    range: RangeInclusive<usize>,
 }
 
+/// A single tag is written `tag`, as before nodes had several, one of `tag` and `tags` is required
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Tagged<T: Clone> {
     node: Box<Node<T>>,
-    tag: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +47,12 @@ struct Meta<T: Clone> {
     data: BTreeMap<String, String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Except<T: Clone> {
+    node: Box<Node<T>>,
+    minus: Box<Node<T>>,
+}
+
 impl<T: Serialize + Clone> Serialize for Node<T> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -34,12 +62,21 @@ impl<T: Serialize + Clone> Serialize for Node<T> {
         match self {
             Node::Seq(nodes) => map.serialize_entry("seq", nodes)?,
             Node::Alt(nodes) => map.serialize_entry("alt", nodes)?,
-            Node::Rep { node, range } if *range == (0..=1) => map.serialize_entry("opt", node)?,
-            Node::Rep { node, range } => map.serialize_entry("rep", &Rep { node: node.clone(), range: range.clone() })?,
+            Node::Rep { node, range, lazy: false } if *range == (0..=1) => map.serialize_entry("opt", node)?,
+            Node::Rep { node, range, lazy } => map.serialize_entry("rep", &Rep { node: node.clone(), range: range.clone(), lazy: *lazy })?,
+            Node::RepSep { node, sep, range } => map.serialize_entry("rep_sep", &RepSep { node: node.clone(), sep: sep.clone(), range: range.clone() })?,
             Node::Terminal(value) => map.serialize_entry("term", value)?,
             Node::NonTerm(value) => map.serialize_entry("non_term", value)?,
-            Node::Tagged { node, tag } => map.serialize_entry("tagged", &Tagged { node: node.clone(), tag: tag.clone() })?,
+            Node::Tagged { node, tags } => {
+                let (tag, tags) = match tags.as_slice() {
+                    [tag] => (Some(tag.clone()), None),
+                    tags => (None, Some(tags.to_vec())),
+                };
+                map.serialize_entry("tagged", &Tagged { node: node.clone(), tag, tags })?
+            }
             Node::Meta { node, meta } => map.serialize_entry("meta", &Meta { node: node.clone(), data: meta.clone() })?,
+            Node::Empty => map.serialize_entry("empty", &())?,
+            Node::Except { node, minus } => map.serialize_entry("except", &Except { node: node.clone(), minus: minus.clone() })?,
         }
         map.end()
     }
@@ -50,7 +87,7 @@ impl<'de, T: Deserialize<'de> + Clone> Deserialize<'de> for Node<T> {
     where
         D: serde::Deserializer<'de>,
     {
-        struct NodeVisitor<T>(std::marker::PhantomData<T>);
+        struct NodeVisitor<T>(core::marker::PhantomData<T>);
 
         impl<'de, T: Deserialize<'de> + Clone> Visitor<'de> for NodeVisitor<T> {
             type Value = Node<T>;
@@ -66,7 +103,7 @@ impl<'de, T: Deserialize<'de> + Clone> Deserialize<'de> for Node<T> {
                 let key: String = map
                     .next_key()?
                     .ok_or_else(|| de::Error::custom("expected a single key in the map"))?;
-                match key.as_str() {
+                let node = match key.as_str() {
                     "seq" => {
                         let value = map.next_value()?;
                         Ok(Node::Seq(value))
@@ -77,11 +114,15 @@ impl<'de, T: Deserialize<'de> + Clone> Deserialize<'de> for Node<T> {
                     }
                     "opt" => {
                         let value: Node<T> = map.next_value()?;
-                        Ok(Node::Rep { node: Box::new(value), range: 0..=1 })
+                        Ok(Node::Rep { node: Box::new(value), range: 0..=1, lazy: false })
                     }
                     "rep" => {
                         let rep: Rep<T> = map.next_value()?;
-                        Ok(Node::Rep { node: rep.node, range: rep.range })
+                        Ok(Node::Rep { node: rep.node, range: rep.range, lazy: rep.lazy })
+                    }
+                    "rep_sep" => {
+                        let rep: RepSep<T> = map.next_value()?;
+                        Ok(Node::RepSep { node: rep.node, sep: rep.sep, range: rep.range })
                     }
                     "term" => {
                         let value = map.next_value()?;
@@ -93,22 +134,42 @@ impl<'de, T: Deserialize<'de> + Clone> Deserialize<'de> for Node<T> {
                     }
                     "tagged" => {
                         let tagged: Tagged<T> = map.next_value()?;
-                        Ok(Node::Tagged { node: tagged.node, tag: tagged.tag })
+                        if tagged.tag.is_none() && tagged.tags.is_none() {
+                            return Err(de::Error::missing_field("tags"));
+                        }
+                        let tags = tagged.tag.into_iter().chain(tagged.tags.into_iter().flatten()).collect();
+                        Ok(Node::Tagged { node: tagged.node, tags })
+                    }
+                    "meta" => {
+                        let meta: Meta<T> = map.next_value()?;
+                        Ok(Node::Meta { node: meta.node, meta: meta.data })
+                    }
+                    "empty" => {
+                        map.next_value::<()>()?;
+                        Ok(Node::Empty)
+                    }
+                    "except" => {
+                        let except: Except<T> = map.next_value()?;
+                        Ok(Node::Except { node: except.node, minus: except.minus })
                     }
                     _ => Err(de::Error::unknown_field(&key, &[
-                        "seq", "alt", "rep", "term", "re", "non_term", "tagged",
+                        "seq", "alt", "opt", "rep", "rep_sep", "term", "non_term", "tagged", "meta", "empty", "except",
                     ])),
+                }?;
+                if map.next_key::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::custom("expected a single key in the map"));
                 }
+                Ok(node)
             }
         }
 
-        deserializer.deserialize_map(NodeVisitor::<T>(std::marker::PhantomData))
+        deserializer.deserialize_map(NodeVisitor::<T>(core::marker::PhantomData))
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
-    use crate::basic::Text;
+    use crate::{basic::{Grammar, Text}, gram};
 
     use super::*;
 
@@ -136,4 +197,84 @@ mod tests {
         let deserialized: Node<Text> = serde_yaml::from_str(&serialized).unwrap();
         assert_eq!(node, deserialized);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn yaml_serialization_rep_sep() {
+        let node = Node::rep_sep(
+            Node::<Text>::NonTerm("item".to_string()),
+            Node::Terminal(Text::String(",".to_string())),
+            0..,
+        );
+
+        let serialized = serde_yaml::to_string(&node).unwrap();
+        assert_eq!(
+            serialized,
+            r#"rep_sep:
+  node:
+    non_term: item
+  sep:
+    term: ','
+  range: {}
+"#
+        );
+
+        let deserialized: Node<Text> = serde_yaml::from_str(&serialized).unwrap();
+        assert_eq!(node, deserialized);
+    }
+
+    fn all_variants() -> Grammar<Text> {
+        let mut grammar = Grammar::new();
+        grammar.start = Some("a".to_string());
+        grammar.add_element("a", Node::Seq(vec![
+            Node::Alt(vec![gram!("x"), gram!(#r"[0-9]+"), Node::Terminal(Text::regex_with_flags("y", "i").unwrap())]),
+            Node::rep(gram!(b), 0..=1),
+            Node::rep(gram!(b), 2..=5),
+            Node::rep(gram!(b), 3..),
+            Node::rep_lazy(gram!(b), 0..=1),
+            Node::rep_sep(gram!(b), gram!(","), 0..=usize::MAX),
+            Node::tagged(gram!(b), "t"),
+            gram!("t" | "u": b),
+            gram!(b).with_description("d"),
+            Node::Seq(vec![]),
+            Node::Empty,
+            Node::except(gram!(#r"[a-z]"), gram!("q")),
+        ])).unwrap();
+        grammar.add_element("b", Node::Alt(vec![])).unwrap();
+        grammar
+    }
+
+    #[test]
+    fn bincode_round_trip() {
+        let config = bincode::config::standard();
+        let grammar = all_variants();
+        let bytes = bincode::encode_to_vec(&grammar, config).unwrap();
+        let (decoded, len): (Grammar<Text>, _) = bincode::decode_from_slice(&bytes, config).unwrap();
+        assert_eq!(decoded, grammar);
+        assert_eq!(len, bytes.len());
+
+        // variants are encoded by index and ranges as their two bounds, unlike
+        // the serde `SerRange` form: `Rep`, `Terminal`, `Text::String`, "a", 2..=5, not lazy
+        let bytes = bincode::encode_to_vec(Node::rep(gram!("a"), 2..=5), config).unwrap();
+        assert_eq!(bytes, [2, 4, 0, 1, b'a', 2, 5, 0]);
+    }
+
+    #[test]
+    fn yaml_round_trip() {
+        let grammar = all_variants();
+        let yaml = serde_yaml::to_string(&grammar).unwrap();
+        assert_eq!(serde_yaml::from_str::<Grammar<Text>>(&yaml).unwrap(), grammar);
+    }
+
+    #[test]
+    fn tagged_without_tags() {
+        let untagged = "tagged:\n  node:\n    non_term: a\n";
+        let err = serde_yaml::from_str::<Node<Text>>(untagged).unwrap_err();
+        assert!(err.to_string().contains("missing field `tags`"), "{err}");
+
+        // a node with no tags is written with an empty list
+        let node = Node::<Text>::Tagged { node: Box::new(gram!(a)), tags: vec![] };
+        let yaml = serde_yaml::to_string(&node).unwrap();
+        assert_eq!(yaml, "tagged:\n  node:\n    non_term: a\n  tags: []\n");
+        assert_eq!(serde_yaml::from_str::<Node<Text>>(&yaml).unwrap(), node);
+    }
+}
@@ -0,0 +1,268 @@
+//! Memoizing the sub-expressions written more than once in the rules
+
+use alloc::{collections::BTreeMap, rc::Rc};
+use core::{fmt, ops::RangeInclusive};
+
+use crate::{basic::{Grammar, Node, TerminalNode}, collections::Map, parsers::naive::{Action, Parsed}, prelude::*};
+
+use super::{StackState, State};
+
+/// The ids of the repeated sub-expressions of a grammar, see [`Parser::memoize_shared_nodes`]
+///
+/// The rules are numbered in a single pass: equal nodes, compared with
+/// their tags and meta, get the same id, and only the ids of the nodes
+/// written at least twice are kept. References, terminals and empty nodes
+/// are left out, references having a cache of their own and the others
+/// being cheaper to match than to look up.
+///
+/// A node is looked up by the ids of its children, numbered first, so each
+/// node is visited once. The terminals have no [`Hash`] nor [`Ord`], they
+/// are told apart with [`PartialEq`] among the distinct ones.
+///
+/// [`Parser::memoize_shared_nodes`]: crate::basic::Parser::memoize_shared_nodes
+pub(crate) struct SharedNodes<T: TerminalNode> {
+    /// By address of the node, nodes outside the grammar have no id
+    ids: Map<*const Node<T>, usize>,
+}
+
+impl<T: TerminalNode> fmt::Debug for SharedNodes<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedNodes").field("nodes", &self.ids.len()).finish_non_exhaustive()
+    }
+}
+
+impl<T: TerminalNode> SharedNodes<T> {
+    pub(crate) fn new(grammar: &Grammar<T>) -> Self {
+        let mut numbering = Numbering { terminals: vec![], keys: Map::new(), occurrences: vec![] };
+        for node in grammar.rules.values() {
+            numbering.number(node);
+        }
+        let ids = numbering
+            .occurrences
+            .into_iter()
+            .filter(|nodes| nodes.len() > 1)
+            .enumerate()
+            .flat_map(|(id, nodes)| nodes.into_iter().map(move |n| (n, id)))
+            .collect();
+        Self { ids }
+    }
+}
+
+/// A node by the ids of its children, see [`Numbering::number`]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Key<'g> {
+    Seq(Vec<usize>),
+    Alt(Vec<usize>),
+    Rep { node: usize, range: (usize, usize), lazy: bool },
+    RepSep { node: usize, sep: usize, range: (usize, usize) },
+    Terminal(usize),
+    NonTerm(&'g str),
+    Tagged { node: usize, tags: &'g [String] },
+    Meta { node: usize, meta: &'g BTreeMap<String, String> },
+    Empty,
+    Except { node: usize, minus: usize },
+}
+
+/// The ids of the distinct nodes of the rules, see [`SharedNodes::new`]
+struct Numbering<'g, T> {
+    /// The distinct terminals, by id
+    terminals: Vec<&'g T>,
+    keys: Map<Key<'g>, usize>,
+    /// The nodes of each id, except the references, terminals and empty nodes
+    occurrences: Vec<Vec<*const Node<T>>>,
+}
+
+impl<'g, T: TerminalNode> Numbering<'g, T> {
+    /// The id of `node`, equal nodes having the same one
+    fn number(&mut self, node: &'g Node<T>) -> usize {
+        let range = |range: &RangeInclusive<usize>| (*range.start(), *range.end());
+        let key = match node {
+            Node::Seq(nodes) => Key::Seq(nodes.iter().map(|n| self.number(n)).collect()),
+            Node::Alt(nodes) => Key::Alt(nodes.iter().map(|n| self.number(n)).collect()),
+            Node::Rep { node, range: r, lazy } => Key::Rep { node: self.number(node), range: range(r), lazy: *lazy },
+            Node::RepSep { node, sep, range: r } => {
+                Key::RepSep { node: self.number(node), sep: self.number(sep), range: range(r) }
+            }
+            Node::Terminal(t) => Key::Terminal(match self.terminals.iter().position(|other| *other == t) {
+                Some(index) => index,
+                None => {
+                    self.terminals.push(t);
+                    self.terminals.len() - 1
+                }
+            }),
+            Node::NonTerm(name) => Key::NonTerm(name),
+            Node::Tagged { node, tags } => Key::Tagged { node: self.number(node), tags },
+            Node::Meta { node, meta } => Key::Meta { node: self.number(node), meta },
+            Node::Empty => Key::Empty,
+            Node::Except { node, minus } => Key::Except { node: self.number(node), minus: self.number(minus) },
+        };
+        let next = self.keys.len();
+        let id = *self.keys.entry(key).or_insert(next);
+        if id == self.occurrences.len() {
+            self.occurrences.push(vec![]);
+        }
+        if !matches!(node, Node::NonTerm(_) | Node::Terminal(_) | Node::Empty) {
+            self.occurrences[id].push(node);
+        }
+        id
+    }
+}
+
+impl<'a, T: TerminalNode> State<'a, T> {
+    pub(crate) fn with_shared_nodes(mut self, shared: Rc<SharedNodes<T>>) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    /// The action of a repeated sub-expression at `pos`, `None` to parse `node` as usual
+    ///
+    /// This is the memoized result if there is one, otherwise `node` is
+    /// parsed as usual under a [`StackState::ParsingShared`] that records it.
+    pub(super) fn enter_shared(&mut self, node: &'a Node<T>, pos: usize) -> Option<Action<&'a Node<T>>> {
+        // the matches of revisited choices are not part of the results
+        if self.revisit_choices {
+            return None;
+        }
+        let id = *self.shared.as_ref()?.ids.get(&(node as *const _))?;
+        if self.entering_shared.take().is_some_and(|entering| core::ptr::eq(entering, node)) {
+            return None;
+        }
+        if let Some(memoized) = self.shared_results.get(&(id, pos)).cloned() {
+            if let Some(parsed) = &memoized {
+                self.reach(parsed.token.span.end);
+            }
+            return Some(Action::Pop { parsed: memoized });
+        }
+        self.entering_shared = Some(node);
+        Some(Action::Push {
+            save_state: StackState::ParsingShared { id, start_pos: pos },
+            next_node: node,
+            next_pos: pos,
+        })
+    }
+
+    /// Memoize the result of the repeated sub-expression `id` at `pos`
+    ///
+    /// As for non-terminals, results depending on the seed of a left
+    /// recursive rule are not memoized.
+    pub(super) fn exit_shared(&mut self, id: usize, pos: usize, parsed: &Option<Parsed<&'a Node<T>>>) {
+        if self.seed_used.is_some() {
+            return;
+        }
+        self.shared_results.insert((id, pos), parsed.clone());
+        // evicted like the results of non-terminals, see `State::with_cache_capacity`
+        if let Some(capacity) = self.cache_capacity
+            && self.shared_results.len() > capacity
+        {
+            let mut keys = self.shared_results.keys().copied().collect::<Vec<_>>();
+            keys.sort_unstable_by_key(|&(_, pos)| pos);
+            for key in &keys[..keys.len() - capacity / 2] {
+                self.shared_results.remove(key);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::cell::RefCell;
+
+    use crate::{basic::{Parser, Text}, parsers::naive::TraceEvent};
+
+    use super::*;
+
+    #[test]
+    fn fewer_steps() {
+        // the arguments are written in both branches, and parsed at the same position
+        let grammar = Grammar::load_ebnf(r#"
+            call = (name , "(" , #'[0-9]+' , ("," , #'[0-9]+')* , ")" , ";")
+                | (name , "(" , #'[0-9]+' , ("," , #'[0-9]+')* , ")" , "{" , "}");
+            name = #'[a-z]+';
+        "#).unwrap();
+        let shared = SharedNodes::new(&grammar);
+        let node = &grammar.rules["call"];
+        let Node::Alt(branches) = node else { panic!() };
+        let Node::Seq(first) = &branches[0] else { panic!() };
+        let Node::Seq(second) = &branches[1] else { panic!() };
+        assert_eq!(shared.ids.get(&(&first[3] as *const _)), shared.ids.get(&(&second[3] as *const _)));
+        assert!(shared.ids.contains_key(&(&first[3] as *const _)));
+        assert!(!shared.ids.contains_key(&(node as *const _)));
+
+        let count = |parser: Parser<'_, Text>, src: &str| {
+            let steps = RefCell::new(0);
+            let parser = parser.with_tracer(|_: TraceEvent<'_, &Node<Text>>| *steps.borrow_mut() += 1);
+            let result = parser.parse_non_term("call", src).unwrap();
+            (result, steps.into_inner())
+        };
+        for src in ["f(1,2,3){}", "f(1,2);", "f(1,", "f(", ""] {
+            let (expected, full) = count(grammar.parser(), src);
+            let (result, memoized) = count(grammar.parser().memoize_shared_nodes(), src);
+            assert_eq!(result, expected, "{src:?}");
+            assert!(memoized <= full, "{src:?}");
+            let (result, _) = count(grammar.parser().memoize_shared_nodes().cache_capacity(1), src);
+            assert_eq!(result, expected, "{src:?}");
+        }
+
+        // the numbers are matched by the first branch only
+        let numbers = |parser: Parser<'_, Text>, src: &str| {
+            let numbers = RefCell::new(0);
+            let parser = parser.with_tracer(|event: TraceEvent<'_, &Node<Text>>| {
+                if let TraceEvent::Enter { node: Node::Terminal(Text::Regex(re)), .. } = event
+                    && re == "[0-9]+"
+                {
+                    *numbers.borrow_mut() += 1;
+                }
+            });
+            parser.parse_non_term("call", src).unwrap();
+            numbers.into_inner()
+        };
+        let src = format!("f({}){{}}", (0..100).map(|i| i.to_string()).collect::<Vec<_>>().join(","));
+        assert_eq!(numbers(grammar.parser(), &src), 200);
+        assert_eq!(numbers(grammar.parser().memoize_shared_nodes(), &src), 101);
+    }
+
+    #[test]
+    fn terminals_compared_by_eq() {
+        // equal debug forms, different terminals
+        #[derive(Clone, PartialEq)]
+        struct Char(char);
+        impl fmt::Debug for Char {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("Char")
+            }
+        }
+        impl TerminalNode for Char {
+            type Src = str;
+            fn parses(&self, src: &str, pos: usize) -> anyhow::Result<Option<usize>> {
+                Ok(src[pos..].starts_with(self.0).then_some(pos + self.0.len_utf8()))
+            }
+            fn to_ebnf(&self) -> String {
+                format!("{:?}", self.0)
+            }
+        }
+
+        let pair = |a, b| Node::seq([Node::Terminal(Char(a)), Node::Terminal(Char(b))]);
+        let mut grammar = Grammar::new();
+        for (name, node) in [("ab", pair('a', 'b')), ("also_ab", pair('a', 'b')), ("cd", pair('c', 'd'))] {
+            grammar.add_element(name, node).unwrap();
+        }
+        let shared = SharedNodes::new(&grammar);
+        let id = |name: &str| shared.ids.get(&(&grammar.rules[name] as *const _)).copied();
+        assert!(id("ab").is_some());
+        assert_eq!(id("ab"), id("also_ab"));
+        assert_eq!(id("cd"), None);
+    }
+
+    #[test]
+    fn left_recursion() {
+        let grammar = Grammar::load_ebnf(r#"
+            expr = (expr , "+" , (term , [";"])) | (term , [";"]);
+            term = #'[0-9]+';
+        "#).unwrap();
+        for src in ["1", "1+2;+3", "1+", "1;+2;"] {
+            let expected = grammar.parse_non_term("expr", src).unwrap();
+            let result = grammar.parser().memoize_shared_nodes().parse_non_term("expr", src).unwrap();
+            assert_eq!(result, expected, "{src:?}");
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Reporting the tokens of rules as they complete
+
+use alloc::{collections::BTreeSet, rc::Rc};
+use core::{cell::RefCell, fmt};
+
+use crate::{basic::{TerminalNode, Token}, prelude::*};
+
+use super::State;
+
+type Callback<'a> = dyn FnMut(&Token) + 'a;
+
+/// The callback of [`Parser::on_complete`]
+///
+/// [`Parser::on_complete`]: crate::basic::Parser::on_complete
+#[derive(Clone)]
+pub(crate) struct OnComplete<'a> {
+    grams: Rc<BTreeSet<String>>,
+    f: Rc<RefCell<Callback<'a>>>,
+}
+
+impl fmt::Debug for OnComplete<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OnComplete").field("grams", &self.grams).finish_non_exhaustive()
+    }
+}
+
+impl<'a> OnComplete<'a> {
+    pub(crate) fn new(grams: BTreeSet<String>, f: impl FnMut(&Token) + 'a) -> Self {
+        Self {
+            grams: Rc::new(grams),
+            f: Rc::new(RefCell::new(f)),
+        }
+    }
+}
+
+impl<'a, T: TerminalNode> State<'a, T> {
+    pub(crate) fn with_on_complete(mut self, on_complete: OnComplete<'a>) -> Self {
+        self.on_complete = Some(on_complete);
+        self
+    }
+
+    /// Report the final result of the rule `name` at a position
+    pub(super) fn completed(&self, name: &str, token: &Token) {
+        if let Some(on_complete) = &self.on_complete
+            && on_complete.grams.contains(name)
+        {
+            if self.reached.is_some() {
+                let mut token = token.clone();
+                super::extend_incomplete_spans(&mut token);
+                (on_complete.f.borrow_mut())(&token);
+            } else {
+                (on_complete.f.borrow_mut())(token);
+            }
+        }
+    }
+}
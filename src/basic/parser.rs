@@ -0,0 +1,909 @@
+use alloc::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{parsers::naive::{self, NoTracer, Tracer}, prelude::*};
+
+use super::{AltPruning, Grammar, IncompleteSpans, OnComplete, Node, SharedNodes, State, TerminalNode, Text, Token, extend_incomplete_spans};
+
+#[cfg(feature = "parallel")]
+type ConfigureState<'a, T> = fn(State<'a, T>, usize) -> State<'a, T>;
+
+/// Parsing entry point with configurable options
+///
+/// The `parse_*` methods of [`Grammar`] use a parser with the default options.
+/// Their results are owned: the grammar is only borrowed while parsing.
+///
+/// A parser is not `Send`: its clones share the indexes built by
+/// [`Parser::prune_alt_branches`] and [`Parser::memoize_shared_nodes`] and
+/// the callback of [`Parser::on_complete`] with `Rc`s. Parsers are cheap to
+/// build, so each thread builds its own, the results being `Send`.
+#[derive(Debug, Clone)]
+pub struct Parser<'a, T: TerminalNode, Tr = NoTracer> {
+    grammar: &'a Grammar<T>,
+    tracer: Tr,
+    /// Minimum branches and [`State::with_parallel_alt`], which carries the `Sync` bounds
+    #[cfg(feature = "parallel")]
+    parallel_alt: Option<(usize, ConfigureState<'a, T>)>,
+    alt_pruning: Option<Rc<AltPruning<T>>>,
+    shared_nodes: Option<Rc<SharedNodes<T>>>,
+    max_terminal_match: Option<usize>,
+    max_repetitions: Option<usize>,
+    line_bounded: bool,
+    captures: bool,
+    on_complete: Option<OnComplete<'a>>,
+    incomplete_spans: IncompleteSpans,
+    revisit_choices: bool,
+    cache_capacity: Option<usize>,
+}
+
+impl<'a, T: TerminalNode> Parser<'a, T> {
+    pub fn new(grammar: &'a Grammar<T>) -> Self {
+        Self {
+            grammar,
+            tracer: NoTracer,
+            #[cfg(feature = "parallel")]
+            parallel_alt: None,
+            alt_pruning: None,
+            shared_nodes: None,
+            max_terminal_match: None,
+            max_repetitions: None,
+            line_bounded: false,
+            captures: false,
+            on_complete: None,
+            incomplete_spans: IncompleteSpans::Minimal,
+            revisit_choices: false,
+            cache_capacity: None,
+        }
+    }
+}
+
+impl<'a, T: TerminalNode, Tr: Tracer<&'a Node<T>>> Parser<'a, T, Tr> {
+    /// Report every step of the parsing loop to `tracer`
+    ///
+    /// Use [`naive::LogTracer`] to log the steps with the [`log`] crate.
+    pub fn with_tracer<Tr2: Tracer<&'a Node<T>>>(self, tracer: Tr2) -> Parser<'a, T, Tr2> {
+        Parser {
+            grammar: self.grammar,
+            tracer,
+            #[cfg(feature = "parallel")]
+            parallel_alt: self.parallel_alt,
+            alt_pruning: self.alt_pruning,
+            shared_nodes: self.shared_nodes,
+            max_terminal_match: self.max_terminal_match,
+            max_repetitions: self.max_repetitions,
+            line_bounded: self.line_bounded,
+            captures: self.captures,
+            on_complete: self.on_complete,
+            incomplete_spans: self.incomplete_spans,
+            revisit_choices: self.revisit_choices,
+            cache_capacity: self.cache_capacity,
+        }
+    }
+
+    /// Let terminals match at most `max_len` units of the source, see [`TerminalNode::parses_within`]
+    ///
+    /// This caps the cost of regexes such as `.*` on large inputs: [`Text`]
+    /// regexes only see the `max_len` bytes after their position.
+    pub fn max_terminal_match(mut self, max_len: usize) -> Self {
+        self.max_terminal_match = Some(max_len);
+        self
+    }
+
+    /// Let terminals match at most up to the end of their line, see [`TerminalNode::line_len`]
+    ///
+    /// For line-oriented formats: a regex such as `/[^;]+/` then stops at the
+    /// end of the line, its newline included, instead of going on over the
+    /// next ones. As with [`Parser::max_terminal_match`], [`Text`] literals
+    /// are not limited, and with both options the shorter limit applies.
+    pub fn line_bounded_terminals(mut self) -> Self {
+        self.line_bounded = true;
+        self
+    }
+
+    /// Stop each repetition after `max` matches, whatever the maximum of its range
+    ///
+    /// A repetition of a node matching a char at a time keeps a token per
+    /// match, so on untrusted input its memory grows with the source. With
+    /// this option, a repetition reaching `max` matches stops there with a
+    /// [`RepetitionLimit`](naive::Diagnostic::RepetitionLimit) diagnostic,
+    /// and is incomplete like a sequence missing an element.
+    pub fn max_repetitions(mut self, max: usize) -> Self {
+        self.max_repetitions = Some(max);
+        self
+    }
+
+    /// Record the named groups of the regexes of terminals in their tokens
+    ///
+    /// The span of a group is then given by [`Token::capture`], groups that
+    /// did not take part in the match are left out. This costs a second
+    /// match of each regex terminal with named groups.
+    pub fn record_captures(mut self) -> Self {
+        self.captures = true;
+        self
+    }
+
+    /// Call `f` with the token of each of the `grams` rules as soon as it is parsed
+    ///
+    /// Tokens are reported in completion order, so children before their
+    /// parents, each rule once per position it is parsed at. This includes
+    /// the rules of the alternatives the parse then discards: every token
+    /// with one of the `grams` in the result has been reported, but not every
+    /// reported token is in the result. Tokens come as the rule produced
+    /// them, without the tags and meta of the nodes around its reference.
+    /// Fragments are never reported.
+    pub fn on_complete<S: Into<String>>(
+        mut self,
+        grams: impl IntoIterator<Item = S>,
+        f: impl FnMut(&Token) + 'a,
+    ) -> Self {
+        self.on_complete = Some(OnComplete::new(grams.into_iter().map(Into::into).collect(), f));
+        self
+    }
+
+    /// Choose how far the tokens of incomplete sequences extend
+    ///
+    /// By default they cover the matched prefix only, ending where the
+    /// `Incomplete` diagnostic is. With [`IncompleteSpans::Maximal`], they
+    /// extend to the furthest position reached while parsing the sequence,
+    /// as far as its terminals could partially match (see
+    /// [`TerminalNode::reaches`]), so that tools can highlight the region of
+    /// the failed attempt.
+    ///
+    /// The spans are extended once the parse is done, so the results are
+    /// the same as by default, only longer: the choices are made and the
+    /// parse resumes after the matched prefix. A token extends up to the
+    /// start of the token after it at most, and its parents extend with it.
+    /// Diagnostics are unchanged. Results reused from the cache count as
+    /// reached up to their end, not as far as their own failed attempts went.
+    pub fn incomplete_spans(mut self, mode: IncompleteSpans) -> Self {
+        self.incomplete_spans = mode;
+        self
+    }
+
+    /// Retry the shorter matches of a choice when the rest of its sequence fails
+    ///
+    /// A choice selects its longest match, so in `("a" | "ab") , "bc"` the
+    /// input `abc` fails after `ab`. With this option the complete matches
+    /// of the other branches are kept, and the elements after the choice are
+    /// parsed again after each of them, in branch order, until they match:
+    /// here after `a`. Parses that succeed without it are unchanged.
+    ///
+    /// Only the choices that are elements of a sequence are revisited, like
+    /// the repetitions backtracking gives back, not those behind a
+    /// non-terminal or a tag. All the branches are parsed anyway to find the
+    /// longest, so the cost is keeping their results and parsing the rest of
+    /// the sequence again.
+    pub fn revisit_choices(mut self) -> Self {
+        self.revisit_choices = true;
+        self
+    }
+
+    /// Keep at most `capacity` results of non-terminals in the cache, unbounded by default
+    ///
+    /// The cache holds a result per rule and position parsed, so it grows
+    /// with the input. When it is full, the half of the results furthest
+    /// behind in the source are dropped: a parse mostly moves forward, so
+    /// they are the least likely to be reused. Results are unchanged, but
+    /// a rule reached again at a dropped position is parsed again, so a
+    /// small capacity trades memory for time on grammars that backtrack.
+    pub fn cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Memoize the sub-expressions written more than once in the rules, by position
+    ///
+    /// Only non-terminals are cached by default, so in
+    /// `(name , args , ";") | (name , args , "{" , "}")` the arguments are
+    /// parsed again by the second branch. With this option, the rules are
+    /// numbered once per parser, equal sub-expressions sharing a number, and
+    /// the result of each one at each position is reused like the one of a
+    /// non-terminal, within the limit of [`Parser::cache_capacity`]. Results
+    /// are unchanged.
+    ///
+    /// This does nothing with [`Parser::revisit_choices`].
+    pub fn memoize_shared_nodes(mut self) -> Self {
+        self.shared_nodes = Some(Rc::new(SharedNodes::new(self.grammar)));
+        self
+    }
+
+    pub fn grammar(&self) -> &'a Grammar<T> {
+        self.grammar
+    }
+
+    pub fn parse_non_term(
+        &self,
+        non_term: &str,
+        source: &T::Src,
+    ) -> anyhow::Result<Option<(Token, Vec<naive::Diagnostic>)>> {
+        self.parse_non_term_at(non_term, source, 0)
+    }
+
+    /// Parse `non_term` from the start of `source`, see [`ParseResult`]
+    pub fn parse(&self, non_term: &str, source: &T::Src) -> anyhow::Result<ParseResult> {
+        Ok(self.parse_non_term(non_term, source)?.into())
+    }
+
+    /// Parse `non_term` starting at byte offset `pos` of `source`
+    ///
+    /// Spans in the result are relative to `source`, not to `&source[pos..]`.
+    /// It is an error if `pos` is not a boundary of `source`, such as a
+    /// position past its end or inside a char, see [`TerminalNode::is_boundary`].
+    pub fn parse_non_term_at(
+        &self,
+        non_term: &str,
+        source: &T::Src,
+        pos: usize,
+    ) -> anyhow::Result<Option<(Token, Vec<naive::Diagnostic>)>> {
+        let (parsed, _) = self.parse_reaching(non_term, source, pos)?;
+        Ok(parsed.map(|parsed| self.finish(parsed)))
+    }
+
+    /// Same as [`Parser::parse_non_term_at`], with the furthest position reached, see [`State::furthest`]
+    fn parse_reaching(
+        &self,
+        non_term: &str,
+        source: &T::Src,
+        pos: usize,
+    ) -> anyhow::Result<(Option<naive::Parsed<&'a Node<T>>>, usize)> {
+        let (name, node) = self.grammar.rules.get_key_value(non_term).ok_or_else(|| {
+            anyhow::anyhow!("No rule for start node {non_term:?}")
+        })?;
+        check_start::<T>(source, pos)?;
+        let mut state = self.state()?;
+        let parsed = state.parse_rule_at(name, node, source, pos, &self.tracer)?;
+        Ok((parsed, state.furthest()))
+    }
+
+    pub fn parse_node(
+        &self,
+        node: &'a Node<T>,
+        source: &T::Src,
+    ) -> anyhow::Result<Option<(Token, Vec<naive::Diagnostic>)>> {
+        self.parse_node_at(node, source, 0)
+    }
+
+    /// Same as [`Parser::parse_non_term_at`], starting from `node`
+    pub fn parse_node_at(
+        &self,
+        node: &'a Node<T>,
+        source: &T::Src,
+        pos: usize,
+    ) -> anyhow::Result<Option<(Token, Vec<naive::Diagnostic>)>> {
+        check_start::<T>(source, pos)?;
+        let parsed = naive::parse_traced_at(source, node, pos, self.state()?, &self.tracer)?;
+        Ok(parsed.map(|parsed| self.finish(parsed)))
+    }
+
+    /// The result of a parse, with the spans of [`Parser::incomplete_spans`]
+    fn finish(&self, parsed: naive::Parsed<&'a Node<T>>) -> (Token, Vec<naive::Diagnostic>) {
+        let (mut token, diagnostics) = parsed.detach();
+        if self.incomplete_spans == IncompleteSpans::Maximal {
+            extend_incomplete_spans(&mut token);
+        }
+        (token, diagnostics)
+    }
+
+    fn state(&self) -> anyhow::Result<State<'a, T>> {
+        let state = State::new(self.grammar);
+        #[cfg(feature = "parallel")]
+        let state = match self.parallel_alt {
+            Some(_) if self.alt_pruning.is_some() || self.shared_nodes.is_some() || self.on_complete.is_some() => {
+                anyhow::bail!(
+                    "Parallel Alt branches cannot be combined with prune_alt_branches, memoize_shared_nodes or on_complete"
+                )
+            }
+            Some((min_branches, configure)) => configure(state, min_branches),
+            None => state,
+        };
+        let state = match self.max_terminal_match {
+            Some(max_len) => state.with_max_terminal_match(max_len),
+            None => state,
+        };
+        let state = match self.max_repetitions {
+            Some(max) => state.with_max_repetitions(max),
+            None => state,
+        };
+        let state = if self.line_bounded { state.with_line_bounded_terminals() } else { state };
+        let state = if self.captures { state.with_captures() } else { state };
+        let state = state.with_incomplete_spans(self.incomplete_spans);
+        let state = if self.revisit_choices { state.with_revisited_choices() } else { state };
+        let state = match self.cache_capacity {
+            Some(capacity) => state.with_cache_capacity(capacity),
+            None => state,
+        };
+        let state = match &self.on_complete {
+            Some(on_complete) => state.with_on_complete(on_complete.clone()),
+            None => state,
+        };
+        let state = match &self.shared_nodes {
+            Some(shared) => state.with_shared_nodes(shared.clone()),
+            None => state,
+        };
+        Ok(match &self.alt_pruning {
+            Some(pruning) => state.with_alt_pruning(pruning.clone()),
+            None => state,
+        })
+    }
+}
+
+impl<Tr> Parser<'_, Text, Tr> {
+    /// Skip the `Alt` branches that cannot start at the current position
+    ///
+    /// A branch is skipped only if it definitely cannot give a result, not
+    /// even an incomplete one: as a sequence is incomplete once its first
+    /// element matches, only the [FIRST set](Grammar::first_sets) of the
+    /// first element counts. It cannot be empty and none of its literals
+    /// begins there. Branches whose first element can start with a regex
+    /// are always tried. Results and diagnostics are unchanged, the sets are
+    /// computed once per parser.
+    ///
+    /// Branches of nodes outside the grammar, such as those passed to
+    /// [`Parser::parse_node`], are always tried.
+    pub fn prune_alt_branches(mut self) -> Self {
+        self.alt_pruning = Some(Rc::new(AltPruning::new(self.grammar)));
+        self
+    }
+}
+
+/// The result of [`Parser::parse`] and [`Grammar::parse`]
+///
+/// A parse can fail, match with diagnostics, or match only a prefix of the
+/// source: [`ParseResult::into_result`] turns all of these into errors.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+pub struct ParseResult {
+    /// The parsed tree, `None` if the rule does not match at all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<Token>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<naive::Diagnostic>,
+    /// The bytes from the start of the source to the end of `token`
+    pub consumed: usize,
+}
+
+impl ParseResult {
+    /// Whether the rule matched all the `src_len` bytes of the source without diagnostics
+    pub fn is_complete(&self, src_len: usize) -> bool {
+        self.token.is_some() && self.diagnostics.is_empty() && self.consumed == src_len
+    }
+
+    /// The token, or an error if the parse is not [complete](ParseResult::is_complete)
+    pub fn into_result(self, src_len: usize) -> anyhow::Result<Token> {
+        let Some(token) = self.token else {
+            anyhow::bail!("Failed to parse");
+        };
+        if !self.diagnostics.is_empty() {
+            let messages = naive::Diagnostic::dedup(self.diagnostics).iter().map(|d| d.message()).collect::<Vec<_>>();
+            anyhow::bail!("Failed to parse: {}", messages.join(", "));
+        }
+        if self.consumed != src_len {
+            anyhow::bail!("Parsed only {} of {src_len} bytes", self.consumed);
+        }
+        Ok(token)
+    }
+
+    /// The token, panicking with the error of [`ParseResult::into_result`]
+    #[track_caller]
+    pub fn unwrap_complete(self, src_len: usize) -> Token {
+        self.into_result(src_len).unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+impl From<Option<(Token, Vec<naive::Diagnostic>)>> for ParseResult {
+    fn from(parsed: Option<(Token, Vec<naive::Diagnostic>)>) -> Self {
+        match parsed {
+            Some((token, diagnostics)) => Self { consumed: token.span.end, token: Some(token), diagnostics },
+            None => Self::default(),
+        }
+    }
+}
+
+impl<'a, T: TerminalNode<Src = str>, Tr: Tracer<&'a Node<T>>> Parser<'a, T, Tr> {
+    /// Parse `non_term` from `source`, asking for more input if it ends mid-parse
+    ///
+    /// This is for input that arrives in chunks, such as the lines of a REPL.
+    /// The parse needs more input when it is not complete and reached the
+    /// end of `source`, see [`State::furthest`]: a terminal was tried there,
+    /// or a literal was cut by it, as `"let"` by `le`. Feeding the rest to
+    /// the [`Continuation`] then parses the whole text again. Otherwise, the
+    /// result is final: more input would not fix an error before the end.
+    ///
+    /// A regex cut by the end of `source`, such as an unterminated string,
+    /// does not report a partial match, so the parse stops where the regex
+    /// starts rather than at the end, and is final.
+    pub fn parse_resumable(&self, non_term: &str, source: &str) -> anyhow::Result<Resumable> {
+        let (parsed, furthest) = self.parse_reaching(non_term, source, 0)?;
+        let result = ParseResult::from(parsed.map(|parsed| self.finish(parsed)));
+        let needs_input = !result.is_complete(source.len()) && furthest >= source.len();
+        Ok(if needs_input {
+            Resumable::NeedsInput(Continuation {
+                non_term: non_term.to_string(),
+                source: source.to_string(),
+                partial: result,
+            })
+        } else {
+            Resumable::Done(result)
+        })
+    }
+}
+
+/// The result of [`Parser::parse_resumable`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resumable {
+    /// The parse is final, complete or not
+    Done(ParseResult),
+    /// The input ended mid-parse, see [`Continuation::feed`]
+    NeedsInput(Continuation),
+}
+
+/// A parse waiting for more input, see [`Parser::parse_resumable`]
+///
+/// The parse loop is not suspended: the continuation keeps the rule and
+/// the text so far, and each [`Continuation::feed`] parses all the text
+/// again, so that tokens across the chunks are matched as a whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Continuation {
+    non_term: String,
+    source: String,
+    /// The result with the input so far, as if it ended there
+    pub partial: ParseResult,
+}
+
+impl Continuation {
+    /// The text fed so far
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Continue the parse with `more` text, using the options of `parser`
+    ///
+    /// The parser should have the grammar and options of the one that
+    /// started the parse.
+    pub fn feed<'a, T: TerminalNode<Src = str>, Tr: Tracer<&'a Node<T>>>(
+        mut self,
+        parser: &Parser<'a, T, Tr>,
+        more: &str,
+    ) -> anyhow::Result<Resumable> {
+        self.source.push_str(more);
+        parser.parse_resumable(&self.non_term, &self.source)
+    }
+
+    /// The result if the input ends here
+    pub fn finish(self) -> ParseResult {
+        self.partial
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<'a, T: TerminalNode + Send + Sync, Tr> Parser<'a, T, Tr>
+where
+    T::Src: Sync,
+{
+    /// See [`State::with_parallel_alt`]
+    pub fn parallel_alt(mut self, min_branches: usize) -> Self {
+        self.parallel_alt = Some((min_branches, |state, n| state.with_parallel_alt(n)));
+        self
+    }
+}
+
+/// Check that parsing can start at `pos` of `source`
+pub(super) fn check_start<T: TerminalNode>(source: &T::Src, pos: usize) -> anyhow::Result<()> {
+    if !T::is_boundary(source, pos) {
+        anyhow::bail!("Cannot start parsing at {pos}, which is not a boundary of the source");
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::cell::RefCell;
+
+    use crate::{basic::Text, gram, parsers::naive::{Diagnostic, LogTracer, TraceEvent}};
+
+    use super::*;
+
+    #[test]
+    fn tracer() {
+        let mut grammar = Grammar::<Text>::new();
+        grammar.add_element("greeting", gram!(("hello", " ", name))).unwrap();
+        grammar.add_element("name", gram!(("world" | "there"))).unwrap();
+
+        let events = RefCell::new(vec![]);
+        let parser = grammar.parser().with_tracer(|event: TraceEvent<'_, &Node<Text>>| {
+            let line = match event {
+                TraceEvent::Enter { node, pos, depth } => format!("{depth} enter {node} @ {pos}"),
+                TraceEvent::Exit { parsed, depth } => {
+                    let span = parsed.map(|p| p.token.span.clone());
+                    format!("{depth} exit {span:?}")
+                }
+            };
+            events.borrow_mut().push(line);
+        });
+
+        let result = parser.parse_non_term("greeting", "hello there").unwrap();
+        assert_eq!(result, grammar.parse_non_term("greeting", "hello there").unwrap());
+        assert_eq!(events.into_inner(), vec![
+            "0 enter \"hello\" \" \" name @ 0",
+            "1 enter \"hello\" @ 0",
+            "1 exit Some(0..5)",
+            "1 enter \" \" @ 5",
+            "1 exit Some(5..6)",
+            "1 enter name @ 6",
+            "2 enter \"world\" | \"there\" @ 6",
+            "3 enter \"world\" @ 6",
+            "3 exit None",
+            "3 enter \"there\" @ 6",
+            "3 exit Some(6..11)",
+            "2 exit Some(6..11)",
+            "1 exit Some(6..11)",
+            "0 exit Some(0..11)",
+        ]);
+
+        let parser = grammar.parser().with_tracer(LogTracer);
+        assert!(parser.parse_non_term("greeting", "hello world").unwrap().is_some());
+    }
+
+    #[test]
+    fn max_terminal_match() {
+        let grammar = Grammar::load_ebnf(r#"
+            fields = (#'[a-z]*' , ";")*;
+        "#).unwrap();
+        let src = "ab;abcd;abc;";
+
+        let (token, diagnostics) = grammar.parse_non_term("fields", src).unwrap().unwrap();
+        assert_eq!(token.span, 0..12);
+        assert!(diagnostics.is_empty());
+
+        // the limit is inclusive, longer fields are cut
+        let parser = grammar.parser().max_terminal_match(3).with_tracer(LogTracer);
+        let (token, diagnostics) = parser.parse_non_term("fields", src).unwrap().unwrap();
+        assert_eq!(token.children[1].children[0].span, 3..6);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].main_span(), 6..6);
+        let (token, _) = parser.parse_non_term_at("fields", src, 8).unwrap().unwrap();
+        assert_eq!(token.span, 8..12);
+    }
+
+    #[test]
+    fn line_bounded_terminals() {
+        let grammar = Grammar::load_ebnf(r#"
+            lines = (#'[^;]+' , ";")*;
+        "#).unwrap();
+        let src = "ab\ncd;ef;";
+
+        let (token, diagnostics) = grammar.parse_non_term("lines", src).unwrap().unwrap();
+        assert_eq!(token.children[0].children[0].span, 0..5);
+        assert!(diagnostics.is_empty());
+
+        // the greedy regex stops after the newline
+        let parser = grammar.parser().line_bounded_terminals();
+        let (token, diagnostics) = parser.parse_non_term("lines", src).unwrap().unwrap();
+        assert_eq!(token.children[0].children[0].span, 0..3);
+        assert_eq!(token.children[1].children[0].span, 3..5);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].main_span(), 3..3);
+
+        // with both limits, the shorter one applies
+        let parser = grammar.parser().line_bounded_terminals().max_terminal_match(1);
+        let (token, _) = parser.parse_non_term("lines", src).unwrap().unwrap();
+        assert_eq!(token.children[0].children[0].span, 0..1);
+    }
+
+    #[test]
+    fn max_repetitions() {
+        let mut grammar = Grammar::load_ebnf(r#"
+            text = "a"* , ".";
+        "#).unwrap();
+        grammar.add_element("list", gram!("a" %% ",")).unwrap();
+        let src = format!("{}.", "a".repeat(100_000));
+
+        let (token, diagnostics) = grammar.parse_non_term("text", &src).unwrap().unwrap();
+        assert_eq!(token.span, 0..src.len());
+        assert!(diagnostics.is_empty());
+
+        let parser = grammar.parser().max_repetitions(1000);
+        let (token, diagnostics) = parser.parse_non_term("text", &src).unwrap().unwrap();
+        assert_eq!(token.span, 0..1000);
+        assert_eq!(token.children[0].children.len(), 1000);
+        assert_eq!(diagnostics, [
+            Diagnostic::RepetitionLimit { span: 0..1000, limit: 1000 },
+            Diagnostic::Incomplete { span: 1000..1000, expected: "\".\"".to_string() },
+        ]);
+        assert_eq!(diagnostics[0].message(), "Repetition at 0 stopped after 1000 matches");
+
+        // within the limit, or with separators
+        assert_eq!(parser.parse("text", "aaa.").unwrap().consumed, 4);
+        let src = vec!["a"; 2000].join(",");
+        let (token, diagnostics) = parser.parse_non_term("list", &src).unwrap().unwrap();
+        assert_eq!(token.span, 0..1999);
+        assert_eq!(diagnostics, [Diagnostic::RepetitionLimit { span: 0..1999, limit: 1000 }]);
+    }
+
+    #[test]
+    fn on_complete() {
+        let grammar = Grammar::load_ebnf(r#"
+            expression = term , (("+" | "-") , term)*;
+            term = factor , ("*" , factor)*;
+            factor = ("(" , expression , ")") | number;
+            number = #'[0-9]+';
+            choice = (one , "x") | uno;
+            one = "1";
+            uno = "1";
+        "#).unwrap();
+        let parse = |start: &str, src: &str, grams: &[&str]| {
+            let completed = RefCell::new(vec![]);
+            let parser = grammar.parser().on_complete(grams.iter().copied(), |token: &Token| {
+                completed.borrow_mut().push((token.gram.clone().unwrap(), token.span.clone()));
+            });
+            let (token, _) = parser.parse_non_term(start, src).unwrap().unwrap();
+            drop(parser);
+            (token, completed.into_inner())
+        };
+
+        let (token, completed) = parse("expression", "1+2*(3-4)", &["term", "number"]);
+        assert_eq!(completed, [
+            ("number", 0..1), ("term", 0..1), ("number", 2..3), ("number", 5..6),
+            ("term", 5..6), ("number", 7..8), ("term", 7..8), ("term", 2..9),
+        ].map(|(gram, span)| (gram.to_string(), span)));
+        for gram in ["term", "number"] {
+            for found in token.iter_grams(gram) {
+                assert!(completed.contains(&(gram.to_string(), found.span.clone())), "{gram} {:?}", found.span);
+            }
+        }
+
+        // the discarded alternative is reported too
+        let (token, completed) = parse("choice", "1", &["one", "uno"]);
+        assert_eq!(token.iter_grams("one").count(), 0);
+        assert_eq!(completed, [("one".to_string(), 0..1), ("uno".to_string(), 0..1)]);
+    }
+
+    #[test]
+    fn record_captures() {
+        let mut grammar = Grammar::<Text>::new();
+        grammar.add_element("item", gram!(((#r"(?P<num>[0-9]+)_(?P<word>[a-z]+)"), ";"))).unwrap();
+        let src = "12_ab;";
+
+        let (token, _) = grammar.parse_non_term("item", src).unwrap().unwrap();
+        assert!(token.children[0].meta.is_empty());
+
+        let (token, _) = grammar.parser().record_captures().parse_non_term("item", src).unwrap().unwrap();
+        let matched = &token.children[0];
+        assert_eq!(matched.meta_get("capture.num"), Some("0..2"));
+        assert_eq!(matched.capture("num"), Some(0..2));
+        assert_eq!(matched.capture("word").map(|span| &src[span]), Some("ab"));
+        assert_eq!(matched.capture("other"), None);
+        assert!(token.children[1].meta.is_empty());
+
+        #[cfg(feature = "parallel")]
+        {
+            let mut grammar = grammar.clone();
+            grammar.add_element("choice", gram!((item | ("1", "2")))).unwrap();
+            let parser = grammar.parser().record_captures().parallel_alt(2);
+            let (token, _) = parser.parse_non_term("choice", src).unwrap().unwrap();
+            let (matched, _) = token.find_meta("capture.num").unwrap();
+            assert_eq!(matched.capture("word"), Some(3..5));
+
+            let parser = grammar.parser().memoize_shared_nodes().parallel_alt(2);
+            assert!(parser.parse_non_term("choice", src).is_err());
+            let parser = grammar.parser().parallel_alt(2).on_complete(["item"], |_: &Token| {});
+            assert!(parser.parse_non_term("choice", src).is_err());
+        }
+    }
+
+    #[test]
+    fn incomplete_spans() {
+        let node = gram!(("foo", ((" ", "bar")+)));
+        let grammar = Grammar::<Text>::new();
+        let parse = |mode, src| {
+            let (token, diagnostics) = grammar.parser().incomplete_spans(mode).parse_node(&node, src).unwrap().unwrap();
+            let rep = token.children.get(1).map(|rep| rep.span.clone());
+            assert!(diagnostics.len() <= 1);
+            (token.span, rep, diagnostics.first().map(|d| d.main_span()))
+        };
+
+        // the literal `bar` partially matches `ba`
+        assert_eq!(parse(IncompleteSpans::Minimal, "foo ba"), (0..4, Some(3..4), Some(4..4)));
+        assert_eq!(parse(IncompleteSpans::Maximal, "foo ba"), (0..6, Some(3..6), Some(4..4)));
+        assert_eq!(parse(IncompleteSpans::Maximal, "foo bar bx"), (0..9, Some(3..9), Some(8..8)));
+
+        // nothing was attempted past the matched prefix
+        for mode in [IncompleteSpans::Minimal, IncompleteSpans::Maximal] {
+            assert_eq!(parse(mode, "foo"), (0..3, None, Some(3..3)));
+            assert_eq!(parse(mode, "foo bar"), (0..7, Some(3..7), None));
+            assert_eq!(parse(mode, "foo x"), (0..4, Some(3..4), Some(4..4)));
+        }
+
+        // the choice and the rest of the parse see the matched prefix only
+        let node = gram!(((("a", "bc") | "a"), "b"));
+        for mode in [IncompleteSpans::Minimal, IncompleteSpans::Maximal] {
+            let (token, diagnostics) = grammar.parser().incomplete_spans(mode).parse_node(&node, "ab").unwrap().unwrap();
+            assert_eq!(token.span, 0..2);
+            assert_eq!(diagnostics, []);
+            assert!(token.meta.is_empty() && token.children.iter().all(|child| child.meta.is_empty()));
+        }
+        // an extension stops at the next token
+        let node = gram!(((("a", "bc")?), "b"));
+        let (token, diagnostics) = grammar.parser().incomplete_spans(IncompleteSpans::Maximal).parse_node(&node, "ab").unwrap().unwrap();
+        assert_eq!(token.span, 0..2);
+        assert!(token.validate_spans().is_ok());
+        assert_eq!(diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn revisit_choices() {
+        let grammar = Grammar::load_ebnf(r#"
+            s = ("a" | "ab" | "abc") , "bc" , [";" , s];
+            t = ("x" | ("x" , "y")) , ("y" | "yz") , "z";
+            u = ("a" | "ab") , ("c" | "bc");
+            v = w , "bc";
+            w = "a" | "ab";
+        "#).unwrap();
+        let parse = |parser: &Parser<Text>, gram, src: &str| {
+            let (token, diagnostics) = parser.parse_non_term(gram, src).unwrap().unwrap();
+            (token.span, diagnostics.is_empty())
+        };
+        let greedy = grammar.parser();
+        let revisit = grammar.parser().revisit_choices();
+
+        // the longest choice leaves no `bc`
+        assert_eq!(parse(&greedy, "s", "abc"), (0..3, false));
+        assert_eq!(parse(&revisit, "s", "abc"), (0..3, true));
+        assert_eq!(parse(&revisit, "s", "abcbc;abc"), (0..9, true));
+        // the last choice is revisited first, then the one before it
+        assert_eq!(parse(&greedy, "t", "xyz"), (0..2, false));
+        assert_eq!(parse(&revisit, "t", "xyz"), (0..3, true));
+        // a choice behind a non-terminal is not revisited
+        assert_eq!(parse(&revisit, "v", "abc"), parse(&greedy, "v", "abc"));
+        assert_eq!(parse(&revisit, "s", "abx"), parse(&greedy, "s", "abx"));
+
+        // annotated choices are revisited too, the alternative keeping the annotations
+        let node = Node::seq([
+            Node::tagged(gram!(("a" | "ab")), "t").with_description("d"),
+            gram!("bc"),
+        ]);
+        let (token, diagnostics) = grammar.parser().revisit_choices().parse_node(&node, "abc").unwrap().unwrap();
+        assert_eq!((token.span, diagnostics), (0..3, vec![]));
+        assert_eq!(token.children[0].span, 0..1);
+        assert_eq!(token.children[0].tags, ["t"]);
+        assert_eq!(token.children[0].meta["description"], "d");
+
+        // inputs that parse greedily are unchanged
+        for (gram, src) in [("s", "abcbc"), ("s", "abbc"), ("u", "abc"), ("t", "xyyzz")] {
+            let greedy = greedy.parse_non_term(gram, src).unwrap();
+            assert_eq!(revisit.parse_non_term(gram, src).unwrap(), greedy, "{src}");
+        }
+
+        #[cfg(feature = "parallel")]
+        {
+            let parallel = grammar.parser().revisit_choices().parallel_alt(2);
+            for (gram, src) in [("s", "abc"), ("s", "abcbc;abc"), ("t", "xyz"), ("v", "abc")] {
+                assert_eq!(parallel.parse_non_term(gram, src).unwrap(), revisit.parse_non_term(gram, src).unwrap(), "{src}");
+            }
+        }
+    }
+
+    #[test]
+    fn parse_result() {
+        let grammar = Grammar::load_ebnf(r#"
+            pair = word , "=" , word;
+            word = #'[a-z]+';
+        "#).unwrap();
+
+        let complete = grammar.parse("pair", "a=b").unwrap();
+        assert_eq!(complete.consumed, 3);
+        assert!(complete.is_complete(3));
+        assert!(!complete.is_complete(4));
+        assert_eq!(complete.clone().unwrap_complete(3).span, 0..3);
+        assert_eq!(grammar.parser().parse("pair", "a=b").unwrap(), complete);
+
+        // a prefix
+        let prefix = grammar.parse("pair", "a=b c").unwrap();
+        assert_eq!(prefix.consumed, 3);
+        assert!(prefix.diagnostics.is_empty());
+        assert_eq!(prefix.into_result(5).unwrap_err().to_string(), "Parsed only 3 of 5 bytes");
+
+        // diagnostics
+        let incomplete = grammar.parse("pair", "a=").unwrap();
+        assert!(incomplete.token.is_some());
+        assert!(!incomplete.is_complete(2));
+        let error = incomplete.into_result(2).unwrap_err().to_string();
+        assert!(error.starts_with("Failed to parse: Incomplete parse at 2"), "{error}");
+
+        // no match
+        let failed = grammar.parse("word", "=").unwrap();
+        assert_eq!(failed, ParseResult::default());
+        assert!(!failed.is_complete(0));
+        assert_eq!(failed.into_result(1).unwrap_err().to_string(), "Failed to parse");
+
+        assert!(grammar.parse("missing", "a").is_err());
+    }
+
+    #[test]
+    fn parse_resumable() {
+        let grammar = Grammar::load_ebnf(r#"
+            call = name , "(" , [args] , ")";
+            args = name , ("," , name)*;
+            name = #'[a-z]+';
+        "#).unwrap();
+        let parser = grammar.parser();
+        let chunked = |chunks: &[&str]| {
+            let mut resumable = parser.parse_resumable("call", "").unwrap();
+            for chunk in chunks {
+                let Resumable::NeedsInput(continuation) = resumable else {
+                    panic!("done before {chunk:?}");
+                };
+                resumable = continuation.feed(&parser, chunk).unwrap();
+            }
+            resumable
+        };
+
+        // chunks give the result of the whole text, even when they split a token
+        for chunks in [&["f(a,", "b)"][..], &["f", "(a", ",b", ")"], &["f(ab", "c)"], &["f(a,b)"]] {
+            let Resumable::Done(result) = chunked(chunks) else {
+                panic!("{chunks:?} needs input");
+            };
+            let src = chunks.concat();
+            assert_eq!(result, grammar.parse("call", &src).unwrap(), "{chunks:?}");
+            assert!(result.is_complete(src.len()));
+        }
+
+        let Resumable::NeedsInput(continuation) = parser.parse_resumable("call", "f(a,").unwrap() else {
+            panic!("f(a, is done");
+        };
+        assert_eq!(continuation.source(), "f(a,");
+        let partial = continuation.finish();
+        assert_eq!(partial, grammar.parse("call", "f(a,").unwrap());
+        assert_eq!(partial.diagnostics[0].main_span(), 4..4);
+
+        // errors before the end are final
+        for src in ["f)", "f(a))", "(", "f(a b"] {
+            assert!(matches!(parser.parse_resumable("call", src).unwrap(), Resumable::Done(_)), "{src}");
+        }
+        assert!(matches!(chunked(&["f(a", "))"]), Resumable::Done(_)));
+
+        // chunks ending inside a literal
+        let grammar = Grammar::load_ebnf(r#"
+            binding = "let" , " " , name , ";";
+            path = name , "::" , name;
+            name = #'[a-z]+';
+        "#).unwrap();
+        let parser = grammar.parser();
+        for (rule, chunks) in [("binding", ["le", "t x;"]), ("path", ["a:", ":b"])] {
+            let Resumable::NeedsInput(continuation) = parser.parse_resumable(rule, chunks[0]).unwrap() else {
+                panic!("{:?} is done", chunks[0]);
+            };
+            let Resumable::Done(result) = continuation.feed(&parser, chunks[1]).unwrap() else {
+                panic!("{chunks:?} needs input");
+            };
+            let src = chunks.concat();
+            assert_eq!(result, grammar.parse(rule, &src).unwrap(), "{chunks:?}");
+            assert!(result.is_complete(src.len()));
+        }
+        assert!(matches!(parser.parse_resumable("binding", "lx").unwrap(), Resumable::Done(_)));
+    }
+
+    #[test]
+    fn parse_result_serde() {
+        let grammar = Grammar::load_ebnf(r#"
+            pair = word , "=" , word;
+            word = #'[a-z]+';
+        "#).unwrap();
+        for src in ["a=b", "a=", "="] {
+            let result = grammar.parse("pair", src).unwrap();
+            let json = serde_json::to_string(&result).unwrap();
+            assert_eq!(serde_json::from_str::<ParseResult>(&json).unwrap(), result, "{json}");
+        }
+
+        let json = serde_json::to_value(grammar.parse("pair", "a=").unwrap()).unwrap();
+        assert_eq!(json["consumed"], 2);
+        assert_eq!(json["diagnostics"], serde_json::json!([{ "kind": "incomplete", "span": [2, 2], "expected": "word" }]));
+        assert_eq!(serde_json::to_string(&ParseResult::default()).unwrap(), r#"{"consumed":0}"#);
+
+        let limit = Diagnostic::RepetitionLimit { span: 0..10, limit: 10 };
+        let json = serde_json::to_string(&limit).unwrap();
+        assert_eq!(json, r#"{"kind":"repetition_limit","span":[0,10],"limit":10}"#);
+        assert_eq!(serde_json::from_str::<Diagnostic>(&json).unwrap(), limit);
+    }
+}
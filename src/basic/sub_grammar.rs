@@ -0,0 +1,147 @@
+use alloc::sync::Arc;
+
+use crate::prelude::*;
+
+use super::{Grammar, TerminalNode, Text};
+
+/// A terminal matching whatever the `start` rule of another grammar matches
+///
+/// This composes grammars at the terminal boundary, without merging their
+/// rules: the sub-grammar is parsed on its own at the position of the
+/// terminal, and the terminal consumes what the `start` rule consumes. The
+/// tree of the sub-parse is not kept, parse the matched span again with the
+/// sub-grammar to get it.
+///
+/// A terminal has no way to report diagnostics, so a sub-parse with
+/// diagnostics, such as an incomplete sequence, is not a match. The outer
+/// parser then handles the terminal like any other that fails.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SubGrammar {
+    grammar: Arc<Grammar<Text>>,
+    start: String,
+}
+
+impl SubGrammar {
+    /// Match the `start` rule of `grammar`, which must exist
+    pub fn new(grammar: Grammar<Text>, start: impl Into<String>) -> anyhow::Result<Self> {
+        let start = start.into();
+        if !grammar.has(&start) {
+            anyhow::bail!("No rule for start node {start:?}");
+        }
+        Ok(Self { grammar: Arc::new(grammar), start })
+    }
+
+    pub fn grammar(&self) -> &Grammar<Text> {
+        &self.grammar
+    }
+
+    pub fn start(&self) -> &str {
+        &self.start
+    }
+}
+
+impl TerminalNode for SubGrammar {
+    type Src = str;
+    fn parses(&self, src: &str, pos: usize) -> anyhow::Result<Option<usize>> {
+        Ok(self.grammar
+            .parse_non_term_at(&self.start, src, pos)?
+            .filter(|(_, diagnostics)| diagnostics.is_empty())
+            .map(|(token, _)| token.span.end))
+    }
+    fn is_boundary(src: &str, pos: usize) -> bool {
+        Text::is_boundary(src, pos)
+    }
+    /// An ISO EBNF special sequence, `? start ?`
+    fn to_ebnf(&self) -> String {
+        format!("? {} ?", self.start)
+    }
+    fn is_nullable(&self) -> bool {
+        self.grammar.nullable_rules().contains(&self.start)
+    }
+}
+
+/// Either a [`Text`] or a [`SubGrammar`] terminal
+///
+/// Nodes built with [`gram!`](crate::gram) can be converted with
+/// [`Node::map_terminals`](super::Node::map_terminals).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SubText {
+    Text(Text),
+    Grammar(SubGrammar),
+}
+
+impl From<Text> for SubText {
+    fn from(text: Text) -> Self {
+        SubText::Text(text)
+    }
+}
+
+impl From<SubGrammar> for SubText {
+    fn from(sub: SubGrammar) -> Self {
+        SubText::Grammar(sub)
+    }
+}
+
+impl TerminalNode for SubText {
+    type Src = str;
+    fn parses(&self, src: &str, pos: usize) -> anyhow::Result<Option<usize>> {
+        match self {
+            SubText::Text(text) => text.parses(src, pos),
+            SubText::Grammar(sub) => sub.parses(src, pos),
+        }
+    }
+    fn is_boundary(src: &str, pos: usize) -> bool {
+        Text::is_boundary(src, pos)
+    }
+    fn to_ebnf(&self) -> String {
+        match self {
+            SubText::Text(text) => text.to_ebnf(),
+            SubText::Grammar(sub) => sub.to_ebnf(),
+        }
+    }
+    fn is_nullable(&self) -> bool {
+        match self {
+            SubText::Text(text) => text.is_nullable(),
+            SubText::Grammar(sub) => sub.is_nullable(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{basic::Node, gram};
+
+    use super::*;
+
+    #[test]
+    fn nested() {
+        // string literals with escapes, as a grammar of their own
+        let string = SubGrammar::new(Grammar::load_ebnf(r#"
+            string = '"' , (char | escape)* , '"';
+            char = #'[^"\\]';
+            escape = "\\" , #'.';
+        "#).unwrap(), "string").unwrap();
+        assert!(SubGrammar::new(Grammar::new(), "string").is_err());
+
+        let mut grammar = Grammar::<SubText>::new();
+        grammar.add_element("pair", gram!((key, ":", (" "?), value)).map_terminals(SubText::from)).unwrap();
+        grammar.add_element("key", Node::Terminal(string.clone().into())).unwrap();
+        grammar.add_element("value", gram!(((#r"[0-9]+") | key)).map_terminals(SubText::from)).unwrap();
+        assert_eq!(grammar.rules["key"].to_ebnf(), "? string ?");
+
+        let src = r#""a\"b": "c""#;
+        let (token, diagnostics) = grammar.parse_non_term("pair", src).unwrap().unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(token.span, 0..src.len());
+        let keys = token.iter_grams("key").map(|t| &src[t.span.clone()]).collect::<Vec<_>>();
+        assert_eq!(keys, [r#""a\"b""#, r#""c""#]);
+
+        // the unterminated string is incomplete in the sub-grammar, so no match
+        let (token, diagnostics) = grammar.parse_non_term("pair", r#""a": "b"#).unwrap().unwrap();
+        assert_eq!(token.span, 0..5);
+        assert_eq!(diagnostics[0].message(), "Incomplete parse at 5: expected value");
+        assert!(grammar.parse_non_term("pair", r#""a"#).unwrap().is_none());
+
+        assert!(!string.is_nullable());
+    }
+}
@@ -1,70 +1,773 @@
+use alloc::borrow::Cow;
+#[cfg(feature = "std")]
+use core::ops::Range;
+
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 
+use crate::prelude::*;
+
 use super::TerminalNode;
 
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[derive(Encode, Decode)]
 #[derive(Serialize, Deserialize)]
-#[serde(into = "String")]
-#[serde(from = "String")]
+#[serde(into = "TextRepr")]
+#[serde(try_from = "TextRepr")]
 pub enum Text {
     /// A string terminal
     String(String),
     /// A regex terminal
     Regex(String),
+    /// A regex terminal with flags, written `/pattern/ims`
+    RegexFlags {
+        pattern: String,
+        /// `i`, letters match both upper and lower case
+        ///
+        /// A plain pattern (see [`Text::literal`]) with this flag is matched
+        /// without the regex engine, by comparing its case folding with the one
+        /// of the source, so that `/straße/i` matches `STRASSE`. The source is
+        /// folded a char at a time as it is compared, it is never copied, and
+        /// neither is the pattern unless it has escapes or folds differently.
+        /// This also works without the `std` feature.
+        case_insensitive: bool,
+        /// `s`, `.` also matches `\n`
+        dot_all: bool,
+        /// `m`, `^` and `$` also match at line boundaries
+        multiline: bool,
+    },
+    /// A keyword, `word` when not followed by an identifier char, written `` `word` ``
+    ///
+    /// Non-default identifier chars are written after it, as in `` `end`[A-Za-z0-9_-] ``.
+    Keyword {
+        word: String,
+        /// The chars that continue an identifier, as the inside of a regex
+        /// class: single chars, ranges such as `a-z` and `\` escapes
+        ident_chars: String,
+    },
 }
 
-impl TerminalNode for Text {
-    type Src = str;
-    fn parses(&self, src: &Self::Src, pos: usize) -> anyhow::Result<Option<usize>> {
+/// How [`Text::to_ebnf_with`] writes regexes, both forms being read back by [`Grammar::load_ebnf`]
+///
+/// [`Grammar::load_ebnf`]: crate::basic::Grammar::load_ebnf
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RegexDelimiter {
+    /// `/re/` followed by the flags, each `/` of the pattern not escaped by a `\` written `\/`
+    ///
+    /// Any `\/` is read back as `/`, including a pattern's own `\/`, which
+    /// matches the same. Other escapes are kept as they are.
+    #[default]
+    Slash,
+    /// `#'re'`, or `#"re"` if the pattern has an unescaped `'`, the pattern as it is
+    ///
+    /// Patterns with flags, with both quotes not escaped by a `\` or ending
+    /// with an escaping `\` have no such form and are written with slashes.
+    Quoted,
+}
+
+impl Text {
+    /// The identifier chars of [`Text::keyword`]
+    pub const IDENT_CHARS: &str = "A-Za-z0-9_";
+
+    /// A keyword that does not match before ASCII letters, digits and `_`
+    pub fn keyword(word: impl Into<String>) -> Self {
+        Text::Keyword { word: word.into(), ident_chars: Self::IDENT_CHARS.to_string() }
+    }
+
+    /// A keyword that does not match before the `ident_chars`, see [`Text::Keyword`]
+    ///
+    /// For example, `-` continues identifiers in Lisp, so that `end` is not a
+    /// keyword in `end-block` with `A-Za-z0-9_-`.
+    pub fn keyword_with(word: impl Into<String>, ident_chars: impl Into<String>) -> anyhow::Result<Self> {
+        let ident_chars = ident_chars.into();
+        class_ranges(&ident_chars)?;
+        Ok(Text::Keyword { word: word.into(), ident_chars })
+    }
+
+    /// A regex terminal with the `flags` letters among `ims`
+    ///
+    /// Without flags, this is a plain [`Text::Regex`].
+    pub fn regex_with_flags(pattern: impl Into<String>, flags: &str) -> anyhow::Result<Self> {
+        let pattern = pattern.into();
+        if flags.is_empty() {
+            return Ok(Text::Regex(pattern));
+        }
+        let (mut case_insensitive, mut dot_all, mut multiline) = (false, false, false);
+        for flag in flags.chars() {
+            match flag {
+                'i' => case_insensitive = true,
+                's' => dot_all = true,
+                'm' => multiline = true,
+                _ => anyhow::bail!("Unknown regex flag {flag:?}"),
+            }
+        }
+        Ok(Text::RegexFlags { pattern, case_insensitive, dot_all, multiline })
+    }
+
+    /// The only string the terminal matches, if it is a literal or a regex without metacharacters
+    ///
+    /// A regex is plain if it only has literal characters and escaped
+    /// punctuation such as `\.` or `\/`. The `s` and `m` flags then have no
+    /// effect, while the `i` flag makes a regex with cased characters match
+    /// more than one string. Escapes of letters and digits (`\d`, `\n`, ...)
+    /// and of `<` and `>` (word boundaries) are not plain, nor are invalid
+    /// regexes, which never match.
+    pub fn literal(&self) -> Option<String> {
+        let (pattern, case_insensitive) = match self {
+            Text::String(s) => return Some(s.clone()),
+            Text::Keyword { .. } => return None,
+            Text::Regex(pattern) => (pattern, false),
+            Text::RegexFlags { pattern, case_insensitive, .. } => (pattern, *case_insensitive),
+        };
+        let literal = plain_pattern(pattern)?.into_owned();
+        let cased = |c: char| c.is_alphabetic() || !c.to_lowercase().eq(c.to_uppercase());
+        if case_insensitive && literal.chars().any(cased) {
+            return None;
+        }
+        #[cfg(feature = "std")]
+        regex::Regex::new(pattern).ok()?;
+        Some(literal)
+    }
+
+    /// [`TerminalNode::parses`], with regexes only seeing `src[..window_end]`
+    #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+    fn parses_before(&self, src: &str, pos: usize, window_end: usize) -> anyhow::Result<Option<usize>> {
+        if !src.is_char_boundary(pos) {
+            anyhow::bail!("Position {pos} is not on a char boundary of a source of {} bytes", src.len());
+        }
+        if let Text::RegexFlags { pattern, case_insensitive: true, .. } = self
+            && let Some(word) = plain_pattern(pattern)
+        {
+            return Ok(match_folded(&word, &src[..window_end], pos));
+        }
         let r = match self {
             Text::String(s) => {
                 let start = pos;
                 let end = pos + s.len();
-                if end <= src.len() && &src[start..end] == s {
+                // bytes are compared so that `end` can be inside a char of
+                // `src`, in which case they differ
+                if end <= src.len() && &src.as_bytes()[start..end] == s.as_bytes() {
                     Some(end)
                 } else {
                     None
                 }
             },
-            Text::Regex(re) => 'a: {
-                // TODO some caching
-                let re = regex::Regex::new(re).map_err(|e| anyhow::anyhow!("Invalid regex: {e}"))?;
-                if let Some(mat) = re.captures(&src[pos..]) {
-                    if mat.get(0).map_or(false, |m| m.start() == 0) {
-                        break 'a Some(pos + mat.get(0).unwrap().end());
-                    }
+            Text::Keyword { word, ident_chars } => {
+                let end = pos + word.len();
+                if end <= src.len() && &src.as_bytes()[pos..end] == word.as_bytes() {
+                    let ranges = class_ranges(ident_chars)?;
+                    let continues = src[end..].chars().next().is_some_and(|c| {
+                        ranges.iter().any(|(first, last)| (*first..=*last).contains(&c))
+                    });
+                    (!continues).then_some(end)
+                } else {
+                    None
                 }
-                None
+            },
+            #[cfg(not(feature = "std"))]
+            Text::Regex(_) | Text::RegexFlags { .. } => anyhow::bail!("Regex terminals need the `std` feature"),
+            #[cfg(feature = "std")]
+            Text::Regex(_) | Text::RegexFlags { .. } => {
+                let re = self.regex()?.expect("a regex terminal");
+                match_at(&re, &src[..window_end], pos)
             },
         };
         Ok(r)
     }
-    fn to_ebnf(&self) -> String {
+
+    /// The compiled regex of a regex terminal, `None` for literals
+    #[cfg(feature = "std")]
+    pub(super) fn regex(&self) -> anyhow::Result<Option<regex::Regex>> {
+        // TODO some caching
+        let re = match self {
+            Text::String(_) | Text::Keyword { .. } => return Ok(None),
+            Text::Regex(re) => regex::Regex::new(re),
+            Text::RegexFlags { pattern, case_insensitive, dot_all, multiline } => regex::RegexBuilder::new(pattern)
+                .case_insensitive(*case_insensitive)
+                .dot_matches_new_line(*dot_all)
+                .multi_line(*multiline)
+                .build(),
+        };
+        Ok(Some(re.map_err(|e| anyhow::anyhow!("Invalid regex: {e}"))?))
+    }
+
+    /// The terminal in EBNF, as [`TerminalNode::to_ebnf`], writing regexes with `delimiter`
+    pub fn to_ebnf_with(&self, delimiter: RegexDelimiter) -> String {
         match self {
             Text::String(s) => format!("{s:?}"),
-            Text::Regex(s) => format!("/{s}/"),
+            Text::Regex(s) => match ['\'', '"'].into_iter().find(|&quote| is_quotable(s, quote)) {
+                Some(quote) if delimiter == RegexDelimiter::Quoted => format!("#{quote}{s}{quote}"),
+                _ => format!("/{}/", escape_slashes(s)),
+            },
+            Text::RegexFlags { pattern, .. } => format!("/{}/{}", escape_slashes(pattern), self.flags()),
+            Text::Keyword { .. } => String::from(self.clone()),
         }
     }
-}
 
-impl Into<String> for Text {
-    fn into(self) -> String {
+    /// The flag letters of a regex, empty for literals and plain regexes
+    fn flags(&self) -> String {
         match self {
-            Text::String(s) => format!("{s}"),
+            Text::RegexFlags { case_insensitive, dot_all, multiline, .. } => [
+                (*case_insensitive, 'i'),
+                (*multiline, 'm'),
+                (*dot_all, 's'),
+            ].into_iter().filter_map(|(set, flag)| set.then_some(flag)).collect(),
+            _ => String::new(),
+        }
+    }
+}
+
+impl TerminalNode for Text {
+    type Src = str;
+    fn parses(&self, src: &Self::Src, pos: usize) -> anyhow::Result<Option<usize>> {
+        self.parses_before(src, pos, src.len())
+    }
+    /// Regexes only see the `max_len` bytes after `pos`, or less so as not to
+    /// cut a char, as if the source ended there. Literals are not limited.
+    fn parses_within(&self, src: &Self::Src, pos: usize, max_len: usize) -> anyhow::Result<Option<usize>> {
+        self.parses_before(src, pos, window_end(src, pos, max_len))
+    }
+    /// The named groups of a regex that took part in the match, literals have none
+    #[cfg(feature = "std")]
+    fn captures(&self, src: &Self::Src, pos: usize, max_len: Option<usize>) -> anyhow::Result<Vec<(String, Range<usize>)>> {
+        let Some(re) = self.regex()?.filter(|re| re.capture_names().flatten().next().is_some()) else {
+            return Ok(vec![]);
+        };
+        let end = max_len.map_or(src.len(), |max_len| window_end(src, pos, max_len));
+        let Some(captures) = re.captures(&src[pos..end]).filter(|c| c.get(0).is_some_and(|m| m.start() == 0)) else {
+            return Ok(vec![]);
+        };
+        Ok(re
+            .capture_names()
+            .flatten()
+            .filter_map(|name| captures.name(name).map(|m| (name.to_string(), pos + m.start()..pos + m.end())))
+            .collect())
+    }
+    /// Literals and keywords reach the end of the common prefix of their word
+    /// and the source, regexes do not report partial matches
+    fn reaches(&self, src: &Self::Src, pos: usize) -> usize {
+        let word = match self {
+            Text::String(word) | Text::Keyword { word, .. } => word,
+            Text::Regex(_) | Text::RegexFlags { .. } => return pos,
+        };
+        let rest = src.get(pos..).unwrap_or_default();
+        pos + word
+            .chars()
+            .zip(rest.chars())
+            .take_while(|(a, b)| a == b)
+            .map(|(c, _)| c.len_utf8())
+            .sum::<usize>()
+    }
+    fn line_len(src: &Self::Src, pos: usize) -> Option<usize> {
+        src.get(pos..)?.find('\n').map(|i| i + 1)
+    }
+    fn is_boundary(src: &str, pos: usize) -> bool {
+        src.is_char_boundary(pos)
+    }
+    fn to_ebnf(&self) -> String {
+        self.to_ebnf_with(RegexDelimiter::Slash)
+    }
+    fn is_nullable(&self) -> bool {
+        // an invalid regex never matches
+        matches!(self.parses("", 0), Ok(Some(_)))
+    }
+}
+
+/// The end of the window of [`TerminalNode::parses_within`], on a char boundary
+pub(super) fn window_end(src: &str, pos: usize, max_len: usize) -> usize {
+    let mut end = pos.saturating_add(max_len).min(src.len());
+    while !src.is_char_boundary(end) {
+        end -= 1;
+    }
+    end
+}
+
+/// The ranges of chars of `class`, the inside of a regex class such as `A-Za-z0-9_`
+///
+/// A `-` is a range only between two chars, `\` escapes the next char. An
+/// unescaped `]` is not allowed, as it would end the written class.
+fn class_ranges(class: &str) -> anyhow::Result<Vec<(char, char)>> {
+    let mut chars = vec![];
+    let mut iter = class.chars();
+    while let Some(c) = iter.next() {
+        match c {
+            '\\' => match iter.next() {
+                Some(c) => chars.push((c, true)),
+                None => anyhow::bail!("Unterminated escape in identifier chars {class:?}"),
+            },
+            ']' => anyhow::bail!("Unescaped `]` in identifier chars {class:?}"),
+            c => chars.push((c, false)),
+        }
+    }
+    let mut ranges = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let first = chars[i].0;
+        if i + 2 < chars.len() && chars[i + 1] == ('-', false) {
+            let last = chars[i + 2].0;
+            if first > last {
+                anyhow::bail!("Invalid range {first}-{last} in identifier chars {class:?}");
+            }
+            ranges.push((first, last));
+            i += 3;
+        } else {
+            ranges.push((first, first));
+            i += 1;
+        }
+    }
+    Ok(ranges)
+}
+
+/// Split a keyword written `` `word` `` or `` `word`[ident chars] `` from the start of `src`
+///
+/// Returns the keyword and the length of its text, `None` if `src` does not
+/// start with one. `` ` `` and `\` are escaped with `\` in the word.
+#[cfg(feature = "std")]
+pub(super) fn split_keyword(src: &str) -> Option<(Text, usize)> {
+    let mut chars = src.char_indices();
+    if chars.next()?.1 != '`' {
+        return None;
+    }
+    let mut word = String::new();
+    let word_end = loop {
+        match chars.next()? {
+            (i, '`') => break i + 1,
+            (_, '\\') => word.push(chars.next()?.1),
+            (_, c) => word.push(c),
+        }
+    };
+    let Some(class) = src[word_end..].strip_prefix('[') else {
+        return Some((Text::keyword(word), word_end));
+    };
+    let mut escaped = false;
+    let class_len = class.find(|c| {
+        let end = c == ']' && !escaped;
+        escaped = c == '\\' && !escaped;
+        end
+    })?;
+    let text = Text::keyword_with(word, &class[..class_len]).ok()?;
+    Some((text, word_end + 1 + class_len + 1))
+}
+
+/// The pattern of a regex made only of literal chars and escaped punctuation, unescaped
+///
+/// See [`Text::literal`], the pattern is only copied if it has escapes.
+fn plain_pattern(pattern: &str) -> Option<Cow<'_, str>> {
+    if !pattern.contains('\\') {
+        let meta = ['.', '^', '$', '*', '+', '?', '(', ')', '[', ']', '{', '}', '|'];
+        return (!pattern.contains(meta)).then_some(Cow::Borrowed(pattern));
+    }
+    let mut literal = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) if escaped.is_ascii_punctuation() && !matches!(escaped, '<' | '>') => {
+                    literal.push(escaped)
+                }
+                _ => return None,
+            },
+            '.' | '^' | '$' | '*' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' => return None,
+            c => literal.push(c),
+        }
+    }
+    Some(Cow::Owned(literal))
+}
+
+/// The case folding of `c`, its lowercase after a round trip through uppercase
+///
+/// This approximates the full Unicode case folding with the case mappings of
+/// `core`: `ß` and `ẞ` fold to `ss`, `ς` and `Σ` to `σ`.
+fn fold_char(c: char) -> impl Iterator<Item = char> {
+    c.to_lowercase().flat_map(char::to_uppercase).flat_map(char::to_lowercase)
+}
+
+/// The case folding of `s`, borrowed if it is already folded
+fn fold_case(s: &str) -> Cow<'_, str> {
+    if s.chars().all(|c| fold_char(c).eq([c])) {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(s.chars().flat_map(fold_char).collect())
+    }
+}
+
+/// The end of the match of `word` starting at `pos`, ignoring case
+///
+/// The chars of `src` are folded one at a time as they are compared with the
+/// folded `word`. A match ends on a char boundary of `src`, so that `s` does
+/// not match the first half of the folding of `ß`.
+fn match_folded(word: &str, src: &str, pos: usize) -> Option<usize> {
+    let word = fold_case(word);
+    let mut expected = word.chars();
+    for (i, c) in src[pos..].char_indices() {
+        if expected.as_str().is_empty() {
+            return Some(pos + i);
+        }
+        for folded in fold_char(c) {
+            if expected.next() != Some(folded) {
+                return None;
+            }
+        }
+    }
+    expected.as_str().is_empty().then_some(src.len())
+}
+
+/// The end of the match of `re` starting exactly at `pos`
+#[cfg(feature = "std")]
+fn match_at(re: &regex::Regex, src: &str, pos: usize) -> Option<usize> {
+    if let Some(mat) = re.captures(&src[pos..])
+        && mat.get(0).is_some_and(|m| m.start() == 0)
+    {
+        return Some(pos + mat.get(0).unwrap().end());
+    }
+    None
+}
+
+/// Escape the unescaped `/` of a regex, to write it between slashes
+fn escape_slashes(re: &str) -> String {
+    let mut escaped = String::with_capacity(re.len());
+    let mut after_backslash = false;
+    for c in re.chars() {
+        if c == '/' && !after_backslash {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        after_backslash = c == '\\' && !after_backslash;
+    }
+    escaped
+}
+
+/// Whether `re` reads back as it is between `quote`s, where a `\` escapes the next char
+fn is_quotable(re: &str, quote: char) -> bool {
+    let mut chars = re.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.next().is_none() => return false,
+            c if c == quote => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Regexes are written as `/re/` and keywords as `` `word` ``
+///
+/// [`Text::from`] reads the regexes back, but not the keywords nor the
+/// literals written like a regex, which the serde form of [`Text`] tells apart.
+impl From<Text> for String {
+    fn from(value: Text) -> Self {
+        match value {
+            Text::String(s) => s,
             Text::Regex(s) => format!("/{s}/"),
+            Text::RegexFlags { ref pattern, .. } => format!("/{pattern}/{}", value.flags()),
+            Text::Keyword { word, ident_chars } => {
+                let word = word.replace('\\', "\\\\").replace('`', "\\`");
+                if ident_chars == Text::IDENT_CHARS {
+                    format!("`{word}`")
+                } else {
+                    format!("`{word}`[{ident_chars}]")
+                }
+            }
         }
     }
 }
 
+/// `/re/` is a regex, anything else a literal
 impl From<String> for Text {
     fn from(value: String) -> Self {
-        if value.starts_with('/') && value.ends_with('/') {
-            Text::Regex(value[1..value.len()-1].to_string())
+        if is_slashed(&value) {
+            Text::Regex(value[1..value.len() - 1].to_string())
         } else {
             Text::String(value)
         }
     }
+}
+
+/// Whether `s` is written like a regex, as `/re/`
+fn is_slashed(s: &str) -> bool {
+    s.len() >= 2 && s.starts_with('/') && s.ends_with('/')
+}
+
+/// The serde form of [`Text`]
+///
+/// A string is read as by [`Text::from`], as in earlier versions. The
+/// terminals a string cannot stand for, the regexes with flags, keywords
+/// and the literals [`Text::from`] would not read back, are written as a map.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum TextRepr {
+    Plain(String),
+    String {
+        string: String,
+    },
+    Regex {
+        regex: String,
+        #[serde(default)]
+        flags: String,
+    },
+    Keyword {
+        keyword: String,
+        #[serde(default = "default_ident_chars", skip_serializing_if = "is_default_ident_chars")]
+        ident_chars: String,
+    },
+}
+
+fn default_ident_chars() -> String {
+    Text::IDENT_CHARS.to_string()
+}
+
+fn is_default_ident_chars(ident_chars: &String) -> bool {
+    ident_chars == Text::IDENT_CHARS
+}
+
+impl From<Text> for TextRepr {
+    fn from(value: Text) -> Self {
+        match value {
+            Text::String(s) if Text::from(s.clone()) != Text::String(s.clone()) => TextRepr::String { string: s },
+            Text::RegexFlags { ref pattern, .. } => TextRepr::Regex { regex: pattern.clone(), flags: value.flags() },
+            Text::Keyword { word, ident_chars } => TextRepr::Keyword { keyword: word, ident_chars },
+            text => TextRepr::Plain(text.into()),
+        }
+    }
+}
+
+impl TryFrom<TextRepr> for Text {
+    type Error = anyhow::Error;
+
+    fn try_from(value: TextRepr) -> Result<Self, Self::Error> {
+        match value {
+            TextRepr::Plain(s) => Ok(Text::from(s)),
+            TextRepr::String { string } => Ok(Text::String(string)),
+            TextRepr::Regex { regex, flags } => Text::regex_with_flags(regex, &flags),
+            TextRepr::Keyword { keyword, ident_chars } => Text::keyword_with(keyword, ident_chars),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serde_round_trip() {
+        let texts = vec![
+            Text::String("a".to_string()),
+            Text::String("/".to_string()),
+            Text::String("/a/".to_string()),
+            Text::String("\\a".to_string()),
+            Text::String("".to_string()),
+            Text::Regex("a/b".to_string()),
+            Text::Regex("a\\/b".to_string()),
+            Text::Regex("".to_string()),
+            Text::regex_with_flags("a/b", "ims").unwrap(),
+            Text::String("/a/x".to_string()),
+            Text::String("`a`".to_string()),
+            Text::keyword("a"),
+            Text::keyword("a`\\b"),
+            Text::keyword_with("a", "a-z\\]-").unwrap(),
+        ];
+        let yaml = serde_yaml::to_string(&texts).unwrap();
+        assert_eq!(serde_yaml::from_str::<Vec<Text>>(&yaml).unwrap(), texts);
+        let json = serde_json::to_string(&texts).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<Text>>(&json).unwrap(), texts);
+
+        // strings are read as in earlier versions
+        let legacy = serde_json::from_str::<Vec<Text>>(r#"["\\x", "/a\\/b/", "/", "/a/i", "a"]"#).unwrap();
+        assert_eq!(legacy, [
+            Text::String("\\x".to_string()),
+            Text::Regex("a\\/b".to_string()),
+            Text::String("/".to_string()),
+            Text::String("/a/i".to_string()),
+            Text::String("a".to_string()),
+        ]);
+        assert_eq!(serde_json::to_string(&legacy).unwrap(), r#"["\\x","/a\\/b/","/","/a/i","a"]"#);
+        // the others are maps
+        assert_eq!(serde_json::to_string(&Text::String("/a/".to_string())).unwrap(), r#"{"string":"/a/"}"#);
+        assert_eq!(serde_json::to_string(&Text::regex_with_flags("a", "i").unwrap()).unwrap(), r#"{"regex":"a","flags":"i"}"#);
+        assert!(serde_json::from_str::<Text>(r#"{"regex":"a","flags":"x"}"#).is_err());
+    }
+
+    #[test]
+    fn keyword() {
+        let end = Text::keyword("end");
+        let lisp_end = Text::keyword_with("end", "A-Za-z0-9_-").unwrap();
+        for (src, default, lisp) in [
+            ("end", Some(3), Some(3)),
+            ("end block", Some(3), Some(3)),
+            ("end)", Some(3), Some(3)),
+            ("end-block", Some(3), None),
+            ("end_block", None, None),
+            ("endblock", None, None),
+            ("end9", None, None),
+            ("endé", Some(3), Some(3)),
+            ("en", None, None),
+        ] {
+            assert_eq!(end.parses(src, 0).unwrap(), default, "{src:?}");
+            assert_eq!(lisp_end.parses(src, 0).unwrap(), lisp, "{src:?}");
+        }
+        assert_eq!(end.to_ebnf(), "`end`");
+        assert_eq!(lisp_end.to_ebnf(), "`end`[A-Za-z0-9_-]");
+        // a string is never read as a keyword, as in earlier versions
+        assert_eq!(Text::from("`end`".to_string()), Text::String("`end`".to_string()));
+        assert_eq!(serde_json::to_string(&end).unwrap(), r#"{"keyword":"end"}"#);
+        assert_eq!(serde_json::to_string(&lisp_end).unwrap(), r#"{"keyword":"end","ident_chars":"A-Za-z0-9_-"}"#);
+        assert_eq!(end.literal(), None);
+
+        assert!(Text::keyword_with("end", "z-a").is_err());
+        assert!(Text::keyword_with("end", "]").is_err());
+        assert!(Text::keyword_with("end", "a\\").is_err());
+        assert!(serde_json::from_str::<Text>(r#"{"keyword":"end","ident_chars":"z-a"}"#).is_err());
+    }
+
+    #[test]
+    fn flags() {
+        let text = Text::regex_with_flags("ab.c$", "is").unwrap();
+        assert_eq!(text.to_ebnf(), "/ab.c$/is");
+        assert_eq!(text.parses("xAB\nc", 1).unwrap(), Some(5));
+        assert_eq!(Text::Regex("ab.c$".to_string()).parses("xAB\nc", 1).unwrap(), None);
+
+        let text = Text::regex_with_flags("^b$", "m").unwrap();
+        assert_eq!(text.parses("a\nb\nc", 2).unwrap(), Some(3));
+
+        assert_eq!(Text::regex_with_flags("a", "").unwrap(), Text::Regex("a".to_string()));
+        assert!(Text::regex_with_flags("a", "x").is_err());
+        assert_eq!(Text::from("/a/x".to_string()), Text::String("/a/x".to_string()));
+    }
+
+    #[test]
+    fn literal() {
+        let literal = |pattern: &str, flags: &str| Text::regex_with_flags(pattern, flags).unwrap().literal();
+        assert_eq!(literal("abc", ""), Some("abc".to_string()));
+        assert_eq!(literal("", ""), Some("".to_string()));
+        assert_eq!(literal("a\\.b\\/c\\\\", ""), Some("a.b/c\\".to_string()));
+        assert_eq!(literal("\\(\\)\\[\\]\\{\\}\\|\\*\\+\\?\\^\\$", ""), Some("()[]{}|*+?^$".to_string()));
+        assert_eq!(literal("é -#&~", ""), Some("é -#&~".to_string()));
+        assert_eq!(literal("a b", "sm"), Some("a b".to_string()));
+        assert_eq!(literal("<=", "i"), Some("<=".to_string()));
+        assert_eq!(Text::String("a.b".to_string()).literal(), Some("a.b".to_string()));
+
+        for plain in ["abc", "a\\.b", "", "x-y"] {
+            let text = Text::Regex(plain.to_string());
+            let literal = Text::String(text.literal().unwrap());
+            for src in ["abc", "a.b", "aXb", "x-y", "xy", ""] {
+                assert_eq!(text.parses(src, 0).unwrap(), literal.parses(src, 0).unwrap(), "{plain:?} on {src:?}");
+            }
+        }
+
+        for (pattern, flags) in [
+            ("a.c", ""), ("^a", ""), ("a$", ""), ("a*", ""), ("a+", ""), ("a?", ""), ("(a)", ""),
+            ("[a]", ""), ("a{2}", ""), ("a|b", ""), ("\\d", ""), ("\\n", ""), ("\\<a", ""),
+            ("a\\", ""), ("(?i)a", ""), ("abc", "i"), ("ß", "i"), ("ⓐ", "i"), ("\\q", ""),
+        ] {
+            assert_eq!(literal(pattern, flags), None, "{pattern:?} with {flags:?}");
+        }
+    }
+
+    #[test]
+    fn char_boundaries() {
+        let grammar = crate::basic::Grammar::load_ebnf(r#"word = "é" , #'[a-zé]+';"#).unwrap();
+        let (token, diagnostics) = grammar.parse_non_term("word", "éaé").unwrap().unwrap();
+        assert_eq!(token.span, 0..5);
+        assert_eq!(token.children[1].span, 2..5);
+        assert!(diagnostics.is_empty());
+
+        // a literal ending inside a char does not match it
+        assert_eq!(Text::String("a".to_string()).parses("é", 0).unwrap(), None);
+        assert_eq!(Text::String("\u{c3}".to_string()).parses("é", 0).unwrap(), None);
+        assert_eq!(Text::String("".to_string()).parses("é", 2).unwrap(), Some(2));
+
+        for text in [Text::String("a".to_string()), Text::Regex("[a-z]*".to_string())] {
+            assert!(text.parses("é", 1).is_err());
+            assert!(text.parses("é", 3).is_err());
+        }
+    }
+
+    #[test]
+    fn case_folding() {
+        let parses = |pattern: &str, src: &str| Text::regex_with_flags(pattern, "i").unwrap().parses(src, 0).unwrap();
+        assert_eq!(parses("straße", "STRASSE!"), Some(7));
+        assert_eq!(parses("STRASSE", "Straße!"), Some(7));
+        assert_eq!(parses("strasse", "STRAẞE"), Some(8));
+        assert_eq!(parses("σίσυφος", "ΣΊΣΥΦΟΣ"), Some(14));
+        assert_eq!(parses("ΣΊΣΥΦΟΣ", "σίσυφοσ"), Some(14));
+        assert_eq!(parses("a\\.é", "A.É"), Some(4));
+        assert_eq!(parses("", "x"), Some(0));
+
+        // a match does not end inside the folding of a char
+        assert_eq!(parses("s", "ß"), None);
+        assert_eq!(parses("stras", "STRAßE"), None);
+        assert_eq!(parses("straßen", "STRASSE"), None);
+
+        // regexes that are not plain still go through the regex engine
+        assert_eq!(parses("é+", "ÉéÉ"), Some(6));
+        let window = Text::regex_with_flags("ß", "i").unwrap();
+        assert_eq!(window.parses_within("SS", 0, 1).unwrap(), None);
+        assert_eq!(window.parses_within("SS", 0, 2).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn parses_within() {
+        let any = Text::Regex(".*".to_string());
+        assert_eq!(any.parses_within("abcdef", 1, 3).unwrap(), Some(4));
+        assert_eq!(any.parses_within("abcdef", 1, 100).unwrap(), Some(6));
+        assert_eq!(any.parses_within("abcdef", 1, 0).unwrap(), Some(1));
+        assert_eq!(any.parses_within("abcdef", 1, usize::MAX).unwrap(), Some(6));
+
+        // a match of exactly `max_len` bytes is found, chars are not cut
+        assert_eq!(Text::Regex("a{3}".to_string()).parses_within("aaaa", 0, 3).unwrap(), Some(3));
+        assert_eq!(Text::Regex("a{3}".to_string()).parses_within("aaaa", 0, 2).unwrap(), None);
+        assert_eq!(any.parses_within("aéb", 0, 2).unwrap(), Some(1));
+        assert_eq!(any.parses_within("aéb", 0, 3).unwrap(), Some(3));
+        assert_eq!(Text::Regex("a.".to_string()).parses_within("aé", 0, 2).unwrap(), None);
+        assert_eq!(Text::regex_with_flags(".*", "s").unwrap().parses_within("a\nb", 0, 2).unwrap(), Some(2));
+
+        // literals are not limited
+        assert_eq!(Text::String("abc".to_string()).parses_within("abcdef", 0, 1).unwrap(), Some(3));
+        assert!(any.parses_within("é", 1, 3).is_err());
+    }
+
+    #[test]
+    fn captures() {
+        let re = Text::regex_with_flags(r"(?P<sign>-)?(?P<num>[0-9]+)_(?P<word>[a-z]+)", "i").unwrap();
+        let captures = |src: &str, pos: usize, max_len: Option<usize>| re.captures(src, pos, max_len).unwrap();
+        assert_eq!(captures("x12_ab", 1, None), [("num".to_string(), 1..3), ("word".to_string(), 4..6)]);
+        assert_eq!(captures("-1_A", 0, None), [("sign".to_string(), 0..1), ("num".to_string(), 1..2), ("word".to_string(), 3..4)]);
+        assert_eq!(captures("12_ab", 0, Some(4)), [("num".to_string(), 0..2), ("word".to_string(), 3..4)]);
+        assert!(captures("x12_ab", 0, None).is_empty());
+
+        assert!(Text::Regex("([0-9]+)".to_string()).captures("12", 0, None).unwrap().is_empty());
+        assert!(Text::String("12".to_string()).captures("12", 0, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn nullable() {
+        assert!(Text::String("".to_string()).is_nullable());
+        assert!(!Text::String("a".to_string()).is_nullable());
+        assert!(Text::Regex("a*".to_string()).is_nullable());
+        assert!(!Text::Regex("a+".to_string()).is_nullable());
+        assert!(Text::regex_with_flags("^$", "m").unwrap().is_nullable());
+    }
+
+    #[test]
+    fn yaml_round_trip() {
+        let texts = vec![Text::String("/".to_string()), Text::Regex("[a-z]+".to_string())];
+        let yaml = serde_yaml::to_string(&texts).unwrap();
+        assert_eq!(serde_yaml::from_str::<Vec<Text>>(&yaml).unwrap(), texts);
+    }
+
+    #[test]
+    fn ebnf_slashes() {
+        assert_eq!(Text::Regex("a/b".to_string()).to_ebnf(), "/a\\/b/");
+        assert_eq!(Text::Regex("a\\/b".to_string()).to_ebnf(), "/a\\/b/");
+        assert_eq!(Text::String("/".to_string()).to_ebnf(), "\"/\"");
+
+        let quoted = |text: &Text| text.to_ebnf_with(RegexDelimiter::Quoted);
+        assert_eq!(quoted(&Text::Regex("a/b".to_string())), "#'a/b'");
+        assert_eq!(quoted(&Text::Regex("a\\/b".to_string())), "#'a\\/b'");
+        assert_eq!(quoted(&Text::Regex("it's/\\'".to_string())), "#\"it's/\\'\"");
+        assert_eq!(quoted(&Text::Regex("'\"/".to_string())), "/'\"\\//");
+        assert_eq!(quoted(&Text::Regex("a\\".to_string())), "/a\\/");
+        assert_eq!(quoted(&Text::regex_with_flags("a/b", "i").unwrap()), "/a\\/b/i");
+    }
 }
\ No newline at end of file
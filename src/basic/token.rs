@@ -1,21 +1,41 @@
-use std::{collections::{BTreeMap, VecDeque}, ops::Range};
+use alloc::collections::{BTreeMap, VecDeque};
+use core::{ops::Range, str::FromStr};
 
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 
+use crate::prelude::*;
+
+mod diagnostics;
+mod from_token;
+mod index;
+mod interned;
+#[cfg(feature = "std")]
+mod json;
+mod source;
+mod spans;
+
+pub use from_token::*;
+pub use index::*;
+pub use interned::*;
+pub use source::*;
+pub use spans::*;
+
+/// Prefix of the meta keys of the named groups of terminals, see [`Token::capture`]
+pub const CAPTURE_PREFIX: &str = "capture.";
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[derive(Serialize, Deserialize)]
 #[derive(Encode, Decode)]
 pub struct Token {
     pub span: Range<usize>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub gram: Option<String>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub tags: Vec<String>,
-    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub meta: BTreeMap<String, String>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub children: Vec<Token>,
 }
 
@@ -39,7 +59,7 @@ impl Token {
     ) -> impl Iterator<Item = &Token> {
         let mut stack = VecDeque::new();
         stack.push_back(self);
-        std::iter::from_fn(move || {
+        core::iter::from_fn(move || {
             while let Some(token) = stack.pop_front() {
                 if token.tags.iter().any(|tag| tag == label) {
                     stack.extend(&token.children);
@@ -58,7 +78,7 @@ impl Token {
     ) -> impl Iterator<Item = &Token> {
         let mut stack = VecDeque::new();
         stack.push_back(self);
-        std::iter::from_fn(move || {
+        core::iter::from_fn(move || {
             while let Some(token) = stack.pop_front() {
                 if token.gram.as_deref() == Some(gram) {
                     stack.extend(&token.children);
@@ -70,9 +90,28 @@ impl Token {
         })
     }
 
+    /// The substrings of `src` matched by each gram of the tree, in document order
+    ///
+    /// Repeated grams collect all their matches, nested ones included, such
+    /// as both `digit-string`s of `1_2`. Spans outside of `src` are skipped.
+    pub fn gram_texts<'a>(&'a self, src: &'a str) -> BTreeMap<String, Vec<&'a str>> {
+        let mut texts = BTreeMap::<String, Vec<&'a str>>::new();
+        self.walk_grams(&mut |gram, span| {
+            if let Some(text) = src.get(span.clone()) {
+                texts.entry(gram.to_string()).or_default().push(text);
+            }
+        });
+        texts
+    }
+
     /// Iterate over the tokens at the given position, descending
     ///
-    /// The deepest token can be accessed with `token.iter_at_pos(p).last()`
+    /// The deepest token can be accessed with `token.iter_at_pos(p).last()`,
+    /// see [`TokenIndex`] for many queries on large trees.
+    ///
+    /// A token is at `pos` if its span contains it, so zero-width tokens are
+    /// never at any position, and neither is the root parsed from an empty
+    /// source.
     pub fn iter_at_pos(
         &self,
         pos: usize,
@@ -81,10 +120,8 @@ impl Token {
             .contains(&pos)
             .then_some(self);
 
-        std::iter::from_fn(move || {
-            let Some(token) = current.take() else {
-                return None;
-            };
+        core::iter::from_fn(move || {
+            let token = current.take()?;
 
             for child in &token.children {
                 if child.span.contains(&pos) {
@@ -96,4 +133,465 @@ impl Token {
             Some(token)
         })
     }
-}
\ No newline at end of file
+
+    /// The deepest token covering `range`, if any
+    ///
+    /// This is the enclosing node for "expand selection": a range crossing
+    /// the boundary between siblings gives their parent. The descent is the
+    /// one of [`Token::iter_at_pos`], a token covers `range` if its span
+    /// contains both its first and its last position, and an empty range is
+    /// treated as the position at its start.
+    pub fn smallest_covering(&self, range: Range<usize>) -> Option<&Token> {
+        let last = range.end.saturating_sub(1).max(range.start);
+        let covers = |token: &&Token| token.span.contains(&range.start) && token.span.contains(&last);
+        let mut token = Some(self).filter(covers)?;
+        while let Some(child) = token.children.iter().find(covers) {
+            token = child;
+        }
+        Some(token)
+    }
+
+    /// Recursively offset all spans by `delta`, saturating at `0`
+    ///
+    /// Useful to move a tree parsed from a sub-slice into the coordinate space
+    /// of the enclosing source.
+    pub fn shift(&mut self, delta: isize) {
+        let apply = |p: usize| p.saturating_add_signed(delta);
+        self.span = apply(self.span.start)..apply(self.span.end);
+        for child in &mut self.children {
+            child.shift(delta);
+        }
+    }
+
+    /// Non-mutating version of [`Token::shift`]
+    pub fn shifted(mut self, delta: isize) -> Self {
+        self.shift(delta);
+        self
+    }
+
+    /// Replace the children, keeping the span of `self`
+    ///
+    /// The new children must lie within the span, which is checked in debug builds.
+    pub fn with_children(mut self, children: Vec<Token>) -> Self {
+        debug_assert!(
+            children.iter().all(|c| self.span.start <= c.span.start && c.span.end <= self.span.end),
+            "children outside of the span {:?}", self.span,
+        );
+        self.children = children;
+        self
+    }
+
+    /// Transform each direct child with `f`
+    ///
+    /// For a recursive transform, `f` can call `map_children` on the child.
+    pub fn map_children(mut self, f: impl FnMut(Token) -> Token) -> Self {
+        let children = core::mem::take(&mut self.children).into_iter().map(f).collect();
+        self.with_children(children)
+    }
+
+    /// Keep only the direct children for which `f` returns `true`
+    pub fn filter_children(mut self, mut f: impl FnMut(&Token) -> bool) -> Self {
+        self.children.retain(|c| f(c));
+        self
+    }
+
+    /// Recursively replace the anonymous children by their own children
+    ///
+    /// A child is anonymous if it has no gram, tags or meta, like the groups
+    /// produced by sequences and repetitions. Leaves are kept, as they are the
+    /// matched terminals.
+    pub fn prune_anonymous(mut self) -> Self {
+        let mut children = vec![];
+        for child in core::mem::take(&mut self.children) {
+            let child = child.prune_anonymous();
+            if child.is_anonymous() && !child.children.is_empty() {
+                children.extend(child.children);
+            } else {
+                children.push(child);
+            }
+        }
+        self.with_children(children)
+    }
+
+    fn is_anonymous(&self) -> bool {
+        self.gram.is_none() && self.tags.is_empty() && self.meta.is_empty()
+    }
+
+    /// The value of the `key` entry of [`Token::meta`]
+    pub fn meta_get(&self, key: &str) -> Option<&str> {
+        self.meta.get(key).map(String::as_str)
+    }
+
+    /// The value of the `key` entry of [`Token::meta`], parsed as `F`
+    ///
+    /// `None` if there is no such entry, an error if it does not parse.
+    pub fn meta_parse<F>(&self, key: &str) -> anyhow::Result<Option<F>>
+    where
+        F: FromStr,
+        F::Err: Into<anyhow::Error>,
+    {
+        let Some(value) = self.meta_get(key) else {
+            return Ok(None);
+        };
+        match value.parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => Err(e.into().context(format!("Invalid value {value:?} for meta key {key:?}"))),
+        }
+    }
+
+    /// The span of the named group `name` of the terminal matched by this token
+    ///
+    /// Groups are only recorded by [`Parser::record_captures`](super::Parser::record_captures),
+    /// as [`CAPTURE_PREFIX`] and the name in [`Token::meta`], with the span
+    /// written `start..end`.
+    pub fn capture(&self, name: &str) -> Option<Range<usize>> {
+        let (start, end) = self.meta_get(&format!("{CAPTURE_PREFIX}{name}"))?.split_once("..")?;
+        Some(start.parse().ok()?..end.parse().ok()?)
+    }
+
+    /// The closest token of the tree with a `key` entry in [`Token::meta`], and its value
+    ///
+    /// The tree is searched breadth first, starting from `self`.
+    pub fn find_meta(&self, key: &str) -> Option<(&Token, &str)> {
+        let mut queue = VecDeque::from([self]);
+        while let Some(token) = queue.pop_front() {
+            if let Some(value) = token.meta_get(key) {
+                return Some((token, value));
+            }
+            queue.extend(&token.children);
+        }
+        None
+    }
+
+    /// Whether the trees are equal except for their spans
+    ///
+    /// Grams, tags, meta and the number and order of children must match at
+    /// every level, so that a re-parse of an edited source can be compared
+    /// with a full parse whatever the offsets. The spans of captures are
+    /// part of the meta, they are compared as written.
+    pub fn same_shape(&self, other: &Token) -> bool {
+        self.gram == other.gram
+            && self.tags == other.tags
+            && self.meta == other.meta
+            && self.children.len() == other.children.len()
+            && self.children.iter().zip(&other.children).all(|(a, b)| a.same_shape(b))
+    }
+
+    /// Maximum nesting level of the tree, a leaf token has depth `1`
+    pub fn depth(&self) -> usize {
+        self.stats().0
+    }
+
+    /// Total number of tokens in the tree, including `self`
+    pub fn node_count(&self) -> usize {
+        self.stats().1
+    }
+
+    /// A compact Lisp-style rendering of the tree, handy for snapshot tests
+    ///
+    /// Each token is written as `(gram [tag tag] start..end child child)`, the
+    /// tags only if any. Anonymous untagged tokens are skipped, their children
+    /// taking their place, except for the root, whose missing gram is written `_`.
+    pub fn to_sexpr(&self) -> String {
+        let mut out = String::new();
+        self.write_sexpr(&mut out);
+        out
+    }
+
+    fn write_sexpr(&self, out: &mut String) {
+        out.push('(');
+        out.push_str(self.gram.as_deref().unwrap_or("_"));
+        if !self.tags.is_empty() {
+            out.push_str(&format!(" [{}]", self.tags.join(" ")));
+        }
+        out.push_str(&format!(" {}..{}", self.span.start, self.span.end));
+        self.write_sexpr_children(out);
+        out.push(')');
+    }
+
+    fn write_sexpr_children(&self, out: &mut String) {
+        for child in &self.children {
+            if child.gram.is_none() && child.tags.is_empty() {
+                child.write_sexpr_children(out);
+            } else {
+                out.push(' ');
+                child.write_sexpr(out);
+            }
+        }
+    }
+
+    /// `(depth, node_count)` in a single traversal
+    fn stats(&self) -> (usize, usize) {
+        let mut depth = 0;
+        let mut count = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((token, level)) = stack.pop() {
+            depth = depth.max(level);
+            count += 1;
+            stack.extend(token.children.iter().map(|c| (c, level + 1)));
+        }
+        (depth, count)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn leaf(span: Range<usize>) -> Token {
+        Token {
+            span,
+            gram: None,
+            tags: vec![],
+            meta: Default::default(),
+            children: vec![],
+        }
+    }
+
+    fn sample() -> Token {
+        Token {
+            span: 0..6,
+            gram: Some("root".to_string()),
+            tags: vec![],
+            meta: Default::default(),
+            children: vec![
+                Token {
+                    span: 0..3,
+                    gram: Some("a".to_string()),
+                    tags: vec![],
+                    meta: Default::default(),
+                    children: vec![leaf(0..1), leaf(1..3)],
+                },
+                leaf(3..6),
+            ],
+        }
+    }
+
+    fn spans(token: &Token) -> Vec<Range<usize>> {
+        let mut out = vec![token.span.clone()];
+        for child in &token.children {
+            out.extend(spans(child));
+        }
+        out
+    }
+
+    #[test]
+    fn shift() {
+        let token = sample();
+        let shifted = token.clone().shifted(10);
+        let expected = spans(&token)
+            .into_iter()
+            .map(|s| s.start + 10..s.end + 10)
+            .collect::<Vec<_>>();
+        assert_eq!(spans(&shifted), expected);
+        assert_eq!(shifted.shifted(-10), token);
+
+        let mut token = sample();
+        token.shift(-2);
+        assert_eq!(spans(&token), vec![0..4, 0..1, 0..0, 0..1, 1..4]);
+    }
+
+    #[test]
+    fn smallest_covering() {
+        let token = sample();
+        let covering = |range| token.smallest_covering(range).map(|t| t.span.clone());
+        assert_eq!(covering(0..1), Some(0..1));
+        assert_eq!(covering(1..3), Some(1..3));
+        assert_eq!(covering(2..3), Some(1..3));
+        assert_eq!(covering(2..2), Some(1..3));
+        assert_eq!(covering(0..2), Some(0..3));
+        assert_eq!(covering(0..3), Some(0..3));
+        assert_eq!(covering(2..4), Some(0..6));
+        assert_eq!(covering(0..6), Some(0..6));
+        assert_eq!(covering(4..6), Some(3..6));
+        assert_eq!(covering(5..7), None);
+        assert_eq!(covering(6..6), None);
+        for pos in 0..7 {
+            assert_eq!(token.smallest_covering(pos..pos + 1), token.iter_at_pos(pos).last());
+        }
+
+        // the empty root covers nothing, the spans below it are followed as they are
+        let mut token = crate::basic::ebnf_tests::nested_labels();
+        assert_eq!(token.smallest_covering(0..1), None);
+        assert_eq!(token.smallest_covering(0..0), None);
+        token.span = 0..3;
+        let covering = |range| token.smallest_covering(range).map(|t| t.span.clone());
+        assert_eq!(covering(0..1), Some(0..3));
+        assert_eq!(covering(0..0), Some(0..3));
+        // crossing the end of the first child, the second one still covers it
+        assert_eq!(covering(0..2), Some(0..2));
+        assert_eq!(covering(1..2), Some(0..2));
+        // crossing the end of both children
+        assert_eq!(covering(1..3), Some(0..3));
+        assert_eq!(covering(3..4), None);
+        for pos in 0..4 {
+            assert_eq!(token.smallest_covering(pos..pos + 1), token.iter_at_pos(pos).last());
+        }
+
+        let grammar = crate::basic::Grammar::load_ebnf(r#"
+            expression = term , ("+" , term)*;
+            term = number | "(" , expression , ")";
+            number = digit+;
+            digit = "0" | "1" | "2" | "3";
+        "#).unwrap();
+        let (token, _) = grammar.parse_non_term("expression", "1+(23+0)").unwrap().unwrap();
+        let token = token.prune_anonymous();
+        let covering = |range| token.smallest_covering(range).map(|t| (t.gram.clone(), t.span.clone()));
+        assert_eq!(covering(3..5), Some((Some("number".to_string()), 3..5)));
+        assert_eq!(covering(4..6), Some((Some("expression".to_string()), 3..7)));
+        assert_eq!(covering(2..4), Some((Some("term".to_string()), 2..8)));
+        assert_eq!(covering(0..3), Some((None, 0..8)));
+    }
+
+    #[test]
+    fn to_sexpr() {
+        assert_eq!(sample().to_sexpr(), "(root 0..6 (a 0..3))");
+        assert_eq!(leaf(1..2).to_sexpr(), "(_ 1..2)");
+
+        let mut token = sample();
+        token.children[1].tags.push("x".to_string());
+        token.children[1].tags.push("y".to_string());
+        assert_eq!(token.to_sexpr(), "(root 0..6 (a 0..3) (_ [x y] 3..6))");
+
+        let grammar = crate::basic::Grammar::load_ebnf(r#"
+            expression = term , ("+" , term)*;
+            term = number | "(" , expression , ")";
+            number = digit+;
+            digit = "0" | "1" | "2" | "3";
+        "#).unwrap();
+        let (token, _) = grammar.parse_non_term("expression", "1+(23)").unwrap().unwrap();
+        assert_eq!(
+            token.to_sexpr(),
+            "(_ 0..6 (term 0..1 (number 0..1 (digit 0..1))) \
+            (term 2..6 (expression 3..5 (term 3..5 (number 3..5 (digit 3..4) (digit 4..5))))))",
+        );
+    }
+
+    #[test]
+    fn editing() {
+        let grammar = crate::basic::Grammar::load_ebnf(r#"
+            sum = number , ("+" , number)*;
+            number = digit+;
+            digit = "0" | "1" | "2" | "3";
+        "#).unwrap();
+        let src = "12+3+0";
+        let (token, _) = grammar.parse_non_term("sum", src).unwrap().unwrap();
+
+        let token = token.prune_anonymous();
+        assert_eq!(token.children.len(), 5);
+        assert_eq!(token.clone().prune_anonymous(), token);
+
+        // drop the zeros and the operators, tag the numbers
+        let numbers = token
+            .filter_children(|c| c.gram.is_some() && &src[c.span.clone()] != "0")
+            .map_children(|c| {
+                let mut c = c.filter_children(|d| d.gram.is_some());
+                c.tags.push("n".to_string());
+                c
+            });
+        assert_eq!(numbers.to_sexpr(), "(_ 0..6 (number [n] 0..2 (digit 0..1) (digit 1..2)) (number [n] 3..4 (digit 3..4)))");
+        let yaml = serde_yaml::to_string(&numbers).unwrap();
+        assert_eq!(serde_yaml::from_str::<Token>(&yaml).unwrap(), numbers);
+
+        assert_eq!(numbers.with_children(vec![]).node_count(), 1);
+    }
+
+    #[test]
+    fn meta() {
+        use crate::{basic::{Grammar, Node, Text}, gram};
+
+        let mut grammar = Grammar::<Text>::new();
+        let meta = |node: Node<Text>, entries: &[(&str, &str)]| Node::Meta {
+            node: Box::new(node),
+            meta: entries.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        grammar.add_element("sum", meta(
+            gram!((number, "+", number)),
+            &[("precedence", "1"), ("assoc", "left")],
+        )).unwrap();
+        grammar.add_element("number", meta(gram!(#r"[0-9]+"), &[("kind", "int")])).unwrap();
+        let (token, _) = grammar.parse_non_term("sum", "1+2").unwrap().unwrap();
+
+        assert_eq!(token.meta_get("assoc"), Some("left"));
+        assert_eq!(token.meta_get("kind"), None);
+        assert_eq!(token.meta_parse::<u8>("precedence").unwrap(), Some(1));
+        assert_eq!(token.meta_parse::<String>("assoc").unwrap(), Some("left".to_string()));
+        assert_eq!(token.meta_parse::<u8>("missing").unwrap(), None);
+        let error = token.meta_parse::<u8>("assoc").unwrap_err();
+        assert_eq!(error.to_string(), r#"Invalid value "left" for meta key "assoc""#);
+
+        let (number, kind) = token.find_meta("kind").unwrap();
+        assert_eq!((number.span.clone(), kind), (0..1, "int"));
+        assert_eq!(token.find_meta("precedence").map(|(t, _)| t), Some(&token));
+        assert_eq!(token.find_meta("missing"), None);
+    }
+
+    #[test]
+    fn depth_and_count() {
+        let token = sample();
+        assert_eq!(token.depth(), 3);
+        assert_eq!(token.node_count(), 5);
+        assert_eq!(leaf(0..0).depth(), 1);
+        assert_eq!(leaf(0..0).node_count(), 1);
+
+        let token = crate::basic::ebnf_tests::nested_labels();
+        assert_eq!(token.depth(), 3);
+        assert_eq!(token.node_count(), 4);
+        assert_eq!(token.children[0].depth(), 2);
+        assert_eq!(token.children[1].node_count(), 1);
+    }
+
+    #[test]
+    fn same_shape() {
+        let token = sample();
+        assert!(token.same_shape(&token));
+        assert!(token.same_shape(&token.clone().shifted(10)));
+
+        // the first leaf grows, moving everything after it
+        let mut longer = sample();
+        longer.children[0].children[0].span = 0..4;
+        longer.children[0].children[1].span = 4..6;
+        longer.children[0].span = 0..6;
+        longer.children[1].span = 6..9;
+        longer.span = 0..9;
+        assert!(token.same_shape(&longer));
+        assert_ne!(token, longer);
+
+        let mut renamed = sample();
+        renamed.children[0].gram = Some("b".to_string());
+        assert!(!token.same_shape(&renamed));
+        let mut tagged = sample();
+        tagged.children[1].tags.push("t".to_string());
+        assert!(!token.same_shape(&tagged));
+        let mut with_meta = sample();
+        with_meta.meta.insert("k".to_string(), "v".to_string());
+        assert!(!token.same_shape(&with_meta));
+        let pruned = sample().filter_children(|c| c.gram.is_some());
+        assert!(!token.same_shape(&pruned));
+        assert!(!pruned.same_shape(&token));
+    }
+
+    #[test]
+    fn gram_texts() {
+        let yaml = include_str!("../../examples/fortran_integer.yaml");
+        let grammar = serde_yaml::from_str::<crate::basic::Grammar<crate::basic::Text>>(yaml).unwrap();
+        let src = "-12_34";
+        let (token, _) = grammar.parse_non_term("signed-int-literal-constant", src).unwrap().unwrap();
+
+        let texts = token.gram_texts(src);
+        let expected: BTreeMap<String, Vec<&str>> = [
+            ("digit", vec!["1", "2", "3", "4"]),
+            ("digit-string", vec!["12", "34"]),
+            ("int-literal-constant", vec!["12_34"]),
+            ("kind-param", vec!["34"]),
+            ("sign", vec!["-"]),
+        ].into_iter().map(|(gram, texts)| (gram.to_string(), texts)).collect();
+        assert_eq!(texts, expected);
+
+        // spans outside of the source are skipped
+        assert_eq!(token.gram_texts("-1"), BTreeMap::from([
+            ("digit".to_string(), vec!["1"]),
+            ("sign".to_string(), vec!["-"]),
+        ]));
+    }
+}
@@ -0,0 +1,96 @@
+use core::ops::Range;
+
+use crate::{parsers::naive::Diagnostic, prelude::*};
+
+use super::Token;
+
+impl Token {
+    /// The `diagnostics` whose main span lies within the span of the token, in their order
+    pub fn diagnostics_within<'d>(&self, diagnostics: &'d [Diagnostic]) -> impl Iterator<Item = &'d Diagnostic> {
+        let span = self.span.clone();
+        diagnostics.iter().filter(move |d| within(&span, &d.main_span()))
+    }
+
+    /// Bucket `diagnostics` under the smallest token of the tree with a gram
+    /// enclosing their main span, or the root
+    ///
+    /// Diagnostics are not stored on the tokens, so that the tree stays the
+    /// same whatever the errors, and this associates them with the rules they
+    /// come from after parsing. An empty span at `p` is enclosed by the tokens
+    /// containing the position `p`, as in [`Token::iter_at_pos`], so a missing
+    /// element after a token goes to its parent. Diagnostics outside of the
+    /// tree go to the root.
+    ///
+    /// The groups are in the order of their first diagnostic and keep the
+    /// order of `diagnostics`.
+    pub fn group_diagnostics<'t, 'd>(&'t self, diagnostics: &'d [Diagnostic]) -> Vec<(&'t Token, Vec<&'d Diagnostic>)> {
+        let mut groups: Vec<(&Token, Vec<&Diagnostic>)> = vec![];
+        for diagnostic in diagnostics {
+            let span = diagnostic.main_span();
+            let mut owner = self;
+            let mut token = self;
+            while let Some(child) = token.children.iter().find(|c| encloses(&c.span, &span)) {
+                token = child;
+                if token.gram.is_some() {
+                    owner = token;
+                }
+            }
+            match groups.iter_mut().find(|(t, _)| core::ptr::eq(*t, owner)) {
+                Some((_, group)) => group.push(diagnostic),
+                None => groups.push((owner, vec![diagnostic])),
+            }
+        }
+        groups
+    }
+}
+
+fn within(outer: &Range<usize>, inner: &Range<usize>) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
+
+/// [`within`], but an empty span must be before the end
+fn encloses(outer: &Range<usize>, inner: &Range<usize>) -> bool {
+    if inner.is_empty() {
+        outer.contains(&inner.start)
+    } else {
+        within(outer, inner)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{basic::Grammar, parsers::naive::Diagnostic};
+
+    #[test]
+    fn group_diagnostics() {
+        let grammar = Grammar::load_ebnf(r#"
+            program = statement+;
+            statement = assignment , ";";
+            assignment = name , "=" , value;
+            name = "a" | "b";
+            value = "1" | "2";
+        "#).unwrap();
+        let src = "a=;b=1;a";
+        let (token, diagnostics) = grammar.parse_non_term("program", src).unwrap().unwrap();
+        assert_eq!(diagnostics.len(), 3);
+
+        let groups = token.group_diagnostics(&diagnostics)
+            .into_iter()
+            .map(|(t, d)| (t.gram.as_deref(), t.span.clone(), d.len()))
+            .collect::<Vec<_>>();
+        // the value missing in the first statement, and the `=` and `;` of the last one at the end
+        assert_eq!(groups, [(Some("statement"), 0..3, 1), (None, 0..8, 2)]);
+
+        let second = token.iter_grams("statement").nth(1).unwrap();
+        assert_eq!(second.diagnostics_within(&diagnostics).count(), 0);
+        assert!(second.group_diagnostics(&[]).is_empty());
+        let first = token.iter_grams("statement").next().unwrap();
+        assert_eq!(first.diagnostics_within(&diagnostics).collect::<Vec<_>>(), [&diagnostics[0]]);
+        assert_eq!(token.diagnostics_within(&diagnostics).count(), 3);
+
+        // an error inside a token, rather than after it
+        let error = Diagnostic::Incomplete { span: 4..6, expected: "x".to_string() };
+        let group = second.group_diagnostics(std::slice::from_ref(&error));
+        assert_eq!(group[0].0.gram.as_deref(), Some("assignment"));
+    }
+}
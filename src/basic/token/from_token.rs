@@ -0,0 +1,12 @@
+use super::Token;
+
+/// A value built from the token of a gram, see [`Grammar::parse_into`]
+///
+/// [`Grammar::parse_into`]: crate::basic::Grammar::parse_into
+pub trait FromToken: Sized {
+    /// The rule the value is parsed from
+    const GRAM: &str;
+
+    /// Build the value from `tok`, a token of [`FromToken::GRAM`] parsed from `src`
+    fn from_token(src: &str, tok: &Token) -> anyhow::Result<Self>;
+}
@@ -0,0 +1,161 @@
+use core::ops::Range;
+
+use crate::prelude::*;
+
+use super::Token;
+
+/// Precomputed lookup of the tokens of a tree by position
+///
+/// The children of every token are sorted by span once, so that
+/// [`TokenIndex::at`] takes `O(depth × log(children))` instead of the linear
+/// scans of [`Token::iter_at_pos`]. The results are the same for trees whose
+/// spans are valid according to [`Token::validate_spans`].
+#[derive(Debug, Clone)]
+pub struct TokenIndex<'t> {
+    /// Every token of the tree, the root first
+    entries: Vec<Entry<'t>>,
+    /// Indices in `entries` of the children of each entry, sorted by span
+    children: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry<'t> {
+    token: &'t Token,
+    /// Range of `children` holding the children of the token
+    children: Range<usize>,
+}
+
+impl<'t> TokenIndex<'t> {
+    pub fn new(root: &'t Token) -> Self {
+        let mut index = Self {
+            entries: vec![Entry { token: root, children: 0..0 }],
+            children: vec![],
+        };
+        let mut next = 0;
+        while next < index.entries.len() {
+            let token = index.entries[next].token;
+            let first_entry = index.entries.len();
+            index.entries.extend(token.children.iter().map(|token| Entry { token, children: 0..0 }));
+
+            let start = index.children.len();
+            index.children.extend(first_entry..index.entries.len());
+            let entries = &index.entries;
+            index.children[start..].sort_by_key(|&i| (entries[i].token.span.start, entries[i].token.span.end));
+            index.entries[next].children = start..index.children.len();
+            next += 1;
+        }
+        index
+    }
+
+    /// The root of the indexed tree
+    pub fn root(&self) -> &'t Token {
+        self.entries[0].token
+    }
+
+    /// The deepest token containing `pos`, the same as `token.iter_at_pos(pos).last()`
+    pub fn at(&self, pos: usize) -> Option<&'t Token> {
+        let mut entry = &self.entries[0];
+        if !entry.token.span.contains(&pos) {
+            return None;
+        }
+        loop {
+            let children = &self.children[entry.children.clone()];
+            // the last child starting at or before `pos` is the only candidate
+            let n = children.partition_point(|&i| self.entries[i].token.span.start <= pos);
+            match n.checked_sub(1).map(|n| &self.entries[children[n]]) {
+                Some(child) if child.token.span.contains(&pos) => entry = child,
+                _ => return Some(entry.token),
+            }
+        }
+    }
+
+    /// [`TokenIndex::at`] for each of `positions`, in a single walk of the tree
+    ///
+    /// The positions are sorted first, so that each token is visited at most
+    /// once however many positions fall within it.
+    pub fn at_many(&self, positions: &[usize]) -> Vec<Option<&'t Token>> {
+        let mut results = vec![None; positions.len()];
+        let mut queries = (0..positions.len()).collect::<Vec<_>>();
+        queries.sort_by_key(|&q| positions[q]);
+
+        let root = &self.entries[0].token.span;
+        let start = queries.partition_point(|&q| positions[q] < root.start);
+        let end = queries.partition_point(|&q| positions[q] < root.end);
+
+        // entries with the range of `queries` whose positions they contain
+        let mut stack = vec![(0, start..end)];
+        while let Some((entry, range)) = stack.pop() {
+            let entry = &self.entries[entry];
+            let children = &self.children[entry.children.clone()];
+            let mut candidate = 0;
+            let mut q = range.start;
+            while q < range.end {
+                let pos = positions[queries[q]];
+                // as in `at`, the last child starting at or before `pos`
+                while candidate + 1 < children.len() && self.entries[children[candidate + 1]].token.span.start <= pos {
+                    candidate += 1;
+                }
+                let child = children.get(candidate).filter(|&&c| self.entries[c].token.span.contains(&pos));
+                let Some(&child) = child else {
+                    results[queries[q]] = Some(entry.token);
+                    q += 1;
+                    continue;
+                };
+                let end = self.entries[child].token.span.end;
+                let group_end = q + queries[q..range.end].partition_point(|&q| positions[q] < end);
+                stack.push((child, q..group_end));
+                q = group_end;
+            }
+        }
+        results
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::basic::Grammar;
+
+    use super::*;
+
+    #[test]
+    fn same_as_iter_at_pos() {
+        let grammar = Grammar::load_ebnf(r#"
+            list = "[" , [item , ("," , item)*] , "]";
+            item = word | list;
+            word = #'[a-z]*';
+        "#).unwrap();
+        let src = "[ab,[c,,[]],defg,,[x]]";
+        let (token, _) = grammar.parse_non_term("list", src).unwrap().unwrap();
+        assert_eq!(token.span, 0..src.len());
+
+        let index = TokenIndex::new(&token);
+        assert!(std::ptr::eq(index.root(), &token));
+        for pos in 0..=src.len() + 1 {
+            let expected = token.iter_at_pos(pos).last();
+            assert_eq!(index.at(pos).map(|t| t as *const _), expected.map(|t| t as *const _), "{pos}");
+        }
+        assert!(index.at(src.len()).is_none());
+    }
+
+    #[test]
+    fn at_many() {
+        let grammar = Grammar::load_ebnf(r#"
+            list = "[" , [item , ("," , item)*] , "]";
+            item = word | list;
+            word = #'[a-z]*';
+        "#).unwrap();
+        let src = "[ab,[c,,[]],defg,,[x]]";
+        let (token, _) = grammar.parse_non_term("list", src).unwrap().unwrap();
+        let index = TokenIndex::new(&token);
+
+        let positions = [7, 0, 30, 21, 5, 5, 12, 1, 22, 9, 2];
+        let results = index.at_many(&positions);
+        for (&pos, result) in positions.iter().zip(results) {
+            let expected = token.iter_at_pos(pos).last();
+            assert_eq!(result.map(|t| t as *const _), expected.map(|t| t as *const _), "{pos}");
+        }
+        let all = (0..src.len()).collect::<Vec<_>>();
+        assert_eq!(index.at_many(&all), all.iter().map(|&p| index.at(p)).collect::<Vec<_>>());
+        assert_eq!(index.at_many(&[]), vec![]);
+    }
+}
@@ -0,0 +1,169 @@
+use alloc::{collections::VecDeque, rc::Rc};
+use core::ops::Range;
+
+use crate::{collections::Map, prelude::*};
+
+use super::Token;
+
+/// Handle to a string stored in a [`SymbolTable`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// Deduplicated storage for the strings of [`InternedToken`] trees
+///
+/// Each string is allocated once, shared by the list resolving symbols and the
+/// map interning them.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    names: Vec<Rc<str>>,
+    index: Map<Rc<str>, Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.index.get(name) {
+            return *symbol;
+        }
+        let symbol = Symbol(self.names.len().try_into().expect("too many symbols"));
+        let name: Rc<str> = Rc::from(name);
+        self.names.push(name.clone());
+        self.index.insert(name, symbol);
+        symbol
+    }
+
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.index.get(name).copied()
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// A [`Token`] whose names are stored once in a [`SymbolTable`]
+///
+/// Large trees repeat the same rule names, tags and meta keys over and over,
+/// this representation stores each of them once and uses a 4 byte handle per
+/// token. Meta values are mostly unique, so they are kept as strings.
+///
+/// The tree is converted once parsed, so this shrinks the trees that are
+/// kept, not the peak memory of parsing.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InternedToken {
+    pub span: Range<usize>,
+    pub gram: Option<Symbol>,
+    pub tags: Vec<Symbol>,
+    pub meta: Vec<(Symbol, String)>,
+    pub children: Vec<InternedToken>,
+}
+
+impl InternedToken {
+    pub fn from_token(token: &Token, symbols: &mut SymbolTable) -> Self {
+        Self {
+            span: token.span.clone(),
+            gram: token.gram.as_deref().map(|g| symbols.intern(g)),
+            tags: token.tags.iter().map(|t| symbols.intern(t)).collect(),
+            meta: token.meta
+                .iter()
+                .map(|(k, v)| (symbols.intern(k), v.clone()))
+                .collect(),
+            children: token.children
+                .iter()
+                .map(|c| Self::from_token(c, symbols))
+                .collect(),
+        }
+    }
+
+    pub fn to_token(&self, symbols: &SymbolTable) -> Token {
+        Token {
+            span: self.span.clone(),
+            gram: self.gram.map(|g| symbols.resolve(g).to_string()),
+            tags: self.tags.iter().map(|t| symbols.resolve(*t).to_string()).collect(),
+            meta: self.meta
+                .iter()
+                .map(|(k, v)| (symbols.resolve(*k).to_string(), v.clone()))
+                .collect(),
+            children: self.children.iter().map(|c| c.to_token(symbols)).collect(),
+        }
+    }
+
+    /// Recursively iterate over all tokens with the given `gram`
+    pub fn iter_grams(&self, gram: Symbol) -> impl Iterator<Item = &InternedToken> {
+        let mut stack = VecDeque::new();
+        stack.push_back(self);
+        core::iter::from_fn(move || {
+            while let Some(token) = stack.pop_front() {
+                stack.extend(&token.children);
+                if token.gram == Some(gram) {
+                    return Some(token);
+                }
+            }
+            None
+        })
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::mem::size_of;
+
+    use crate::basic::Grammar;
+
+    use super::*;
+
+    fn owned_heap_size(token: &Token) -> usize {
+        token.gram.as_ref().map_or(0, |g| g.capacity())
+            + token.tags.capacity() * size_of::<String>()
+            + token.tags.iter().map(|t| t.capacity()).sum::<usize>()
+            + token.meta.iter().map(|(k, v)| k.capacity() + v.capacity() + 2 * size_of::<String>()).sum::<usize>()
+            + token.children.capacity() * size_of::<Token>()
+            + token.children.iter().map(owned_heap_size).sum::<usize>()
+    }
+
+    fn interned_heap_size(token: &InternedToken) -> usize {
+        token.tags.capacity() * size_of::<Symbol>()
+            + token.meta.iter().map(|(_, v)| v.capacity()).sum::<usize>()
+            + token.meta.capacity() * size_of::<(Symbol, String)>()
+            + token.children.capacity() * size_of::<InternedToken>()
+            + token.children.iter().map(interned_heap_size).sum::<usize>()
+    }
+
+    #[test]
+    fn round_trip_and_savings() {
+        let grammar = Grammar::load_ebnf(r#"
+            expression = term , (("+" | "-") , term)*;
+            term = factor , (("*" | "/") , factor)*;
+            factor = ("(" , expression , ")") | number;
+            number = digit+;
+            digit = "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9";
+        "#).unwrap();
+        let src = "((((1+2)*3)-(4/5))+67)*(8+9)-1234";
+        let (token, _) = grammar.parse_non_term("expression", src).unwrap().unwrap();
+
+        let mut symbols = SymbolTable::new();
+        let interned = InternedToken::from_token(&token, &mut symbols);
+        assert_eq!(interned.to_token(&symbols), token);
+        assert_eq!(symbols.len(), 5);
+
+        for gram in ["digit", "term"] {
+            let spans = interned.iter_grams(symbols.get(gram).unwrap()).map(|t| t.span.clone());
+            assert!(spans.eq(token.iter_grams(gram).map(|t| t.span.clone())), "{gram}");
+        }
+
+        let owned = owned_heap_size(&token);
+        let compact = interned_heap_size(&interned);
+        assert!(compact < owned, "owned: {owned} bytes, interned: {compact} bytes");
+    }
+}
@@ -0,0 +1,90 @@
+use serde_json::{Map, Value, json};
+
+use super::Token;
+
+impl Token {
+    /// The tree as nested JSON objects, with the text of `src` each token matched
+    ///
+    /// Each token is written `{"gram": ..., "text": ..., "children": [...]}`,
+    /// with a `"tags"` array if it has any. Unlike the serde encoding of
+    /// tokens, spans and meta are left out. Tokens without gram or tags, such
+    /// as the groups of sequences and the matched terminals, are skipped,
+    /// their children taking their place, except for the root, whose gram is
+    /// then `null`. The text is `null` if the span is not in `src`.
+    pub fn to_json_value(&self, src: &str) -> Value {
+        self.to_json_value_with(src, false)
+    }
+
+    /// Same as [`Token::to_json_value`], keeping the anonymous tokens if `anonymous`
+    pub fn to_json_value_with(&self, src: &str, anonymous: bool) -> Value {
+        let mut children = vec![];
+        self.push_json_children(src, anonymous, &mut children);
+        let mut object = Map::new();
+        object.insert("gram".to_string(), json!(self.gram));
+        if !self.tags.is_empty() {
+            object.insert("tags".to_string(), json!(self.tags));
+        }
+        object.insert("text".to_string(), json!(src.get(self.span.clone())));
+        object.insert("children".to_string(), Value::Array(children));
+        Value::Object(object)
+    }
+
+    fn push_json_children(&self, src: &str, anonymous: bool, out: &mut Vec<Value>) {
+        for child in &self.children {
+            if !anonymous && child.gram.is_none() && child.tags.is_empty() {
+                child.push_json_children(src, anonymous, out);
+            } else {
+                out.push(child.to_json_value_with(src, anonymous));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::basic::{Grammar, Text};
+
+    use super::*;
+
+    #[test]
+    fn to_json_value() {
+        let grammar = Grammar::<Text>::load_ebnf(r#"
+            expr = (expr , ("+" | "-") , term) | term;
+            term = (term , ("*" | "/") , factor) | factor;
+            factor = ("(" , expr , ")") | number;
+            number = #'[0-9]+';
+        "#).unwrap();
+        let src = "1+2*3";
+        let (token, _) = grammar.parse_non_term("expr", src).unwrap().unwrap();
+
+        let leaf = |gram: &str, text: &str| json!({"gram": gram, "text": text, "children": []});
+        let number = |text: &str| json!({
+            "gram": "factor",
+            "text": text,
+            "children": [leaf("number", text)],
+        });
+        assert_eq!(token.to_json_value(src), json!({
+            "gram": null,
+            "text": "1+2*3",
+            "children": [
+                {"gram": "expr", "text": "1", "children": [{"gram": "term", "text": "1", "children": [number("1")]}]},
+                {"gram": "term", "text": "2*3", "children": [
+                    {"gram": "term", "text": "2", "children": [number("2")]},
+                    number("3"),
+                ]},
+            ],
+        }));
+
+        // with the anonymous tokens, the operators are there
+        let full = token.to_json_value_with(src, true);
+        let texts = full["children"].as_array().unwrap().iter().map(|c| c["text"].clone()).collect::<Vec<_>>();
+        assert_eq!(texts, [json!("1"), json!("+"), json!("2*3")]);
+        assert_eq!(full["children"][1], json!({"gram": null, "text": "+", "children": []}));
+
+        let mut tagged = token.clone();
+        tagged.tags.push("root".to_string());
+        tagged.span = 0..10;
+        assert_eq!(tagged.to_json_value(src)["tags"], json!(["root"]));
+        assert_eq!(tagged.to_json_value(src)["text"], Value::Null);
+    }
+}
@@ -0,0 +1,112 @@
+use core::ops::Deref;
+
+use crate::prelude::*;
+
+use super::Token;
+
+impl Token {
+    /// The text of the token in `src`
+    ///
+    /// Unlike `&src[token.span.clone()]`, this is an error rather than a panic
+    /// if the span is not a valid range of `src`, as when the token was parsed
+    /// from or shifted into another source.
+    pub fn span_str<'s>(&self, src: &'s str) -> anyhow::Result<&'s str> {
+        src.get(self.span.clone()).ok_or_else(|| {
+            anyhow::anyhow!("Span {:?} is not a valid range of a source of {} bytes", self.span, src.len())
+        })
+    }
+
+    /// Bind the token to the source it was parsed from
+    pub fn with_source<'a>(&'a self, src: &'a str) -> SourcedToken<'a> {
+        SourcedToken { token: self, src }
+    }
+}
+
+/// A [`Token`] bound to its source, see [`Token::with_source`]
+///
+/// It dereferences to the token, and its methods returning tokens keep them
+/// bound to the same source.
+#[derive(Debug, Clone, Copy)]
+pub struct SourcedToken<'a> {
+    pub token: &'a Token,
+    pub src: &'a str,
+}
+
+impl<'a> SourcedToken<'a> {
+    /// The text of the token, see [`Token::span_str`]
+    pub fn text(&self) -> anyhow::Result<&'a str> {
+        self.token.span_str(self.src)
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = SourcedToken<'a>> + use<'a> {
+        let src = self.src;
+        self.token.children.iter().map(move |token| token.with_source(src))
+    }
+
+    /// See [`Token::iter_grams`]
+    pub fn iter_grams(&self, gram: &'a str) -> impl Iterator<Item = SourcedToken<'a>> + use<'a> {
+        let src = self.src;
+        self.token.iter_grams(gram).map(move |token| token.with_source(src))
+    }
+
+    /// See [`Token::iter_label`]
+    pub fn iter_label(&self, label: &'a str) -> impl Iterator<Item = SourcedToken<'a>> + use<'a> {
+        let src = self.src;
+        self.token.iter_label(label).map(move |token| token.with_source(src))
+    }
+}
+
+impl Deref for SourcedToken<'_> {
+    type Target = Token;
+
+    fn deref(&self) -> &Token {
+        self.token
+    }
+}
+
+impl<'a> TryFrom<SourcedToken<'a>> for &'a str {
+    type Error = anyhow::Error;
+
+    fn try_from(token: SourcedToken<'a>) -> anyhow::Result<Self> {
+        token.text()
+    }
+}
+
+impl TryFrom<SourcedToken<'_>> for String {
+    type Error = anyhow::Error;
+
+    fn try_from(token: SourcedToken<'_>) -> anyhow::Result<Self> {
+        token.text().map(str::to_string)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::basic::{Grammar, Text};
+
+    #[test]
+    fn sourced() {
+        let grammar = Grammar::<Text>::load_ebnf(r#"
+            pair = key , "=" , value;
+            key = #'[a-z]+';
+            value = #'[0-9]+';
+        "#).unwrap();
+        let (token, _) = grammar.parse_non_term("pair", "ab=12").unwrap().unwrap();
+
+        let pair = token.with_source("ab=12");
+        assert_eq!(pair.text().unwrap(), "ab=12");
+        assert_eq!(pair.iter_grams("key").map(|t| t.text().unwrap()).collect::<Vec<_>>(), ["ab"]);
+        assert_eq!(pair.children().count(), 3);
+        assert_eq!(pair.span, 0..5);
+        let value: String = pair.iter_grams("value").next().unwrap().try_into().unwrap();
+        assert_eq!(value, "12");
+
+        // bound to a source it was not parsed from
+        let wrong = token.with_source("=1");
+        assert!(wrong.text().is_err());
+        assert_eq!(wrong.iter_grams("key").next().unwrap().text().unwrap(), "=1");
+        assert!(<&str>::try_from(wrong.iter_grams("value").next().unwrap()).is_err());
+        assert!(token.iter_grams("key").next().unwrap().span_str("aé").is_err());
+        assert!(token.span_str("").is_err());
+    }
+}
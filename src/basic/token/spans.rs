@@ -0,0 +1,107 @@
+use core::{fmt, ops::Range};
+
+use super::Token;
+
+/// A violation of the span invariants of a [`Token`] tree, see [`Token::validate_spans`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SpanError {
+    /// A span that ends before it starts
+    Inverted { span: Range<usize> },
+    /// A child that is not contained in the span of its parent
+    OutsideParent { parent: Range<usize>, child: Range<usize> },
+    /// A child that starts before the end of the previous one
+    Unordered { previous: Range<usize>, next: Range<usize> },
+}
+
+impl fmt::Display for SpanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpanError::Inverted { span } => write!(f, "span {span:?} ends before it starts"),
+            SpanError::OutsideParent { parent, child } => {
+                write!(f, "child span {child:?} is outside of its parent {parent:?}")
+            }
+            SpanError::Unordered { previous, next } => {
+                write!(f, "span {next:?} overlaps or precedes its previous sibling {previous:?}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for SpanError {}
+
+impl Token {
+    /// Check that every child lies within its parent and that siblings are in
+    /// order and do not overlap, reporting the first violation depth-first
+    ///
+    /// Gaps between the children are allowed, such as the separators that
+    /// separated repetitions leave out.
+    pub fn validate_spans(&self) -> Result<(), SpanError> {
+        if self.span.start > self.span.end {
+            return Err(SpanError::Inverted { span: self.span.clone() });
+        }
+        let mut previous: Option<&Range<usize>> = None;
+        for child in &self.children {
+            child.validate_spans()?;
+            if child.span.start < self.span.start || child.span.end > self.span.end {
+                return Err(SpanError::OutsideParent {
+                    parent: self.span.clone(),
+                    child: child.span.clone(),
+                });
+            }
+            if let Some(previous) = previous && child.span.start < previous.end {
+                return Err(SpanError::Unordered {
+                    previous: previous.clone(),
+                    next: child.span.clone(),
+                });
+            }
+            previous = Some(&child.span);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::basic::Grammar;
+
+    use super::*;
+
+    fn token(span: Range<usize>, children: Vec<Token>) -> Token {
+        Token {
+            span,
+            gram: None,
+            tags: vec![],
+            meta: Default::default(),
+            children,
+        }
+    }
+
+    #[test]
+    fn validate_spans() {
+        let grammar = Grammar::load_ebnf(r#"
+            list = "[" , [item , ("," , item)*] , "]";
+            item = #'[a-z]*';
+        "#).unwrap();
+        for src in ["[]", "[a,,bc]", "[a,"] {
+            let (token, _) = grammar.parse_non_term("list", src).unwrap().unwrap();
+            assert_eq!(token.validate_spans(), Ok(()), "{src:?}");
+        }
+
+        assert_eq!(token(0..4, vec![token(0..1, vec![]), token(1..1, vec![]), token(2..4, vec![])]).validate_spans(), Ok(()));
+        assert_eq!(
+            token(0..4, vec![token(0..2, vec![token(1..3, vec![])])]).validate_spans(),
+            Err(SpanError::OutsideParent { parent: 0..2, child: 1..3 }),
+        );
+        assert_eq!(
+            token(0..4, vec![token(0..2, vec![]), token(1..3, vec![])]).validate_spans(),
+            Err(SpanError::Unordered { previous: 0..2, next: 1..3 }),
+        );
+        let inverted = Range { start: 3, end: 1 };
+        let tree = token(0..4, vec![token(inverted.clone(), vec![])]);
+        assert_eq!(tree.validate_spans(), Err(SpanError::Inverted { span: inverted }));
+        assert_eq!(
+            SpanError::Unordered { previous: 0..2, next: 1..3 }.to_string(),
+            "span 1..3 overlaps or precedes its previous sibling 0..2",
+        );
+    }
+}
@@ -1,2 +1,35 @@
+//! Without the default `std` feature, only the core parser is available and
+//! it needs just `alloc`: nodes, grammars and tokens, [`parsers::naive`] and
+//! the [`basic::Parser`] builder. Regex terminals fail to parse there.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 pub mod basic;
 pub mod parsers;
+
+#[cfg(any(all(test, feature = "std"), feature = "testing"))]
+pub mod testing;
+
+/// The `alloc` items that are not in the `core` prelude
+mod prelude {
+    pub use alloc::{
+        boxed::Box,
+        format,
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+}
+
+/// The maps of the parser: hash maps with `std`, B-trees with `alloc` only
+#[cfg(feature = "std")]
+mod collections {
+    pub use std::collections::{hash_map as map, HashMap as Map, HashSet as Set};
+}
+
+#[cfg(not(feature = "std"))]
+mod collections {
+    pub use alloc::collections::{btree_map as map, BTreeMap as Map, BTreeSet as Set};
+}
@@ -2,41 +2,264 @@
 Naive iterative parser for EBNF-like grammars.
 */
 
-use std::{fmt::{self, Debug}, ops::Range};
+use core::{fmt::{self, Debug}, ops::Range};
 
+use serde::{Deserialize, Serialize};
+
+use crate::{collections::Set, prelude::*};
+
+#[cfg(feature = "codespan")]
+mod codespan;
+
+/// A problem found while parsing
+///
+/// Serialized with its kind in snake case and its span as `[start, end]`,
+/// as in `{"kind": "incomplete", "span": [4, 4], "expected": "term"}`.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum Diagnostic {
     Incomplete {
+        #[serde(with = "compact_span")]
         span: Range<usize>,
         expected: String,
     },
+    /// A repetition spanning `span` was stopped after `limit` matches, see
+    /// [`Parser::max_repetitions`](crate::basic::Parser::max_repetitions)
+    RepetitionLimit {
+        #[serde(with = "compact_span")]
+        span: Range<usize>,
+        limit: usize,
+    },
+}
+
+/// Spans serialized as `[start, end]` rather than `{"start": start, "end": end}`
+mod compact_span {
+    use core::ops::Range;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(span: &Range<usize>, serializer: S) -> Result<S::Ok, S::Error> {
+        (span.start, span.end).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Range<usize>, D::Error> {
+        let (start, end) = <(usize, usize)>::deserialize(deserializer)?;
+        Ok(start..end)
+    }
 }
 
 impl Diagnostic { // TODO remove TerminalNode bound
     pub fn main_span(&self) -> Range<usize> {
         match self {
-            Diagnostic::Incomplete { span, .. } => span.clone(),
+            Diagnostic::Incomplete { span, .. } | Diagnostic::RepetitionLimit { span, .. } => span.clone(),
         }
     }
     pub fn message(&self) -> String {
+        self.message_at(self.main_span().start.to_string())
+    }
+
+    /// Same as [`Diagnostic::message`], with the position written `line:column`
+    ///
+    /// The position is the one of the start of the span in `src`, see [`line_col`].
+    pub fn message_with_source(&self, src: &str) -> String {
+        let (line, column) = line_col(src, self.main_span().start);
+        self.message_at(format!("{line}:{column}"))
+    }
+
+    fn message_at(&self, at: String) -> String {
         match self {
-            Diagnostic::Incomplete { span, expected } => format!("Incomplete parse at {}: expected {expected}", span.start),
+            Diagnostic::Incomplete { expected, .. } => format!("Incomplete parse at {at}: expected {expected}"),
+            Diagnostic::RepetitionLimit { limit, .. } => format!("Repetition at {at} stopped after {limit} matches"),
+        }
+    }
+
+    /// Remove the repeated diagnostics and merge the adjacent ones with the same span
+    ///
+    /// The first occurrence of each diagnostic is kept, in order. Consecutive
+    /// `Incomplete` diagnostics with the same span, as left by alternatives
+    /// failing at the same place, become one expecting any of them, written
+    /// `a | b`.
+    pub fn dedup(diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        let mut seen = Set::new();
+        let mut merged: Vec<Diagnostic> = vec![];
+        for diagnostic in diagnostics {
+            if !seen.insert(diagnostic.clone()) {
+                continue;
+            }
+            match (merged.last_mut(), diagnostic) {
+                (
+                    Some(Diagnostic::Incomplete { span: last_span, expected: last_expected }),
+                    Diagnostic::Incomplete { span, expected },
+                ) if *last_span == span => {
+                    if !last_expected.split(" | ").any(|e| e == expected) {
+                        last_expected.push_str(" | ");
+                        last_expected.push_str(&expected);
+                    }
+                }
+                (_, diagnostic) => merged.push(diagnostic),
+            }
+        }
+        merged
+    }
+}
+
+/// The line and column of the byte `offset` of `src`, both starting at 1
+///
+/// Lines end after `\n`, and columns count chars, so that a `\r` before
+/// the newline is the last char of its line. An offset inside a char is in
+/// the column of that char, an offset past the end of `src` at its end.
+pub fn line_col(src: &str, offset: usize) -> (usize, usize) {
+    let mut offset = offset.min(src.len());
+    while !src.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &src[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Source order: by start of the main span, then by end and message
+impl Ord for Diagnostic {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        fn key(d: &Diagnostic) -> (usize, usize, u8, &str, usize) {
+            match d {
+                Diagnostic::Incomplete { span, expected } => (span.start, span.end, 0, expected, 0),
+                Diagnostic::RepetitionLimit { span, limit } => (span.start, span.end, 1, "", *limit),
+            }
         }
+        key(self).cmp(&key(other))
+    }
+}
+
+impl PartialOrd for Diagnostic {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A list of [`Diagnostic`]s, such as the ones gathered from several parses
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    /// The diagnostics in source order, see [`Diagnostic`'s `Ord`](Diagnostic#impl-Ord-for-Diagnostic)
+    pub fn sorted(mut self) -> Self {
+        self.0.sort();
+        self
+    }
+
+    /// See [`Diagnostic::dedup`]
+    pub fn dedup(self) -> Self {
+        Self(Diagnostic::dedup(self.0))
+    }
+}
+
+impl From<Vec<Diagnostic>> for Diagnostics {
+    fn from(diagnostics: Vec<Diagnostic>) -> Self {
+        Self(diagnostics)
+    }
+}
+
+impl FromIterator<Diagnostic> for Diagnostics {
+    fn from_iter<I: IntoIterator<Item = Diagnostic>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<I: IntoIterator<Item = Diagnostic>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+impl core::ops::Deref for Diagnostics {
+    type Target = [Diagnostic];
+    fn deref(&self) -> &[Diagnostic] {
+        &self.0
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = alloc::vec::IntoIter<Diagnostic>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'d> IntoIterator for &'d Diagnostics {
+    type Item = &'d Diagnostic;
+    type IntoIter = core::slice::Iter<'d, Diagnostic>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
     }
 }
 
 // TODO into a parser struct
+/// Parse `source` from `start`
+///
+/// The result does not borrow `start` nor `state`: for [`Node`](crate::basic::Node)s
+/// the borrow of the grammar ends when this returns, and the token can be
+/// kept or sent elsewhere.
 pub fn parse_recursive<N: AbstractNode + Debug>(
     source: &N::Src,
     start: N,
-    mut state: N::State,
+    state: N::State,
+) -> anyhow::Result<Option<(N::Token, Vec<Diagnostic>)>> {
+    parse_recursive_at(source, start, 0, state)
+}
+
+/// Same as [`parse_recursive`], but starts parsing at `pos`
+///
+/// Spans of the resulting token and diagnostics are relative to `source`.
+pub fn parse_recursive_at<N: AbstractNode + Debug>(
+    source: &N::Src,
+    start: N,
+    pos: usize,
+    state: N::State,
 ) -> anyhow::Result<Option<(N::Token, Vec<Diagnostic>)>> {
+    Ok(parse_parsed_at(source, start, pos, state)?.map(Parsed::detach))
+}
+
+/// Same as [`parse_recursive_at`], but returns the raw [`Parsed`] result
+pub fn parse_parsed_at<N: AbstractNode + Debug>(
+    source: &N::Src,
+    start: N,
+    pos: usize,
+    state: N::State,
+) -> anyhow::Result<Option<Parsed<N>>> {
+    parse_traced_at(source, start, pos, state, &NoTracer)
+}
+
+/// Same as [`parse_parsed_at`], reporting every step to `tracer`
+///
+/// The diagnostics of the result are sorted in source order.
+pub fn parse_traced_at<N: AbstractNode + Debug>(
+    source: &N::Src,
+    start: N,
+    pos: usize,
+    mut state: N::State,
+    tracer: &impl Tracer<N>,
+) -> anyhow::Result<Option<Parsed<N>>> {
+    parse_with_state_at(source, start, pos, &mut state, tracer)
+}
+
+/// Same as [`parse_traced_at`], but borrows the state so that it can be reused
+pub fn parse_with_state_at<N: AbstractNode + Debug>(
+    source: &N::Src,
+    start: N,
+    pos: usize,
+    state: &mut N::State,
+    tracer: &impl Tracer<N>,
+) -> anyhow::Result<Option<Parsed<N>>> {
     let mut stack: Vec<N::StackState> = vec![];
 
     // initialization
     let mut curr_step = Step::ParsingNode {
         node: start,
-        pos: 0,
+        pos,
     };
 
     // parsing loop
@@ -45,7 +268,8 @@ pub fn parse_recursive<N: AbstractNode + Debug>(
 
         curr_step = match curr_step {
             Step::ParsingNode { node, pos } => {
-                let action = node.action(source, pos, &mut state)?;
+                tracer.trace(TraceEvent::Enter { node: &node, pos, depth: stack.len() });
+                let action = node.action(source, pos, state)?;
                 match action {
                     Action::Push { save_state, next_node, next_pos } => {
                         stack.push(save_state);
@@ -55,23 +279,40 @@ pub fn parse_recursive<N: AbstractNode + Debug>(
                         }
                     },
                     Action::Pop { parsed } => {
+                        tracer.trace(TraceEvent::Exit { parsed: parsed.as_ref(), depth: stack.len() });
                         Step::Polling { parsed }
                     },
                 }
             },
             Step::Polling { parsed } => if let Some(stack_state) = stack.pop() {
-                let poll = stack_state.poll(parsed, &mut state);
-                match poll {
-                    StackPoll::Finished(parsed) => {
-                        Step::Polling { parsed } // ! pos???
-                    },
-                    StackPoll::Feed(state, node, pos) => {
-                        stack.push(state);
-                        Step::ParsingNode {
-                            node,
-                            pos, // ! pos???
-                        }
-                    },
+                let mut poll = stack_state.poll(parsed, state);
+                // nodes that complete right away, such as terminals, are fed
+                // back to their parent without a round trip through the stack
+                loop {
+                    match poll {
+                        StackPoll::Finished(parsed) => {
+                            tracer.trace(TraceEvent::Exit { parsed: parsed.as_ref(), depth: stack.len() });
+                            break Step::Polling { parsed } // ! pos???
+                        },
+                        StackPoll::Feed(stack_state, node, pos) => {
+                            let depth = stack.len() + 1;
+                            tracer.trace(TraceEvent::Enter { node: &node, pos, depth });
+                            match node.action(source, pos, state)? {
+                                Action::Pop { parsed } => {
+                                    tracer.trace(TraceEvent::Exit { parsed: parsed.as_ref(), depth });
+                                    poll = stack_state.poll(parsed, state);
+                                },
+                                Action::Push { save_state, next_node, next_pos } => {
+                                    stack.push(stack_state);
+                                    stack.push(save_state);
+                                    break Step::ParsingNode {
+                                        node: next_node,
+                                        pos: next_pos, // ! pos???
+                                    }
+                                },
+                            }
+                        },
+                    }
                 }
             } else {
                 break 'a parsed;
@@ -79,13 +320,14 @@ pub fn parse_recursive<N: AbstractNode + Debug>(
         };
     };
 
-    Ok(parsed.map(|t| {
-        (t.token, t.diagnostics)
+    Ok(parsed.map(|mut parsed| {
+        parsed.diagnostics.sort();
+        parsed
     }))
 }
 
 /// Check the stack for recursion limit
-fn check_stack<'a, N: AbstractNode, S: AbstractStackState<N>>(
+fn check_stack<N: AbstractNode, S: AbstractStackState<N>>(
     stack: &[S],
 ) -> anyhow::Result<()> {
     if stack.len() > 1000 {
@@ -101,6 +343,65 @@ fn check_stack<'a, N: AbstractNode, S: AbstractStackState<N>>(
     Ok(())
 }
 
+/// A step of the parsing loop, see [`Tracer`]
+#[derive(Debug)]
+pub enum TraceEvent<'t, N: AbstractNode> {
+    /// `node` is about to be parsed at `pos`
+    Enter {
+        node: &'t N,
+        pos: usize,
+        depth: usize,
+    },
+    /// The innermost node being parsed completed with `parsed`
+    Exit {
+        parsed: Option<&'t Parsed<N>>,
+        depth: usize,
+    },
+}
+
+/// Observer of the parsing loop, useful to debug grammars
+///
+/// `depth` is the size of the parser stack when the event happens.
+pub trait Tracer<N: AbstractNode> {
+    fn trace(&self, event: TraceEvent<'_, N>);
+}
+
+/// A tracer that does nothing, compiled away entirely
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoTracer;
+
+impl<N: AbstractNode> Tracer<N> for NoTracer {
+    #[inline(always)]
+    fn trace(&self, _event: TraceEvent<'_, N>) {}
+}
+
+/// A tracer that logs every step at `trace` level using the [`log`] crate
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogTracer;
+
+impl<N: AbstractNode> Tracer<N> for LogTracer {
+    fn trace(&self, event: TraceEvent<'_, N>) {
+        match event {
+            TraceEvent::Enter { node, pos, depth } => {
+                log::trace!("{:indent$}{node} @ {pos}", "", indent = depth * 2);
+            }
+            TraceEvent::Exit { parsed: None, depth } => {
+                log::trace!("{:indent$}-> no match", "", indent = depth * 2);
+            }
+            TraceEvent::Exit { parsed: Some(parsed), depth } => {
+                let status = if parsed.incomplete.is_some() { "incomplete" } else { "match" };
+                log::trace!("{:indent$}-> {status}", "", indent = depth * 2);
+            }
+        }
+    }
+}
+
+impl<N: AbstractNode, F: Fn(TraceEvent<'_, N>)> Tracer<N> for F {
+    fn trace(&self, event: TraceEvent<'_, N>) {
+        self(event)
+    }
+}
+
 #[derive(Debug)]
 pub enum Action<Node: AbstractNode> {
     Push {
@@ -129,7 +430,8 @@ pub trait AbstractNode: Sized + Clone + fmt::Display { // TODO remove clone
     type Src: ?Sized;
     type State;
     type StackState: AbstractStackState<Self>;
-    type Token: Sized + Clone + Debug; // TODO remove debug
+    /// The result of a parse, owned so that it outlives the nodes and the state
+    type Token: Sized + Clone + Debug + 'static; // TODO remove debug
     fn action(
         self,
         src: &Self::Src,
@@ -145,6 +447,16 @@ pub struct Parsed<N: AbstractNode> {
     pub incomplete: Option<N>,
 }
 
+impl<N: AbstractNode> Parsed<N> {
+    /// The token and the diagnostics, without `incomplete`
+    ///
+    /// `incomplete` is the only part that can borrow the grammar, the rest
+    /// outlives it.
+    pub fn detach(self) -> (N::Token, Vec<Diagnostic>) {
+        (self.token, self.diagnostics)
+    }
+}
+
 #[derive(Debug)]
 enum Step<N: AbstractNode> {
     ParsingNode {
@@ -158,10 +470,11 @@ enum Step<N: AbstractNode> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{basic::{Grammar, State}, parsers::tests::cases};
+    use crate::{basic::{Grammar, Node, State, Text}, gram, parsers::tests::cases};
 
     use super::*;
 
+    #[cfg(feature = "std")]
     #[test]
     fn alt_term() {
         for (grammar, input, expected) in cases() {
@@ -172,10 +485,391 @@ mod tests {
                 &grammar,
                 State::new(&Grammar::new()),
             ).unwrap();
+            if let Some((token, _)) = &result {
+                assert_eq!(token.validate_spans(), Ok(()), "Invalid spans for input: {}", input);
+            }
+            assert_eq!(result, expected, "Failed for input: {}", input);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn strip_annotations() {
+        fn strip(mut token: crate::basic::Token) -> crate::basic::Token {
+            token.tags.clear();
+            token.meta.clear();
+            token.map_children(strip)
+        }
+
+        for (node, input, expected) in cases() {
+            let mut annotated = Node::tagged(node.clone(), "t").with_description("d");
+            annotated.walk_mut(&mut |n| if let Node::Seq(elements) = n {
+                elements.iter_mut().for_each(|e| *e = Node::tagged(e.clone(), "e"));
+            });
+            annotated.strip_annotations();
+            assert_eq!(annotated, node);
+            let result = parse_recursive(input, &annotated, State::new(&Grammar::new())).unwrap();
             assert_eq!(result, expected, "Failed for input: {}", input);
         }
+
+        let grammar = crate::grammar! {
+            /// A list
+            list = ("[", ("items": items), "]");
+            items = item %% ",";
+            item = (("number": number) | ("name": name));
+            number = #r"[0-9]+";
+            name = #r"[a-z]+";
+            /// Annotated nodes that give back matches or are revisited
+            padded = (("as": "a"*), "a", ("choice": ("b" | "bc")), "c");
+        };
+        let stripped = grammar.strip_annotations();
+        assert!(stripped.complexity() < grammar.complexity());
+        assert!(grammar.rules.values().zip(stripped.rules.values()).all(|(a, b)| a.structurally_eq(b)));
+        for src in ["[a,1,bc]", "[]", "[a,", "[1,2]x", "x"] {
+            let expected = grammar.parse_non_term("list", src).unwrap().map(|(t, d)| (strip(t), d));
+            assert_eq!(stripped.parse_non_term("list", src).unwrap(), expected, "{src:?}");
+        }
+        for src in ["aabc", "abcc", "ab"] {
+            let expected = grammar.parse_non_term("padded", src).unwrap().map(|(t, d)| (strip(t), d));
+            assert_eq!(stripped.parse_non_term("padded", src).unwrap(), expected, "{src:?}");
+            let revisit = grammar.parser().revisit_choices();
+            let expected = revisit.parse_non_term("padded", src).unwrap().map(|(t, d)| (strip(t), d));
+            assert_eq!(stripped.parser().revisit_choices().parse_non_term("padded", src).unwrap(), expected, "{src:?}");
+        }
+    }
+
+    #[test]
+    fn cases_without_std() {
+        // regex terminals need `std`, the other cases parse the same
+        let mut parsed = 0;
+        for (node, input, expected) in cases() {
+            let mut regex = false;
+            node.walk(&mut |n| regex |= matches!(n, Node::Terminal(Text::Regex(_) | Text::RegexFlags { .. })));
+            if regex {
+                continue;
+            }
+            let result = parse_recursive(input, &node, State::new(&Grammar::new())).unwrap();
+            assert_eq!(result, expected, "Failed for input: {}", input);
+            parsed += 1;
+        }
+        assert!(parsed > 0);
+
+        let grammar = crate::grammar! {
+            list = item %% ",";
+            item = ("a" | ("[", list, "]"));
+        };
+        let (token, diagnostics) = grammar.parse_non_term("list", "a,[a,a],a").unwrap().unwrap();
+        assert_eq!(token.span, 0..9);
+        assert!(diagnostics.is_empty());
+        assert_eq!(token.iter_grams("item").count(), 5);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn token_outlives_grammar() {
+        let parse = |src: &str| {
+            let grammar = crate::grammar! { list = "a" %% ","; };
+            let state = State::new(&grammar);
+            parse_parsed_at(src, &grammar.rules["list"], 0, state).unwrap().unwrap().detach()
+        };
+
+        let (token, diagnostics) = parse("a,a,");
+        let span = std::thread::spawn(move || token.span).join().unwrap();
+        assert_eq!(span, 0..3);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn empty_seq_and_alt() {
+        let grammar = Grammar::new();
+        let empty_seq = Node::<Text>::Seq(vec![]);
+        let empty_alt = Node::<Text>::Alt(vec![]);
+
+        let (token, diagnostics) = grammar.parse_node(&empty_seq, "foo").unwrap().unwrap();
+        assert_eq!(token.span, 0..0);
+        assert!(diagnostics.is_empty());
+
+        assert_eq!(grammar.parse_node(&empty_alt, "foo").unwrap(), None);
+
+        let node = Node::Seq(vec![gram!("foo"), empty_seq.clone(), gram!("bar")]);
+        let (token, diagnostics) = grammar.parse_node(&node, "foobar").unwrap().unwrap();
+        assert_eq!(token.span, 0..6);
+        assert_eq!(token.children[1].span, 3..3);
+        assert!(diagnostics.is_empty());
+
+        let node = Node::Alt(vec![empty_alt, gram!("foo")]);
+        let (token, _) = grammar.parse_node(&node, "foo").unwrap().unwrap();
+        assert_eq!(token.span, 0..3);
+    }
+
+    #[test]
+    fn empty_node() {
+        let grammar = Grammar::new();
+        let (token, diagnostics) = grammar.parse_node(&gram!(()), "foo").unwrap().unwrap();
+        assert_eq!(token.span, 0..0);
+        assert!(token.children.is_empty());
+        assert!(diagnostics.is_empty());
+
+        let node = gram!(("foo", ("bar" | ()), "baz"));
+        assert_eq!(node.to_ebnf(), r#""foo" ("bar" | "") "baz""#);
+        let (token, _) = grammar.parse_node(&node, "foobaz").unwrap().unwrap();
+        assert_eq!(token.span, 0..6);
+        assert_eq!(token.children[1].span, 3..3);
+        let (token, _) = grammar.parse_node(&node, "foobarbaz").unwrap().unwrap();
+        assert_eq!(token.children[1].span, 3..6);
+        assert!(grammar.nullable(&gram!(("x" | ()))));
+    }
+
+    #[test]
+    fn dedup_diagnostics() {
+        let incomplete = |span: Range<usize>, expected: &str| Diagnostic::Incomplete { span, expected: expected.to_string() };
+        let diagnostics = vec![
+            incomplete(0..1, "a"),
+            incomplete(0..1, "a"),
+            incomplete(2..2, "b"),
+            incomplete(2..2, "c"),
+            incomplete(2..2, "b"),
+            incomplete(3..3, "d"),
+            incomplete(0..1, "a"),
+            incomplete(2..2, "e"),
+        ];
+        assert_eq!(Diagnostic::dedup(diagnostics.clone()), [
+            incomplete(0..1, "a"),
+            incomplete(2..2, "b | c"),
+            incomplete(3..3, "d"),
+            incomplete(2..2, "e"),
+        ]);
+        assert_eq!(Diagnostics::from(diagnostics).sorted().dedup().0, [
+            incomplete(0..1, "a"),
+            incomplete(2..2, "b | c | e"),
+            incomplete(3..3, "d"),
+        ]);
+        assert_eq!(Diagnostic::dedup(vec![]), []);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn message_with_source() {
+        let grammar = Grammar::load_ebnf(r#"
+            block = "{" , (statement , ";")* , #'\s*' , "}";
+            statement = #'\s*[a-zé]+ = ' , #'[0-9]+';
+        "#).unwrap();
+        let src = "{\n  a = 1;\n  bé = 2;\n  c = ;\n}";
+        let (_, diagnostics) = grammar.parse_non_term("block", src).unwrap().unwrap();
+        let at = src.find("= ;").unwrap() + 2;
+        assert_eq!(diagnostics[0].main_span().start, at);
+        assert_eq!(diagnostics[0].message(), format!("Incomplete parse at {at}: expected /[0-9]+/"));
+        assert_eq!(diagnostics[0].message_with_source(src), "Incomplete parse at 4:7: expected /[0-9]+/");
+
+        assert_eq!(line_col(src, 0), (1, 1));
+        assert_eq!(line_col(src, 1), (1, 2));
+        assert_eq!(line_col(src, 2), (2, 1));
+        // columns count chars, a byte inside `é` is in its column
+        let after = src.find('é').unwrap();
+        assert_eq!(line_col(src, after + 1), (3, 4));
+        assert_eq!(line_col(src, after + 2), (3, 5));
+        assert_eq!(line_col(src, 1000), (5, 2));
+        assert_eq!(line_col("", 0), (1, 1));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn alt_ties() {
+        let grammar = Grammar::new();
+        let chosen = |node: Node<Text>, src| {
+            let states = [
+                State::new(&grammar),
+                #[cfg(feature = "parallel")]
+                State::new(&grammar).with_parallel_alt(2),
+            ];
+            let tags = states.map(|state| {
+                let (token, _) = parse_recursive(src, &node, state).unwrap().unwrap();
+                token.tags.concat()
+            });
+            assert!(tags.iter().all(|t| *t == tags[0]), "{tags:?}");
+            tags[0].clone()
+        };
+
+        assert_eq!(chosen(gram!((("1": "ab") | ("2": "ab"))), "ab"), "1");
+        assert_eq!(chosen(gram!((("2": "ab") | ("1": "ab"))), "ab"), "2");
+        assert_eq!(chosen(gram!((("1": "a") | ("2": "ab") | ("3": "ab"))), "ab"), "2");
+        assert_eq!(chosen(gram!((("1": "a") | ("2": ((#r"a"), "b")) | ("3": "ab"))), "ab"), "2");
+
+        // complete matches win over incomplete ones of the same length
+        assert_eq!(chosen(gram!((("1": ("a", "b")) | ("2": "a"))), "ax"), "2");
+        assert_eq!(chosen(gram!((("1": "a") | ("2": ("a", "b")))), "ax"), "1");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn empty_source() {
+        let grammar = Grammar::new();
+        let parse = |node: Node<Text>| grammar.parse_node(&node, "").unwrap().map(|(token, diagnostics)| {
+            assert_eq!(token.validate_spans(), Ok(()));
+            (token.span, diagnostics)
+        });
+
+        // nodes that can match nothing succeed with a zero-width token
+        for node in [
+            Node::Seq(vec![]),
+            gram!((("a"?), ("b"*))),
+            gram!(("a" | ())),
+            gram!("a"*),
+            gram!("a"?),
+            Node::rep_sep(gram!("a"), gram!(","), 0..),
+            gram!(""),
+            gram!(#r"[a-z]*"),
+        ] {
+            assert_eq!(parse(node.clone()), Some((0..0, vec![])), "{node}");
+        }
+
+        // the others fail, unless they are part of a started sequence
+        for node in [gram!(("a", "b")), gram!(("a" | "b")), gram!("a"+), gram!("a" %% ","), gram!("a"), gram!(#r"[a-z]+")] {
+            assert_eq!(parse(node.clone()), None, "{node}");
+        }
+        let (span, diagnostics) = parse(gram!((("x"?), ("a"+)))).unwrap();
+        assert_eq!(span, 0..0);
+        assert_eq!(diagnostics, [Diagnostic::Incomplete { span: 0..0, expected: "\"a\"+".to_string() }]);
+
+        let grammar = Grammar::load_ebnf("list = \"a\"*;").unwrap();
+        let (token, _) = grammar.parse_non_term("list", "").unwrap().unwrap();
+        assert_eq!(token.to_sexpr(), "(_ 0..0)");
+        assert_eq!(token.iter_at_pos(0).count(), 0);
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn nullable_repetition() {
+        let grammar = Grammar::new();
+        let spans = |node: &Node<Text>, src| {
+            let (token, diagnostics) = grammar.parse_node(node, src).unwrap().unwrap();
+            assert!(diagnostics.is_empty());
+            assert_eq!(token.validate_spans(), Ok(()));
+            (token.span, token.children.iter().map(|c| c.span.clone()).collect::<Vec<_>>())
+        };
+
+        assert_eq!(spans(&gram!(("a"?)*), ""), (0..0, vec![]));
+        assert_eq!(spans(&gram!(("a"?)*), "aab"), (0..2, vec![0..1, 1..2]));
+        // the empty match satisfies the minimum
+        assert_eq!(spans(&gram!(("a"?)+), "b"), (0..0, vec![0..0]));
+        assert_eq!(spans(&gram!(("a"?)+), "ab"), (0..1, vec![0..1]));
+        assert_eq!(spans(&gram!(("a"*)*), "aa"), (0..2, vec![0..2]));
+        assert_eq!(spans(&gram!(("a"?) %% ("," ?)), "aab"), (0..2, vec![0..1, 1..2]));
+        assert_eq!(spans(&gram!(("a"?) %% ","), "b"), (0..0, vec![0..0]));
+        // and is repeated up to larger minimums
+        assert_eq!(spans(&Node::rep(gram!("a"?), 3..), "b"), (0..0, vec![0..0, 0..0, 0..0]));
+        assert_eq!(spans(&Node::rep(gram!("a"?), 3..), "ab"), (0..1, vec![0..1, 1..1, 1..1]));
+        assert_eq!(spans(&Node::rep_sep(gram!("a"?), gram!(","?), 3..), "a"), (0..1, vec![0..1, 1..1, 1..1]));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn diagnostics_in_source_order() {
+        let grammar = Grammar::<Text>::load_ebnf(r#"
+            list = item , ("," , item)*;
+            item = ("(" , "a" , ")") | ("[" , "a" , "]");
+        "#).unwrap();
+        let (_, diagnostics) = grammar.parse_non_term("list", "(a),[a,(a").unwrap().unwrap();
+        let starts = diagnostics.iter().map(|d| d.main_span().start).collect::<Vec<_>>();
+        assert_eq!(starts, [6, 9]);
+
+        let incomplete = |start, expected: &str| Diagnostic::Incomplete {
+            span: start..start,
+            expected: expected.to_string(),
+        };
+        let diagnostics = Diagnostics::from(vec![incomplete(5, "b"), incomplete(1, "x"), incomplete(5, "a")]);
+        assert_eq!(diagnostics.sorted(), Diagnostics(vec![incomplete(1, "x"), incomplete(5, "a"), incomplete(5, "b")]));
+
+        // limits are compared as numbers
+        let limit = |limit| Diagnostic::RepetitionLimit { span: 0..1, limit };
+        let diagnostics = Diagnostics::from(vec![limit(1000), incomplete(0, "a"), limit(999)]);
+        assert_eq!(diagnostics.sorted(), Diagnostics(vec![incomplete(0, "a"), limit(999), limit(1000)]));
+    }
+
+    #[test]
+    fn lazy_repetition() {
+        let grammar = Grammar::new();
+        let parse = |node: &Node<Text>, src| {
+            let (token, diagnostics) = grammar.parse_node(node, src).unwrap()?;
+            assert_eq!(token.validate_spans(), Ok(()));
+            let children = token.children.first().map(|c| c.children.len());
+            Some((token.span, children, diagnostics.is_empty()))
+        };
+
+        assert_eq!(parse(&gram!((("a"*?), "ab")), "aab"), Some((0..3, Some(1), true)));
+        assert_eq!(parse(&gram!((("a"+?), "a")), "aaa"), Some((0..2, Some(1), true)));
+        assert_eq!(parse(&gram!((("a"??), "ab")), "aab"), Some((0..3, Some(1), true)));
+        // on its own, or as the last element, it matches the minimum
+        assert_eq!(parse(&gram!("a"*?), "aa"), Some((0..0, None, true)));
+        assert_eq!(parse(&gram!(("x", ("a"+?))), "xaa"), Some((0..2, Some(0), true)));
+        // the maximum bounds the retries
+        assert_eq!(parse(&gram!((("a"??), "b")), "aab"), Some((0..1, Some(1), false)));
+        assert_eq!(parse(&gram!((("a"*?), "b")), "c"), Some((0..0, Some(0), false)));
+
+        // retries go back to the earlier lazy repetitions
+        let (token, diagnostics) = grammar.parse_node(&gram!((("a"*?), ("b"*?), "abc")), "aababc").unwrap().unwrap();
+        assert!(diagnostics.is_empty());
+        let counts = token.children.iter().map(|c| c.children.len()).collect::<Vec<_>>();
+        assert_eq!(counts, [2, 1, 0]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn greedy_repetition_gives_back() {
+        let grammar = Grammar::load_ebnf(r#"
+            word = (letter*), letter;
+            letter = #'[a-z]';
+            digits = (#'[0-9]'+), "0", ";";
+            list = ("x", ",")*, "x", ";";
+        "#).unwrap();
+        let parse = |start, src| {
+            let (token, diagnostics) = grammar.parse_non_term(start, src).unwrap()?;
+            assert_eq!(token.validate_spans(), Ok(()));
+            let counts = token.children.iter().map(|c| c.children.len()).collect::<Vec<_>>();
+            Some((token.span, counts, diagnostics))
+        };
+
+        assert_eq!(parse("word", "abc"), Some((0..3, vec![2, 1], vec![])));
+        assert_eq!(parse("word", "a"), Some((0..1, vec![0, 1], vec![])));
+        assert_eq!(parse("digits", "1200;"), Some((0..5, vec![3, 0, 0], vec![])));
+        assert_eq!(parse("list", "x,x,x;"), Some((0..6, vec![2, 0, 0], vec![])));
+
+        // without a way to succeed, the furthest attempt is reported
+        assert_eq!(parse("digits", "123;"), Some((0..3, vec![3], vec![Diagnostic::Incomplete {
+            span: 3..3,
+            expected: "\"0\"".to_string(),
+        }])));
+        assert_eq!(parse("word", ""), Some((0..0, vec![0], vec![Diagnostic::Incomplete {
+            span: 0..0,
+            expected: "letter".to_string(),
+        }])));
+
+        // the giveback only retries the elements after the repetition
+        let grammar = Grammar::new();
+        let (token, diagnostics) = grammar.parse_node(&gram!((("a"*), "ab")), "aab").unwrap().unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(token.children[0].span, 0..1);
+
+        // annotations do not change what is given back
+        for node in [
+            Node::seq([Node::tagged(gram!("a"*), "d"), gram!("a")]),
+            Node::seq([gram!("a"*).with_description("d"), gram!("a")]),
+            Node::seq([Node::tagged(gram!("a"*?), "d"), gram!("a"), gram!("b")]),
+        ] {
+            let mut stripped = node.clone();
+            stripped.strip_annotations();
+            for src in ["aa", "aab", "a", ""] {
+                let result = grammar.parse_node(&node, src).unwrap().map(|(token, diagnostics)| (token.span, diagnostics));
+                let expected = grammar.parse_node(&stripped, src).unwrap().map(|(token, diagnostics)| (token.span, diagnostics));
+                assert_eq!(result, expected, "{node} on {src:?}");
+            }
+        }
+        let (token, diagnostics) = grammar.parse_node(&Node::seq([Node::tagged(gram!("a"*), "d"), gram!("a")]), "aa").unwrap().unwrap();
+        assert_eq!((token.span, diagnostics), (0..2, vec![]));
+        assert_eq!(token.children[0].tags, ["d"]);
     }
 
+    #[cfg(feature = "std")]
     #[test]
     fn test_parse_complex_ebnf() {
         let source = r#"
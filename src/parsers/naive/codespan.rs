@@ -0,0 +1,51 @@
+//! Rendering of [`Diagnostic`]s with [`codespan_reporting`], enabled by the `codespan` feature
+
+use codespan_reporting::{
+    diagnostic::{self, Label},
+    files::SimpleFile,
+    term::{self, Config},
+};
+
+use super::Diagnostic;
+
+impl Diagnostic {
+    /// The equivalent [`codespan_reporting`] diagnostic, labelling the span in `file_id`
+    pub fn to_codespan<FileId>(&self, file_id: FileId) -> diagnostic::Diagnostic<FileId> {
+        match self {
+            Diagnostic::Incomplete { span, expected } => diagnostic::Diagnostic::error()
+                .with_message("incomplete parse")
+                .with_label(Label::primary(file_id, span.clone()).with_message(format!("expected {expected}"))),
+            Diagnostic::RepetitionLimit { span, limit } => diagnostic::Diagnostic::error()
+                .with_message("repetition limit reached")
+                .with_label(Label::primary(file_id, span.clone()).with_message(format!("stopped after {limit} matches"))),
+        }
+    }
+
+    /// Render the diagnostic as plain text, with the lines of `src` around the
+    /// span and a caret under it, `name` being the name of the source file
+    pub fn render(&self, name: &str, src: &str) -> String {
+        let file = SimpleFile::new(name, src);
+        term::emit_into_string(&Config::default(), &file, &self.to_codespan(()))
+            .unwrap_or_else(|_| self.message())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::basic::Grammar;
+
+    #[test]
+    fn render() {
+        let grammar = Grammar::load_ebnf(r#"
+            call = name , "(" , name , ")";
+            name = #'[a-z]+';
+        "#).unwrap();
+        let src = "print(x";
+        let (_, diagnostics) = grammar.parse_non_term("call", src).unwrap().unwrap();
+        let rendered = diagnostics[0].render("main.txt", src);
+        assert!(rendered.contains("error: incomplete parse"), "{rendered}");
+        assert!(rendered.contains("main.txt:1:8"), "{rendered}");
+        assert!(rendered.contains("1 │ print(x"), "{rendered}");
+        assert!(rendered.contains("expected \")\""), "{rendered}");
+    }
+}
@@ -1,9 +1,12 @@
-use crate::{basic::{Node, Text, Token}, gram};
+use crate::{basic::{Node, Text, Token}, gram, prelude::*};
 
 use super::naive::Diagnostic;
 
-pub fn cases() -> Vec<(Node<Text>, &'static str, Option<(Token, Vec<Diagnostic>)>)> {
-    let mut tests: Vec<(Node<Text>, &str, Option<(Token, Vec<Diagnostic>)>)> = Vec::new();
+pub type Case = (Node<Text>, &'static str, Option<(Token, Vec<Diagnostic>)>);
+
+#[allow(clippy::vec_init_then_push)]
+pub fn cases() -> Vec<Case> {
+    let mut tests: Vec<Case> = Vec::new();
 
     tests.push((
         gram! {
@@ -291,5 +294,84 @@ pub fn cases() -> Vec<(Node<Text>, &'static str, Option<(Token, Vec<Diagnostic>)
         None,
     ));
 
+    tests.push((
+        gram! {
+            "a" %% ","
+        },
+        "a,a,a",
+        Some((
+            Token {
+                span: 0..5,
+                gram: None,
+                tags: vec![],
+                meta: Default::default(),
+                children: vec![
+                    Token {
+                        span: 0..1,
+                        gram: None,
+                        tags: vec![],
+                        meta: Default::default(),
+                        children: vec![],
+                    },
+                    Token {
+                        span: 2..3,
+                        gram: None,
+                        tags: vec![],
+                        meta: Default::default(),
+                        children: vec![],
+                    },
+                    Token {
+                        span: 4..5,
+                        gram: None,
+                        tags: vec![],
+                        meta: Default::default(),
+                        children: vec![],
+                    },
+                ],
+            },
+            vec![],
+        )),
+    ));
+
+    tests.push((
+        gram! {
+            "a" %% ", "
+        },
+        "a, a, ",
+        Some((
+            Token {
+                span: 0..4,
+                gram: None,
+                tags: vec![],
+                meta: Default::default(),
+                children: vec![
+                    Token {
+                        span: 0..1,
+                        gram: None,
+                        tags: vec![],
+                        meta: Default::default(),
+                        children: vec![],
+                    },
+                    Token {
+                        span: 3..4,
+                        gram: None,
+                        tags: vec![],
+                        meta: Default::default(),
+                        children: vec![],
+                    },
+                ],
+            },
+            vec![],
+        )),
+    ));
+
+    tests.push((
+        gram! {
+            "a" %% ","
+        },
+        ",a",
+        None,
+    ));
+
     tests
 }
@@ -0,0 +1,174 @@
+/*!
+Assertion helpers for parser tests, enabled by the `testing` feature.
+
+Expectations are written as [`Token::to_sexpr`] strings rather than nested
+[`Token`] literals:
+
+```
+use yasp::{basic::Grammar, testing::assert_parses};
+
+let grammar = Grammar::load_ebnf(r#"
+    (* start: pair *)
+    pair = number , "," , number;
+    number = digit+;
+    digit = "0" | "1";
+"#).unwrap();
+
+assert_parses(&grammar, "10,1", "(_ 0..4 (number 0..2 (digit 0..1) (digit 1..2)) (number 3..4 (digit 3..4)))");
+```
+*/
+
+use crate::basic::{Grammar, Node, TerminalNode, Text, Token};
+
+/// Levels of nesting allowed beyond the shortest input, see [`assert_grammar_roundtrips`]
+const EXTRA_DEPTH: usize = 4;
+
+/// Assert that the start rule of `grammar` parses the whole `input` without
+/// diagnostics, into a tree whose [`Token::to_sexpr`] is `expected`
+///
+/// The spans of the tree are checked with [`Token::validate_spans`] too.
+#[track_caller]
+pub fn assert_parses<T: TerminalNode<Src = str>>(grammar: &Grammar<T>, input: &str, expected: &str) {
+    let (_, node) = grammar.require_start().unwrap_or_else(|e| panic!("{e}"));
+    assert_parses_node(grammar, node, input, expected);
+}
+
+/// Same as [`assert_parses`], starting from `node` instead of the start rule
+#[track_caller]
+pub fn assert_parses_node<T: TerminalNode<Src = str>>(
+    grammar: &Grammar<T>,
+    node: &Node<T>,
+    input: &str,
+    expected: &str,
+) {
+    let token = parse_complete(grammar, node, input)
+        .unwrap_or_else(|e| panic!("{input:?} does not parse: {e}"));
+    assert_eq!(token.to_sexpr(), expected, "Unexpected tree for {input:?}");
+}
+
+/// Assert that the start rule of `grammar` does not parse the whole `input`
+/// cleanly, either failing, leaving input behind or reporting diagnostics
+#[track_caller]
+pub fn assert_rejects<T: TerminalNode<Src = str>>(grammar: &Grammar<T>, input: &str) {
+    let (_, node) = grammar.require_start().unwrap_or_else(|e| panic!("{e}"));
+    if let Ok(token) = parse_complete(grammar, node, input) {
+        panic!("{input:?} unexpectedly parses as {}", token.to_sexpr());
+    }
+}
+
+/// Assert that `n` random inputs of the rule `start` parse back whole, without diagnostics
+///
+/// The inputs are drawn by [`Grammar::generate`] with a fixed seed, so a
+/// failure is reproducible, nesting a few more non-terminals than the
+/// shortest input of `start` needs. A grammar can fail where the parser is
+/// not context-free, for example with a greedy regex that consumes the text
+/// of the next terminal.
+#[track_caller]
+pub fn assert_grammar_roundtrips(grammar: &Grammar<Text>, start: &str, n: usize) {
+    let mut rng = xorshift(0x2545_f491_4f6c_dd1d);
+    let shortest = (0..=grammar.rules.len())
+        .find(|&depth| grammar.generate(start, &mut rng, depth).is_some())
+        .unwrap_or_else(|| panic!("{start:?} has no finite input"));
+    let node = Node::NonTerm(start.to_string());
+    for i in 0..n {
+        let input = grammar.generate(start, &mut rng, shortest + EXTRA_DEPTH).unwrap();
+        if let Err(e) = parse_complete(grammar, &node, &input) {
+            panic!("Generated input {i} {input:?} does not parse: {e}");
+        }
+    }
+}
+
+/// A xorshift generator from `seed`, drawing numbers below the bound it is called with
+///
+/// Enough to draw choices, as for [`Grammar::generate`], not for anything statistical.
+pub fn xorshift(mut seed: u64) -> impl FnMut(usize) -> usize {
+    move |bound| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed % bound as u64) as usize
+    }
+}
+
+fn parse_complete<T: TerminalNode<Src = str>>(
+    grammar: &Grammar<T>,
+    node: &Node<T>,
+    input: &str,
+) -> anyhow::Result<Token> {
+    let (token, diagnostics) = grammar
+        .parse_node(node, input)?
+        .ok_or_else(|| anyhow::anyhow!("no match"))?;
+    if let Some(diagnostic) = diagnostics.first() {
+        anyhow::bail!("{}", diagnostic.message());
+    }
+    if token.span.end != input.len() {
+        anyhow::bail!("input left after {}", token.span.end);
+    }
+    token.validate_spans()?;
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{basic::Text, gram};
+
+    use super::*;
+
+    fn grammar() -> Grammar<Text> {
+        Grammar::load_ebnf(r#"
+            (* start: list *)
+            list = "[" , [item , ("," , item)*] , "]";
+            item = number | list;
+            number = digit+;
+            digit = "0" | "1" | "2";
+        "#).unwrap()
+    }
+
+    #[test]
+    fn parses() {
+        let grammar = grammar();
+        assert_parses(&grammar, "[]", "(_ 0..2)");
+        assert_parses(&grammar, "[1,[]]", "(_ 0..6 (item 1..2 (number 1..2 (digit 1..2))) (item 3..5 (list 3..5)))");
+        assert_parses_node(&grammar, &gram!(digit+), "21", "(_ 0..2 (digit 0..1) (digit 1..2))");
+    }
+
+    #[test]
+    fn rejects() {
+        let grammar = grammar();
+        assert_rejects(&grammar, "[1,");
+        assert_rejects(&grammar, "[1]]");
+        assert_rejects(&grammar, "x");
+    }
+
+    #[test]
+    fn grammar_roundtrips() {
+        assert_grammar_roundtrips(&grammar(), "list", 50);
+
+        // left recursion and separated repetitions
+        let mut grammar = Grammar::load_ebnf(r#"
+            expression = (expression , ("+" | "-") , term) | term;
+            term = (term , ("*" | "/") , factor) | factor;
+            factor = ("(" , expression , ")") | number | ("-" , factor) | call;
+            call = name , "(" , args , ")";
+            number = #'[0-9]+(\.[0-9]+)?';
+            name = #'[a-z]+';
+        "#).unwrap();
+        grammar.add_element("args", gram!(expression %% ",")).unwrap();
+        assert_grammar_roundtrips(&grammar, "expression", 50);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not parse")]
+    fn greedy_regex_does_not_roundtrip() {
+        let grammar = Grammar::load_ebnf(r#"
+            plural = #'[a-z]+' , "s";
+        "#).unwrap();
+        assert_grammar_roundtrips(&grammar, "plural", 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected tree")]
+    fn wrong_tree() {
+        assert_parses(&grammar(), "[]", "(_ 0..1)");
+    }
+}